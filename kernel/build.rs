@@ -27,6 +27,24 @@ fn main() {
         panic!("Failed to assemble trampoline.s");
     }
 
+    //the tests start a 32 bit process, assembled and linked here
+    if std::env::var_os("CARGO_FEATURE_RUN_TESTS").is_some() {
+        let object = out_dir.clone() + "/compat_test.o";
+        if !(Command::new("nasm")
+            .args(["-f", "elf32", "src/tests/compat_test.asm", "-o", &object])
+            .status()
+            .expect("Failed to run nasm on compat_test.asm")
+            .success()
+            && Command::new("ld")
+                .args(["-m", "elf_i386", "-o", &(out_dir.clone() + "/compat_test"), &object])
+                .status()
+                .expect("Failed to run ld on compat_test.o")
+                .success())
+        {
+            panic!("Failed to build compat_test.asm");
+        }
+    }
+
     // Set the flag to generate the linker map file
     println!("cargo:rustc-link-arg=-T{}", link_script_file.display());
     //println!("cargo:rustc-link-arg=Map=/home/nejc/dev/meowOS/kernel.map");
@@ -34,6 +52,7 @@ fn main() {
     // Re-run the build script if the build configuration changes
     println!("cargo:rerun-if-changed=build.rs");
     println!("cargo:rerun-if-changed=kernel/linker_script.ld");
+    println!("cargo:rerun-if-changed=src/tests/compat_test.asm");
     println!("cargo:rustc-link-search={}", out_dir);
     println!("cargo:rustc-link-lib=static=trampoline");
 }
@@ -124,6 +124,14 @@ impl CpuLocals {
     pub fn get_lock_info() -> &'static mut LockInfo {
         &mut Self::get().lock_info
     }
+
+    ///Locals of every cpu that has been started, ordered by apic id
+    pub fn all() -> impl Iterator<Item = &'static Self> {
+        unsafe { CPU_LOCALS.assume_init_ref() }
+            .iter()
+            .filter(|addr| addr.0 != 0)
+            .map(|addr| unsafe { &*(addr.0 as *const Self) })
+    }
 }
 
 //FS register contains thread local storage of a process
@@ -1,10 +1,15 @@
-use std::{println, time::Instant};
+use std::{
+    println,
+    time::{Duration, Instant, UNIX_EPOCH},
+};
 
 mod hpet;
 mod rtc;
 mod tsc;
 
 static mut SELECTED_TIMER: SelectedTimer = SelectedTimer::Tsc;
+///Time at which the clocks were started
+static mut BOOT_TIME: Instant = UNIX_EPOCH;
 
 trait Timer {
     fn start(&self, now: Instant) -> bool;
@@ -14,6 +19,7 @@ trait Timer {
 pub fn init() {
     let now = rtc::RTC_WRAPPER.get_time();
     println!("Current time: {:?}", now);
+    unsafe { BOOT_TIME = now };
     let tsc_success = unsafe { tsc::TSC_WRAPPER.start(now) };
     if !tsc_success {
         panic!("HPET not yet ready to use");
@@ -26,6 +32,11 @@ pub fn init() {
     // let _success = unsafe { hpet::HPET.start(now) };
 }
 
+///Time since the clocks were initialized, early in boot
+pub fn uptime() -> Duration {
+    unsafe { BOOT_TIME }.elapsed()
+}
+
 #[derive(Debug, Clone, Copy)]
 enum SelectedTimer {
     Tsc,
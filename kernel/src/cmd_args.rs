@@ -1,26 +1,95 @@
-use core::str::FromStr;
-use uuid::{self, Uuid};
+use std::{boxed::Box, vec::Vec};
+
+const DEFAULT_INIT: &str = "/bin/init";
 
 #[derive(Debug)]
 pub struct CmdArgs {
-    pub root_partition: Uuid,
+    ///Root filesystem source, in any format the mount syscall takes (`PARTUUID=`, `PARTLABEL=`,
+    ///a plain partition uuid or a /dev node). A tmpfs is used as root if it is missing
+    pub root: Option<Box<str>>,
+    ///Filesystem type of root, detected from the partition if missing
+    pub root_fs_type: Option<Box<str>>,
+    ///Mount root read only, set by `ro` and cleared by `rw`. Such a root must already have the
+    ///directories dev, proc and tmp
+    pub read_only: bool,
+    ///Path of the first process (pid 1) on the root filesystem
+    pub init: Box<str>,
+    ///Arguments the kernel doesn't know, in order. Flags without `=` have no value
+    pub unknown: Vec<(Box<str>, Option<Box<str>>)>,
 }
 
-impl CmdArgs {
-    pub fn new(arg_str: &str) -> Self {
-        let args = arg_str.split_whitespace();
-        let mut root_partition = None;
-        for arg in args {
-            let (key, value) = arg.split_at(arg.find("=").unwrap());
-            if key == "root" {
-                //value is in uuid format
-                let uuid = Uuid::from_str(&value[1..]).unwrap();
-                root_partition = Some(uuid);
-            }
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CmdArgsError {
+    ///Key that needs a value but has none, like `root` or `root=`
+    MissingValue(Box<str>),
+    ///Key given more than once
+    Duplicate(Box<str>),
+    ///Argument starting with `=`
+    MissingKey(Box<str>),
+}
+
+impl core::fmt::Display for CmdArgsError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            CmdArgsError::MissingValue(key) => write!(f, "\"{}\" needs a value, like {}=<value>", key, key),
+            CmdArgsError::Duplicate(key) => write!(f, "\"{}\" is given more than once", key),
+            CmdArgsError::MissingKey(arg) => write!(f, "\"{}\" has no key before '='", arg),
         }
+    }
+}
 
+impl Default for CmdArgs {
+    fn default() -> Self {
         Self {
-            root_partition: root_partition.unwrap(),
+            root: None,
+            root_fs_type: None,
+            read_only: false,
+            init: DEFAULT_INIT.into(),
+            unknown: Vec::new(),
         }
     }
 }
+
+impl CmdArgs {
+    pub fn new(arg_str: &str) -> Result<Self, CmdArgsError> {
+        let mut cmd_args = Self::default();
+        let mut init = None;
+        for arg in arg_str.split_whitespace() {
+            //only the first '=' separates, root=PARTUUID=... keeps the rest in the value
+            let (key, value) = match arg.split_once('=') {
+                Some(("", _)) => return Err(CmdArgsError::MissingKey(arg.into())),
+                Some((key, value)) => (key, Some(value)),
+                None => (arg, None),
+            };
+            match key {
+                "root" => set_once(&mut cmd_args.root, key, value)?,
+                "rootfstype" => set_once(&mut cmd_args.root_fs_type, key, value)?,
+                "init" => set_once(&mut init, key, value)?,
+                "ro" if value.is_none() => cmd_args.read_only = true,
+                "rw" if value.is_none() => cmd_args.read_only = false,
+                _ => cmd_args.unknown.push((key.into(), value.map(Box::from))),
+            }
+        }
+        if let Some(init) = init {
+            cmd_args.init = init;
+        }
+        Ok(cmd_args)
+    }
+
+    ///Value of an unknown argument, None if it is missing or a flag without value
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.unknown
+            .iter()
+            .find(|(unknown_key, _)| **unknown_key == *key)
+            .and_then(|(_, value)| value.as_deref())
+    }
+}
+
+fn set_once(field: &mut Option<Box<str>>, key: &str, value: Option<&str>) -> Result<(), CmdArgsError> {
+    let value = value.filter(|value| !value.is_empty()).ok_or(CmdArgsError::MissingValue(key.into()))?;
+    if field.is_some() {
+        return Err(CmdArgsError::Duplicate(key.into()));
+    }
+    *field = Some(value.into());
+    Ok(())
+}
@@ -67,3 +67,35 @@ pub fn get_manufacturer_id() -> [u8; 12] {
     let leaf = get_cpuid_leaf(0).expect("CPUID leaf 0 should always succeed");
     unsafe { std::mem::transmute((leaf.ebx, leaf.edx, leaf.ecx)) }
 }
+
+///The processor brand string from leaves 0x80000002-0x80000004, without padding
+pub fn get_brand_string() -> Option<[u8; 48]> {
+    let max_extended = get_cpuid_leaf(0x80000000)?.eax;
+    if max_extended < 0x80000004 {
+        return None;
+    }
+    let mut brand = [0u8; 48];
+    for (i, leaf) in (0x80000002..=0x80000004).enumerate() {
+        let leaf = get_cpuid_leaf(leaf)?;
+        for (j, reg) in [leaf.eax, leaf.ebx, leaf.ecx, leaf.edx].iter().enumerate() {
+            let start = i * 16 + j * 4;
+            brand[start..start + 4].copy_from_slice(&reg.to_le_bytes());
+        }
+    }
+    Some(brand)
+}
+
+///Returns (family, model, stepping) with the extended family and model already added
+pub fn get_family_model_stepping() -> (u32, u32, u32) {
+    let eax = get_cpuid_leaf(1).expect("CPUID leaf 1 should always succeed").eax;
+    let stepping = eax & 0xF;
+    let mut model = (eax >> 4) & 0xF;
+    let mut family = (eax >> 8) & 0xF;
+    if family == 0xF {
+        family += (eax >> 20) & 0xFF;
+    }
+    if family == 0x6 || family >= 0xF {
+        model |= ((eax >> 16) & 0xF) << 4;
+    }
+    (family, model, stepping)
+}
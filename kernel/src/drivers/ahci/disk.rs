@@ -487,6 +487,10 @@ impl VirtualPort {
 
 #[async_trait::async_trait]
 impl BlockDevice for VirtualPort {
+    fn sector_count(&self) -> usize {
+        self.sectors as usize
+    }
+
     async fn read(&self, start_sec_index: usize, sec_count: usize, buffer: &[PhysAddr]) {
         OPERATIONS.fetch_add(1, core::sync::atomic::Ordering::AcqRel);
         assert!(sec_count <= self.sectors as usize);
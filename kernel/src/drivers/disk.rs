@@ -7,6 +7,8 @@ use crate::vfs::{DeviceId, InodeIndex};
 
 #[async_trait::async_trait]
 pub trait BlockDevice: Debug + Send + Sync {
+    ///Size of the device in 512 byte sectors
+    fn sector_count(&self) -> usize;
     async fn read(&self, sector: usize, sec_count: usize, buffer: &[PhysAddr]);
     async fn write(&self, sector: usize, sec_count: usize, buffer: &[PhysAddr]);
 }
@@ -1,6 +1,8 @@
-use std::{boxed::Box, mem_utils::VirtAddr};
+use std::{boxed::Box, error::ErrorCode, mem_utils::VirtAddr};
+
+use super::Rfs;
+use rfs_layout::{BLOCK_SIZE_SECTORS, Key};
 
-use super::{BLOCK_SIZE_SECTORS, Rfs};
 use crate::{
     drivers::disk::MountedPartition,
     memory::{
@@ -83,55 +85,98 @@ impl BtreeNode {
     }
 
     ///Returns the block intex on the disk (each has 8 sectors) where the inode is stored
-    pub async fn find_inode_block(node: VirtAddr, key_index: u32, fs_data: &Rfs) -> Option<u32> {
+    pub async fn find_inode_block(node: VirtAddr, key_index: u32, fs_data: &Rfs) -> Result<Option<u32>, ErrorCode> {
         for i in 0..341 {
             let key = Self::get_key(node, i);
             if key.index == 0 {
                 if Self::get_child(node, i) == 0 {
-                    return None;
+                    return Ok(None);
                 }
                 let child_block = Self::get_child(node, i);
-                let child_node = unsafe { fs_data.get_node(child_block).await.1 };
+                let child_node = unsafe { fs_data.get_node(child_block).await?.1 };
                 return Box::pin(BtreeNode::find_inode_block(child_node, key_index, fs_data)).await;
             }
             if key.index == key_index {
-                return Some(key.inode_block);
+                return Ok(Some(key.inode_block));
             }
             if key.index > key_index {
                 if Self::get_child(node, i) == 0 {
-                    return None;
+                    return Ok(None);
                 }
                 let child_block = Self::get_child(node, i);
-                let child_node = unsafe { fs_data.get_node(child_block).await.1 };
+                let child_node = unsafe { fs_data.get_node(child_block).await?.1 };
                 return Box::pin(BtreeNode::find_inode_block(child_node, key_index, fs_data)).await;
             }
         }
         if Self::get_child(node, 341) == 0 {
-            return None;
+            return Ok(None);
         }
         let child_block = Self::get_child(node, 341);
-        let child_node = unsafe { fs_data.get_node(child_block).await.1 };
+        let child_node = unsafe { fs_data.get_node(child_block).await?.1 };
         Box::pin(BtreeNode::find_inode_block(child_node, key_index, fs_data)).await
     }
 
-    //returns a new root node if the root was split
-    pub async fn insert_key_root(node: VirtAddr, block: u32, key: Key, fs_data: &Rfs) -> Option<u32> {
+    ///Changes the block stored with a key, returns false if the key is not in the tree
+    pub async fn set_inode_block(block: u32, key_index: u32, inode_block: u32, fs_data: &Rfs) -> Result<bool, ErrorCode> {
+        let node = unsafe { fs_data.get_node(block).await?.1 };
+        for i in 0..341 {
+            let key = Self::get_key(node, i);
+            if key.index == key_index {
+                Self::set_key(node, i, Key { index: key_index, inode_block });
+                unsafe { fs_data.get_node(block).await?.0 = true };
+                return Ok(true);
+            }
+            if key.index == 0 || key.index > key_index {
+                let child_block = Self::get_child(node, i);
+                if child_block == 0 {
+                    return Ok(false);
+                }
+                return Box::pin(BtreeNode::set_inode_block(child_block, key_index, inode_block, fs_data)).await;
+            }
+        }
+        let child_block = Self::get_child(node, 341);
+        if child_block == 0 {
+            return Ok(false);
+        }
+        Box::pin(BtreeNode::set_inode_block(child_block, key_index, inode_block, fs_data)).await
+    }
+
+    ///Frees every node of the tree, used for the name index of a removed directory
+    pub async fn free_tree(block: u32, fs_data: &Rfs) -> Result<(), ErrorCode> {
+        let node = unsafe { fs_data.get_node(block).await?.1 };
+        for i in 0..342 {
+            let child_block = Self::get_child(node, i);
+            if child_block == 0 {
+                break;
+            }
+            Box::pin(BtreeNode::free_tree(child_block, fs_data)).await?;
+        }
+        unsafe { fs_data.remove_inode_cache_entry(block) };
+        fs_data.free_block(block).await?;
+        BtreeNode::drop(node);
+        Ok(())
+    }
+
+    //returns a new root node if the root was split. Fails with NoSpace if a split finds no free
+    //block, or with FileSystemInconsistency if a node doesn't match its checksum, the nodes in the
+    //cache may be changed by then
+    pub async fn insert_key_root(node: VirtAddr, block: u32, key: Key, fs_data: &Rfs) -> Result<Option<u32>, ErrorCode> {
         let is_leaf = Self::get_child(node, 0) == 0;
         let is_full = Self::get_key(node, 340).index != 0;
 
         if is_leaf {
             if is_full {
-                let new_root_block = Self::split_root(node, block, fs_data).await;
-                let new_root_node = unsafe { fs_data.get_node(new_root_block).await.1 };
+                let new_root_block = Self::split_root(node, block, fs_data).await?;
+                let new_root_node = unsafe { fs_data.get_node(new_root_block).await?.1 };
                 if key.index < BtreeNode::get_key(new_root_node, 0).index {
-                    BtreeNode::insert_key_internal(new_root_node, new_root_block, 0, key, fs_data).await;
+                    BtreeNode::insert_key_internal(new_root_node, new_root_block, 0, key, fs_data).await?;
                 } else {
-                    BtreeNode::insert_key_internal(new_root_node, new_root_block, block, key, fs_data).await;
+                    BtreeNode::insert_key_internal(new_root_node, new_root_block, block, key, fs_data).await?;
                 }
-                Some(new_root_block)
+                Ok(Some(new_root_block))
             } else {
-                BtreeNode::insert_non_full(node, block, key, None, fs_data).await;
-                None
+                BtreeNode::insert_non_full(node, block, key, None, fs_data).await?;
+                Ok(None)
             }
         } else {
             //find first bigger key index
@@ -144,45 +189,51 @@ impl BtreeNode {
         }
     }
 
-    async fn insert_into_root_child(node: VirtAddr, block: u32, child_index: usize, key: Key, fs_data: &Rfs) -> Option<u32> {
+    async fn insert_into_root_child(
+        node: VirtAddr,
+        block: u32,
+        child_index: usize,
+        key: Key,
+        fs_data: &Rfs,
+    ) -> Result<Option<u32>, ErrorCode> {
         let is_full = Self::get_key(node, 340).index != 0;
         let child_node_index = Self::get_child(node, child_index);
         let rebalance_result = BtreeNode::insert_key_internal(
-            unsafe { fs_data.get_node(child_node_index).await.1 },
+            unsafe { fs_data.get_node(child_node_index).await?.1 },
             child_node_index,
             block,
             key,
             fs_data,
-        ).await;
+        ).await?;
         match rebalance_result {
-            RebalanceResult::None => None,
+            RebalanceResult::None => Ok(None),
             RebalanceResult::Merge(_) => {
                 unreachable!("Nodes should not be merged when inserting keys");
             }
             RebalanceResult::Rotate(_) => {
                 //child does everything
-                None
+                Ok(None)
             }
             RebalanceResult::Split(new_block, new_key) => {
                 if is_full {
-                    let new_root_block = Self::split_root(node, block, fs_data).await;
-                    let new_root_node = unsafe { fs_data.get_node(new_root_block).await.1 };
+                    let new_root_block = Self::split_root(node, block, fs_data).await?;
+                    let new_root_node = unsafe { fs_data.get_node(new_root_block).await?.1 };
                     if new_key.index < BtreeNode::get_key(new_root_node, 0).index {
-                        BtreeNode::insert_key_internal(new_root_node, new_root_block, 0, new_key, fs_data).await;
+                        BtreeNode::insert_key_internal(new_root_node, new_root_block, 0, new_key, fs_data).await?;
                     } else {
-                        BtreeNode::insert_key_internal(new_root_node, new_root_block, block, new_key, fs_data).await;
+                        BtreeNode::insert_key_internal(new_root_node, new_root_block, block, new_key, fs_data).await?;
                     }
-                    Some(new_root_block)
+                    Ok(Some(new_root_block))
                 } else {
-                    Self::insert_non_full(node, block, new_key, Some(new_block), fs_data).await;
-                    None
+                    Self::insert_non_full(node, block, new_key, Some(new_block), fs_data).await?;
+                    Ok(None)
                 }
             }
         }
     }
 
     //returns a new root node if the root was merged
-    pub async fn delete_key_root(node: VirtAddr, block: u32, key_index: u32, fs_data: &Rfs) -> Option<u32> {
+    pub async fn delete_key_root(node: VirtAddr, block: u32, key_index: u32, fs_data: &Rfs) -> Result<Option<u32>, ErrorCode> {
         assert!(key_index > 2); //no deleting null keys, bad block file or root
         let is_leaf = Self::get_child(node, 0) == 0;
 
@@ -198,17 +249,17 @@ impl BtreeNode {
                 }
             }
             Self::set_key(node, 340, Key::empty());
-            unsafe { fs_data.get_node(block).await.0 = true };
+            unsafe { fs_data.get_node(block).await?.0 = true };
             assert!(deleted);
-            return None;
+            return Ok(None);
         }
 
         for i in 0..341 {
             if key_index == Self::get_key(node, i).index {
                 //child on the left of the key
                 let child_block = Self::get_child(node, i);
-                let child_node = unsafe { fs_data.get_node(child_block).await.1 };
-                let (key, rebalance_result) = BtreeNode::take_biggest_key(child_node, child_block, block, fs_data).await;
+                let child_node = unsafe { fs_data.get_node(child_block).await?.1 };
+                let (key, rebalance_result) = BtreeNode::take_biggest_key(child_node, child_block, block, fs_data).await?;
                 //If the result is a merge, this key has escaped to the merged child. Find and
                 //replace it there. No need to worry about additional merges, as the node will be
                 //full
@@ -225,17 +276,17 @@ impl BtreeNode {
                         } else {
                             BtreeNode::set_key(child_node, i - 1, key);
                         }
-                        unsafe { fs_data.get_node(child_block).await.0 = true };
+                        unsafe { fs_data.get_node(child_block).await?.0 = true };
 
                         if Self::get_key(node, 0).index == 0 {
                             //root is empty, merge
                             unsafe { fs_data.remove_inode_cache_entry(block) };
-                            fs_data.free_block(block).await;
+                            fs_data.free_block(block).await?;
                             let child = Self::get_child(node, 0);
                             BtreeNode::drop(node);
-                            return Some(child);
+                            return Ok(Some(child));
                         } else {
-                            return None;
+                            return Ok(None);
                         }
                     }
                     RebalanceResult::Rotate(direction) => {
@@ -243,24 +294,24 @@ impl BtreeNode {
                             for i in 160..180 {
                                 if BtreeNode::get_key(child_node, i).index == key_index {
                                     BtreeNode::set_key(child_node, i, key);
-                                    unsafe { fs_data.get_node(child_block).await.0 = true };
-                                    return None;
+                                    unsafe { fs_data.get_node(child_block).await?.0 = true };
+                                    return Ok(None);
                                 }
                             }
                             unreachable!("Key not found in child");
                         } else {
                             assert!(BtreeNode::get_key(child_node, i).index != key_index);
                             Self::set_key(node, i, key);
-                            unsafe { fs_data.get_node(block).await.0 = true };
-                            return None;
+                            unsafe { fs_data.get_node(block).await?.0 = true };
+                            return Ok(None);
                         }
                     }
 
                     RebalanceResult::Split(_, _) => unreachable!("Split should not happen when deleting keys"),
                     RebalanceResult::None => {
                         Self::set_key(node, i, key);
-                        unsafe { fs_data.get_node(block).await.0 = true };
-                        return None;
+                        unsafe { fs_data.get_node(block).await?.0 = true };
+                        return Ok(None);
                     }
                 }
             } else if key_index < Self::get_key(node, i).index || Self::get_key(node, i).index == 0 {
@@ -276,27 +327,27 @@ impl BtreeNode {
         key_index: u32,
         child_index: usize,
         fs_data: &Rfs,
-    ) -> Option<u32> {
+    ) -> Result<Option<u32>, ErrorCode> {
         let child_block = Self::get_child(node, child_index);
-        let child_node = unsafe { fs_data.get_node(child_block).await.1 };
-        let rebalance_result = BtreeNode::delete_key_internal(child_node, child_block, key_index, block, fs_data).await;
+        let child_node = unsafe { fs_data.get_node(child_block).await?.1 };
+        let rebalance_result = BtreeNode::delete_key_internal(child_node, child_block, key_index, block, fs_data).await?;
 
         match rebalance_result {
             RebalanceResult::Merge(_) => {
                 if Self::get_key(node, 0).index == 0 {
                     //root is empty, merge
                     unsafe { fs_data.remove_inode_cache_entry(block) };
-                    fs_data.free_block(block).await;
+                    fs_data.free_block(block).await?;
                     let child = BtreeNode::get_child(node, 0);
                     BtreeNode::drop(node);
-                    Some(child)
+                    Ok(Some(child))
                 } else {
-                    None
+                    Ok(None)
                 }
             }
-            RebalanceResult::Rotate(_) => None,
+            RebalanceResult::Rotate(_) => Ok(None),
             RebalanceResult::Split(_, _) => unreachable!("Split should not happen when deleting keys"),
-            RebalanceResult::None => None,
+            RebalanceResult::None => Ok(None),
         }
     }
 
@@ -306,7 +357,7 @@ impl BtreeNode {
         key_index: u32,
         parent_block: u32,
         fs_data: &Rfs,
-    ) -> RebalanceResult {
+    ) -> Result<RebalanceResult, ErrorCode> {
         let is_leaf = Self::get_child(node, 0) == 0;
 
         if is_leaf {
@@ -321,31 +372,31 @@ impl BtreeNode {
                 }
             }
             Self::set_key(node, 340, Key::empty());
-            unsafe { fs_data.get_node(block).await.0 = true };
+            unsafe { fs_data.get_node(block).await?.0 = true };
             assert!(deleted);
             let needs_rebalance = Self::get_key(node, 169).index == 0;
             if !needs_rebalance {
-                return RebalanceResult::None;
+                return Ok(RebalanceResult::None);
             }
             let mut left = false;
-            let mut result = BtreeNode::rotate_left_take(node, block, parent_block, fs_data, true).await;
+            let mut result = BtreeNode::rotate_left_take(node, block, parent_block, fs_data, true).await?;
             if !result {
                 left = true;
-                result = BtreeNode::rotate_right_take(node, block, parent_block, fs_data, true).await;
+                result = BtreeNode::rotate_right_take(node, block, parent_block, fs_data, true).await?;
             }
             if result {
-                return RebalanceResult::Rotate(if left { RotateDirection::Left } else { RotateDirection::Right });
+                return Ok(RebalanceResult::Rotate(if left { RotateDirection::Left } else { RotateDirection::Right }));
             }
-            let direction = BtreeNode::merge(node, block, parent_block, fs_data).await;
-            return RebalanceResult::Merge(direction);
+            let direction = BtreeNode::merge(node, block, parent_block, fs_data).await?;
+            return Ok(RebalanceResult::Merge(direction));
         }
 
         for i in 0..341 {
             if key_index == Self::get_key(node, i).index {
                 //child on the left of the key
                 let child_block = Self::get_child(node, i);
-                let child_node = unsafe { fs_data.get_node(child_block).await.1 };
-                let (key, rebalance_result) = BtreeNode::take_biggest_key(child_node, child_block, block, fs_data).await;
+                let child_node = unsafe { fs_data.get_node(child_block).await?.1 };
+                let (key, rebalance_result) = BtreeNode::take_biggest_key(child_node, child_block, block, fs_data).await?;
                 //If the result is a merge, this key has escaped to the merged child. Find and
                 //replace it there. No need to worry about additional merges, as the node will be
                 //full
@@ -362,27 +413,27 @@ impl BtreeNode {
                         } else {
                             BtreeNode::set_key(child_node, i - 1, key);
                         }
-                        unsafe { fs_data.get_node(child_block).await.0 = true };
+                        unsafe { fs_data.get_node(child_block).await?.0 = true };
 
                         if Self::get_key(node, 169).index == 0 {
                             //Node is too small, fix
 
                             let mut left = false;
-                            let mut result = BtreeNode::rotate_left_take(node, block, parent_block, fs_data, false).await;
+                            let mut result = BtreeNode::rotate_left_take(node, block, parent_block, fs_data, false).await?;
                             if !result {
                                 left = true;
-                                result = BtreeNode::rotate_right_take(node, block, parent_block, fs_data, false).await;
+                                result = BtreeNode::rotate_right_take(node, block, parent_block, fs_data, false).await?;
                             }
                             if result {
-                                return RebalanceResult::Rotate(if left {
+                                return Ok(RebalanceResult::Rotate(if left {
                                     RotateDirection::Left
                                 } else {
                                     RotateDirection::Right
-                                });
+                                }));
                             }
 
-                            let direction = BtreeNode::merge(node, block, parent_block, fs_data).await;
-                            return RebalanceResult::Merge(direction);
+                            let direction = BtreeNode::merge(node, block, parent_block, fs_data).await?;
+                            return Ok(RebalanceResult::Merge(direction));
                         }
                     }
                     RebalanceResult::Rotate(direction) => {
@@ -390,24 +441,24 @@ impl BtreeNode {
                             for i in 160..341 {
                                 if Self::get_key(node, i).index == key_index {
                                     Self::set_key(node, i, key);
-                                    unsafe { fs_data.get_node(block).await.0 = true };
-                                    return RebalanceResult::None;
+                                    unsafe { fs_data.get_node(block).await?.0 = true };
+                                    return Ok(RebalanceResult::None);
                                 }
                             }
                             unreachable!("Key not found in child");
                         } else {
                             assert!(Self::get_key(node, i).index != key_index);
-                            unsafe { fs_data.get_node(block).await.0 = true };
+                            unsafe { fs_data.get_node(block).await?.0 = true };
                             BtreeNode::set_key(node, i, key);
-                            return RebalanceResult::None;
+                            return Ok(RebalanceResult::None);
                         }
                     }
 
                     RebalanceResult::Split(_, _) => unreachable!("Split should not happen when deleting keys"),
                     RebalanceResult::None => {
-                        unsafe { fs_data.get_node(block).await.0 = true };
+                        unsafe { fs_data.get_node(block).await?.0 = true };
                         BtreeNode::set_key(node, i, key);
-                        return RebalanceResult::None;
+                        return Ok(RebalanceResult::None);
                     }
                 }
                 unreachable!();
@@ -418,7 +469,12 @@ impl BtreeNode {
         Box::pin(BtreeNode::delete_key_internal(node, block, key_index, 341, fs_data)).await
     }
 
-    async fn take_biggest_key(node: VirtAddr, block: u32, parent_block: u32, fs_data: &Rfs) -> (Key, RebalanceResult) {
+    async fn take_biggest_key(
+        node: VirtAddr,
+        block: u32,
+        parent_block: u32,
+        fs_data: &Rfs,
+    ) -> Result<(Key, RebalanceResult), ErrorCode> {
         //this code is almost identical to the delete_key_internal
         let is_leaf = BtreeNode::get_child(node, 0) == 0;
         if is_leaf {
@@ -436,30 +492,30 @@ impl BtreeNode {
 
                 //we need to rebalance
                 let mut left = false;
-                let mut result = BtreeNode::rotate_left_take(node, block, parent_block, fs_data, true).await;
+                let mut result = BtreeNode::rotate_left_take(node, block, parent_block, fs_data, true).await?;
                 if !result {
                     left = true;
-                    result = BtreeNode::rotate_right_take(node, block, parent_block, fs_data, true).await;
+                    result = BtreeNode::rotate_right_take(node, block, parent_block, fs_data, true).await?;
                 }
                 index += 1;
 
                 if result {
                     let key = BtreeNode::get_key(node, index);
                     BtreeNode::set_key(node, index, Key::empty());
-                    return (
+                    return Ok((
                         key,
                         RebalanceResult::Rotate(if left { RotateDirection::Left } else { RotateDirection::Right }),
-                    );
+                    ));
                 } else {
                     //merge is needed
-                    let direction = BtreeNode::merge(node, block, parent_block, fs_data).await;
-                    return (key, RebalanceResult::Merge(direction));
+                    let direction = BtreeNode::merge(node, block, parent_block, fs_data).await?;
+                    return Ok((key, RebalanceResult::Merge(direction)));
                 }
             }
             let key = BtreeNode::get_key(node, index);
-            unsafe { fs_data.get_node(block).await.0 = true };
+            unsafe { fs_data.get_node(block).await?.0 = true };
             BtreeNode::set_key(node, index, Key::empty());
-            return (key, RebalanceResult::None);
+            return Ok((key, RebalanceResult::None));
         }
 
         for i in (0..341).rev() {
@@ -477,15 +533,15 @@ impl BtreeNode {
         child_index: usize,
         parent_block: u32,
         fs_data: &Rfs,
-    ) -> (Key, RebalanceResult) {
+    ) -> Result<(Key, RebalanceResult), ErrorCode> {
         let child_node_block = BtreeNode::get_child(node, child_index);
         let (key, rebalance_result) = BtreeNode::take_biggest_key(
-            unsafe { fs_data.get_node(child_node_block).await.1 },
+            unsafe { fs_data.get_node(child_node_block).await?.1 },
             child_node_block,
             block,
             fs_data,
         )
-        .await;
+        .await?;
         match rebalance_result {
             RebalanceResult::Merge(_direction) => {
                 let mut last_key = 0;
@@ -497,40 +553,40 @@ impl BtreeNode {
                 }
                 if last_key > 169 {
                     //is still balanced
-                    return (key, RebalanceResult::None);
+                    return Ok((key, RebalanceResult::None));
                 }
                 //rotate
-                let mut result = BtreeNode::rotate_left_give(block, parent_block, fs_data, false).await;
+                let mut result = BtreeNode::rotate_left_give(block, parent_block, fs_data, false).await?;
                 if !result {
-                    result = BtreeNode::rotate_right_give(block, parent_block, fs_data, false).await;
+                    result = BtreeNode::rotate_right_give(block, parent_block, fs_data, false).await?;
                 }
                 if result {
-                    return (key, RebalanceResult::None);
+                    return Ok((key, RebalanceResult::None));
                 }
 
-                let direction = BtreeNode::merge(node, block, parent_block, fs_data).await;
-                (key, RebalanceResult::Merge(direction))
+                let direction = BtreeNode::merge(node, block, parent_block, fs_data).await?;
+                Ok((key, RebalanceResult::Merge(direction)))
             }
-            RebalanceResult::Rotate(_) => (key, RebalanceResult::None),
+            RebalanceResult::Rotate(_) => Ok((key, RebalanceResult::None)),
             RebalanceResult::Split(_, _) => unreachable!("Split should not happen when taking keys"),
-            RebalanceResult::None => (key, RebalanceResult::None),
+            RebalanceResult::None => Ok((key, RebalanceResult::None)),
         }
     }
 
-    async fn merge(node: VirtAddr, block: u32, parent_block: u32, fs_data: &Rfs) -> MergeDirection {
-        let parent = unsafe { fs_data.get_node(parent_block).await.1 };
+    async fn merge(node: VirtAddr, block: u32, parent_block: u32, fs_data: &Rfs) -> Result<MergeDirection, ErrorCode> {
+        let parent = unsafe { fs_data.get_node(parent_block).await?.1 };
         let self_index = unsafe { &*(parent.0 as *const BtreeNode) }.children.iter().position(|&x| x == block).unwrap();
         let (left_node, right_node, separator, direction, right_block, left_block);
         if self_index == 0 {
             left_block = block;
             left_node = node;
             right_block = BtreeNode::get_child(parent, self_index + 1);
-            right_node = unsafe { fs_data.get_node(right_block).await.1 };
+            right_node = unsafe { fs_data.get_node(right_block).await?.1 };
             separator = BtreeNode::get_key(parent, self_index);
             direction = MergeDirection::RightToCurrent;
         } else {
             left_block = BtreeNode::get_child(parent, self_index - 1);
-            left_node = unsafe { fs_data.get_node(left_block).await.1 };
+            left_node = unsafe { fs_data.get_node(left_block).await?.1 };
             right_node = node;
             separator = BtreeNode::get_key(parent, self_index - 1);
             direction = MergeDirection::CurrentToLeft;
@@ -572,19 +628,19 @@ impl BtreeNode {
         BtreeNode::set_child(parent, 341, 0);
 
         BtreeNode::drop(right_node);
-        fs_data.free_block(right_block).await;
+        fs_data.free_block(right_block).await?;
         unsafe { fs_data.remove_inode_cache_entry(right_block) };
 
-        unsafe { fs_data.get_node(left_block).await.0 = true };
-        unsafe { fs_data.get_node(parent_block).await.0 = true };
+        unsafe { fs_data.get_node(left_block).await?.0 = true };
+        unsafe { fs_data.get_node(parent_block).await?.0 = true };
 
-        direction
+        Ok(direction)
     }
 
     //returns the new root
-    async fn split_root(node: VirtAddr, block: u32, fs_data: &Rfs) -> u32 {
-        let sibling_block = fs_data.allocate_block().await;
-        let parent_block = fs_data.allocate_block().await;
+    async fn split_root(node: VirtAddr, block: u32, fs_data: &Rfs) -> Result<u32, ErrorCode> {
+        let sibling_block = fs_data.allocate_block().await?;
+        let parent_block = fs_data.allocate_block().await?;
         let sibling_node = BtreeNode::new();
         let parent_node = BtreeNode::new();
 
@@ -606,20 +662,26 @@ impl BtreeNode {
         BtreeNode::set_child(parent_node, 0, block);
         BtreeNode::set_child(parent_node, 1, sibling_block);
 
-        unsafe { fs_data.get_node(block).await.0 = true };
+        unsafe { fs_data.get_node(block).await?.0 = true };
 
-        parent_block
+        Ok(parent_block)
     }
 
-    async fn insert_key_internal(node: VirtAddr, block: u32, parent_block: u32, key: Key, fs_data: &Rfs) -> RebalanceResult {
+    async fn insert_key_internal(
+        node: VirtAddr,
+        block: u32,
+        parent_block: u32,
+        key: Key,
+        fs_data: &Rfs,
+    ) -> Result<RebalanceResult, ErrorCode> {
         let is_leaf = BtreeNode::get_child(node, 0) == 0;
         if is_leaf {
             let is_full = BtreeNode::get_key(node, 340).index != 0;
             if is_full {
                 return BtreeNode::insert_full(node, block, parent_block, key, None, fs_data).await;
             } else {
-                BtreeNode::insert_non_full(node, block, key, None, fs_data).await;
-                return RebalanceResult::None;
+                BtreeNode::insert_non_full(node, block, key, None, fs_data).await?;
+                return Ok(RebalanceResult::None);
             }
         }
         //find first bigger key index
@@ -638,38 +700,38 @@ impl BtreeNode {
         parent_block: u32,
         key: Key,
         fs_data: &Rfs,
-    ) -> RebalanceResult {
+    ) -> Result<RebalanceResult, ErrorCode> {
         let child_node_index = BtreeNode::get_child(node, child_index);
         let rebalance_result = BtreeNode::insert_key_internal(
-            unsafe { fs_data.get_node(child_node_index).await.1 },
+            unsafe { fs_data.get_node(child_node_index).await?.1 },
             child_node_index,
             block,
             key,
             fs_data,
         )
-        .await;
+        .await?;
         match rebalance_result {
-            RebalanceResult::None => RebalanceResult::None,
+            RebalanceResult::None => Ok(RebalanceResult::None),
             RebalanceResult::Merge(_) => {
                 unreachable!("Nodes should not be merged when inserting keys");
             }
             RebalanceResult::Rotate(_) => {
                 //child does everything
-                RebalanceResult::None
+                Ok(RebalanceResult::None)
             }
             RebalanceResult::Split(new_block, new_key) => {
                 let self_full = BtreeNode::get_key(node, 340).index != 0;
                 if self_full {
                     BtreeNode::insert_full(node, block, parent_block, new_key, Some(new_block), fs_data).await
                 } else {
-                    BtreeNode::insert_non_full(node, block, new_key, Some(new_block), fs_data).await;
-                    RebalanceResult::None
+                    BtreeNode::insert_non_full(node, block, new_key, Some(new_block), fs_data).await?;
+                    Ok(RebalanceResult::None)
                 }
             }
         }
     }
 
-    async fn insert_non_full(node: VirtAddr, block: u32, key: Key, child: Option<u32>, fs_data: &Rfs) {
+    async fn insert_non_full(node: VirtAddr, block: u32, key: Key, child: Option<u32>, fs_data: &Rfs) -> Result<(), ErrorCode> {
         let mut ptr: i32 = 339;
         let key_inserted = false;
         while ptr >= 0 && BtreeNode::get_key(node, ptr as usize).index == 0 {
@@ -678,7 +740,7 @@ impl BtreeNode {
         if ptr < 0 {
             BtreeNode::set_key(node, 0, key);
             //is empty root
-            return;
+            return Ok(());
         }
 
         while ptr >= 0 && !key_inserted {
@@ -690,13 +752,14 @@ impl BtreeNode {
             } else {
                 BtreeNode::set_key(node, ptr as usize + 1, key);
                 BtreeNode::set_child(node, ptr as usize + 2, child.unwrap_or(0));
-                unsafe { fs_data.get_node(block).await.0 = true };
-                return;
+                unsafe { fs_data.get_node(block).await?.0 = true };
+                return Ok(());
             }
         }
         BtreeNode::set_key(node, 0, key);
         BtreeNode::set_child(node, 1, child.unwrap_or(0));
-        unsafe { fs_data.get_node(block).await.0 = true };
+        unsafe { fs_data.get_node(block).await?.0 = true };
+        Ok(())
     }
 
     ///Child must be on the right of the key
@@ -707,12 +770,12 @@ impl BtreeNode {
         key: Key,
         child: Option<u32>,
         fs_data: &Rfs,
-    ) -> RebalanceResult {
+    ) -> Result<RebalanceResult, ErrorCode> {
         let mut left = true;
-        let mut result = BtreeNode::rotate_left_give(block, parent_block, fs_data, child.is_none()).await;
+        let mut result = BtreeNode::rotate_left_give(block, parent_block, fs_data, child.is_none()).await?;
         if !result {
             left = false;
-            result = BtreeNode::rotate_right_give(block, parent_block, fs_data, child.is_none()).await;
+            result = BtreeNode::rotate_right_give(block, parent_block, fs_data, child.is_none()).await?;
         }
 
         if result {
@@ -733,16 +796,16 @@ impl BtreeNode {
                     BtreeNode::set_child(node, i + 2, BtreeNode::get_child(node, i + 1));
                 }
             }
-            unsafe { fs_data.get_node(block).await.0 = true };
-            return RebalanceResult::Rotate(if left { RotateDirection::Left } else { RotateDirection::Right });
+            unsafe { fs_data.get_node(block).await?.0 = true };
+            return Ok(RebalanceResult::Rotate(if left { RotateDirection::Left } else { RotateDirection::Right }));
         }
 
         //-------------------SPLIT NODE-------------------
-        let new_block = fs_data.allocate_block().await;
+        let new_block = fs_data.allocate_block().await?;
         let new_node = BtreeNode::new();
 
         unsafe { fs_data.add_node(new_block, new_node) };
-        unsafe { fs_data.get_node(block).await.0 = true };
+        unsafe { fs_data.get_node(block).await?.0 = true };
 
         //copy half of the elements to the new node, but take care to insert the key when
         //necessary. One node has 341 keys. 170/171 after split
@@ -794,25 +857,25 @@ impl BtreeNode {
         let separator = BtreeNode::get_key(node, 171);
         BtreeNode::set_key(node, 171, Key::empty());
 
-        unsafe { fs_data.get_node(block).await.0 = true };
-        unsafe { fs_data.get_node(new_block).await.0 = true };
+        unsafe { fs_data.get_node(block).await?.0 = true };
+        unsafe { fs_data.get_node(new_block).await?.0 = true };
 
-        RebalanceResult::Split(new_block, separator)
+        Ok(RebalanceResult::Split(new_block, separator))
     }
 
-    async fn rotate_left_take(node: VirtAddr, block: u32, parent_block: u32, fs_data: &Rfs, leaf: bool) -> bool {
-        let parent = unsafe { fs_data.get_node(parent_block).await.1 };
+    async fn rotate_left_take(node: VirtAddr, block: u32, parent_block: u32, fs_data: &Rfs, leaf: bool) -> Result<bool, ErrorCode> {
+        let parent = unsafe { fs_data.get_node(parent_block).await?.1 };
         let self_index = unsafe { &*(parent.0 as *const BtreeNode) }.children.iter().position(|&x| x == block).unwrap();
         if self_index == 0 {
-            return false;
+            return Ok(false);
         }
-        let left_sibling = unsafe { fs_data.get_node(BtreeNode::get_child(parent, self_index - 1)).await };
+        let left_sibling = unsafe { fs_data.get_node(BtreeNode::get_child(parent, self_index - 1)).await? };
         let left_key = unsafe { &*(parent.0 as *const BtreeNode) }.keys[self_index - 1];
 
         let sibling_has_elements = BtreeNode::get_key(left_sibling.1, 170).index != 0;
         let self_has_space = BtreeNode::get_key(node, 340).index == 0;
         if !sibling_has_elements || !self_has_space {
-            return false;
+            return Ok(false);
         }
 
         //shift self elements to the right
@@ -856,25 +919,25 @@ impl BtreeNode {
         }
 
         left_sibling.0 = true;
-        unsafe { fs_data.get_node(block).await.0 = true };
-        unsafe { fs_data.get_node(parent_block).await.0 = true };
+        unsafe { fs_data.get_node(block).await?.0 = true };
+        unsafe { fs_data.get_node(parent_block).await?.0 = true };
 
-        true
+        Ok(true)
     }
 
-    async fn rotate_right_take(node: VirtAddr, block: u32, parent_block: u32, fs_data: &Rfs, leaf: bool) -> bool {
-        let parent = unsafe { fs_data.get_node(parent_block).await.1 };
+    async fn rotate_right_take(node: VirtAddr, block: u32, parent_block: u32, fs_data: &Rfs, leaf: bool) -> Result<bool, ErrorCode> {
+        let parent = unsafe { fs_data.get_node(parent_block).await?.1 };
         let self_index = unsafe { &*(parent.0 as *const BtreeNode) }.children.iter().position(|&x| x == block).unwrap();
         if unsafe { *(*(parent.0 as *const BtreeNode) ).children.get_unchecked(self_index + 1) } == 0 {
-            return false;
+            return Ok(false);
         }
-        let right_sibling = unsafe { fs_data.get_node((*(parent.0 as *const BtreeNode)).children[self_index + 1]).await };
+        let right_sibling = unsafe { fs_data.get_node((*(parent.0 as *const BtreeNode)).children[self_index + 1]).await? };
         let right_key = BtreeNode::get_key(parent, self_index);
 
         let sibling_has_elements = BtreeNode::get_key(right_sibling.1, 170).index != 0;
         let self_has_space = BtreeNode::get_key(node, 340).index == 0;
         if !sibling_has_elements || !self_has_space {
-            return false;
+            return Ok(false);
         }
 
         let mut last_key_index = 0;
@@ -916,51 +979,35 @@ impl BtreeNode {
         }
 
         right_sibling.0 = true;
-        unsafe { fs_data.get_node(block).await.0 = true };
-        unsafe { fs_data.get_node(parent_block).await.0 = true };
+        unsafe { fs_data.get_node(block).await?.0 = true };
+        unsafe { fs_data.get_node(parent_block).await?.0 = true };
 
-        true
+        Ok(true)
     }
 
-    async fn rotate_left_give(block: u32, parent_block: u32, fs_data: &Rfs, leaf: bool) -> bool {
-        let parent = unsafe { fs_data.get_node(parent_block).await.1 };
+    async fn rotate_left_give(block: u32, parent_block: u32, fs_data: &Rfs, leaf: bool) -> Result<bool, ErrorCode> {
+        let parent = unsafe { fs_data.get_node(parent_block).await?.1 };
         let self_index = unsafe { &*(parent.0 as *const BtreeNode) }.children.iter().position(|&x| x == block).unwrap();
         if self_index == 0 {
-            return false;
+            return Ok(false);
         }
         let left_sibling_block = unsafe { &*(parent.0 as *const BtreeNode) }.children[self_index - 1];
-        let left_sibling = unsafe { fs_data.get_node(left_sibling_block).await };
+        let left_sibling = unsafe { fs_data.get_node(left_sibling_block).await? };
         BtreeNode::rotate_right_take(left_sibling.1, left_sibling_block, parent_block, fs_data, leaf).await
     }
 
-    async fn rotate_right_give(block: u32, parent_block: u32, fs_data: &Rfs, leaf: bool) -> bool {
-        let parent = unsafe { fs_data.get_node(parent_block).await.1 };
+    async fn rotate_right_give(block: u32, parent_block: u32, fs_data: &Rfs, leaf: bool) -> Result<bool, ErrorCode> {
+        let parent = unsafe { fs_data.get_node(parent_block).await?.1 };
         let self_index = unsafe { &*(parent.0 as *const BtreeNode) }.children.iter().position(|&x| x == block).unwrap();
         if self_index == 341 || unsafe { *(*(parent.0 as *const BtreeNode) ).children.get_unchecked(self_index + 1) } == 0 {
-            return false;
+            return Ok(false);
         }
         let right_sibling_block = unsafe { &*(parent.0 as *const BtreeNode) }.children[self_index + 1];
-        let right_sibling = unsafe { fs_data.get_node(right_sibling_block).await };
+        let right_sibling = unsafe { fs_data.get_node(right_sibling_block).await? };
         BtreeNode::rotate_right_take(right_sibling.1, right_sibling_block, parent_block, fs_data, leaf).await
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-#[repr(C)]
-pub struct Key {
-    pub index: u32,
-    pub inode_block: u32,
-}
-
-impl Key {
-    fn empty() -> Self {
-        Self {
-            index: 0,
-            inode_block: 0,
-        }
-    }
-}
-
 //rotations are done by children, not recorded here
 enum RebalanceResult {
     ///always merge right into left so left doesn't have to be rearranged. Children should merge
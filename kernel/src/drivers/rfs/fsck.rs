@@ -0,0 +1,115 @@
+//!Runs rfs_fsck when an rfs partition is mounted. Off by default, `rfs.fsck=check` only reports
+//!problems and `rfs.fsck=repair` also fixes them
+
+use core::{
+    convert::Infallible,
+    sync::atomic::{AtomicU8, Ordering},
+};
+use rfs_fsck::{Block, Disk};
+use rfs_layout::{BLOCK_SIZE, BLOCK_SIZE_SECTORS};
+use std::{
+    mem_utils::{PhysAddr, VirtAddr, get_at_virtual_addr},
+    println, printlnc,
+};
+
+use super::rfs::get_working_block;
+use crate::{drivers::disk::MountedPartition, memory::PAGE_TREE_ALLOCATOR};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum MountCheck {
+    Off,
+    Check,
+    Repair,
+}
+
+impl MountCheck {
+    ///Value of the `rfs.fsck` kernel argument
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "off" => Some(MountCheck::Off),
+            "check" => Some(MountCheck::Check),
+            "repair" => Some(MountCheck::Repair),
+            _ => None,
+        }
+    }
+}
+
+static MOUNT_CHECK: AtomicU8 = AtomicU8::new(MountCheck::Off as u8);
+
+pub fn set_mount_check(check: MountCheck) {
+    MOUNT_CHECK.store(check as u8, Ordering::Relaxed);
+}
+
+fn mount_check() -> MountCheck {
+    match MOUNT_CHECK.load(Ordering::Relaxed) {
+        1 => MountCheck::Check,
+        2 => MountCheck::Repair,
+        _ => MountCheck::Off,
+    }
+}
+
+///Reads and writes go straight to the partition, the check runs before anything else uses it
+struct PartitionDisk<'a> {
+    partition: &'a MountedPartition,
+    working_block: PhysAddr,
+    working_block_binding: VirtAddr,
+}
+
+impl Drop for PartitionDisk<'_> {
+    fn drop(&mut self) {
+        unsafe { PAGE_TREE_ALLOCATOR.deallocate(self.working_block_binding) };
+    }
+}
+
+impl Disk for PartitionDisk<'_> {
+    type Error = Infallible;
+
+    fn blocks(&self) -> u32 {
+        self.partition.partition.size_sectors as u32 / BLOCK_SIZE_SECTORS as u32
+    }
+
+    async fn read_block(&mut self, block: u32, buffer: &mut Block) -> Result<(), Infallible> {
+        self.partition
+            .read(block as usize * BLOCK_SIZE_SECTORS, BLOCK_SIZE_SECTORS, &[self.working_block])
+            .await;
+        buffer.copy_from_slice(unsafe { get_at_virtual_addr::<[u8; BLOCK_SIZE]>(self.working_block_binding) });
+        Ok(())
+    }
+
+    async fn write_block(&mut self, block: u32, buffer: &Block) -> Result<(), Infallible> {
+        unsafe { get_at_virtual_addr::<[u8; BLOCK_SIZE]>(self.working_block_binding) }.copy_from_slice(buffer);
+        self.partition
+            .write(block as usize * BLOCK_SIZE_SECTORS, BLOCK_SIZE_SECTORS, &[self.working_block])
+            .await;
+        Ok(())
+    }
+}
+
+///Checks the partition if the kernel command line asked for it. The journal has to be replayed
+///first, a transaction that was committed but not written home looks like corruption
+pub(super) async fn check_on_mount(partition: &MountedPartition) {
+    let repair = match mount_check() {
+        MountCheck::Off => return,
+        MountCheck::Check => false,
+        MountCheck::Repair => true,
+    };
+    let (working_block, working_block_binding) = get_working_block();
+    let mut disk = PartitionDisk {
+        partition,
+        working_block,
+        working_block_binding,
+    };
+    let Ok(report) = rfs_fsck::check(&mut disk, repair).await;
+    if report.is_clean() {
+        println!("rfs: partition {} is clean", partition.partition.name);
+        return;
+    }
+    for problem in report.problems.iter() {
+        if report.repaired && problem.repairable() {
+            println!("rfs: repaired: {}", problem);
+        } else {
+            printlnc!((255, 0, 0), "rfs: {}", problem);
+        }
+    }
+}
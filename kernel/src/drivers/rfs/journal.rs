@@ -0,0 +1,464 @@
+//!Write-ahead journal for metadata. Changed metadata blocks are kept in memory until the running
+//!transaction commits, then written to the journal region, marked complete with a commit record
+//!and only after that written to their real location. A complete transaction that didn't reach
+//!its location before a crash is written again on mount, an incomplete one is ignored.
+//!
+//!Journal layout: a descriptor block listing the target blocks, the new content of each target,
+//!and a commit record in the first sector after them, see [`Descriptor`]
+//!
+//!On filesystems with [`FEATURE_CHECKSUMS`] the journal also keeps the checksum table: every block
+//!committed gets its checksum updated in the same transaction, and metadata read from the disk is
+//!verified. A block that doesn't match is never used: the read fails with
+//![`ErrorCode::FileSystemInconsistency`], the filesystem refuses every further operation and
+//!nothing is committed anymore, so nothing built from it reaches the disk
+//!
+//!A transaction is never split. Operations are sized to fit into one, a transaction that still
+//!outgrows the journal is thrown away on commit and the operation fails
+use core::sync::atomic::{AtomicBool, Ordering};
+use std::{
+    collections::btree_map::BTreeMap,
+    error::ErrorCode,
+    lock_w_info,
+    mem_utils::{PhysAddr, get_at_virtual_addr, memset_virtual_addr, translate_phys_virt_addr},
+    printlnc,
+    sync::{
+        async_lock::{AsyncSpinlock, AsyncSpinlockGuard},
+        no_int_spinlock::NoIntSpinlock,
+    },
+};
+
+use super::rfs::get_working_block;
+use crate::{
+    drivers::disk::MountedPartition,
+    memory::{PAGE_TREE_ALLOCATOR, physical_allocator},
+};
+use rfs_layout::{
+    BLOCK_SIZE, BLOCK_SIZE_SECTORS, COMMIT_MAGIC, ChecksumBlock, CommitRecord, DESCRIPTOR_MAGIC, Descriptor, FEATURE_CHECKSUMS,
+    MAX_TARGETS, checksum_slot, checksum_table_blocks, crc32c,
+};
+
+#[derive(Debug)]
+pub(super) struct Journal {
+    ///held for the whole of an operation changing metadata, so a transaction never holds half of one
+    lock: AsyncSpinlock<()>,
+    ///first block of the journal, 0 for filesystems formatted without one. Changes are then
+    ///written directly on commit
+    start: u32,
+    ///number of blocks that fit into a transaction
+    capacity: usize,
+    sequence: NoIntSpinlock<u64>,
+    ///new content of the blocks changed in the running transaction
+    transaction: NoIntSpinlock<BTreeMap<u32, PhysAddr>>,
+    ///first block of the checksum table, 0 if the filesystem has no checksums
+    checksum_table: u32,
+    ///blocks of the checksum table
+    table_blocks: u32,
+    ///a block didn't match its checksum. Stays set until the filesystem is mounted again
+    failed: AtomicBool,
+    ///the running transaction needs more than `capacity` blocks, it is thrown away on commit
+    overflow: AtomicBool,
+}
+
+impl Journal {
+    ///Replays a complete transaction left by a crash. `checksum_table` is 0 if the filesystem has
+    ///no checksums
+    pub(super) async fn open(partition: &MountedPartition, start: u32, blocks: u32, checksum_table: u32) -> Self {
+        let mut sequence = 0;
+        if start != 0 {
+            let (working_block, working_block_binding) = get_working_block();
+            partition
+                .read(start as usize * BLOCK_SIZE_SECTORS, BLOCK_SIZE_SECTORS, &[working_block])
+                .await;
+            let descriptor = unsafe { get_at_virtual_addr::<Descriptor>(working_block_binding) };
+            if descriptor.magic == DESCRIPTOR_MAGIC {
+                sequence = descriptor.sequence;
+                if descriptor.block_count != 0 && Self::is_committed(partition, start, descriptor).await {
+                    Self::replay(partition, start, descriptor).await;
+                    descriptor.block_count = 0;
+                    partition
+                        .write(start as usize * BLOCK_SIZE_SECTORS, BLOCK_SIZE_SECTORS, &[working_block])
+                        .await;
+                }
+            }
+            unsafe { PAGE_TREE_ALLOCATOR.deallocate(working_block_binding) };
+        }
+        let capacity = if start == 0 {
+            MAX_TARGETS
+        } else {
+            usize::min(blocks.saturating_sub(2) as usize, MAX_TARGETS)
+        };
+        Self {
+            lock: AsyncSpinlock::new(()),
+            start,
+            //every block can need a table block of its own on commit
+            capacity: if checksum_table != 0 { capacity / 2 } else { capacity },
+            sequence: NoIntSpinlock::new(sequence),
+            transaction: NoIntSpinlock::new(BTreeMap::new()),
+            checksum_table,
+            table_blocks: checksum_table_blocks(partition.partition.size_sectors as u32 / BLOCK_SIZE_SECTORS as u32),
+            failed: AtomicBool::new(false),
+            overflow: AtomicBool::new(false),
+        }
+    }
+
+    ///First block of the checksum table if the superblock enables checksums, otherwise 0
+    pub(super) fn checksum_table(features: u32, checksum_table: u32) -> u32 {
+        if features & FEATURE_CHECKSUMS != 0 {
+            checksum_table
+        } else {
+            0
+        }
+    }
+
+    ///A block didn't match its checksum since mount
+    pub(super) fn failed(&self) -> bool {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    ///The running transaction doesn't fit into the journal
+    pub(super) fn overflowed(&self) -> bool {
+        self.overflow.load(Ordering::Relaxed)
+    }
+
+    fn is_table_block(&self, block: u32) -> bool {
+        (self.checksum_table..self.checksum_table + self.table_blocks).contains(&block)
+    }
+
+    ///Checks a block just read from the disk against the checksum table. A block that doesn't match
+    ///fails with FileSystemInconsistency, and the journal fails with it. Blocks of the running
+    ///transaction are newer than the table and are not checked
+    pub(super) async fn verify(&self, partition: &MountedPartition, block: u32, image: PhysAddr) -> Result<(), ErrorCode> {
+        if self.checksum_table == 0 || lock_w_info!(self.transaction).contains_key(&block) {
+            return Ok(());
+        }
+        let (table_block, slot) = checksum_slot(block);
+        let (table_frame, table_frame_binding) = get_working_block();
+        self.read_table_block(partition, self.checksum_table + table_block, table_frame)
+            .await;
+        let table = unsafe { get_at_virtual_addr::<ChecksumBlock>(table_frame_binding) };
+        let valid = table.checksum == table.own_checksum() && table.checksums[slot] == block_checksum(image);
+        unsafe { PAGE_TREE_ALLOCATOR.deallocate(table_frame_binding) };
+        if !valid {
+            printlnc!((255, 0, 0), "rfs: block {} doesn't match its checksum", block);
+            self.failed.store(true, Ordering::Relaxed);
+            return Err(ErrorCode::FileSystemInconsistency);
+        }
+        Ok(())
+    }
+
+    ///Reads a block of the checksum table, the running transaction holds the newest version
+    async fn read_table_block(&self, partition: &MountedPartition, block: u32, buffer: PhysAddr) {
+        {
+            let transaction = lock_w_info!(self.transaction);
+            if let Some(image) = transaction.get(&block) {
+                for sector in 0..BLOCK_SIZE_SECTORS {
+                    copy_sector(*image, sector, &[buffer], sector);
+                }
+                return;
+            }
+        }
+        partition
+            .read(block as usize * BLOCK_SIZE_SECTORS, BLOCK_SIZE_SECTORS, &[buffer])
+            .await;
+    }
+
+    async fn is_committed(partition: &MountedPartition, start: u32, descriptor: &Descriptor) -> bool {
+        if descriptor.block_count as usize > MAX_TARGETS {
+            return false;
+        }
+        let (commit_block, commit_block_binding) = get_working_block();
+        let commit_sector = (start + descriptor.block_count + 1) as usize * BLOCK_SIZE_SECTORS;
+        partition.read(commit_sector, 1, &[commit_block]).await;
+        let commit = unsafe { get_at_virtual_addr::<CommitRecord>(commit_block_binding) };
+        let committed = commit.magic == COMMIT_MAGIC && commit.sequence == descriptor.sequence;
+        unsafe { PAGE_TREE_ALLOCATOR.deallocate(commit_block_binding) };
+        committed
+    }
+
+    async fn replay(partition: &MountedPartition, start: u32, descriptor: &Descriptor) {
+        let (block, block_binding) = get_working_block();
+        for (i, target) in descriptor.targets[..descriptor.block_count as usize].iter().enumerate() {
+            let journal_sector = (start as usize + 1 + i) * BLOCK_SIZE_SECTORS;
+            partition.read(journal_sector, BLOCK_SIZE_SECTORS, &[block]).await;
+            partition
+                .write(*target as usize * BLOCK_SIZE_SECTORS, BLOCK_SIZE_SECTORS, &[block])
+                .await;
+        }
+        unsafe { PAGE_TREE_ALLOCATOR.deallocate(block_binding) };
+    }
+
+    ///Must be held while changing metadata, and until commit returns
+    pub(super) async fn begin(&self) -> AsyncSpinlockGuard<'_, ()> {
+        self.lock.lock().await
+    }
+
+    ///Reads sectors, including the changes of the running transaction. Fails with
+    ///FileSystemInconsistency if a block read doesn't match its checksum
+    pub(super) async fn read(
+        &self,
+        partition: &MountedPartition,
+        sector: usize,
+        sec_count: usize,
+        buffer: &[PhysAddr],
+    ) -> Result<(), ErrorCode> {
+        if self.checksum_table != 0 {
+            return self.read_verified(partition, sector, sec_count, buffer).await;
+        }
+        partition.read(sector, sec_count, buffer).await;
+        let transaction = lock_w_info!(self.transaction);
+        if transaction.is_empty() {
+            return Ok(());
+        }
+        for i in 0..sec_count {
+            let block = ((sector + i) / BLOCK_SIZE_SECTORS) as u32;
+            if let Some(image) = transaction.get(&block) {
+                copy_sector(*image, (sector + i) % BLOCK_SIZE_SECTORS, buffer, i);
+            }
+        }
+        Ok(())
+    }
+
+    ///Like read, but the checksums cover whole blocks, so each block is read whole and verified
+    ///before the sectors are copied out of it
+    async fn read_verified(
+        &self,
+        partition: &MountedPartition,
+        sector: usize,
+        sec_count: usize,
+        buffer: &[PhysAddr],
+    ) -> Result<(), ErrorCode> {
+        let (block_frame, block_frame_binding) = get_working_block();
+        let first_block = sector / BLOCK_SIZE_SECTORS;
+        let last_block = (sector + sec_count - 1) / BLOCK_SIZE_SECTORS;
+        for block in first_block..=last_block {
+            let mut in_transaction = false;
+            {
+                let transaction = lock_w_info!(self.transaction);
+                if let Some(image) = transaction.get(&(block as u32)) {
+                    copy_block_sectors(*image, block, sector, sec_count, buffer);
+                    in_transaction = true;
+                }
+            }
+            if in_transaction {
+                continue;
+            }
+            partition
+                .read(block * BLOCK_SIZE_SECTORS, BLOCK_SIZE_SECTORS, &[block_frame])
+                .await;
+            if let Err(e) = self.verify(partition, block as u32, block_frame).await {
+                unsafe { PAGE_TREE_ALLOCATOR.deallocate(block_frame_binding) };
+                return Err(e);
+            }
+            copy_block_sectors(block_frame, block, sector, sec_count, buffer);
+        }
+        unsafe { PAGE_TREE_ALLOCATOR.deallocate(block_frame_binding) };
+        Ok(())
+    }
+
+    ///Adds the sectors to the running transaction. Nothing is written until commit
+    ///Journal must be held
+    pub(super) async fn write(&self, partition: &MountedPartition, sector: usize, sec_count: usize, buffer: &[PhysAddr]) {
+        let first_block = sector / BLOCK_SIZE_SECTORS;
+        let last_block = (sector + sec_count - 1) / BLOCK_SIZE_SECTORS;
+        for block in first_block..=last_block {
+            let block = block as u32;
+            let image = lock_w_info!(self.transaction).get(&block).copied();
+            let image = match image {
+                Some(image) => image,
+                None => {
+                    if lock_w_info!(self.transaction).len() >= self.capacity {
+                        //kept so the rest of the operation reads what it wrote, commit refuses it
+                        self.overflow.store(true, Ordering::Relaxed);
+                    }
+                    self.load_image(partition, block).await
+                }
+            };
+            for i in 0..sec_count {
+                if (sector + i) / BLOCK_SIZE_SECTORS == block as usize {
+                    copy_sector_from(buffer, i, image, (sector + i) % BLOCK_SIZE_SECTORS);
+                }
+            }
+        }
+    }
+
+    ///Adds a block to the running transaction, with its current content as the image
+    async fn load_image(&self, partition: &MountedPartition, block: u32) -> PhysAddr {
+        let image = physical_allocator::allocate_frame();
+        partition
+            .read(block as usize * BLOCK_SIZE_SECTORS, BLOCK_SIZE_SECTORS, &[image])
+            .await;
+        lock_w_info!(self.transaction).insert(block, image);
+        image
+    }
+
+    ///Updates the checksums of the blocks in the running transaction, the changed table blocks
+    ///join the transaction. Fails the journal if a table block was already broken, its other
+    ///checksums would be sealed with it
+    async fn update_checksums(&self, partition: &MountedPartition) {
+        if self.checksum_table == 0 {
+            return;
+        }
+        let targets = lock_w_info!(self.transaction).clone();
+        let mut table_blocks = BTreeMap::new();
+        for (target, image) in targets.iter() {
+            if self.is_table_block(*target) {
+                continue;
+            }
+            let (table_block, slot) = checksum_slot(*target);
+            let table_block = self.checksum_table + table_block;
+            let table_image = match lock_w_info!(self.transaction).get(&table_block).copied() {
+                Some(table_image) => table_image,
+                None => {
+                    let table_image = self.load_image(partition, table_block).await;
+                    let table = unsafe { get_at_virtual_addr::<ChecksumBlock>(translate_phys_virt_addr(table_image)) };
+                    if table.checksum != table.own_checksum() {
+                        printlnc!((255, 0, 0), "rfs: checksum table block {} is broken", table_block);
+                        self.failed.store(true, Ordering::Relaxed);
+                        return;
+                    }
+                    table_image
+                }
+            };
+            let table = unsafe { get_at_virtual_addr::<ChecksumBlock>(translate_phys_virt_addr(table_image)) };
+            table.checksums[slot] = block_checksum(*image);
+            table_blocks.insert(table_block, table_image);
+        }
+        for table_image in table_blocks.into_values() {
+            let table = unsafe { get_at_virtual_addr::<ChecksumBlock>(translate_phys_virt_addr(table_image)) };
+            table.checksum = table.own_checksum();
+        }
+    }
+
+    ///Throws away the running transaction
+    fn discard(&self) {
+        let transaction = core::mem::take(&mut *lock_w_info!(self.transaction));
+        for image in transaction.into_values() {
+            unsafe { physical_allocator::deallocate_frame(image) };
+        }
+    }
+
+    ///Throws away the running transaction of an operation that failed
+    ///Journal must be held
+    pub(super) fn abort(&self) {
+        self.overflow.store(false, Ordering::Relaxed);
+        self.discard();
+    }
+
+    ///Drops changes to a freed block, so they don't overwrite whatever it is used for next
+    pub(super) fn forget(&self, block: u32) {
+        if let Some(image) = lock_w_info!(self.transaction).remove(&block) {
+            unsafe { physical_allocator::deallocate_frame(image) };
+        }
+    }
+
+    ///Writes the running transaction to the journal, then to the real locations. After a checksum
+    ///failure the transaction is thrown away instead, one too big for the journal is thrown away
+    ///and fails with NoSpace
+    ///Journal must be held
+    pub(super) async fn commit(&self, partition: &MountedPartition) -> Result<(), ErrorCode> {
+        if self.overflow.swap(false, Ordering::Relaxed) {
+            printlnc!(
+                (255, 0, 0),
+                "rfs: transaction doesn't fit into the journal, nothing of it is written"
+            );
+            self.discard();
+            return Err(ErrorCode::NoSpace);
+        }
+        if lock_w_info!(self.transaction).is_empty() {
+            return Ok(());
+        }
+        self.update_checksums(partition).await;
+        if self.failed() {
+            self.discard();
+            return Ok(());
+        }
+        //stays readable until every block reached its location
+        let transaction = lock_w_info!(self.transaction).clone();
+        if self.start != 0 {
+            let sequence = {
+                let mut sequence = lock_w_info!(self.sequence);
+                *sequence += 1;
+                *sequence
+            };
+            let (descriptor_block, descriptor_block_binding) = get_working_block();
+            unsafe { memset_virtual_addr(descriptor_block_binding, 0, 4096) };
+            let descriptor = unsafe { get_at_virtual_addr::<Descriptor>(descriptor_block_binding) };
+            descriptor.magic = DESCRIPTOR_MAGIC;
+            descriptor.block_count = transaction.len() as u32;
+            descriptor.sequence = sequence;
+            for (i, (target, image)) in transaction.iter().enumerate() {
+                descriptor.targets[i] = *target;
+                let journal_sector = (self.start as usize + 1 + i) * BLOCK_SIZE_SECTORS;
+                partition.write(journal_sector, BLOCK_SIZE_SECTORS, &[*image]).await;
+            }
+            let descriptor_sector = self.start as usize * BLOCK_SIZE_SECTORS;
+            partition
+                .write(descriptor_sector, BLOCK_SIZE_SECTORS, &[descriptor_block])
+                .await;
+
+            //the transaction is complete once this sector is written
+            let (commit_block, commit_block_binding) = get_working_block();
+            unsafe {
+                memset_virtual_addr(commit_block_binding, 0, 512);
+                let commit = get_at_virtual_addr::<CommitRecord>(commit_block_binding);
+                commit.magic = COMMIT_MAGIC;
+                commit.sequence = sequence;
+            }
+            let commit_sector = (self.start as usize + 1 + transaction.len()) * BLOCK_SIZE_SECTORS;
+            partition.write(commit_sector, 1, &[commit_block]).await;
+            unsafe { PAGE_TREE_ALLOCATOR.deallocate(commit_block_binding) };
+
+            for (target, image) in transaction.iter() {
+                partition
+                    .write(*target as usize * BLOCK_SIZE_SECTORS, BLOCK_SIZE_SECTORS, &[*image])
+                    .await;
+            }
+            descriptor.block_count = 0;
+            partition
+                .write(descriptor_sector, BLOCK_SIZE_SECTORS, &[descriptor_block])
+                .await;
+            unsafe { PAGE_TREE_ALLOCATOR.deallocate(descriptor_block_binding) };
+        } else {
+            for (target, image) in transaction.iter() {
+                partition
+                    .write(*target as usize * BLOCK_SIZE_SECTORS, BLOCK_SIZE_SECTORS, &[*image])
+                    .await;
+            }
+        }
+        self.discard();
+        Ok(())
+    }
+}
+
+///Checksum of a whole block, as kept in the checksum table
+pub(super) fn block_checksum(image: PhysAddr) -> u32 {
+    crc32c(unsafe { get_at_virtual_addr::<[u8; BLOCK_SIZE]>(translate_phys_virt_addr(image)) })
+}
+
+///Copies the sectors of `block` that are part of the read of `sec_count` sectors at `sector`
+fn copy_block_sectors(image: PhysAddr, block: usize, sector: usize, sec_count: usize, buffer: &[PhysAddr]) {
+    for i in 0..sec_count {
+        if (sector + i) / BLOCK_SIZE_SECTORS == block {
+            copy_sector(image, (sector + i) % BLOCK_SIZE_SECTORS, buffer, i);
+        }
+    }
+}
+
+///Copies sector `image_sector` of a block image to sector `buffer_sector` of a buffer
+fn copy_sector(image: PhysAddr, image_sector: usize, buffer: &[PhysAddr], buffer_sector: usize) {
+    let src = translate_phys_virt_addr(image).0 + image_sector as u64 * 512;
+    let dst = sector_addr(buffer, buffer_sector);
+    unsafe { core::ptr::copy_nonoverlapping(src as *const u8, dst as *mut u8, 512) };
+}
+
+///Copies sector `buffer_sector` of a buffer to sector `image_sector` of a block image
+fn copy_sector_from(buffer: &[PhysAddr], buffer_sector: usize, image: PhysAddr, image_sector: usize) {
+    let src = sector_addr(buffer, buffer_sector);
+    let dst = translate_phys_virt_addr(image).0 + image_sector as u64 * 512;
+    unsafe { core::ptr::copy_nonoverlapping(src as *const u8, dst as *mut u8, 512) };
+}
+
+fn sector_addr(buffer: &[PhysAddr], sector: usize) -> u64 {
+    let frame = buffer[sector / BLOCK_SIZE_SECTORS];
+    translate_phys_virt_addr(frame).0 + (sector % BLOCK_SIZE_SECTORS) as u64 * 512
+}
@@ -1,37 +1,84 @@
 use uuid::Uuid;
 
 use super::{
-    DirEntry, Inode, InodeBitmask, InodeSize, SuperBlock,
-    btree::{BtreeNode, Key},
+    btree::BtreeNode,
+    fsck, inode_from_vfs, inode_to_vfs,
+    journal::{Journal, block_checksum},
+    times::{self, AtimeMode, Touch},
 };
 use crate::{
-    drivers::{disk::MountedPartition, rfs::BLOCK_SIZE_SECTORS},
-    memory::{PAGE_TREE_ALLOCATOR, paging::LiminePat, physical_allocator},
-    vfs::{self, FileSystem, FileSystemFactory, InodeIndex, InodeType, ROOT_INODE_INDEX},
+    drivers::disk::MountedPartition,
+    memory::{
+        PAGE_TREE_ALLOCATOR,
+        paging::{LiminePat, PageTree},
+        physical_allocator,
+    },
+    vfs::{self, FileSystem, FileSystemFactory, InodeIdentifier, InodeIndex, InodeType, ROOT_INODE_INDEX},
+};
+use core::{
+    str,
+    sync::atomic::{AtomicU32, Ordering},
+};
+use rfs_layout::{
+    BLOCK_SIZE_SECTORS, ChecksumBlock, DirEntry, Extent, ExtentNode, FEATURE_CHECKSUMS, GROUP_BLOCK_SIZE, INDEXED_DIR_ENTRIES,
+    INLINE_DATA_SIZE, Inode, InodeBitmask, InodeExtents, InodeMode, InodeSize, JOURNAL_BLOCKS, JOURNAL_START, Key, MAX_NAME_LEN,
+    NODE_EXTENTS, PARTITION_TYPE_GUID, SUPERBLOCK_BLOCK, SuperBlock, checksum_slot, checksum_table_blocks, extent_depth_for,
+    name_hash, next_hash, ptr_levels_for,
 };
-use core::str;
 use std::{
     boxed::Box,
-    collections::btree_map::BTreeMap,
+    collections::{btree_map::BTreeMap, btree_set::BTreeSet},
+    error::ErrorCode,
     lock_w_info,
     mem_utils::{PhysAddr, VirtAddr, get_at_virtual_addr, memset_virtual_addr, set_at_virtual_addr},
+    printlnc,
     sync::arc::Arc,
     sync::{async_lock::AsyncSpinlock, async_rw_lock::AsyncRWlock, no_int_spinlock::NoIntSpinlock},
     vec::Vec,
 };
 
-const GROUP_BLOCK_SIZE: u64 = 4096 * 8;
+///Most blocks moved by one disk command, the AHCI driver takes up to 248 frames
+const MAX_RUN_BLOCKS: u32 = 248;
+///Most file blocks a write or truncate adds or frees in one transaction. Their metadata (bitmaps,
+///pointer blocks, the inode) stays well within the journal, bigger operations are done in parts
+const MAX_TRANSACTION_BLOCKS: u64 = 4096;
+const ENTRY_SIZE: u64 = core::mem::size_of::<DirEntry>() as u64;
+
+///New inodes are mapped by pointers instead of extents, like on filesystems written before extents
+///existed. Only the tests set it, to keep the pointer paths covered
+#[cfg(feature = "run_tests")]
+static POINTER_INODES: core::sync::atomic::AtomicBool = core::sync::atomic::AtomicBool::new(false);
+
+#[cfg(feature = "run_tests")]
+pub fn set_pointer_inodes(enabled: bool) {
+    POINTER_INODES.store(enabled, Ordering::Relaxed);
+}
+
+///Size of a new, empty inode
+fn new_inode_size() -> InodeSize {
+    #[cfg(feature = "run_tests")]
+    if POINTER_INODES.load(Ordering::Relaxed) {
+        return InodeSize::new(0);
+    }
+    InodeSize::new_extents()
+}
 
 pub struct RfsFactory;
 
 impl RfsFactory {
-    pub const UUID: Uuid = Uuid::from_u128(0xb1b3b44dbece44dfba0e964a35a05a16);
+    pub const UUID: Uuid = Uuid::from_u128(PARTITION_TYPE_GUID);
 }
 
 #[async_trait::async_trait]
 impl FileSystemFactory for RfsFactory {
+    fn name(&self) -> &'static str {
+        "rfs"
+    }
+
     async fn mount(&self, partition: MountedPartition) -> Arc<dyn FileSystem + Send> {
-        Arc::new(Rfs::new(partition).await)
+        let rfs = Rfs::new(partition).await;
+        fsck::check_on_mount(&rfs.partition).await;
+        Arc::new(rfs)
     }
 }
 
@@ -57,8 +104,13 @@ pub struct Rfs {
 
     //bye bye performance
     file_locks: NoIntSpinlock<BTreeMap<u32, Arc<AsyncRWlock<()>>>>,
+    ///Inodes without links that were still open when unlinked. Freed once closed
+    orphans: NoIntSpinlock<BTreeSet<u32>>,
 
     block_alloc_lock: AsyncSpinlock<()>,
+    ///groups before this one have no free block, allocate_block starts searching here
+    full_groups: AtomicU32,
+    journal: Journal,
     //written once
     groups: u32,
     //written once
@@ -71,7 +123,7 @@ pub struct Rfs {
 unsafe impl Send for Rfs {}
 unsafe impl Sync for Rfs {}
 
-fn get_working_block() -> (PhysAddr, VirtAddr) {
+pub(super) fn get_working_block() -> (PhysAddr, VirtAddr) {
     let working_block = physical_allocator::allocate_frame();
     let working_block_binding = unsafe { PAGE_TREE_ALLOCATOR.allocate(Some(working_block), false) };
     unsafe {
@@ -91,7 +143,16 @@ impl Rfs {
 
         partition.read(BLOCK_SIZE_SECTORS, 1, &[working_block]).await;
         let header = unsafe { get_at_virtual_addr::<SuperBlock>(working_block_binding) };
-        let root_block = header.inode_tree;
+        let checksum_table = Journal::checksum_table(header.features, header.checksum_table);
+        let journal = Journal::open(&partition, header.journal_start, header.journal_blocks, checksum_table).await;
+        //replaying the journal may have changed the superblock
+        partition.read(BLOCK_SIZE_SECTORS, BLOCK_SIZE_SECTORS, &[working_block]).await;
+        let header = unsafe { get_at_virtual_addr::<SuperBlock>(working_block_binding) };
+        let root_block = match journal.verify(&partition, SUPERBLOCK_BLOCK, working_block).await {
+            Ok(()) => header.inode_tree,
+            //the journal failed, every operation refuses to run, so the root is never read
+            Err(_) => 0,
+        };
         unsafe { PAGE_TREE_ALLOCATOR.deallocate(working_block_binding) };
 
         // driver.format_partition();
@@ -104,7 +165,10 @@ impl Rfs {
             groups,
             blocks,
             file_locks: NoIntSpinlock::new(BTreeMap::new()),
+            orphans: NoIntSpinlock::new(BTreeSet::new()),
             block_alloc_lock: AsyncSpinlock::new(()),
+            full_groups: AtomicU32::new(0),
+            journal,
         }
     }
 
@@ -113,6 +177,129 @@ impl Rfs {
         self as *const Self as *mut Self
     }
 
+    ///Reads metadata, including changes that are not committed yet. Fails with
+    ///FileSystemInconsistency if it doesn't match its checksum
+    async fn read_sectors(&self, sector: usize, sec_count: usize, buffer: &[PhysAddr]) -> Result<(), ErrorCode> {
+        self.journal.read(&self.partition, sector, sec_count, buffer).await
+    }
+
+    ///read_sectors into a working block, which is freed if the read fails
+    async fn read_or_release(
+        &self,
+        sector: usize,
+        sec_count: usize,
+        working_block: PhysAddr,
+        working_block_binding: VirtAddr,
+    ) -> Result<(), ErrorCode> {
+        let read = self.read_sectors(sector, sec_count, &[working_block]).await;
+        if read.is_err() {
+            unsafe { PAGE_TREE_ALLOCATOR.deallocate(working_block_binding) };
+        }
+        read
+    }
+
+    ///Writes metadata as part of the running transaction
+    async fn write_sectors(&self, sector: usize, sec_count: usize, buffer: &[PhysAddr]) {
+        self.journal.write(&self.partition, sector, sec_count, buffer).await;
+    }
+
+    ///File content is written directly, before the metadata pointing to it is committed. After a
+    ///checksum failure the blocks may belong to something else, so nothing is written
+    async fn write_data(&self, sector: usize, sec_count: usize, buffer: &[PhysAddr]) {
+        if self.journal.failed() {
+            return;
+        }
+        self.partition.write(sector, sec_count, buffer).await;
+    }
+
+    ///Fails once metadata didn't match its checksum, whatever was read since may be garbage
+    fn check_consistent(&self) -> Result<(), ErrorCode> {
+        if self.journal.failed() {
+            return Err(ErrorCode::FileSystemInconsistency);
+        }
+        Ok(())
+    }
+
+    ///Runs an operation changing metadata as a single transaction. Nothing of it is committed if
+    ///it fails, if metadata it read didn't match its checksum, or if it doesn't fit into the journal
+    async fn transaction<T>(&self, operation: impl Future<Output = Result<T, ErrorCode>>) -> Result<T, ErrorCode> {
+        let journal_guard = self.journal.begin().await;
+        //state kept outside of the disk, restored if the transaction is thrown away
+        let root_block = self.root_block;
+        let orphans = lock_w_info!(self.orphans).clone();
+        let full_groups = self.full_groups.load(Ordering::Relaxed);
+        let res = match self.check_consistent() {
+            Ok(()) => operation.await,
+            Err(e) => Err(e),
+        };
+        let committed = if res.is_ok() {
+            self.commit().await
+        } else {
+            self.abort().await;
+            Ok(())
+        };
+        if res.is_err() || committed.is_err() {
+            let self_mut = unsafe { &mut *self.to_mut_ptr() };
+            self_mut.root_block = root_block;
+            *lock_w_info!(self.orphans) = orphans;
+            self.full_groups.store(full_groups, Ordering::Relaxed);
+        }
+        drop(journal_guard);
+        self.check_consistent()?;
+        committed?;
+        res
+    }
+
+    ///Adds changed inode tree nodes to the running transaction and commits it
+    ///journal must be held
+    async fn commit(&self) -> Result<(), ErrorCode> {
+        let inode_lock = self.inode_lock.lock().await;
+        let self_mut = unsafe { &mut *self.to_mut_ptr() };
+        for (block, (modified, node)) in self_mut.inode_tree_cache.iter_mut() {
+            if *modified {
+                let root_page_table = PageTree::get_level4_addr();
+                let node_frame = std::mem_utils::translate_virt_phys_addr(*node, root_page_table).unwrap();
+                self.write_sectors(*block as usize * BLOCK_SIZE_SECTORS, BLOCK_SIZE_SECTORS, &[node_frame])
+                    .await;
+                *modified = false;
+            }
+        }
+        //checked after the nodes joined the transaction, they may be what doesn't fit
+        if self.journal.failed() || self.journal.overflowed() {
+            //the transaction is thrown away, cached nodes may hold its changes
+            for (_, node) in core::mem::take(&mut self_mut.inode_tree_cache).into_values() {
+                BtreeNode::drop(node);
+            }
+        }
+        drop(inode_lock);
+        self.journal.commit(&self.partition).await
+    }
+
+    ///Throws away the running transaction. Cached inode tree nodes may hold its changes, so they
+    ///are dropped too
+    ///journal must be held
+    async fn abort(&self) {
+        let inode_lock = self.inode_lock.lock().await;
+        let self_mut = unsafe { &mut *self.to_mut_ptr() };
+        for (_, node) in core::mem::take(&mut self_mut.inode_tree_cache).into_values() {
+            BtreeNode::drop(node);
+        }
+        drop(inode_lock);
+        self.journal.abort();
+    }
+
+    ///Looks up the block holding an inode in the inode tree
+    async fn find_inode_block(&self, inode_index: InodeIndex) -> Result<u32, ErrorCode> {
+        self.check_consistent()?;
+        let inode_lock = self.inode_lock.lock().await;
+        let inode_block_index = match unsafe { self.get_node(self.root_block).await } {
+            Ok(root) => BtreeNode::find_inode_block(root.1, inode_index as u32, self).await,
+            Err(err) => Err(err),
+        };
+        drop(inode_lock);
+        inode_block_index?.ok_or(ErrorCode::InodeNotPresent)
+    }
+
     fn get_file_lock(&self, inode_index: u32) -> Arc<AsyncRWlock<()>> {
         let mut inode_locks = lock_w_info!(self.file_locks);
         let file_lock = inode_locks
@@ -123,17 +310,17 @@ impl Rfs {
         file_lock
     }
 
-    pub async fn allocate_block(&self) -> u32 {
-        let lock = self.block_alloc_lock.lock();
+    pub async fn allocate_block(&self) -> Result<u32, ErrorCode> {
+        let lock = self.block_alloc_lock.lock().await;
         let (group_memory, group_mem_binding) = get_working_block();
-        for i in 0..self.groups {
-            self.partition
-                .read(
-                    i as usize * GROUP_BLOCK_SIZE as usize * BLOCK_SIZE_SECTORS,
-                    1,
-                    &[group_memory],
-                )
-                .await;
+        for i in self.full_groups.load(Ordering::Relaxed)..self.groups {
+            self.read_or_release(
+                i as usize * GROUP_BLOCK_SIZE as usize * BLOCK_SIZE_SECTORS,
+                BLOCK_SIZE_SECTORS,
+                group_memory,
+                group_mem_binding,
+            )
+            .await?;
             for j in (0..4096).step_by(8) {
                 let qword: u64 = unsafe { *get_at_virtual_addr(group_mem_binding + j) };
                 if qword != 0xFFFFFFFFFFFFFFFF {
@@ -143,117 +330,222 @@ impl Rfs {
                             unsafe {
                                 set_at_virtual_addr(group_mem_binding + j, allocated);
                             }
-                            self.partition
-                                .write(i as usize * GROUP_BLOCK_SIZE as usize, 1, &[group_memory])
-                                .await;
+                            self.write_sectors(
+                                i as usize * GROUP_BLOCK_SIZE as usize * BLOCK_SIZE_SECTORS,
+                                BLOCK_SIZE_SECTORS,
+                                &[group_memory],
+                            )
+                            .await;
                             drop(lock);
 
-                            unsafe { physical_allocator::deallocate_frame(group_memory) };
-                            return i * GROUP_BLOCK_SIZE as u32 + j as u32 * 64 + k;
+                            unsafe { PAGE_TREE_ALLOCATOR.deallocate(group_mem_binding) };
+                            return Ok(i * GROUP_BLOCK_SIZE as u32 + j as u32 * 8 + k);
                         }
                     }
                 }
             }
+            //the groups before were full too
+            self.full_groups.store(i + 1, Ordering::Relaxed);
         }
         drop(lock);
-        panic!("No free blocks")
+        unsafe { PAGE_TREE_ALLOCATOR.deallocate(group_mem_binding) };
+        Err(ErrorCode::NoSpace)
     }
 
-    pub async fn free_block(&self, block: u32) {
-        let lock = self.block_alloc_lock.lock();
+    pub async fn free_block(&self, block: u32) -> Result<(), ErrorCode> {
+        let lock = self.block_alloc_lock.lock().await;
         let (group_memory, group_mem_binding) = get_working_block();
         let group = block / GROUP_BLOCK_SIZE as u32;
         let block_in_group = block % GROUP_BLOCK_SIZE as u32;
         let qword = block_in_group / 64;
         let bit = block_in_group % 64;
 
-        self.partition
-            .read(group as usize * GROUP_BLOCK_SIZE as usize, 1, &[group_memory])
-            .await;
+        let group_sector = group as usize * GROUP_BLOCK_SIZE as usize * BLOCK_SIZE_SECTORS;
+        self.read_or_release(group_sector, BLOCK_SIZE_SECTORS, group_memory, group_mem_binding)
+            .await?;
         let mut qword_data: u64 = unsafe { *get_at_virtual_addr(group_mem_binding + qword as u64 * 8) };
         assert!(qword_data & (1 << bit) != 0, "Block already free");
         qword_data &= !(1 << bit);
         unsafe {
             set_at_virtual_addr(group_mem_binding + (qword as u64 * 8), qword_data);
         }
-        drop(lock)
+        self.write_sectors(group_sector, BLOCK_SIZE_SECTORS, &[group_memory]).await;
+        self.full_groups.fetch_min(group, Ordering::Relaxed);
+        drop(lock);
+        self.journal.forget(block);
+        unsafe { PAGE_TREE_ALLOCATOR.deallocate(group_mem_binding) };
+        Ok(())
+    }
+
+    ///Allocates up to `max` contiguous blocks, from the first free block at or after `goal`, so a
+    ///file growing after its last block stays contiguous. Returns the first block and the count
+    pub async fn allocate_run(&self, goal: u32, max: u32) -> Result<(u32, u32), ErrorCode> {
+        let lock = self.block_alloc_lock.lock().await;
+        let (group_memory, group_mem_binding) = get_working_block();
+        let goal = if goal < self.blocks { goal } else { 0 };
+        let goal_group = goal / GROUP_BLOCK_SIZE as u32;
+        let is_free = |bitmap: &[u8; 4096], bit: u32| bitmap[bit as usize / 8] & (1 << (bit % 8)) == 0;
+        //the group of the goal is searched again from its start once all others are full
+        for i in 0..=self.groups {
+            let group = (goal_group + i) % self.groups;
+            let group_sector = group as usize * GROUP_BLOCK_SIZE as usize * BLOCK_SIZE_SECTORS;
+            self.read_or_release(group_sector, BLOCK_SIZE_SECTORS, group_memory, group_mem_binding)
+                .await?;
+            let bitmap = unsafe { get_at_virtual_addr::<[u8; 4096]>(group_mem_binding) };
+            let start = if i == 0 { goal % GROUP_BLOCK_SIZE as u32 } else { 0 };
+            let Some(first) = (start..GROUP_BLOCK_SIZE as u32).find(|bit| is_free(bitmap, *bit)) else {
+                continue;
+            };
+            let mut count = 0;
+            while count < max && first + count < GROUP_BLOCK_SIZE as u32 && is_free(bitmap, first + count) {
+                bitmap[(first + count) as usize / 8] |= 1 << ((first + count) % 8);
+                count += 1;
+            }
+            self.write_sectors(group_sector, BLOCK_SIZE_SECTORS, &[group_memory]).await;
+            drop(lock);
+            unsafe { PAGE_TREE_ALLOCATOR.deallocate(group_mem_binding) };
+            return Ok((group * GROUP_BLOCK_SIZE as u32 + first, count));
+        }
+        drop(lock);
+        unsafe { PAGE_TREE_ALLOCATOR.deallocate(group_mem_binding) };
+        Err(ErrorCode::NoSpace)
+    }
+
+    ///Frees blocks `first..first + count`, one bitmap write per group
+    pub async fn free_run(&self, first: u32, count: u32) -> Result<(), ErrorCode> {
+        let lock = self.block_alloc_lock.lock().await;
+        let (group_memory, group_mem_binding) = get_working_block();
+        let end = first + count;
+        let mut block = first;
+        while block < end {
+            let group = block / GROUP_BLOCK_SIZE as u32;
+            let group_start = group * GROUP_BLOCK_SIZE as u32;
+            let group_end = u32::min(group_start + GROUP_BLOCK_SIZE as u32, end);
+            let group_sector = group as usize * GROUP_BLOCK_SIZE as usize * BLOCK_SIZE_SECTORS;
+            self.read_or_release(group_sector, BLOCK_SIZE_SECTORS, group_memory, group_mem_binding)
+                .await?;
+            let bitmap = unsafe { get_at_virtual_addr::<[u8; 4096]>(group_mem_binding) };
+            for bit in (block - group_start)..(group_end - group_start) {
+                assert!(bitmap[bit as usize / 8] & (1 << (bit % 8)) != 0, "Block already free");
+                bitmap[bit as usize / 8] &= !(1 << (bit % 8));
+            }
+            self.write_sectors(group_sector, BLOCK_SIZE_SECTORS, &[group_memory]).await;
+            self.full_groups.fetch_min(group, Ordering::Relaxed);
+            block = group_end;
+        }
+        drop(lock);
+        for block in first..end {
+            self.journal.forget(block);
+        }
+        unsafe { PAGE_TREE_ALLOCATOR.deallocate(group_mem_binding) };
+        Ok(())
     }
 
     /// Safety
     /// must hold inode tree lock
-    pub async unsafe fn allocate_inode(&self) -> u32 {
+    pub async unsafe fn allocate_inode(&self) -> Result<u32, ErrorCode> {
         let (block_memory, block_mem_binding) = get_working_block();
-        self.partition.read(BLOCK_SIZE_SECTORS, 1, &[block_memory]).await;
+        self.read_or_release(BLOCK_SIZE_SECTORS, 1, block_memory, block_mem_binding)
+            .await?;
         let superblock: &mut SuperBlock = unsafe { get_at_virtual_addr(block_mem_binding) };
         let mut next_ptr = superblock.inode_bitmask;
         let mut block_index = 0;
         loop {
-            self.partition
-                .read(next_ptr as usize * BLOCK_SIZE_SECTORS, 8, &[block_memory])
-                .await;
+            self.read_or_release(next_ptr as usize * BLOCK_SIZE_SECTORS, 8, block_memory, block_mem_binding)
+                .await?;
             let bitmask: &mut InodeBitmask = unsafe { get_at_virtual_addr(block_mem_binding) };
             for (bit_index, byte_mask) in bitmask.inodes.iter_mut().enumerate() {
                 if *byte_mask != 0xFF {
                     for j in 0..8 {
                         if *byte_mask & (1 << j) == 0 {
                             *byte_mask |= 1 << j;
-                            self.partition.write(next_ptr as usize * 8, 8, &[block_memory]).await;
-                            unsafe { physical_allocator::deallocate_frame(block_memory) };
-                            return block_index as u32 * 8 * bitmask.inodes.len() as u32 + (bit_index as u32 * 8) + j;
+                            self.write_sectors(next_ptr as usize * 8, 8, &[block_memory]).await;
+                            unsafe { PAGE_TREE_ALLOCATOR.deallocate(block_mem_binding) };
+                            return Ok(block_index as u32 * 8 * bitmask.inodes.len() as u32 + (bit_index as u32 * 8) + j);
                         }
                     }
                 }
             }
             block_index += 1;
             if bitmask.next_ptr == 0 {
-                let new_block = self.allocate_block().await;
+                let new_block = match self.allocate_block().await {
+                    Ok(block) => block,
+                    Err(e) => {
+                        unsafe { PAGE_TREE_ALLOCATOR.deallocate(block_mem_binding) };
+                        return Err(e);
+                    }
+                };
                 bitmask.next_ptr = new_block;
-                self.partition.write(next_ptr as usize * 8, 8, &[block_memory]).await;
+                self.write_sectors(next_ptr as usize * 8, 8, &[block_memory]).await;
                 unsafe { std::mem_utils::memset_virtual_addr(block_mem_binding, 0, 4096) };
-                self.partition.write(new_block as usize * 8, 1, &[block_memory]).await;
+                let bitmask: &mut InodeBitmask = unsafe { get_at_virtual_addr(block_mem_binding) };
                 bitmask.inodes[0] = 1;
+                self.write_sectors(new_block as usize * 8, 8, &[block_memory]).await;
                 unsafe { PAGE_TREE_ALLOCATOR.deallocate(block_mem_binding) };
-                return block_index as u32 * 8 * bitmask.inodes.len() as u32;
+                return Ok(block_index as u32 * 8 * bitmask.inodes.len() as u32);
             } else {
                 next_ptr = bitmask.next_ptr;
             }
         }
     }
 
-    pub async fn remove_inode_from_bitmask(&mut self, inode_index: u32) {
+    /// Safety
+    /// must hold inode tree lock
+    pub async unsafe fn remove_inode_from_bitmask(&self, inode_index: u32) -> Result<(), ErrorCode> {
         let (block_memory, block_mem_binding) = get_working_block();
-        self.partition.read(1, 1, &[block_memory]).await;
+        self.read_or_release(BLOCK_SIZE_SECTORS, 1, block_memory, block_mem_binding)
+            .await?;
         let superblock: &mut SuperBlock = unsafe { get_at_virtual_addr(block_mem_binding) };
         let mut next_ptr = superblock.inode_bitmask;
-        self.partition.read(next_ptr as usize * 8, 8, &[block_memory]).await;
+        self.read_or_release(next_ptr as usize * 8, 8, block_memory, block_mem_binding)
+            .await?;
         let mut inode_bitmask: &mut InodeBitmask = unsafe { get_at_virtual_addr(block_mem_binding) };
 
-        let inode_lock = self.inode_lock.lock().await;
         for _i in 0..(inode_index / (inode_bitmask.inodes.len() as u32 * 8)) {
-            self.partition
-                .read(inode_bitmask.next_ptr as usize * 8, 8, &[block_memory])
-                .await;
-            inode_bitmask = unsafe { get_at_virtual_addr(block_mem_binding) };
             next_ptr = inode_bitmask.next_ptr;
+            self.read_or_release(next_ptr as usize * 8, 8, block_memory, block_mem_binding)
+                .await?;
+            inode_bitmask = unsafe { get_at_virtual_addr(block_mem_binding) };
         }
         let byte_index = (inode_index % (inode_bitmask.inodes.len() as u32 * 8)) / 8;
         let bit_index = (inode_index % (inode_bitmask.inodes.len() as u32 * 8)) % 8;
         inode_bitmask.inodes[byte_index as usize] &= !(1 << bit_index);
-        self.partition.write(next_ptr as usize * 8, 8, &[block_memory]).await;
-        drop(inode_lock);
+        self.write_sectors(next_ptr as usize * 8, 8, &[block_memory]).await;
+        unsafe { PAGE_TREE_ALLOCATOR.deallocate(block_mem_binding) };
+        Ok(())
+    }
+
+    /// Safety
+    /// must hold inode tree lock
+    async unsafe fn set_root_block(&self, root_block: u32) -> Result<(), ErrorCode> {
+        let self_mut = unsafe { &mut *self.to_mut_ptr() };
+        self_mut.root_block = root_block;
+        let (block_memory, block_mem_binding) = get_working_block();
+        self.read_or_release(BLOCK_SIZE_SECTORS, 1, block_memory, block_mem_binding)
+            .await?;
+        let superblock: &mut SuperBlock = unsafe { get_at_virtual_addr(block_mem_binding) };
+        superblock.inode_tree = root_block;
+        self.write_sectors(BLOCK_SIZE_SECTORS, 1, &[block_memory]).await;
         unsafe { PAGE_TREE_ALLOCATOR.deallocate(block_mem_binding) };
+        Ok(())
     }
 
     /// Safety
     /// must hold inode tree lock
-    pub async unsafe fn get_node(&self, node_block: u32) -> &mut (bool, VirtAddr) {
+    /// Fails with FileSystemInconsistency if a node read from the disk doesn't match its checksum
+    pub async unsafe fn get_node(&self, node_block: u32) -> Result<&mut (bool, VirtAddr), ErrorCode> {
         let self_mut = unsafe { &mut *self.to_mut_ptr() };
         if let std::collections::btree_map::Entry::Vacant(e) = self_mut.inode_tree_cache.entry(node_block) {
             let data = BtreeNode::read_from_disk(&self.partition, node_block).await;
+            let node_frame = std::mem_utils::translate_virt_phys_addr(data, PageTree::get_level4_addr()).unwrap();
+            if let Err(err) = self.journal.verify(&self.partition, node_block, node_frame).await {
+                BtreeNode::drop(data);
+                return Err(err);
+            }
             e.insert((false, data));
         }
 
-        self_mut.inode_tree_cache.get_mut(&node_block).unwrap()
+        Ok(self_mut.inode_tree_cache.get_mut(&node_block).unwrap())
     }
 
     /// Safety
@@ -296,47 +588,65 @@ impl Rfs {
 
     pub async fn format_partition(&self) {
         let whole_blocks = self.partition.partition.size_sectors as u64 / 8;
-        assert!(whole_blocks >= 4, "Partition too small");
-        let whole_groups = whole_blocks / GROUP_BLOCK_SIZE;
-        let last_group_blocks = whole_blocks % GROUP_BLOCK_SIZE;
+        assert!(
+            whole_blocks >= JOURNAL_START as u64 + JOURNAL_BLOCKS as u64,
+            "Partition too small"
+        );
+        let groups = whole_blocks.div_ceil(GROUP_BLOCK_SIZE);
+        let checksum_table = JOURNAL_START + JOURNAL_BLOCKS;
+        let table_blocks = checksum_table_blocks(whole_blocks as u32);
+        assert!(
+            (checksum_table + table_blocks) as u64 <= u64::min(GROUP_BLOCK_SIZE, whole_blocks),
+            "Checksum table doesn't fit into the first group"
+        );
+        let last_group_blocks = whole_blocks - (groups - 1) * GROUP_BLOCK_SIZE;
         let (group_memory, group_mem_binding) = get_working_block();
-        unsafe {
-            memset_virtual_addr(group_mem_binding, 0, 4096);
-
-            //first 5 groups are taken
-            set_at_virtual_addr::<u8>(group_mem_binding, 0b11111);
-        }
 
         //----------Initialize free block tables----------
-        for i in 0..whole_groups {
-            self.partition
-                .write(i as usize * GROUP_BLOCK_SIZE as usize, 8, &[group_memory])
-                .await;
-        }
-        let last_group_invalid = GROUP_BLOCK_SIZE - last_group_blocks;
-        let last_group_invalid_partial = last_group_invalid
-            .unbounded_shr(8 - last_group_invalid as u32 % 8)
-            .unbounded_shl(8 - last_group_invalid as u32 % 8);
-        for i in 0..(last_group_invalid / 8) {
+        //every group bitmap takes its own first block, blocks past the end of the partition are taken
+        for i in 0..groups {
             unsafe {
-                set_at_virtual_addr::<u8>(group_mem_binding + 4095 - i, 0xFF);
+                memset_virtual_addr(group_mem_binding, 0, 4096);
+                //first group also holds the superblock, root node, root inode and inode bitmask
+                set_at_virtual_addr::<u8>(group_mem_binding, if i == 0 { 0b11111 } else { 0b1 });
+            }
+            if i == groups - 1 {
+                for block in last_group_blocks..GROUP_BLOCK_SIZE {
+                    let byte = group_mem_binding + block / 8;
+                    unsafe { set_at_virtual_addr::<u8>(byte, *get_at_virtual_addr::<u8>(byte) | (1 << (block % 8))) };
+                }
             }
+            self.partition
+                .write(
+                    i as usize * GROUP_BLOCK_SIZE as usize * BLOCK_SIZE_SECTORS,
+                    8,
+                    &[group_memory],
+                )
+                .await;
         }
-        unsafe {
-            set_at_virtual_addr::<u8>(
-                group_mem_binding + 4095 - (last_group_invalid / 8),
-                last_group_invalid_partial as u8,
-            );
+        unsafe { std::mem_utils::memset_virtual_addr(group_mem_binding, 0, 4096) };
+
+        //----------Reserve the journal after the inode bitmask, then the checksum table----------
+        self.partition.read(0, 8, &[group_memory]).await;
+        for block in JOURNAL_START..(checksum_table + table_blocks) {
+            let byte = group_mem_binding + block as u64 / 8;
+            unsafe { set_at_virtual_addr::<u8>(byte, *get_at_virtual_addr::<u8>(byte) | (1 << (block % 8))) };
         }
+        self.partition.write(0, 8, &[group_memory]).await;
+        //an empty descriptor means there is nothing to replay
+        unsafe { std::mem_utils::memset_virtual_addr(group_mem_binding, 0, 4096) };
         self.partition
-            .write(whole_groups as usize * GROUP_BLOCK_SIZE as usize, 8, &[group_memory])
+            .write(JOURNAL_START as usize * BLOCK_SIZE_SECTORS, 8, &[group_memory])
             .await;
-        unsafe { std::mem_utils::memset_virtual_addr(group_mem_binding, 0, 4096) };
 
         //----------Initialize header at block 1----------
         let header = SuperBlock {
             inode_tree: 2,
             inode_bitmask: 4,
+            journal_start: JOURNAL_START,
+            journal_blocks: JOURNAL_BLOCKS,
+            features: FEATURE_CHECKSUMS,
+            checksum_table,
         };
         unsafe { set_at_virtual_addr(group_mem_binding, header) };
         self.partition.write(BLOCK_SIZE_SECTORS, 1, &[group_memory]).await;
@@ -358,15 +668,19 @@ impl Rfs {
         unsafe { std::mem_utils::memset_virtual_addr(group_mem_binding, 0, core::mem::size_of::<Key>()) };
 
         //----------Initialize root inode block at block 3----------
+        let now = times::now();
         let root_inode = Inode {
-            size: InodeSize(0), //size 0, 0 levels of pointers
-            inode_type_mode: InodeType::new_dir(0o755),
+            size: InodeSize::new_extents(),
+            inode_type_mode: InodeMode::new_dir(0o755),
             link_count: 0,
             uid: 0,
             gid: 0,
-            access_time: 0,
-            modification_time: 0,
-            stat_change_time: 0,
+            reserved: 0,
+            access_time: now,
+            modification_time: now,
+            stat_change_time: now,
+            index_root: 0,
+            reserved_tail: 0,
         };
         unsafe { set_at_virtual_addr(group_mem_binding, root_inode) };
 
@@ -383,510 +697,1354 @@ impl Rfs {
         unsafe { set_at_virtual_addr::<u8>(group_mem_binding, 0b111) };
         self.partition.write(4 * BLOCK_SIZE_SECTORS, 1, &[group_memory]).await;
 
+        //----------Checksum the metadata written above----------
+        //only parts of some blocks were written, so the checksums come from what is on disk
+        let mut metadata = (0..groups as u32)
+            .map(|group| group * GROUP_BLOCK_SIZE as u32)
+            .collect::<Vec<_>>();
+        metadata.extend(1..=4);
+        let (table_frame, table_frame_binding) = get_working_block();
+        for table_block in 0..table_blocks {
+            unsafe { memset_virtual_addr(table_frame_binding, 0, 4096) };
+            let table = unsafe { get_at_virtual_addr::<ChecksumBlock>(table_frame_binding) };
+            for block in metadata.iter().filter(|block| checksum_slot(**block).0 == table_block) {
+                self.partition
+                    .read(*block as usize * BLOCK_SIZE_SECTORS, BLOCK_SIZE_SECTORS, &[group_memory])
+                    .await;
+                table.checksums[checksum_slot(*block).1] = block_checksum(group_memory);
+            }
+            table.checksum = table.own_checksum();
+            self.partition
+                .write(
+                    (checksum_table + table_block) as usize * BLOCK_SIZE_SECTORS,
+                    BLOCK_SIZE_SECTORS,
+                    &[table_frame],
+                )
+                .await;
+        }
+
         unsafe {
+            PAGE_TREE_ALLOCATOR.deallocate(table_frame_binding);
             PAGE_TREE_ALLOCATOR.deallocate(group_mem_binding);
         }
     }
 
-    #[allow(unreachable_code)]
     /// Safety
     /// file lock must be held
-    async fn increase_file_size(&self, inode_frame_binding: VirtAddr, inode_frame: PhysAddr, inode_block: u32, size_new: u64) {
+    async fn increase_file_size(
+        &self,
+        inode_frame_binding: VirtAddr,
+        inode_frame: PhysAddr,
+        inode_block: u32,
+        size_new: u64,
+    ) -> Result<(), ErrorCode> {
         let inode_data: &mut Inode = unsafe { get_at_virtual_addr(inode_frame_binding) };
+        if inode_data.size.extents() {
+            return self
+                .increase_extent_file_size(inode_frame_binding, inode_frame, inode_block, size_new)
+                .await;
+        }
         let mut levels_curr = inode_data.size.ptr_levels() as u32;
         let size_old = inode_data.size.size();
 
-        let mut max_file_size = 512 * 7;
-        let mut levels_new: u32 = 0;
-        while max_file_size < size_new {
-            max_file_size *= 1024;
-            levels_new += 1;
-        }
+        let levels_new = ptr_levels_for(size_new);
         assert!(levels_new <= 3, "File too big");
 
         let (working_block, working_block_binding) = get_working_block();
         //increase file depth
-        {
-            while levels_new > levels_curr {
-                levels_curr += 1;
-
-                self.partition.read(inode_block as usize * 8 + 1, 7, &[working_block]).await;
-
-                let new_block_index = self.allocate_block().await;
-                self.partition.write(new_block_index as usize * 8, 7, &[working_block]).await;
-
-                unsafe {
-                    std::mem_utils::memset_virtual_addr(working_block_binding, 0, 512 * 7);
-                    set_at_virtual_addr(working_block_binding, new_block_index)
-                };
-                self.partition.write(inode_block as usize * 8 + 1, 1, &[working_block]).await;
+        if size_old == 0 {
+            //nothing to keep, pointers are allocated below
+            levels_curr = levels_new;
+        }
+        while levels_new > levels_curr {
+            levels_curr += 1;
+
+            //the inline data or the pointers move into a new block, which takes the first pointer
+            self.read_or_release(inode_block as usize * 8 + 1, 7, working_block, working_block_binding)
+                .await?;
+
+            let new_block_index = match self.allocate_block().await {
+                Ok(block) => block,
+                Err(e) => {
+                    unsafe { PAGE_TREE_ALLOCATOR.deallocate(working_block_binding) };
+                    return Err(e);
+                }
+            };
+            if levels_curr == 1 {
+                self.write_data(new_block_index as usize * 8, 7, &[working_block]).await;
+            } else {
+                self.write_sectors(new_block_index as usize * 8, 7, &[working_block]).await;
             }
+
+            unsafe {
+                std::mem_utils::memset_virtual_addr(working_block_binding, 0, 512 * 7);
+                set_at_virtual_addr(working_block_binding, new_block_index)
+            };
+            self.write_sectors(inode_block as usize * 8 + 1, 7, &[working_block]).await;
         }
 
         inode_data.size.set_ptr_levels(levels_new as u64);
         inode_data.size.set_size(size_new);
-        self.partition
-            .write(inode_block as usize * BLOCK_SIZE_SECTORS, 1, &[inode_frame])
+        self.write_sectors(inode_block as usize * BLOCK_SIZE_SECTORS, 1, &[inode_frame])
             .await;
 
-        let blocks_old = size_old.div_ceil(4096);
-        let blocks_new = size_new.div_ceil(4096);
-
         if levels_new == 0 {
             //no allocation is necessary
             unsafe { PAGE_TREE_ALLOCATOR.deallocate(working_block_binding) };
-            return;
+            return Ok(());
         }
-        if levels_new == 1 {
-            assert!(blocks_new <= 512 * 7 / 4, "Function did not increase levels enough");
-            self.partition
-                .read(inode_block as usize * BLOCK_SIZE_SECTORS + 1, 7, &[working_block])
-                .await;
-            let pointers = unsafe { get_at_virtual_addr::<[u32; 512 / 4 * 7]>(working_block_binding) };
-            for i in blocks_old..blocks_new {
-                let new_block = self.allocate_block().await;
-                pointers[i as usize] = new_block;
-            }
-            self.partition
-                .write(inode_block as usize * BLOCK_SIZE_SECTORS + 1, 7, &[working_block])
-                .await;
-        } else {
-            //level = 2 or 3
-            todo!("This probably doesn't work");
-            let pointers = unsafe { get_at_virtual_addr::<[u32; 512 / 4 * 7]>(inode_frame_binding + 512) };
-            let pointer_capacity = u64::pow(1024, levels_new - 1);
-            for i in 0..(512 / 4 * 7) {
-                if blocks_old >= blocks_new {
-                    //all allocated
-                    break;
-                }
-                if pointer_capacity * (i + 1) <= blocks_old {
-                    //nothing to do as this pointer is already filled
-                    continue;
-                }
-
-                let (lower_frame, lower_frame_binding) = get_working_block();
 
-                if pointer_capacity * i < blocks_old {
-                    //lower is partially allocated
-                    self.partition
-                        .read(pointers[i as usize] as usize * 8, 8, &[lower_frame])
-                        .await;
-                } else {
-                    //lower did not exist yet
-                    let lower_block_index = self.allocate_block().await;
-                    pointers[i as usize] = lower_block_index;
-                }
-                self.allocate_blocks_for_size_increase(
-                    levels_new - 1,
-                    i as u32,
-                    lower_frame_binding,
-                    blocks_new as u32,
-                    blocks_old as u32,
-                )
+        self.read_or_release(
+            inode_block as usize * BLOCK_SIZE_SECTORS + 1,
+            7,
+            working_block,
+            working_block_binding,
+        )
+        .await?;
+        let pointers = unsafe { get_at_virtual_addr::<[u32; 512 / 4 * 7]>(working_block_binding) };
+        let allocated = self
+            .allocate_blocks(levels_new, pointers, size_old.div_ceil(4096), size_new.div_ceil(4096))
+            .await;
+        if allocated.is_ok() {
+            self.write_sectors(inode_block as usize * BLOCK_SIZE_SECTORS + 1, 7, &[working_block])
                 .await;
-                self.partition
-                    .write(pointers[i as usize] as usize * 8, 8, &[lower_frame])
-                    .await;
-                unsafe { PAGE_TREE_ALLOCATOR.deallocate(lower_frame_binding) };
-                blocks_old = pointer_capacity * (i + 1);
-            }
         }
         unsafe { PAGE_TREE_ALLOCATOR.deallocate(working_block_binding) };
+        allocated
     }
 
-    ///Block index must point to a block of only pointers. Will loop through pointers, skip any
-    ///unnecessary, step into the last one, and allocate new pointers
-    ///this block must be memory mapped and set to uncacheable. As such, this function will also
-    ///not write it to disk or deallocate it by itself
-    ///index of the pointer to this block in the level of that pointer, globally, not just in
-    ///that pointer set (eg. sizes go beyond 1023)
-    ///Pointer capacity is in blocks, not bytes
-    ///Inode lock must be held
-    async fn allocate_blocks_for_size_increase(
-        &self,
-        level: u32,
-        ptr_index: u32,
-        block_page: VirtAddr,
-        blocks_new: u32,
-        mut blocks_old: u32,
-    ) {
-        let pointers = unsafe { get_at_virtual_addr::<[u32; 1024]>(block_page) };
-        let pointer_capacity = u32::pow(1024, level - 1);
-        let blocks_before_current = pointer_capacity * ptr_index;
-
-        if level == 1 {
-            if blocks_old >= blocks_new {
-                return;
+    ///Frees blocks past the new size and removes levels of pointers that are no longer needed
+    ///file lock must be held
+    async fn decrease_file_size(&self, inode_block: u32, size_new: u64) -> Result<(), ErrorCode> {
+        let (working_block, working_block_binding) = get_working_block();
+        self.read_or_release(inode_block as usize * 8, 1, working_block, working_block_binding)
+            .await?;
+        let inode_data: &mut Inode = unsafe { get_at_virtual_addr(working_block_binding) };
+        if inode_data.size.extents() {
+            unsafe { PAGE_TREE_ALLOCATOR.deallocate(working_block_binding) };
+            return self.decrease_extent_file_size(inode_block, size_new).await;
+        }
+        let mut levels_curr = inode_data.size.ptr_levels() as u32;
+        let blocks_old = inode_data.size.size().div_ceil(4096);
+        let blocks_new = size_new.div_ceil(4096);
+        let levels_new = ptr_levels_for(size_new);
+
+        if let Err(e) = self.free_data_blocks(inode_block, levels_curr, blocks_new, blocks_old).await {
+            unsafe { PAGE_TREE_ALLOCATOR.deallocate(working_block_binding) };
+            return Err(e);
+        }
+
+        //everything left is under the first pointer, which takes the place of the inode pointers
+        while levels_curr > levels_new && blocks_new != 0 {
+            self.read_or_release(inode_block as usize * 8 + 1, 1, working_block, working_block_binding)
+                .await?;
+            let first_pointer: u32 = unsafe { *get_at_virtual_addr(working_block_binding) };
+            if levels_curr == 1 {
+                //file data isn't journaled or checksummed
+                self.partition.read(first_pointer as usize * 8, 8, &[working_block]).await;
+            } else {
+                self.read_or_release(first_pointer as usize * 8, 8, working_block, working_block_binding)
+                    .await?;
             }
-            for i in 0..1024 {
-                //ptr capacity is 1
-                if ptr_index + i < blocks_old {
-                    continue;
-                }
-                let new_block = self.allocate_block().await;
-                pointers[i as usize] = new_block;
+            //pointers or the inline data, both are the first 7 sectors
+            self.write_sectors(inode_block as usize * 8 + 1, 7, &[working_block]).await;
+            if let Err(e) = self.free_block(first_pointer).await {
+                unsafe { PAGE_TREE_ALLOCATOR.deallocate(working_block_binding) };
+                return Err(e);
             }
-            return;
+            levels_curr -= 1;
         }
 
-        for i in 0..1024 {
-            if blocks_old >= blocks_new {
-                return;
+        self.read_or_release(inode_block as usize * 8, 1, working_block, working_block_binding)
+            .await?;
+        let inode_data: &mut Inode = unsafe { get_at_virtual_addr(working_block_binding) };
+        inode_data.size.set_ptr_levels(levels_new as u64);
+        inode_data.size.set_size(size_new);
+        self.write_sectors(inode_block as usize * 8, 1, &[working_block]).await;
+        unsafe { PAGE_TREE_ALLOCATOR.deallocate(working_block_binding) };
+        Ok(())
+    }
+
+    ///Zeroes the block containing `offset` from `offset` to the end of the block, so the bytes read
+    ///as zeroes once the file grows past them. Offset must not be past the file size
+    ///file lock must be held
+    async fn zero_block_tail(&self, inode: InodeIndex, offset: u64) -> Result<(), ErrorCode> {
+        let tail = offset % 4096;
+        if tail == 0 {
+            return Ok(());
+        }
+        let block_start = offset - tail;
+        let (working_block, working_block_binding) = get_working_block();
+        let read = unsafe { self.read_locked(inode, block_start, tail, &[working_block]).await };
+        let written = match read {
+            Ok(_) => {
+                unsafe { memset_virtual_addr(working_block_binding + tail, 0, (4096 - tail) as usize) };
+                self.write_locked(inode, block_start, tail, &[working_block]).await
             }
-            if blocks_before_current + (i + 1) * pointer_capacity <= blocks_old {
+            Err(e) => Err(e),
+        };
+        unsafe { PAGE_TREE_ALLOCATOR.deallocate(working_block_binding) };
+        written.map(|_| ())
+    }
+
+    ///Allocates data blocks `first..end`, counted from the start of the pointers, under pointers
+    ///at the given level, level 1 pointing to data blocks. Blocks before first are already allocated.
+    ///The pointers are changed in place, writing them to disk is up to the caller
+    ///file lock must be held
+    async fn allocate_blocks(&self, level: u32, pointers: &mut [u32], first: u64, end: u64) -> Result<(), ErrorCode> {
+        let pointer_capacity = u64::pow(1024, level - 1);
+        for i in (first / pointer_capacity)..end.div_ceil(pointer_capacity) {
+            if level == 1 {
+                pointers[i as usize] = self.allocate_block().await?;
                 continue;
             }
-
+            let pointer_start = i * pointer_capacity;
+            if pointer_start >= first {
+                //lower did not exist yet
+                pointers[i as usize] = self.allocate_block().await?;
+            }
             let (lower_frame, lower_frame_binding) = get_working_block();
-            if blocks_before_current + pointer_capacity * i < blocks_old {
+            if pointer_start < first {
                 //lower is partially allocated
-                self.partition
-                    .read(pointers[i as usize] as usize * 8, 8, &[lower_frame])
-                    .await;
+                self.read_or_release(pointers[i as usize] as usize * 8, 8, lower_frame, lower_frame_binding)
+                    .await?;
             } else {
-                //lower did not exist yet
-                let lower_block_index = self.allocate_block().await;
-                pointers[i as usize] = lower_block_index;
+                unsafe { memset_virtual_addr(lower_frame_binding, 0, 4096) };
             }
-            Box::pin(self.allocate_blocks_for_size_increase(
+            let lower_pointers = unsafe { get_at_virtual_addr::<[u32; 1024]>(lower_frame_binding) };
+            let allocated = Box::pin(self.allocate_blocks(
                 level - 1,
-                i + (ptr_index * 1024),
-                lower_frame_binding,
-                blocks_new,
-                blocks_old,
+                lower_pointers,
+                first.saturating_sub(pointer_start),
+                u64::min(end - pointer_start, pointer_capacity),
             ))
             .await;
-            self.partition
-                .write(pointers[i as usize] as usize * 8, 8, &[lower_frame])
-                .await;
+            if allocated.is_ok() {
+                self.write_sectors(pointers[i as usize] as usize * 8, 8, &[lower_frame]).await;
+            }
             unsafe { PAGE_TREE_ALLOCATOR.deallocate(lower_frame_binding) };
-            blocks_old = blocks_before_current + pointer_capacity * (i + 1);
+            allocated?;
         }
+        Ok(())
     }
 
-    ///must hold inode lock
-    async unsafe fn delete_block(&mut self, level: u32, block_index: u32) {
+    ///Data blocks `first..end` of a file, counted in blocks from the start of the file
+    ///file lock must be held
+    async fn find_data_blocks(&self, inode_block: u32, levels: u32, first: u64, end: u64) -> Result<Vec<u32>, ErrorCode> {
         let (working_block, working_block_binding) = get_working_block();
-        self.partition.read(block_index as usize * 8, 8, &[working_block]).await;
-        let pointers = unsafe { get_at_virtual_addr::<[u32; 1024]>(working_block_binding) };
-        for i in 0..1024 {
-            if level == 1 {
-                self.free_block(pointers[i]).await;
-            } else {
-                unsafe { Box::pin(self.delete_block(level - 1, pointers[i])).await };
+        self.read_or_release(inode_block as usize * 8 + 1, 7, working_block, working_block_binding)
+            .await?;
+        let mut pointers = unsafe { get_at_virtual_addr::<[u32; 512 / 4 * 7]>(working_block_binding) }.to_vec();
+        //block index of the first pointer
+        let mut first_ptr = 0;
+        let mut level = levels;
+        while level > 1 {
+            let pointer_capacity = u64::pow(1024, level - 1);
+            let first_relevant = (first - first_ptr) / pointer_capacity;
+            let last_relevant = (end - 1 - first_ptr) / pointer_capacity;
+            first_ptr += first_relevant * pointer_capacity;
+
+            let mut new_pointers = Vec::with_capacity((last_relevant - first_relevant + 1) as usize * 1024);
+            for i in first_relevant..=last_relevant {
+                self.read_or_release(pointers[i as usize] as usize * 8, 8, working_block, working_block_binding)
+                    .await?;
+                new_pointers.extend_from_slice(unsafe { get_at_virtual_addr::<[u32; 1024]>(working_block_binding) });
             }
+            pointers = new_pointers;
+            level -= 1;
         }
+        unsafe { PAGE_TREE_ALLOCATOR.deallocate(working_block_binding) };
+        Ok(pointers[(first - first_ptr) as usize..(end - first_ptr) as usize].to_vec())
     }
 
-    async unsafe fn read_locked(&self, inode: InodeIndex, offset_bytes: u64, size_bytes: u64, buffer: &[PhysAddr]) -> u64 {
-        if size_bytes == 0 {
-            return 0;
+    ///Frees the data blocks in `first..end` of a file, counted in blocks from the start of the file,
+    ///along with pointer blocks that no longer point to anything. Does not change the inode
+    ///file lock must be held
+    async fn free_data_blocks(&self, inode_block: u32, levels: u32, first: u64, end: u64) -> Result<(), ErrorCode> {
+        if levels == 0 || first >= end {
+            return Ok(());
         }
-        assert!(buffer.len() == (size_bytes).div_ceil(4096) as usize);
-        assert!(offset_bytes % 4096 == 0);
-
-        let inode_tree_lock = self.inode_lock.lock().await;
-
-        let root = unsafe { self.get_node(self.root_block).await.1 };
-        let inode_block_index = BtreeNode::find_inode_block(root, inode as u32, self).await.unwrap();
-        drop(inode_tree_lock); //file inode lock is held, so file won't move. Found the block
-        let (inode_block, inode_block_binding) = get_working_block();
-        self.partition.read(inode_block_index as usize * 8, 1, &[inode_block]).await;
-        let inode_data: &mut Inode = unsafe { get_at_virtual_addr(inode_block_binding) };
-
-        if offset_bytes >= inode_data.size.size() {
-            unsafe { PAGE_TREE_ALLOCATOR.deallocate(inode_block_binding) };
-            return 0;
+        let (working_block, working_block_binding) = get_working_block();
+        self.read_or_release(inode_block as usize * 8 + 1, 7, working_block, working_block_binding)
+            .await?;
+        let pointers = unsafe { get_at_virtual_addr::<[u32; 512 / 4 * 7]>(working_block_binding) };
+        let pointer_capacity = u64::pow(1024, levels - 1);
+        for i in (first / pointer_capacity)..end.div_ceil(pointer_capacity) {
+            let pointer_start = i * pointer_capacity;
+            let deleted = self
+                .delete_block(
+                    levels - 1,
+                    pointers[i as usize],
+                    first.saturating_sub(pointer_start),
+                    u64::min(end - pointer_start, pointer_capacity),
+                )
+                .await;
+            if deleted.is_err() {
+                unsafe { PAGE_TREE_ALLOCATOR.deallocate(working_block_binding) };
+                return deleted;
+            }
         }
-        let max_size = inode_data.size.size() - offset_bytes;
-        let size_bytes = u64::min(size_bytes, max_size);
-        let aligned_size = size_bytes.div_ceil(4096) * 4096;
-        let ret_size = u64::min(max_size, aligned_size);
+        unsafe { PAGE_TREE_ALLOCATOR.deallocate(working_block_binding) };
+        Ok(())
+    }
 
-        if aligned_size == 0 {
-            unsafe { PAGE_TREE_ALLOCATOR.deallocate(inode_block_binding) };
-            return 0;
+    ///Frees data blocks `first..end` under a block at the given level of pointers, level 0 being
+    ///a data block. The block itself is freed when first is 0, as nothing under it is left
+    ///file lock must be held
+    async fn delete_block(&self, level: u32, block_index: u32, first: u64, end: u64) -> Result<(), ErrorCode> {
+        if level > 0 {
+            let (working_block, working_block_binding) = get_working_block();
+            self.read_or_release(block_index as usize * 8, 8, working_block, working_block_binding)
+                .await?;
+            let pointers = unsafe { get_at_virtual_addr::<[u32; 1024]>(working_block_binding) };
+            let pointer_capacity = u64::pow(1024, level - 1);
+            for i in (first / pointer_capacity)..end.div_ceil(pointer_capacity) {
+                let pointer_start = i * pointer_capacity;
+                let deleted = Box::pin(self.delete_block(
+                    level - 1,
+                    pointers[i as usize],
+                    first.saturating_sub(pointer_start),
+                    u64::min(end - pointer_start, pointer_capacity),
+                ))
+                .await;
+                if deleted.is_err() {
+                    unsafe { PAGE_TREE_ALLOCATOR.deallocate(working_block_binding) };
+                    return deleted;
+                }
+            }
+            unsafe { PAGE_TREE_ALLOCATOR.deallocate(working_block_binding) };
         }
+        if first == 0 {
+            self.free_block(block_index).await?;
+        }
+        Ok(())
+    }
 
-        let mut levels = inode_data.size.ptr_levels();
-        if levels == 0 {
-            self.partition.read(inode_block_index as usize * 8 + 1, 7, buffer).await;
-            unsafe { PAGE_TREE_ALLOCATOR.deallocate(inode_block_binding) };
-            return ret_size;
+    ///Extents of a file and the extent tree nodes above them, `area` being the extents in the inode
+    ///file lock must be held
+    async fn read_extent_tree(&self, area: &InodeExtents, depth: u32) -> Result<(Vec<Extent>, Vec<u32>), ErrorCode> {
+        let mut extents = area.extents[..area.count as usize].to_vec();
+        let mut nodes = Vec::new();
+        let (working_block, working_block_binding) = get_working_block();
+        for _ in 0..depth {
+            let mut lower = Vec::new();
+            for extent in extents.iter() {
+                self.read_or_release(extent.block as usize * 8, 8, working_block, working_block_binding)
+                    .await?;
+                let node = unsafe { get_at_virtual_addr::<ExtentNode>(working_block_binding) };
+                lower.extend_from_slice(&node.extents[..node.count as usize]);
+                nodes.push(extent.block);
+            }
+            extents = lower;
         }
-        //read first level pointers
-        self.partition
-            .read(inode_block_index as usize * 8 + 1, 7, &[inode_block])
-            .await;
+        unsafe { PAGE_TREE_ALLOCATOR.deallocate(working_block_binding) };
+        Ok((extents, nodes))
+    }
 
-        let mut pointers: Vec<u32> = std::Vec::with_capacity(512 / 4 * 7);
-        for i in (0..(512 * 7)).step_by(4) {
-            pointers.push(unsafe { *get_at_virtual_addr(inode_block_binding + i) });
+    ///Extents holding file blocks `first..end`, cut to that range. Only the nodes over the range
+    ///are read
+    ///file lock must be held
+    async fn find_extents(&self, inode_block: u32, depth: u32, first: u32, end: u32) -> Result<Vec<Extent>, ErrorCode> {
+        let (working_block, working_block_binding) = get_working_block();
+        self.read_or_release(inode_block as usize * 8 + 1, 7, working_block, working_block_binding)
+            .await?;
+        let area = unsafe { get_at_virtual_addr::<InodeExtents>(working_block_binding) };
+        let overlaps = |extent: &&Extent| extent.file_block < end && extent.end() > first;
+        let mut extents = area.extents[..area.count as usize]
+            .iter()
+            .filter(overlaps)
+            .copied()
+            .collect::<Vec<_>>();
+        for _ in 0..depth {
+            let mut lower = Vec::new();
+            for extent in extents.iter() {
+                self.read_or_release(extent.block as usize * 8, 8, working_block, working_block_binding)
+                    .await?;
+                let node = unsafe { get_at_virtual_addr::<ExtentNode>(working_block_binding) };
+                lower.extend(node.extents[..node.count as usize].iter().filter(overlaps));
+            }
+            extents = lower;
         }
+        unsafe { PAGE_TREE_ALLOCATOR.deallocate(working_block_binding) };
+        Ok(extents
+            .into_iter()
+            .map(|extent| {
+                let start = u32::max(extent.file_block, first);
+                Extent {
+                    file_block: start,
+                    block: extent.block + (start - extent.file_block),
+                    length: u32::min(extent.end(), end) - start,
+                }
+            })
+            .collect())
+    }
 
-        let mut first_ptr = 0;
-        while levels > 1 {
-            let ptr_span = 4096 + (levels - 1) * 1024;
-            let first_relevant = (offset_bytes - first_ptr) / ptr_span;
-            let last_relevant = (offset_bytes + aligned_size - 1 - first_ptr) / ptr_span;
-            first_ptr += first_relevant * ptr_span;
+    ///Stores the extents in the inode extent area, with as many levels of nodes above them as
+    ///needed. Nodes of the old tree are reused before new ones are allocated, the rest is freed.
+    ///Returns the depth of the tree, writing the area to disk is up to the caller
+    ///file lock must be held
+    async fn write_extent_tree(
+        &self,
+        area: &mut InodeExtents,
+        mut extents: Vec<Extent>,
+        old_nodes: Vec<u32>,
+    ) -> Result<u32, ErrorCode> {
+        let depth = extent_depth_for(extents.len());
+        let mut old_nodes = old_nodes.into_iter();
+        let (working_block, working_block_binding) = get_working_block();
+        for _ in 0..depth {
+            let mut upper = Vec::with_capacity(extents.len().div_ceil(NODE_EXTENTS));
+            for chunk in extents.chunks(NODE_EXTENTS) {
+                let node_block = match old_nodes.next() {
+                    Some(node_block) => node_block,
+                    None => match self.allocate_block().await {
+                        Ok(node_block) => node_block,
+                        Err(e) => {
+                            unsafe { PAGE_TREE_ALLOCATOR.deallocate(working_block_binding) };
+                            return Err(e);
+                        }
+                    },
+                };
+                unsafe { memset_virtual_addr(working_block_binding, 0, 4096) };
+                let node = unsafe { get_at_virtual_addr::<ExtentNode>(working_block_binding) };
+                node.count = chunk.len() as u32;
+                node.extents[..chunk.len()].copy_from_slice(chunk);
+                self.write_sectors(node_block as usize * 8, 8, &[working_block]).await;
+                upper.push(Extent {
+                    file_block: chunk[0].file_block,
+                    block: node_block,
+                    length: chunk.iter().map(|extent| extent.length).sum(),
+                });
+            }
+            extents = upper;
+        }
+        for node_block in old_nodes {
+            if let Err(e) = self.free_block(node_block).await {
+                unsafe { PAGE_TREE_ALLOCATOR.deallocate(working_block_binding) };
+                return Err(e);
+            }
+        }
+        unsafe { PAGE_TREE_ALLOCATOR.deallocate(working_block_binding) };
+        area.count = extents.len() as u32;
+        area.extents[..extents.len()].copy_from_slice(&extents);
+        Ok(depth)
+    }
 
-            let mut new_pointers = std::Vec::with_capacity((last_relevant - first_relevant + 1) as usize * 1024);
-            for i in first_relevant..=last_relevant {
-                self.partition
-                    .read(pointers[i as usize] as usize * 8, 8, &[inode_block])
-                    .await;
-                for i in (0..4096).step_by(4) {
-                    new_pointers.push(unsafe { *get_at_virtual_addr(inode_block_binding + i) });
+    ///increase_file_size for files mapped by extents. Blocks are allocated in runs after the last
+    ///block of the file, inline data moves to the first block once it no longer fits
+    ///file lock must be held
+    async fn increase_extent_file_size(
+        &self,
+        inode_frame_binding: VirtAddr,
+        inode_frame: PhysAddr,
+        inode_block: u32,
+        size_new: u64,
+    ) -> Result<(), ErrorCode> {
+        let inode_data: &mut Inode = unsafe { get_at_virtual_addr(inode_frame_binding) };
+        let size_old = inode_data.size.size();
+        let was_inline = inode_data.size.is_inline();
+        if size_new > INLINE_DATA_SIZE {
+            let (area_frame, area_binding) = get_working_block();
+            self.read_or_release(inode_block as usize * 8 + 1, 7, area_frame, area_binding)
+                .await?;
+            let area = unsafe { get_at_virtual_addr::<InodeExtents>(area_binding) };
+            let tree = if was_inline {
+                Ok((Vec::new(), Vec::new()))
+            } else {
+                self.read_extent_tree(area, inode_data.size.ptr_levels() as u32).await
+            };
+            let (mut extents, nodes) = match tree {
+                Ok(tree) => tree,
+                Err(e) => {
+                    unsafe { PAGE_TREE_ALLOCATOR.deallocate(area_binding) };
+                    return Err(e);
+                }
+            };
+
+            let blocks_new = size_new.div_ceil(4096) as u32;
+            let mut file_block = extents.last().map(|extent| extent.end()).unwrap_or(0);
+            //new files start right after their inode block
+            let mut goal = extents
+                .last()
+                .map(|extent| extent.block + extent.length)
+                .unwrap_or(inode_block + 1);
+            while file_block < blocks_new {
+                let (block, length) = match self.allocate_run(goal, blocks_new - file_block).await {
+                    Ok(run) => run,
+                    Err(e) => {
+                        unsafe { PAGE_TREE_ALLOCATOR.deallocate(area_binding) };
+                        return Err(e);
+                    }
+                };
+                match extents.last_mut() {
+                    Some(last) if last.block + last.length == block => last.length += length,
+                    _ => extents.push(Extent {
+                        file_block,
+                        block,
+                        length,
+                    }),
                 }
+                file_block += length;
+                goal = block + length;
             }
-            pointers = new_pointers;
-            levels -= 1;
+
+            if was_inline && size_old > 0 {
+                unsafe { memset_virtual_addr(area_binding + INLINE_DATA_SIZE, 0, 4096 - INLINE_DATA_SIZE as usize) };
+                self.write_data(extents[0].block as usize * 8, 8, &[area_frame]).await;
+            }
+            unsafe { memset_virtual_addr(area_binding, 0, 4096) };
+            let depth = self.write_extent_tree(area, extents, nodes).await;
+            if depth.is_ok() {
+                self.write_sectors(inode_block as usize * 8 + 1, 7, &[area_frame]).await;
+            }
+            unsafe { PAGE_TREE_ALLOCATOR.deallocate(area_binding) };
+            inode_data.size.set_ptr_levels(depth? as u64);
+        }
+        inode_data.size.set_size(size_new);
+        self.write_sectors(inode_block as usize * BLOCK_SIZE_SECTORS, 1, &[inode_frame])
+            .await;
+        Ok(())
+    }
+
+    ///decrease_file_size for files mapped by extents. Data that fits into the inode block again
+    ///moves back into it
+    ///file lock must be held
+    async fn decrease_extent_file_size(&self, inode_block: u32, size_new: u64) -> Result<(), ErrorCode> {
+        let (working_block, working_block_binding) = get_working_block();
+        self.read_or_release(inode_block as usize * 8, 1, working_block, working_block_binding)
+            .await?;
+        let inode_data: &mut Inode = unsafe { get_at_virtual_addr(working_block_binding) };
+        if !inode_data.size.is_inline() {
+            let (area_frame, area_binding) = get_working_block();
+            let shrunk = self
+                .shrink_extents(inode_data, inode_block, size_new, area_frame, area_binding)
+                .await;
+            unsafe { PAGE_TREE_ALLOCATOR.deallocate(area_binding) };
+            if let Err(e) = shrunk {
+                unsafe { PAGE_TREE_ALLOCATOR.deallocate(working_block_binding) };
+                return Err(e);
+            }
+        }
+        inode_data.size.set_size(size_new);
+        self.write_sectors(inode_block as usize * 8, 1, &[working_block]).await;
+        unsafe { PAGE_TREE_ALLOCATOR.deallocate(working_block_binding) };
+        Ok(())
+    }
+
+    ///Frees the extents of a file past `size_new` and stores what is kept in the inode extent area,
+    ///read into `area_frame`. Sets the depth of the tree in the inode
+    ///file lock must be held
+    async fn shrink_extents(
+        &self,
+        inode_data: &mut Inode,
+        inode_block: u32,
+        size_new: u64,
+        area_frame: PhysAddr,
+        area_binding: VirtAddr,
+    ) -> Result<(), ErrorCode> {
+        self.read_sectors(inode_block as usize * 8 + 1, 7, &[area_frame]).await?;
+        let area = unsafe { get_at_virtual_addr::<InodeExtents>(area_binding) };
+        let (extents, nodes) = self.read_extent_tree(area, inode_data.size.ptr_levels() as u32).await?;
+        let to_inline = size_new <= INLINE_DATA_SIZE;
+        let blocks_kept = if to_inline { 0 } else { size_new.div_ceil(4096) as u32 };
+        if to_inline && size_new > 0 {
+            self.partition.read(extents[0].block as usize * 8, 7, &[area_frame]).await;
+        } else {
+            unsafe { memset_virtual_addr(area_binding, 0, 4096) };
         }
 
-        //At this point each pointer points to a 4k region
-        let first_relevant = (offset_bytes - first_ptr) / 4096;
-        let last_relevant = (offset_bytes + aligned_size - 1 - first_ptr) / 4096;
-        for i in first_relevant..=last_relevant {
-            let i = i as usize;
-            let buf_index = i - first_relevant as usize;
+        let mut kept = Vec::new();
+        for extent in extents {
+            if extent.end() <= blocks_kept {
+                kept.push(extent);
+            } else if extent.file_block < blocks_kept {
+                let length = blocks_kept - extent.file_block;
+                self.free_run(extent.block + length, extent.length - length).await?;
+                kept.push(Extent { length, ..extent });
+            } else {
+                self.free_run(extent.block, extent.length).await?;
+            }
+        }
+        //the old nodes are enough for what is kept, so this doesn't allocate
+        let depth = if to_inline {
+            for node in nodes {
+                self.free_block(node).await?;
+            }
+            0
+        } else {
+            self.write_extent_tree(area, kept, nodes).await?
+        };
+        self.write_sectors(inode_block as usize * 8 + 1, 7, &[area_frame]).await;
+        inode_data.size.set_ptr_levels(depth as u64);
+        Ok(())
+    }
+
+    ///Frees all blocks of an inode without links, and removes it from the inode tree and bitmask
+    ///file lock must be held
+    async fn reclaim_inode(&self, inode_index: u32) -> Result<(), ErrorCode> {
+        let inode_block_index = self.find_inode_block(inode_index as InodeIndex).await?;
+        let (inode_block, inode_block_binding) = get_working_block();
+        self.read_or_release(inode_block_index as usize * 8, 1, inode_block, inode_block_binding)
+            .await?;
+        let inode_data: &mut Inode = unsafe { get_at_virtual_addr(inode_block_binding) };
+        let levels = inode_data.size.ptr_levels() as u32;
+        let blocks = inode_data.size.size().div_ceil(4096);
+        let extents = inode_data.size.extents();
+        let index_root = inode_data.size.indexed().then_some(inode_data.index_root);
+        unsafe { PAGE_TREE_ALLOCATOR.deallocate(inode_block_binding) };
+
+        if let Some(index_root) = index_root {
+            let inode_lock = self.inode_lock.lock().await;
+            let freed = BtreeNode::free_tree(index_root, self).await;
+            drop(inode_lock);
+            freed?;
+        }
+        if extents {
+            self.decrease_extent_file_size(inode_block_index, 0).await?;
+        } else {
+            self.free_data_blocks(inode_block_index, levels, 0, blocks).await?;
+        }
+        self.free_block(inode_block_index).await?;
+
+        let inode_lock = self.inode_lock.lock().await;
+        let removed = unsafe { self.remove_inode_entry(inode_index).await };
+        drop(inode_lock);
+        removed?;
+        lock_w_info!(self.file_locks).remove(&inode_index);
+        Ok(())
+    }
+
+    /// Safety
+    /// must hold inode tree lock
+    async unsafe fn remove_inode_entry(&self, inode_index: u32) -> Result<(), ErrorCode> {
+        let root = unsafe { self.get_node(self.root_block).await? }.1;
+        if let Some(new_root) = BtreeNode::delete_key_root(root, self.root_block, inode_index, self).await? {
+            unsafe { self.set_root_block(new_root).await? };
+        }
+        unsafe { self.remove_inode_from_bitmask(inode_index).await }
+    }
+
+    ///Frees orphaned inodes that are no longer open
+    async fn reclaim_orphans(&self) {
+        let device = self.partition.partition.device;
+        let closed = lock_w_info!(self.orphans)
+            .iter()
+            .copied()
+            .filter(|index| {
+                !vfs::file::is_open(InodeIdentifier {
+                    device_id: device,
+                    index: *index as InodeIndex,
+                })
+            })
+            .collect::<Vec<_>>();
+        for inode_index in closed {
+            lock_w_info!(self.orphans).remove(&inode_index);
+            let file_lock = self.get_file_lock(inode_index);
+            let _write_guard = file_lock.lock_write().await;
+            if let Err(e) = self.reclaim_inode(inode_index).await {
+                printlnc!((255, 0, 0), "rfs: failed to free unlinked inode {}: {:?}", inode_index, e);
+            }
+        }
+    }
+
+    async unsafe fn read_locked(
+        &self,
+        inode: InodeIndex,
+        offset_bytes: u64,
+        size_bytes: u64,
+        buffer: &[PhysAddr],
+    ) -> Result<u64, ErrorCode> {
+        if size_bytes == 0 {
+            return Ok(0);
+        }
+        assert!(buffer.len() == (size_bytes).div_ceil(4096) as usize);
+        assert!(offset_bytes % 4096 == 0);
+
+        //file inode lock is held, so file won't move
+        let inode_block_index = self.find_inode_block(inode).await?;
+        let (inode_block, inode_block_binding) = get_working_block();
+        self.read_or_release(inode_block_index as usize * 8, 1, inode_block, inode_block_binding)
+            .await?;
+        let inode_data: &mut Inode = unsafe { get_at_virtual_addr(inode_block_binding) };
+
+        if offset_bytes >= inode_data.size.size() {
+            unsafe { PAGE_TREE_ALLOCATOR.deallocate(inode_block_binding) };
+            return Ok(0);
+        }
+        let max_size = inode_data.size.size() - offset_bytes;
+        let size_bytes = u64::min(size_bytes, max_size);
+        let aligned_size = size_bytes.div_ceil(4096) * 4096;
+        let ret_size = u64::min(max_size, aligned_size);
+
+        if aligned_size == 0 {
+            unsafe { PAGE_TREE_ALLOCATOR.deallocate(inode_block_binding) };
+            return Ok(0);
+        }
+
+        let levels = inode_data.size.ptr_levels();
+        if inode_data.size.is_inline() {
+            let read = self.read_sectors(inode_block_index as usize * 8 + 1, 7, buffer).await;
+            unsafe { PAGE_TREE_ALLOCATOR.deallocate(inode_block_binding) };
+            return read.map(|_| ret_size);
+        }
+        let first_block = offset_bytes / 4096;
+        if inode_data.size.extents() {
+            let end_block = first_block + aligned_size / 4096;
+            let extents = match self
+                .find_extents(inode_block_index, levels as u32, first_block as u32, end_block as u32)
+                .await
+            {
+                Ok(extents) => extents,
+                Err(e) => {
+                    unsafe { PAGE_TREE_ALLOCATOR.deallocate(inode_block_binding) };
+                    return Err(e);
+                }
+            };
+            //one command per extent, as long as the disk takes it
+            for extent in extents {
+                for done in (0..extent.length).step_by(MAX_RUN_BLOCKS as usize) {
+                    let count = u32::min(MAX_RUN_BLOCKS, extent.length - done);
+                    let buf_index = (extent.file_block + done) as usize - first_block as usize;
+                    self.partition
+                        .read(
+                            (extent.block + done) as usize * BLOCK_SIZE_SECTORS,
+                            count as usize * BLOCK_SIZE_SECTORS,
+                            &buffer[buf_index..buf_index + count as usize],
+                        )
+                        .await;
+                }
+            }
+            unsafe { PAGE_TREE_ALLOCATOR.deallocate(inode_block_binding) };
+            return Ok(ret_size);
+        }
+        let data_blocks = match self
+            .find_data_blocks(
+                inode_block_index,
+                levels as u32,
+                first_block,
+                first_block + aligned_size / 4096,
+            )
+            .await
+        {
+            Ok(data_blocks) => data_blocks,
+            Err(e) => {
+                unsafe { PAGE_TREE_ALLOCATOR.deallocate(inode_block_binding) };
+                return Err(e);
+            }
+        };
+        for (buf_index, data_block) in data_blocks.iter().enumerate() {
             self.partition
                 .read(
-                    pointers[i] as usize * BLOCK_SIZE_SECTORS,
+                    *data_block as usize * BLOCK_SIZE_SECTORS,
                     BLOCK_SIZE_SECTORS,
                     &buffer[buf_index..=buf_index],
                 )
                 .await;
         }
         unsafe { PAGE_TREE_ALLOCATOR.deallocate(inode_block_binding) };
-        ret_size
+        Ok(ret_size)
     }
 
-    pub async fn write_locked(&self, inode: InodeIndex, offset: u64, size: u64, buffer: &[PhysAddr]) -> (vfs::Inode, u64) {
-        let inode = inode as u32;
+    pub async fn write_locked(
+        &self,
+        inode: InodeIndex,
+        offset: u64,
+        size: u64,
+        buffer: &[PhysAddr],
+    ) -> Result<(vfs::Inode, u64), ErrorCode> {
         assert!(offset % 4096 == 0);
         assert!(size.div_ceil(4096) <= buffer.len() as u64);
         //get info about file currently
 
-        let inode_lock = self.inode_lock.lock().await;
-
-        let root = unsafe { self.get_node(self.root_block).await.1 };
-        let inode_block_index = BtreeNode::find_inode_block(root, inode, self).await.unwrap();
-        drop(inode_lock); //file lock is held, so file won't move. Found the block
+        //file lock is held, so file won't move
+        let inode_block_index = self.find_inode_block(inode).await?;
+        let inode = inode as u32;
         let (inode_block, inode_block_binding) = get_working_block();
-        self.partition.read(inode_block_index as usize * 8, 8, &[inode_block]).await;
+        self.read_or_release(inode_block_index as usize * 8, 8, inode_block, inode_block_binding)
+            .await?;
         let inode_data: &mut Inode = unsafe { get_at_virtual_addr(inode_block_binding) };
 
         let size_curr = inode_data.size.size();
         let size_new = u64::max(offset + size, size_curr);
         if size_new > size_curr {
-            self.increase_file_size(inode_block_binding, inode_block, inode_block_index, size_new)
+            let increased = self
+                .increase_file_size(inode_block_binding, inode_block, inode_block_index, size_new)
                 .await;
+            if let Err(e) = increased {
+                unsafe { PAGE_TREE_ALLOCATOR.deallocate(inode_block_binding) };
+                return Err(e);
+            }
         }
 
-        self.partition
-            .read(inode_block_index as usize * BLOCK_SIZE_SECTORS, 8, &[inode_block])
-            .await;
+        self.read_or_release(
+            inode_block_index as usize * BLOCK_SIZE_SECTORS,
+            8,
+            inode_block,
+            inode_block_binding,
+        )
+        .await?;
         //create a new reference to avoid rustc optimization issues. This is really a no-op anyway
         let inode_data: &mut Inode = unsafe { get_at_virtual_addr(inode_block_binding) };
+        Touch::Modify.apply(inode_data, times::now());
+        self.write_sectors(inode_block_index as usize * 8, 1, &[inode_block]).await;
 
-        let vfs_inode = inode_data.to_vfs(inode, &self.partition.partition);
-
-        let mut levels = inode_data.size.ptr_levels();
+        let vfs_inode = inode_to_vfs(inode_data, inode, &self.partition.partition);
 
-        //Root now contains 1 pointer, to possibly data, table of pointers or a single pointer
-        //to... if increased by >1 level
+        let levels = inode_data.size.ptr_levels();
 
-        if levels == 0 {
+        if inode_data.size.is_inline() {
             assert!(size <= 512 * 7);
-            self.partition.write(inode_block_index as usize * 8 + 1, 7, buffer).await;
-            self.partition.write(inode_block_index as usize * 8, 1, &[inode_block]).await;
+            self.write_sectors(inode_block_index as usize * 8 + 1, 7, buffer).await;
             unsafe { PAGE_TREE_ALLOCATOR.deallocate(inode_block_binding) };
-            return (vfs_inode, size);
-        }
-
-        let mut pointers: Vec<u32> = std::Vec::new();
-        for i in (0..(512 * 7)).step_by(4) {
-            pointers.push(unsafe { *get_at_virtual_addr(inode_block_binding + 512 + i) });
+            return Ok((vfs_inode, size));
         }
 
         let aligned_size = size.div_ceil(4096) * 4096;
-
-        let mut first_ptr = 0;
-        while levels > 1 {
-            let ptr_span = 4096 + (levels - 1) * 1024;
-            let first_relevant = (offset - first_ptr) / ptr_span;
-            let last_relevant = (offset + aligned_size - 1 - first_ptr) / ptr_span;
-            first_ptr += first_relevant * ptr_span;
-
-            let mut new_pointers =
-                std::Vec::with_capacity((pointers.len() - (last_relevant - first_relevant + 1) as usize) * 1024);
-            for i in first_relevant..=last_relevant {
-                self.partition
-                    .read(pointers[i as usize] as usize * BLOCK_SIZE_SECTORS, 8, &[inode_block])
+        let first_block = offset / 4096;
+        if inode_data.size.extents() {
+            let end_block = first_block + aligned_size / 4096;
+            let extents = match self
+                .find_extents(inode_block_index, levels as u32, first_block as u32, end_block as u32)
+                .await
+            {
+                Ok(extents) => extents,
+                Err(e) => {
+                    unsafe { PAGE_TREE_ALLOCATOR.deallocate(inode_block_binding) };
+                    return Err(e);
+                }
+            };
+            for extent in extents {
+                for done in (0..extent.length).step_by(MAX_RUN_BLOCKS as usize) {
+                    let count = u32::min(MAX_RUN_BLOCKS, extent.length - done);
+                    let buffer_index = (extent.file_block + done) as usize - first_block as usize;
+                    self.write_data(
+                        (extent.block + done) as usize * BLOCK_SIZE_SECTORS,
+                        count as usize * BLOCK_SIZE_SECTORS,
+                        &buffer[buffer_index..buffer_index + count as usize],
+                    )
                     .await;
-                for i in 0..1024 {
-                    new_pointers.push(unsafe { *get_at_virtual_addr(inode_block_binding + i * 4) });
                 }
             }
-            pointers = new_pointers;
-            levels -= 1;
+            unsafe { PAGE_TREE_ALLOCATOR.deallocate(inode_block_binding) };
+            return Ok((vfs_inode, size));
         }
+        let data_blocks = match self
+            .find_data_blocks(
+                inode_block_index,
+                levels as u32,
+                first_block,
+                first_block + aligned_size / 4096,
+            )
+            .await
+        {
+            Ok(data_blocks) => data_blocks,
+            Err(e) => {
+                unsafe { PAGE_TREE_ALLOCATOR.deallocate(inode_block_binding) };
+                return Err(e);
+            }
+        };
+        for (buffer_index, data_block) in data_blocks.iter().enumerate() {
+            self.write_data(
+                *data_block as usize * BLOCK_SIZE_SECTORS,
+                8,
+                &buffer[buffer_index..=buffer_index],
+            )
+            .await;
+        }
+        unsafe { PAGE_TREE_ALLOCATOR.deallocate(inode_block_binding) };
 
-        //with write to whole blocks we do not repsect size
+        Ok((vfs_inode, size))
+    }
 
-        //At this point each pointer points to a 4k region
-        let first_relevant = (offset - first_ptr) / 4096;
-        let last_relevant = (offset + aligned_size - 1 - first_ptr) / 4096;
-        for i in first_relevant..=last_relevant {
-            let i = i as usize;
-            let buffer_index = i - first_relevant as usize;
-            self.partition
-                .write(
-                    pointers[i] as usize * BLOCK_SIZE_SECTORS,
-                    8,
-                    &buffer[buffer_index..=buffer_index],
-                )
-                .await;
+    ///Adds a directory entry, the caller is responsible for the link count of the inode
+    ///file lock of the directory must be held
+    async fn link_locked(
+        &self,
+        inode_index: InodeIndex,
+        parent_inode_index: InodeIndex,
+        name: &str,
+    ) -> Result<vfs::Inode, ErrorCode> {
+        let parent_inode_block_index = self.find_inode_block(parent_inode_index).await?;
+        let (dir_size, index_root) = self.dir_info(parent_inode_block_index).await?;
+        let position = dir_size / ENTRY_SIZE;
+        let dir_entry = DirEntry::new(inode_index as u32, name.as_bytes());
+        let vfs_inode = self.write_entry(parent_inode_index, position, &dir_entry).await?;
+        match index_root {
+            Some(index_root) => {
+                self.index_insert(parent_inode_block_index, index_root, name.as_bytes(), position)
+                    .await?;
+            }
+            None if position + 1 >= INDEXED_DIR_ENTRIES => {
+                self.build_dir_index(parent_inode_index, parent_inode_block_index, dir_size + ENTRY_SIZE)
+                    .await?;
+            }
+            None => {}
         }
+        Ok(vfs_inode)
+    }
+
+    ///Sets the times of the inode to now
+    ///the inode must not move, a transaction or the file lock is enough
+    async fn touch(&self, inode_block_index: u32, touch: Touch) -> Result<(), ErrorCode> {
+        let (inode_block, inode_block_binding) = get_working_block();
+        self.read_or_release(inode_block_index as usize * 8, 1, inode_block, inode_block_binding)
+            .await?;
+        let inode_data: &mut Inode = unsafe { get_at_virtual_addr(inode_block_binding) };
+        touch.apply(inode_data, times::now());
+        self.write_sectors(inode_block_index as usize * 8, 1, &[inode_block]).await;
         unsafe { PAGE_TREE_ALLOCATOR.deallocate(inode_block_binding) };
+        Ok(())
+    }
 
-        (vfs_inode, size)
+    ///Updates the access time after a read if `rfs.atime` asks for it. Read only mounts are left alone
+    ///file lock must not be held, this may start a transaction
+    async fn update_access_time(&self, inode: InodeIndex) -> Result<(), ErrorCode> {
+        let mode = times::atime_mode();
+        if mode == AtimeMode::Off || vfs::is_read_only(self.partition.partition.device) {
+            return Ok(());
+        }
+        //checked without the journal so most reads don't wait for it, a race only costs a write
+        if !mode.needs_update(&self.stat(inode).await?, times::now()) {
+            return Ok(());
+        }
+        self.transaction(async {
+            let inode_block_index = self.find_inode_block(inode).await?;
+            self.touch(inode_block_index, Touch::Access).await
+        })
+        .await
     }
 
-    async fn link_locked(&self, inode_index: InodeIndex, parent_inode_index: InodeIndex, name: &str) -> vfs::Inode {
-        //TODO: i don't increase link count ??
+    ///Moves the size of a file towards `size` by at most MAX_TRANSACTION_BLOCKS blocks, as one
+    ///part of a truncate. A file already at least `size` long is left alone if `extend_only` is
+    ///set. Returns whether the file got there
+    ///journal must be held
+    async fn truncate_part(&self, inode: InodeIndex, size: u64, extend_only: bool) -> Result<bool, ErrorCode> {
+        let inode_block_index = self.find_inode_block(inode).await?;
+        let file_lock = self.get_file_lock(inode as u32);
+        let _write_guard = file_lock.lock_write().await;
 
-        let inode_lock = self.inode_lock.lock().await;
+        let (inode_block, inode_block_binding) = get_working_block();
+        self.read_or_release(inode_block_index as usize * 8, 1, inode_block, inode_block_binding)
+            .await?;
+        let inode_data: &mut Inode = unsafe { get_at_virtual_addr(inode_block_binding) };
+        if inode_data.inode_type_mode.is_dir() {
+            unsafe { PAGE_TREE_ALLOCATOR.deallocate(inode_block_binding) };
+            return Err(ErrorCode::IsDirectory);
+        }
+        //extents count blocks in 32 bits
+        let too_big = if inode_data.size.extents() {
+            size.div_ceil(4096) > u32::MAX as u64
+        } else {
+            ptr_levels_for(size) > 3
+        };
+        if too_big {
+            unsafe { PAGE_TREE_ALLOCATOR.deallocate(inode_block_binding) };
+            return Err(ErrorCode::InvalidArgument);
+        }
+        let size_old = inode_data.size.size();
+        if extend_only && size <= size_old {
+            unsafe { PAGE_TREE_ALLOCATOR.deallocate(inode_block_binding) };
+            return Ok(true);
+        }
+        let blocks_old = size_old.div_ceil(4096);
+        let part_size = if size < size_old {
+            u64::max(size, blocks_old.saturating_sub(MAX_TRANSACTION_BLOCKS) * 4096)
+        } else {
+            u64::min(size, (blocks_old + MAX_TRANSACTION_BLOCKS) * 4096)
+        };
 
-        let root = unsafe { self.get_node(self.root_block).await.1 };
-        let (working_block, working_block_binding) = get_working_block();
-        let parent_inode_block_index = BtreeNode::find_inode_block(root, parent_inode_index as u32, self)
+        let resized = if part_size < size_old {
+            self.shrink_part(inode, inode_block_index, part_size).await
+        } else if part_size > size_old {
+            self.grow_part(
+                inode,
+                inode_block_binding,
+                inode_block,
+                inode_block_index,
+                size_old,
+                part_size,
+            )
             .await
-            .unwrap();
-        drop(inode_lock); //file lock is held, so file won't move. Found the block
-        self.partition
-            .read(parent_inode_block_index as usize * BLOCK_SIZE_SECTORS, 1, &[working_block])
-            .await;
-        let inode_data: &mut Inode = unsafe { get_at_virtual_addr(working_block_binding) };
-        let offset = inode_data.size.size();
-
-        let needs_second_block = (offset + core::mem::size_of::<DirEntry>() as u64) % 4096 < (offset % 4096);
-        let (second_block, second_block_binding);
-        if needs_second_block {
-            (second_block, second_block_binding) = get_working_block();
         } else {
-            second_block = PhysAddr(0);
-            second_block_binding = VirtAddr(0);
+            Ok(())
+        };
+        let resized = match resized {
+            Ok(()) if part_size != size_old => self.touch(inode_block_index, Touch::Modify).await,
+            resized => resized,
+        };
+        drop(_write_guard);
+        unsafe { PAGE_TREE_ALLOCATOR.deallocate(inode_block_binding) };
+        resized.map(|()| part_size == size)
+    }
+
+    ///The shrinking half of truncate_part
+    ///file lock must be held
+    async fn shrink_part(&self, inode: InodeIndex, inode_block_index: u32, part_size: u64) -> Result<(), ErrorCode> {
+        self.decrease_file_size(inode_block_index, part_size).await?;
+        self.zero_block_tail(inode, part_size).await
+    }
+
+    ///The growing half of truncate_part, the inode is in the given frame
+    ///file lock must be held
+    async fn grow_part(
+        &self,
+        inode: InodeIndex,
+        inode_block_binding: VirtAddr,
+        inode_block: PhysAddr,
+        inode_block_index: u32,
+        size_old: u64,
+        part_size: u64,
+    ) -> Result<(), ErrorCode> {
+        self.increase_file_size(inode_block_binding, inode_block, inode_block_index, part_size)
+            .await?;
+        //the old tail and new blocks may hold data of deleted files
+        self.zero_block_tail(inode, size_old).await?;
+        let blocks_old = size_old.div_ceil(4096);
+        let blocks_new = part_size.div_ceil(4096);
+        if blocks_new > blocks_old {
+            let (zero_block, zero_block_binding) = get_working_block();
+            unsafe { memset_virtual_addr(zero_block_binding, 0, 4096) };
+            let buffer = (blocks_old..blocks_new).map(|_| zero_block).collect::<Vec<_>>();
+            let written = self
+                .write_locked(inode, blocks_old * 4096, part_size - blocks_old * 4096, &buffer)
+                .await;
+            unsafe { PAGE_TREE_ALLOCATOR.deallocate(zero_block_binding) };
+            written?;
         }
+        Ok(())
+    }
 
-        if offset % 4096 != 0 {
-            unsafe {
-                self.read_locked(
-                    parent_inode_index,
-                    offset & (!0xFFF),
-                    u64::min(4096, inode_data.size.size()),
-                    &[working_block],
-                )
+    ///Size of a directory and the root of its name index, if it has one
+    async fn dir_info(&self, dir_block: u32) -> Result<(u64, Option<u32>), ErrorCode> {
+        let (working_block, working_block_binding) = get_working_block();
+        self.read_or_release(dir_block as usize * 8, 1, working_block, working_block_binding)
+            .await?;
+        let inode_data = unsafe { get_at_virtual_addr::<Inode>(working_block_binding) };
+        let info = (
+            inode_data.size.size(),
+            inode_data.size.indexed().then_some(inode_data.index_root),
+        );
+        unsafe { PAGE_TREE_ALLOCATOR.deallocate(working_block_binding) };
+        Ok(info)
+    }
+
+    async fn set_dir_index_root(&self, dir_block: u32, index_root: u32) -> Result<(), ErrorCode> {
+        let (working_block, working_block_binding) = get_working_block();
+        self.read_or_release(dir_block as usize * 8, 1, working_block, working_block_binding)
+            .await?;
+        let inode_data = unsafe { get_at_virtual_addr::<Inode>(working_block_binding) };
+        inode_data.size.set_indexed(true);
+        inode_data.index_root = index_root;
+        self.write_sectors(dir_block as usize * 8, 1, &[working_block]).await;
+        unsafe { PAGE_TREE_ALLOCATOR.deallocate(working_block_binding) };
+        Ok(())
+    }
+
+    ///Maps zeroed frames for the blocks holding bytes `start..end` of a file
+    fn map_range(start: u64, end: u64) -> (Box<[PhysAddr]>, VirtAddr) {
+        let frames = (start / 4096..end.div_ceil(4096))
+            .map(|_| physical_allocator::allocate_frame())
+            .collect::<Box<[_]>>();
+        let binding = unsafe { PAGE_TREE_ALLOCATOR.mmap_contigious(&frames, false) };
+        unsafe { memset_virtual_addr(binding, 0, frames.len() * 4096) };
+        (frames, binding)
+    }
+
+    fn unmap_range(frames: &[PhysAddr], binding: VirtAddr) {
+        for i in 0..frames.len() as u64 {
+            unsafe { PAGE_TREE_ALLOCATOR.deallocate(binding + i * 4096) };
+        }
+    }
+
+    ///file lock must be held
+    async fn read_entry(&self, dir: InodeIndex, position: u64) -> Result<DirEntry, ErrorCode> {
+        let start = position * ENTRY_SIZE;
+        let (frames, binding) = Self::map_range(start, start + ENTRY_SIZE);
+        let read = unsafe {
+            self.read_locked(dir, start & !0xFFF, frames.len() as u64 * 4096, &frames)
                 .await
-            };
+        };
+        let dir_entry = unsafe { get_at_virtual_addr::<DirEntry>(binding + start % 4096) }.clone();
+        Self::unmap_range(&frames, binding);
+        read.map(|_| dir_entry)
+    }
+
+    ///Writes the entry at `position`, which may be right after the last one
+    ///file lock must be held
+    async fn write_entry(&self, dir: InodeIndex, position: u64, dir_entry: &DirEntry) -> Result<vfs::Inode, ErrorCode> {
+        let start = position * ENTRY_SIZE;
+        let end = start + ENTRY_SIZE;
+        let (frames, binding) = Self::map_range(start, end);
+        //the entries around it are written back as they are
+        let read = unsafe {
+            self.read_locked(dir, start & !0xFFF, frames.len() as u64 * 4096, &frames)
+                .await
+        };
+        let written = match read {
+            Ok(_) => {
+                unsafe { set_at_virtual_addr(binding + start % 4096, dir_entry.clone()) };
+                //writing up to the end of the entry lets small directories stay in the inode block
+                self.write_locked(dir, start & !0xFFF, end - (start & !0xFFF), &frames).await
+            }
+            Err(e) => Err(e),
+        };
+        Self::unmap_range(&frames, binding);
+        written.map(|(vfs_inode, _)| vfs_inode)
+    }
+
+    ///file lock must be held
+    async fn read_entries(&self, dir: InodeIndex, dir_size: u64) -> Result<Vec<DirEntry>, ErrorCode> {
+        if dir_size == 0 {
+            return Ok(Vec::new());
         }
-        let name_bytes = name.as_bytes();
-        let mut name_byte_arr: [u8; 128] = [0; 128];
-        for char in name_bytes.iter().enumerate() {
-            name_byte_arr[char.0] = *char.1;
+        let (frames, binding) = Self::map_range(0, dir_size);
+        let read = unsafe { self.read_locked(dir, 0, dir_size, &frames).await };
+        let entries = (0..dir_size / ENTRY_SIZE)
+            .map(|i| unsafe { get_at_virtual_addr::<DirEntry>(binding + i * ENTRY_SIZE) }.clone())
+            .collect();
+        Self::unmap_range(&frames, binding);
+        read.map(|_| entries)
+    }
+
+    ///Position and content of the entry called `name`. With a name index, only the entries
+    ///stored under the hashes tried are read
+    ///file lock must be held
+    async fn find_entry(&self, dir: InodeIndex, dir_block: u32, name: &str) -> Result<Option<(u64, DirEntry)>, ErrorCode> {
+        let name = name.as_bytes();
+        let (dir_size, index_root) = self.dir_info(dir_block).await?;
+        let Some(index_root) = index_root else {
+            return Ok(self
+                .read_entries(dir, dir_size)
+                .await?
+                .into_iter()
+                .enumerate()
+                .find(|(_, dir_entry)| dir_entry.name() == name)
+                .map(|(position, dir_entry)| (position as u64, dir_entry)));
+        };
+        let mut hash = name_hash(name);
+        while let Some(position) = self.index_get(index_root, hash).await? {
+            let dir_entry = self.read_entry(dir, position).await?;
+            if dir_entry.name() == name {
+                return Ok(Some((position, dir_entry)));
+            }
+            hash = next_hash(hash);
+        }
+        Ok(None)
+    }
+
+    ///Gives a directory a name index holding all of its entries
+    ///file lock must be held
+    async fn build_dir_index(&self, dir: InodeIndex, dir_block: u32, dir_size: u64) -> Result<(), ErrorCode> {
+        let mut index_root = self.allocate_block().await?;
+        let inode_lock = self.inode_lock.lock().await;
+        unsafe { self.add_node(index_root, BtreeNode::new()) };
+        drop(inode_lock);
+        self.set_dir_index_root(dir_block, index_root).await?;
+        for (position, dir_entry) in self.read_entries(dir, dir_size).await?.iter().enumerate() {
+            index_root = self
+                .index_insert(dir_block, index_root, dir_entry.name(), position as u64)
+                .await?;
         }
-        let temp_offset = offset & 0xFFF;
-        let dir_entry = DirEntry {
-            inode: inode_index as u32,
-            name: name_byte_arr,
+        Ok(())
+    }
+
+    ///Position of the entry stored under a hash in a name index
+    async fn index_get(&self, index_root: u32, hash: u32) -> Result<Option<u64>, ErrorCode> {
+        let inode_lock = self.inode_lock.lock().await;
+        let position = match unsafe { self.get_node(index_root).await } {
+            Ok(root) => BtreeNode::find_inode_block(root.1, hash, self).await,
+            Err(e) => Err(e),
         };
-        let dir_entry_bytes = &dir_entry as *const DirEntry as *const u8;
+        drop(inode_lock);
+        Ok(position?.map(u64::from))
+    }
 
-        for i in 0..core::mem::size_of::<DirEntry>() {
-            let new_off = temp_offset + i as u64;
-            if new_off < 4096 {
-                unsafe { set_at_virtual_addr(working_block_binding + new_off, dir_entry_bytes.add(i).read()) }
-            } else {
-                assert!(needs_second_block);
-                unsafe { set_at_virtual_addr(second_block_binding + new_off % 4096, dir_entry_bytes.add(i).read()) }
+    ///Adds the entry at `position` under the first free hash, starting at the one of its name.
+    ///Returns the root of the index, which changes when the root is split
+    async fn index_insert(&self, dir_block: u32, index_root: u32, name: &[u8], position: u64) -> Result<u32, ErrorCode> {
+        let mut hash = name_hash(name);
+        while self.index_get(index_root, hash).await?.is_some() {
+            hash = next_hash(hash);
+        }
+        let key = Key {
+            index: hash,
+            inode_block: position as u32,
+        };
+        let inode_lock = self.inode_lock.lock().await;
+        let new_root = match unsafe { self.get_node(index_root).await } {
+            Ok(root) => BtreeNode::insert_key_root(root.1, index_root, key, self).await,
+            Err(e) => Err(e),
+        };
+        drop(inode_lock);
+        match new_root? {
+            Some(new_root) => {
+                self.set_dir_index_root(dir_block, new_root).await?;
+                Ok(new_root)
             }
+            None => Ok(index_root),
         }
+    }
 
-        let write_size = if needs_second_block {
-            8192
-        } else {
-            //could do 4096, but this allows small dirs do have their content in the same block as
-            //the inode. This is unneeded for anything over 7 sectors, so
-            offset + core::mem::size_of::<DirEntry>() as u64
-        };
-        let buffers: &[PhysAddr] = if needs_second_block {
-            &[working_block, second_block]
-        } else {
-            &[working_block]
+    ///Returns the root of the index, which changes when the root is merged
+    async fn index_delete(&self, dir_block: u32, index_root: u32, hash: u32) -> Result<u32, ErrorCode> {
+        let inode_lock = self.inode_lock.lock().await;
+        let new_root = match unsafe { self.get_node(index_root).await } {
+            Ok(root) => BtreeNode::delete_key_root(root.1, index_root, hash, self).await,
+            Err(e) => Err(e),
         };
-        let vfs_inode = self
-            .write_locked(parent_inode_index, offset & (!0xFFF), write_size, buffers)
-            .await;
+        drop(inode_lock);
+        match new_root? {
+            Some(new_root) => {
+                self.set_dir_index_root(dir_block, new_root).await?;
+                Ok(new_root)
+            }
+            None => Ok(index_root),
+        }
+    }
 
-        if needs_second_block {
-            unsafe { PAGE_TREE_ALLOCATOR.deallocate(second_block_binding) };
+    ///Removes the entry at `position` from the name index. The entries under the hashes right
+    ///after it are added again, a search stopping at the gap wouldn't find them otherwise
+    ///file lock must be held
+    async fn index_remove(
+        &self,
+        dir: InodeIndex,
+        dir_block: u32,
+        mut index_root: u32,
+        name: &[u8],
+        position: u64,
+    ) -> Result<u32, ErrorCode> {
+        let mut hash = name_hash(name);
+        loop {
+            match self.index_get(index_root, hash).await? {
+                Some(found) if found == position => break,
+                Some(_) => hash = next_hash(hash),
+                None => return Ok(index_root),
+            }
         }
-        unsafe { PAGE_TREE_ALLOCATOR.deallocate(working_block_binding) };
-        vfs_inode.0
+        index_root = self.index_delete(dir_block, index_root, hash).await?;
+        let mut next = next_hash(hash);
+        while let Some(moved) = self.index_get(index_root, next).await? {
+            index_root = self.index_delete(dir_block, index_root, next).await?;
+            let dir_entry = self.read_entry(dir, moved).await?;
+            index_root = self.index_insert(dir_block, index_root, dir_entry.name(), moved).await?;
+            next = next_hash(next);
+        }
+        Ok(index_root)
+    }
+
+    ///Points the name index at the new position of an entry
+    async fn index_move(&self, index_root: u32, name: &[u8], from: u64, to: u64) -> Result<(), ErrorCode> {
+        let mut hash = name_hash(name);
+        while let Some(found) = self.index_get(index_root, hash).await? {
+            if found == from {
+                let inode_lock = self.inode_lock.lock().await;
+                let moved = BtreeNode::set_inode_block(index_root, hash, to as u32, self).await;
+                drop(inode_lock);
+                return moved.map(|_| ());
+            }
+            hash = next_hash(hash);
+        }
+        Ok(())
     }
 }
 
 #[async_trait::async_trait]
 impl FileSystem for Rfs {
+    fn uses_page_cache(&self) -> bool {
+        true
+    }
+
     async fn unmount(&self) {
+        //fails only after a checksum mismatch, which was reported when it happened
+        let _ = self
+            .transaction(async {
+                self.reclaim_orphans().await;
+                Ok(())
+            })
+            .await;
         self.clean_inode_tree_cache().await;
     }
 
-    async fn read(&self, inode: InodeIndex, offset_bytes: u64, size_bytes: u64, buffer: &[PhysAddr]) -> u64 {
+    async fn read(&self, inode: InodeIndex, offset_bytes: u64, size_bytes: u64, buffer: &[PhysAddr]) -> Result<u64, ErrorCode> {
+        self.find_inode_block(inode).await?;
         let file_lock = self.get_file_lock(inode as u32);
         let _read_guard = file_lock.lock_read().await;
         let bytes_read = unsafe { self.read_locked(inode, offset_bytes, size_bytes, buffer).await };
         drop(_read_guard);
-        bytes_read
+        let bytes_read = bytes_read?;
+        self.check_consistent()?;
+        self.update_access_time(inode).await?;
+        Ok(bytes_read)
     }
 
-    async fn write(&self, inode: InodeIndex, offset: u64, size: u64, buffer: &[PhysAddr]) -> (vfs::Inode, u64) {
-        let file_lock = self.get_file_lock(inode as u32);
-        let _write_guard = file_lock.lock_write().await;
-        let vfs_inode = self.write_locked(inode, offset, size, buffer).await;
-        drop(_write_guard);
-        vfs_inode
+    async fn write(
+        &self,
+        inode: InodeIndex,
+        offset: u64,
+        size: u64,
+        buffer: &[PhysAddr],
+    ) -> Result<(vfs::Inode, u64), ErrorCode> {
+        //a gap before the write is filled first, in parts like truncate
+        if offset > self.stat(inode).await?.size {
+            while !self.transaction(self.truncate_part(inode, offset, true)).await? {}
+        }
+        let mut done = 0;
+        loop {
+            let part_size = u64::min(size - done, MAX_TRANSACTION_BLOCKS * 4096);
+            let part_buffer = &buffer[(done / 4096) as usize..];
+            let (vfs_inode, written) = self
+                .transaction(async {
+                    if self.stat(inode).await?.type_mode.is_dir() {
+                        return Err(ErrorCode::IsDirectory);
+                    }
+                    let file_lock = self.get_file_lock(inode as u32);
+                    let _write_guard = file_lock.lock_write().await;
+                    let written = self.write_locked(inode, offset + done, part_size, part_buffer).await;
+                    drop(_write_guard);
+                    written
+                })
+                .await?;
+            done += written;
+            if done == size {
+                return Ok((vfs_inode, done));
+            }
+        }
     }
 
-    async fn stat(&self, inode: InodeIndex) -> crate::vfs::Inode {
+    async fn stat(&self, inode: InodeIndex) -> Result<crate::vfs::Inode, ErrorCode> {
+        let inode_block_index = self.find_inode_block(inode).await?;
         let inode = inode as u32;
-        let inode_lock = self.inode_lock.lock().await;
-        let root = unsafe { self.get_node(self.root_block).await.1 };
-        let inode_block_index = BtreeNode::find_inode_block(root, inode, self).await.unwrap();
-        drop(inode_lock);
         let (inode_block, inode_block_binding) = get_working_block();
         //no need to get file lock since this doesn't move
-        self.partition.read(inode_block_index as usize * 8, 1, &[inode_block]).await;
+        self.read_or_release(inode_block_index as usize * 8, 1, inode_block, inode_block_binding)
+            .await?;
         let inode_data: &mut Inode = unsafe { get_at_virtual_addr(inode_block_binding) };
-        let vfs_inode = inode_data.to_vfs(inode, &self.partition.partition);
+        let vfs_inode = inode_to_vfs(inode_data, inode, &self.partition.partition);
         unsafe { PAGE_TREE_ALLOCATOR.deallocate(inode_block_binding) };
-        vfs_inode
+        self.check_consistent()?;
+        Ok(vfs_inode)
     }
 
-    async fn set_stat(&self, inode_index: InodeIndex, vfs_inode_data: vfs::Inode) {
-        let inode_lock = self.inode_lock.lock().await;
-        let root = unsafe { self.get_node(self.root_block).await.1 };
-        let inode_block_index = BtreeNode::find_inode_block(root, inode_index as u32, self).await.unwrap();
-        drop(inode_lock);
-        let (inode_block, inode_block_binding) = get_working_block();
-        self.partition.read(inode_block_index as usize * 8, 1, &[inode_block]).await;
-        let inode_data: &mut Inode = unsafe { get_at_virtual_addr(inode_block_binding) };
-        *inode_data = Inode::from_vfs(vfs_inode_data, inode_data.link_count, InodeSize(inode_data.size.0));
-        //no need to get file lock since this doesn't move
-        self.partition.write(inode_block_index as usize * 8, 1, &[inode_block]).await;
-        unsafe { PAGE_TREE_ALLOCATOR.deallocate(inode_block_binding) };
+    async fn set_stat(&self, inode_index: InodeIndex, vfs_inode_data: vfs::Inode) -> Result<(), ErrorCode> {
+        self.transaction(async {
+            let inode_block_index = self.find_inode_block(inode_index).await?;
+            let (inode_block, inode_block_binding) = get_working_block();
+            self.read_or_release(inode_block_index as usize * 8, 1, inode_block, inode_block_binding)
+                .await?;
+            let inode_data: &mut Inode = unsafe { get_at_virtual_addr(inode_block_binding) };
+            *inode_data = inode_from_vfs(
+                vfs_inode_data,
+                inode_data.link_count,
+                InodeSize::new(inode_data.size.raw()),
+                inode_data.index_root,
+            );
+            Touch::Change.apply(inode_data, times::now());
+            //no need to get file lock since this doesn't move
+            self.write_sectors(inode_block_index as usize * 8, 1, &[inode_block]).await;
+            unsafe { PAGE_TREE_ALLOCATOR.deallocate(inode_block_binding) };
+            Ok(())
+        })
+        .await
     }
 
     async fn create(
@@ -896,168 +2054,289 @@ impl FileSystem for Rfs {
         type_mode: crate::vfs::InodeType,
         uid: u16,
         gid: u16,
-    ) -> (vfs::Inode, vfs::Inode) {
-        let new_inode_block_index = self.allocate_block().await;
-        let inode_lock = self.inode_lock.lock().await;
-        let inode_index = unsafe { self.allocate_inode().await };
-        let inode = Inode {
-            size: InodeSize(0),
-            inode_type_mode: type_mode,
-            link_count: 1,
-            uid,
-            gid,
-            access_time: 0,
-            modification_time: 0,
-            stat_change_time: 0,
-        };
-        let (inode_block, inode_block_binding) = get_working_block();
-        let vfs_inode = inode.to_vfs(inode_index, &self.partition.partition);
-        unsafe { set_at_virtual_addr(inode_block_binding, inode) };
-        self.partition
-            .write(new_inode_block_index as usize * 8, 1, &[inode_block])
-            .await;
-
-        let root = unsafe { self.get_node(self.root_block).await.1 };
-        BtreeNode::insert_key_root(
-            root,
-            self.root_block,
-            Key {
-                index: inode_index,
-                inode_block: new_inode_block_index,
-            },
-            self,
-        )
-        .await;
-        let parent_lock = self.get_file_lock(parent_dir as u32);
-        let new_lock = self.get_file_lock(inode_index);
-        let _parent_guard = parent_lock.lock_write().await;
-        let _new_guard = new_lock.lock_write().await;
-        drop(inode_lock);
-
-        let parent_vfs_inode = self.link_locked(inode_index as InodeIndex, parent_dir, name).await;
-
-        drop(_new_guard);
-        drop(_parent_guard);
+    ) -> Result<(vfs::Inode, vfs::Inode), ErrorCode> {
+        self.transaction(async {
+            check_name(name)?;
+            if !self.stat(parent_dir).await?.type_mode.is_dir() {
+                return Err(ErrorCode::NotDirectory);
+            }
+            let new_inode_block_index = self.allocate_block().await?;
+            let inode_lock = self.inode_lock.lock().await;
+            let inode_index = unsafe { self.allocate_inode().await? };
+            let now = times::now();
+            let inode = Inode {
+                size: new_inode_size(),
+                inode_type_mode: InodeMode(type_mode.bits()),
+                link_count: 1,
+                uid,
+                gid,
+                reserved: 0,
+                access_time: now,
+                modification_time: now,
+                stat_change_time: now,
+                index_root: 0,
+                reserved_tail: 0,
+            };
+            let (inode_block, inode_block_binding) = get_working_block();
+            let vfs_inode = inode_to_vfs(&inode, inode_index, &self.partition.partition);
+            unsafe { set_at_virtual_addr(inode_block_binding, inode) };
+            self.write_sectors(new_inode_block_index as usize * 8, 1, &[inode_block])
+                .await;
+            unsafe { PAGE_TREE_ALLOCATOR.deallocate(inode_block_binding) };
 
-        unsafe { PAGE_TREE_ALLOCATOR.deallocate(inode_block_binding) };
+            let root = unsafe { self.get_node(self.root_block).await? }.1;
+            let new_root = BtreeNode::insert_key_root(
+                root,
+                self.root_block,
+                Key {
+                    index: inode_index,
+                    inode_block: new_inode_block_index,
+                },
+                self,
+            )
+            .await?;
+            if let Some(new_root) = new_root {
+                unsafe { self.set_root_block(new_root).await? };
+            }
+            let parent_lock = self.get_file_lock(parent_dir as u32);
+            let new_lock = self.get_file_lock(inode_index);
+            let _parent_guard = parent_lock.lock_write().await;
+            let _new_guard = new_lock.lock_write().await;
+            drop(inode_lock);
 
-        (vfs_inode, parent_vfs_inode)
-    }
+            let parent_vfs_inode = self.link_locked(inode_index as InodeIndex, parent_dir, name).await?;
 
-    async fn unlink(&self, _parent_inode: InodeIndex, _name: &str) {
-        todo!()
-    }
+            drop(_new_guard);
+            drop(_parent_guard);
 
-    async fn link(&self, inode_index: InodeIndex, parent_inode_index: InodeIndex, name: &str) -> vfs::Inode {
-        let parent_lock = self.get_file_lock(parent_inode_index as u32);
-        let child_lock = self.get_file_lock(inode_index as u32);
-        let _parent_guard = parent_lock.lock_write().await;
-        let _child_guard = child_lock.lock_write().await;
-        let vfs_inode = self.link_locked(inode_index, parent_inode_index, name).await;
-        drop(_child_guard);
-        drop(_parent_guard);
-        vfs_inode
+            Ok((vfs_inode, parent_vfs_inode))
+        })
+        .await
     }
 
-    async fn truncate(&self, _inode: InodeIndex, _size: InodeIndex) {
-        todo!()
+    async fn symlink(
+        &self,
+        name: &str,
+        parent_dir: InodeIndex,
+        target: &str,
+        uid: u16,
+        gid: u16,
+    ) -> Result<(vfs::Inode, vfs::Inode), ErrorCode> {
+        let (inode, parent_vfs_inode) = self.create(name, parent_dir, InodeType::new_symlink(0o777), uid, gid).await?;
+        //the target is stored as the file content
+        let (target_block, target_block_binding) = get_working_block();
+        let len = target.len().min(4096);
+        unsafe {
+            memset_virtual_addr(target_block_binding, 0, 4096);
+            core::ptr::copy_nonoverlapping(target.as_ptr(), target_block_binding.0 as *mut u8, len);
+        }
+        let written = self.write(inode.index, 0, len as u64, &[target_block]).await;
+        unsafe { PAGE_TREE_ALLOCATOR.deallocate(target_block_binding) };
+        Ok((written?.0, parent_vfs_inode))
     }
 
-    async fn rename(&self, inode: InodeIndex, parent_inode: InodeIndex, name: &str) {
-        let inode_lock = self.inode_lock.lock().await;
-
-        let root = unsafe { self.get_node(self.root_block).await.1 };
-        let parent_inode_block_index = BtreeNode::find_inode_block(root, parent_inode as u32, self).await.unwrap();
-        drop(inode_lock); //file lock is held, so file won't move. Found the block
-        let (working_block, working_block_binding) = get_working_block();
-
-        let parent_lock = self.get_file_lock(parent_inode as u32);
-        let _parent_guard = parent_lock.lock_write().await;
-
-        self.partition
-            .read(parent_inode_block_index as usize * 8, 1, &[working_block])
-            .await;
-        let parent_inode_data = unsafe { get_at_virtual_addr::<Inode>(working_block_binding) };
-        let dir_size = parent_inode_data.size.size();
-        let dir_block_count = dir_size.div_ceil(4096);
+    async fn unlink(&self, parent_inode: InodeIndex, name: &str) -> Result<(), ErrorCode> {
+        self.transaction(async {
+            check_name(name)?;
+            let parent_inode_block_index = self.find_inode_block(parent_inode).await?;
+            let parent_lock = self.get_file_lock(parent_inode as u32);
+            let _parent_guard = parent_lock.lock_write().await;
+
+            let (working_block, working_block_binding) = get_working_block();
+            self.read_or_release(parent_inode_block_index as usize * 8, 1, working_block, working_block_binding)
+                .await?;
+            let parent_inode_data = unsafe { get_at_virtual_addr::<Inode>(working_block_binding) };
+            let is_dir = parent_inode_data.inode_type_mode.is_dir();
+            let dir_size = parent_inode_data.size.size();
+            let index_root = parent_inode_data.size.indexed().then_some(parent_inode_data.index_root);
+            if !is_dir || dir_size == 0 {
+                unsafe { PAGE_TREE_ALLOCATOR.deallocate(working_block_binding) };
+                return Err(if is_dir { ErrorCode::NoEntry } else { ErrorCode::NotDirectory });
+            }
+            let found = match self.find_entry(parent_inode, parent_inode_block_index, name).await {
+                Ok(found) => found,
+                Err(e) => {
+                    unsafe { PAGE_TREE_ALLOCATOR.deallocate(working_block_binding) };
+                    return Err(e);
+                }
+            };
+            let Some((position, dir_entry)) = found else {
+                unsafe { PAGE_TREE_ALLOCATOR.deallocate(working_block_binding) };
+                return Err(ErrorCode::NoEntry);
+            };
+            let inode_index = dir_entry.inode;
 
-        let mut frames = Vec::with_capacity(dir_block_count as usize);
-        for _ in 0..dir_block_count {
-            frames.push(physical_allocator::allocate_frame());
-        }
-        let folder_binding = unsafe { PAGE_TREE_ALLOCATOR.mmap_contigious(&frames, false) };
-        for i in 0..dir_block_count {
-            unsafe {
-                PAGE_TREE_ALLOCATOR
-                    .get_page_table_entry_mut(folder_binding + i * 4096)
-                    .unwrap()
-                    .set_pat(LiminePat::UC);
+            let inode_block_index = match self.find_inode_block(inode_index as InodeIndex).await {
+                Ok(inode_block_index) => inode_block_index,
+                Err(e) => {
+                    unsafe { PAGE_TREE_ALLOCATOR.deallocate(working_block_binding) };
+                    return Err(e);
+                }
+            };
+            let file_lock = self.get_file_lock(inode_index);
+            let _file_guard = file_lock.lock_write().await;
+            self.read_or_release(inode_block_index as usize * 8, 1, working_block, working_block_binding)
+                .await?;
+            let inode_data = unsafe { get_at_virtual_addr::<Inode>(working_block_binding) };
+            if inode_data.inode_type_mode.is_dir() && inode_data.size.size() != 0 {
+                unsafe { PAGE_TREE_ALLOCATOR.deallocate(working_block_binding) };
+                return Err(ErrorCode::DirectoryNotEmpty);
             }
-        }
+            inode_data.link_count = inode_data.link_count.saturating_sub(1);
+            Touch::Change.apply(inode_data, times::now());
+            let link_count = inode_data.link_count;
+            self.write_sectors(inode_block_index as usize * 8, 1, &[working_block]).await;
+            unsafe { PAGE_TREE_ALLOCATOR.deallocate(working_block_binding) };
 
-        unsafe { self.read_locked(parent_inode, 0, dir_size, &frames).await };
-        let mut affected_inode = 0;
-        for i in 0..(dir_size / core::mem::size_of::<DirEntry>() as u64) {
-            let dir_entry =
-                unsafe { get_at_virtual_addr::<DirEntry>(folder_binding + i * core::mem::size_of::<DirEntry>() as u64) };
-            if dir_entry.inode == inode as u32 {
-                let name_bytes = name.as_bytes();
-                let mut name_byte_arr: [u8; 128] = [0; 128];
-                for char in name_bytes.iter().enumerate() {
-                    name_byte_arr[char.0] = *char.1;
+            //the last entry takes the place of the removed one
+            let last = dir_size / ENTRY_SIZE - 1;
+            let index_root = match index_root {
+                Some(index_root) => Some(
+                    self.index_remove(parent_inode, parent_inode_block_index, index_root, dir_entry.name(), position)
+                        .await?,
+                ),
+                None => None,
+            };
+            if position != last {
+                let last_entry = self.read_entry(parent_inode, last).await?;
+                self.write_entry(parent_inode, position, &last_entry).await?;
+                if let Some(index_root) = index_root {
+                    self.index_move(index_root, last_entry.name(), last, position).await?;
                 }
-                let mut new_dir_entry = dir_entry.clone();
-                new_dir_entry.name = name_byte_arr;
-                unsafe {
-                    set_at_virtual_addr(folder_binding + i * core::mem::size_of::<DirEntry>() as u64, new_dir_entry);
+            }
+            self.decrease_file_size(parent_inode_block_index, dir_size - ENTRY_SIZE)
+                .await?;
+            self.touch(parent_inode_block_index, Touch::Modify).await?;
+            drop(_parent_guard);
+
+            if link_count == 0 {
+                let inode_identifier = InodeIdentifier {
+                    device_id: self.partition.partition.device,
+                    index: inode_index as InodeIndex,
+                };
+                if vfs::file::is_open(inode_identifier) {
+                    lock_w_info!(self.orphans).insert(inode_index);
+                } else {
+                    self.reclaim_inode(inode_index).await?;
                 }
-                affected_inode = i;
-                break;
             }
-        }
-        let affected_block = affected_inode * core::mem::size_of::<DirEntry>() as u64 / 4096;
-        let next_block_affeted =
-            ((affected_inode + 1) * core::mem::size_of::<DirEntry>() as u64 - 1) / 4096 == affected_block + 1;
-        let write_size = if next_block_affeted { 8192 } else { 4096 };
-        let buffers = if next_block_affeted {
-            &frames[affected_block as usize..(affected_block + 2) as usize]
-        } else {
-            &frames[affected_block as usize..(affected_block + 1) as usize]
-        };
-        self.write(parent_inode, affected_block * 4096, write_size, buffers).await;
-        drop(_parent_guard);
+            drop(_file_guard);
+            self.reclaim_orphans().await;
+            Ok(())
+        })
+        .await
+    }
 
-        for i in 0..dir_block_count {
-            unsafe { PAGE_TREE_ALLOCATOR.deallocate(folder_binding + i * 4096) };
-        }
-        unsafe { PAGE_TREE_ALLOCATOR.deallocate(folder_binding) };
-        unsafe { PAGE_TREE_ALLOCATOR.deallocate(working_block_binding) };
+    async fn link(&self, inode_index: InodeIndex, parent_inode_index: InodeIndex, name: &str) -> Result<vfs::Inode, ErrorCode> {
+        self.transaction(async {
+            check_name(name)?;
+            self.find_inode_block(inode_index).await?;
+            if !self.stat(parent_inode_index).await?.type_mode.is_dir() {
+                return Err(ErrorCode::NotDirectory);
+            }
+            let parent_lock = self.get_file_lock(parent_inode_index as u32);
+            let child_lock = self.get_file_lock(inode_index as u32);
+            let _parent_guard = parent_lock.lock_write().await;
+            let _child_guard = child_lock.lock_write().await;
+            let inode_block_index = self.find_inode_block(inode_index).await?;
+            let (inode_block, inode_block_binding) = get_working_block();
+            self.read_or_release(inode_block_index as usize * 8, 1, inode_block, inode_block_binding)
+                .await?;
+            let inode_data: &mut Inode = unsafe { get_at_virtual_addr(inode_block_binding) };
+            inode_data.link_count += 1;
+            Touch::Change.apply(inode_data, times::now());
+            self.write_sectors(inode_block_index as usize * 8, 1, &[inode_block]).await;
+            unsafe { PAGE_TREE_ALLOCATOR.deallocate(inode_block_binding) };
+            lock_w_info!(self.orphans).remove(&(inode_index as u32));
+            let vfs_inode = self.link_locked(inode_index, parent_inode_index, name).await?;
+            drop(_child_guard);
+            drop(_parent_guard);
+            Ok(vfs_inode)
+        })
+        .await
     }
 
-    async fn read_dir(&self, inode_index: InodeIndex) -> Box<[crate::drivers::disk::DirEntry]> {
-        let inode_lock = self.inode_lock.lock().await;
+    async fn truncate(&self, inode: InodeIndex, size: u64) -> Result<(), ErrorCode> {
+        //every part is a transaction of its own and leaves a complete file behind
+        while !self.transaction(self.truncate_part(inode, size, false)).await? {}
+        Ok(())
+    }
+
+    async fn rename(&self, inode: InodeIndex, parent_inode: InodeIndex, name: &str) -> Result<(), ErrorCode> {
+        self.transaction(async {
+            check_name(name)?;
+            let parent_inode_block_index = self.find_inode_block(parent_inode).await?;
+            let parent_lock = self.get_file_lock(parent_inode as u32);
+            let _parent_guard = parent_lock.lock_write().await;
+
+            //the name index is keyed by name, only a scan finds the entry of an inode
+            let (dir_size, index_root) = self.dir_info(parent_inode_block_index).await?;
+            let entries = self.read_entries(parent_inode, dir_size).await?;
+            let Some(position) = entries.iter().position(|dir_entry| dir_entry.inode == inode as u32) else {
+                return Err(ErrorCode::NoEntry);
+            };
+            let position = position as u64;
+            if let Some(index_root) = index_root {
+                let old_name = entries[position as usize].name();
+                let index_root = self
+                    .index_remove(parent_inode, parent_inode_block_index, index_root, old_name, position)
+                    .await?;
+                self.index_insert(parent_inode_block_index, index_root, name.as_bytes(), position)
+                    .await?;
+            }
+            let dir_entry = DirEntry::new(inode as u32, name.as_bytes());
+            self.write_entry(parent_inode, position, &dir_entry).await?;
+            drop(_parent_guard);
+            let inode_block_index = self.find_inode_block(inode).await?;
+            self.touch(inode_block_index, Touch::Change).await
+        })
+        .await
+    }
 
-        let root = unsafe { self.get_node(self.root_block).await.1 };
-        let inode_block_index = BtreeNode::find_inode_block(root, inode_index as u32, self).await.unwrap();
-        drop(inode_lock); //file lock is held, so file won't move. Found the block
+    async fn lookup(&self, dir: InodeIndex, name: &str) -> Result<InodeIndex, ErrorCode> {
+        check_name(name)?;
+        let dir_block = self.find_inode_block(dir).await?;
+        let dir_lock = self.get_file_lock(dir as u32);
+        let _read_guard = dir_lock.lock_read().await;
+        if !self.stat(dir).await?.type_mode.is_dir() {
+            return Err(ErrorCode::NotDirectory);
+        }
+        let found = self.find_entry(dir, dir_block, name).await;
+        drop(_read_guard);
+        self.check_consistent()?;
+        found?
+            .map(|(_, dir_entry)| dir_entry.inode as InodeIndex)
+            .ok_or(ErrorCode::NoEntry)
+    }
+
+    async fn read_dir(&self, inode_index: InodeIndex) -> Result<Box<[crate::drivers::disk::DirEntry]>, ErrorCode> {
+        let inode_block_index = self.find_inode_block(inode_index).await?;
         let (inode_block, inode_block_binding) = get_working_block();
 
         let file_lock = self.get_file_lock(inode_index as u32);
         let _file_guard = file_lock.lock_read().await;
 
-        self.partition.read(inode_block_index as usize * 8, 1, &[inode_block]).await;
+        self.read_or_release(inode_block_index as usize * 8, 1, inode_block, inode_block_binding)
+            .await?;
         let inode: &mut Inode = unsafe { get_at_virtual_addr(inode_block_binding) };
 
+        if !inode.inode_type_mode.is_dir() {
+            unsafe { PAGE_TREE_ALLOCATOR.deallocate(inode_block_binding) };
+            return Err(ErrorCode::NotDirectory);
+        }
         let needed_blocks = inode.size.size().div_ceil(4096);
         if needed_blocks == 0 {
-            return Box::new([]);
+            unsafe { PAGE_TREE_ALLOCATOR.deallocate(inode_block_binding) };
+            return Ok(Box::new([]));
         }
         let phys_addresses = (0..needed_blocks)
             .map(|_| physical_allocator::allocate_frame())
             .collect::<Box<[_]>>();
         let virt_addr_start = unsafe { PAGE_TREE_ALLOCATOR.mmap_contigious(&phys_addresses, false) };
-        unsafe { self.read_locked(inode_index, 0, inode.size.size(), &phys_addresses).await };
+        unsafe { self.read_locked(inode_index, 0, inode.size.size(), &phys_addresses).await? };
         drop(_file_guard);
+        self.check_consistent()?;
+        self.update_access_time(inode_index).await?;
         let mut entries = Vec::new();
         let mut offset = 0;
         while offset < inode.size.size() {
@@ -1072,6 +2351,13 @@ impl FileSystem for Rfs {
             offset += core::mem::size_of::<DirEntry>() as u64;
         }
 
-        entries.into_boxed_slice()
+        Ok(entries.into_boxed_slice())
+    }
+}
+
+fn check_name(name: &str) -> Result<(), ErrorCode> {
+    if name.len() > MAX_NAME_LEN {
+        return Err(ErrorCode::NameTooLong);
     }
+    Ok(())
 }
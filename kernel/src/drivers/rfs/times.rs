@@ -0,0 +1,89 @@
+//!Inode times. Reads update the access time as the `rfs.atime` kernel argument says: `strict`
+//!on every read, `relatime` (the default) only when it isn't newer than the modification or
+//!change time or is a day old, and `off` never
+
+use core::sync::atomic::{AtomicU8, Ordering};
+use rfs_layout::Inode;
+use std::time::{Instant, UNIX_EPOCH};
+
+use crate::vfs;
+
+const RELATIME_INTERVAL: u32 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum AtimeMode {
+    Off,
+    Relatime,
+    Strict,
+}
+
+impl AtimeMode {
+    ///Value of the `rfs.atime` kernel argument
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "off" => Some(AtimeMode::Off),
+            "relatime" => Some(AtimeMode::Relatime),
+            "strict" => Some(AtimeMode::Strict),
+            _ => None,
+        }
+    }
+
+    ///Whether a read at `now` writes the access time of the inode
+    pub(super) fn needs_update(self, inode: &vfs::Inode, now: u32) -> bool {
+        if inode.access_time == now {
+            return false;
+        }
+        match self {
+            AtimeMode::Off => false,
+            AtimeMode::Relatime => {
+                inode.access_time <= inode.modification_time
+                    || inode.access_time <= inode.stat_change_time
+                    || now.saturating_sub(inode.access_time) >= RELATIME_INTERVAL
+            }
+            AtimeMode::Strict => true,
+        }
+    }
+}
+
+static ATIME_MODE: AtomicU8 = AtomicU8::new(AtimeMode::Relatime as u8);
+
+pub fn set_atime_mode(mode: AtimeMode) {
+    ATIME_MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+pub(super) fn atime_mode() -> AtimeMode {
+    match ATIME_MODE.load(Ordering::Relaxed) {
+        0 => AtimeMode::Off,
+        2 => AtimeMode::Strict,
+        _ => AtimeMode::Relatime,
+    }
+}
+
+pub(super) fn now() -> u32 {
+    Instant::now().duration_since(UNIX_EPOCH).as_secs() as u32
+}
+
+///Which times of an inode an operation sets to now
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Touch {
+    ///The content was read
+    Access,
+    ///The content changed, which is also a change of the inode
+    Modify,
+    ///Only the inode changed: mode, owner, links or name
+    Change,
+}
+
+impl Touch {
+    pub(super) fn apply(self, inode: &mut Inode, now: u32) {
+        match self {
+            Touch::Access => inode.access_time = now,
+            Touch::Modify => {
+                inode.modification_time = now;
+                inode.stat_change_time = now;
+            }
+            Touch::Change => inode.stat_change_time = now,
+        }
+    }
+}
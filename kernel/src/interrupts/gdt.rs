@@ -9,15 +9,20 @@ pub const NMI_IST: u16 = 2;
 pub const MACHINE_CHECK_IST: u16 = 3;
 pub const DEBUG_IST: u16 = 4;
 
+///User segment selectors, RPL included
+pub const USER_CODE_32_SELECTOR: u16 = 0x18 | 0x3;
+pub const USER_DATA_SELECTOR: u16 = 0x20 | 0x3;
+pub const USER_CODE_64_SELECTOR: u16 = 0x28 | 0x3;
+
 pub const KERNEL_STACK_SIZE_BYTES: usize = KERNEL_STACK_SIZE_PAGES as usize * 0x1000;
 pub static mut STATIC_GDT_PTR: TablePointer = TablePointer { limit: 0, base: 0 };
 
-const GDT_LEN: usize = 7;
+const GDT_LEN: usize = 8;
 
 pub fn load_gdt(ptr: TablePointer) {
     unsafe { core::arch::asm!("lgdt [{}]", in(reg) core::ptr::addr_of!(ptr), options(readonly, nostack, preserves_flags)) };
     set_cs();
-    unsafe { core::arch::asm!("mov ax, 0x28", "ltr ax", out("ax") _, options(nostack, preserves_flags, raw)) };
+    unsafe { core::arch::asm!("mov ax, 0x30", "ltr ax", out("ax") _, options(nostack, preserves_flags, raw)) };
 }
 
 #[repr(C, packed)]
@@ -123,17 +128,19 @@ pub struct GlobalDescriptorTable {
 impl GlobalDescriptorTable {
     const fn default() -> Self {
         GlobalDescriptorTable {
-            //user segments are inverted because sysret requres user code to be user data + 8
+            //sysret requires user code 32, user data and user code 64 to be in this order. The
+            //user data segment is flat 32 bit, so it is shared by both modes
             table: [
                 /*0x00*/ create_segment_descriptor(0, 0, 0, 0), //null descriptor
                 /*0x08*/ create_segment_descriptor(0, 0xFFFFF, 0x9A, 0xA), //code segment
                 /*0x10*/ create_segment_descriptor(0, 0xFFFFF, 0x92, 0xC), //data segment
-                /*0x18*/ create_segment_descriptor(0, 0xFFFFF, 0xF2, 0xC), //user data segment
-                /*0x20*/ create_segment_descriptor(0, 0xFFFFF, 0xFA, 0xA), //user code segment
-                /*0x28*/ create_segment_descriptor(0, 0x0, 0x0, 0x0), //TSS segment placeholder
-                /*0x00*/ create_segment_descriptor(0, 0x0, 0x0, 0x0), //TSS segment placeholder
+                /*0x18*/ create_segment_descriptor(0, 0xFFFFF, 0xFA, 0xC), //user code segment (32 bit)
+                /*0x20*/ create_segment_descriptor(0, 0xFFFFF, 0xF2, 0xC), //user data segment
+                /*0x28*/ create_segment_descriptor(0, 0xFFFFF, 0xFA, 0xA), //user code segment
+                /*0x30*/ create_segment_descriptor(0, 0x0, 0x0, 0x0), //TSS segment placeholder
+                /*0x38*/ create_segment_descriptor(0, 0x0, 0x0, 0x0), //TSS segment placeholder
             ],
-            len: 5, //len is 2 shorter to account for tss placeholders
+            len: 6, //len is 2 shorter to account for tss placeholders
         }
     }
 }
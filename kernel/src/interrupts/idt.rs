@@ -1,6 +1,7 @@
 use crate::handler;
 use crate::interrupts::macros::InterruptProcessorState;
 use crate::interrupts::macros::general_interrupt_handler;
+use crate::proc::compat_syscall_interrupt;
 
 use super::gdt::{DEBUG_IST, DOUBLE_FAULT_IST, MACHINE_CHECK_IST, NMI_IST};
 use super::handlers::*;
@@ -133,6 +134,7 @@ impl Idt {
 
         self.set(Entry::new(handler!(apic_timer_tick)), 100);
         self.set(Entry::new(handler!(inter_processor_interrupt)), 101);
+        self.set(Entry::user_callable(handler!(compat_syscall_interrupt)), 0x80);
         self.set(Entry::new(handler!(first_context_switch)), 254);
         self.set(Entry::new(handler!(spurious_interrupt)), 255);
 
@@ -146,6 +148,7 @@ impl Idt {
         //32 + 13 (45) - fpu
         //32 + 14 (46) - ata????
         //67 - apic error
+        //128 (0x80) - 32 bit syscalls
         //253 - inter processor interrupt
         //254 - first context switch
         //255 - spurious interrupt
@@ -192,6 +195,11 @@ impl Entry {
         Self::new_custom(0x8, handler, construct_entry_options(ist_index, false, 0, true))
     }
 
+    ///Can be triggered with int from userspace
+    pub fn user_callable(handler: extern "C" fn() -> !) -> Self {
+        Self::new_custom(0x8, handler, construct_entry_options(0, false, 3, true))
+    }
+
     const fn missing() -> Self {
         Self {
             gdt_selector: 0,
@@ -204,7 +212,7 @@ impl Entry {
     }
 }
 
-//privilige level is always 0 (kernel)
+//privilige level is 0 (kernel) unless the interrupt is callable from userspace
 //always interrupt gate, which clears IF
 //type bits 11 10 9 8
 //
@@ -1,6 +1,6 @@
 use crate::proc::{interrupt_context_switch, save_and_release_current, StackCpuStateData};
 
-use super::{disable_interrupts, enable_interrupts};
+use super::{USER_CODE_32_SELECTOR, USER_CODE_64_SELECTOR, USER_DATA_SELECTOR, disable_interrupts, enable_interrupts};
 
 #[macro_export]
 macro_rules! handler {
@@ -245,7 +245,9 @@ pub struct InterruptFrame {
 }
 
 impl InterruptProcessorState {
-    pub fn new(rip: u64, rsp: u64) -> Self {
+    ///Starting state of a userspace process. 32 bit processes are started in compatibility mode
+    pub fn new(rip: u64, rsp: u64, is_32_bit: bool) -> Self {
+        let cs = if is_32_bit { USER_CODE_32_SELECTOR } else { USER_CODE_64_SELECTOR };
         Self {
             r15: 0,
             r14: 0,
@@ -265,10 +267,10 @@ impl InterruptProcessorState {
             err_code: 0,
             interrupt_frame: InterruptFrame {
                 rip,
-                cs: 0x23,
+                cs: cs as u64,
                 rflags: 0x202,
                 rsp,
-                ss: 0x1B,
+                ss: USER_DATA_SELECTOR as u64,
             },
         }
     }
@@ -1,5 +1,5 @@
 mod gdt;
-pub use gdt::{STATIC_GDT_PTR, create_new_gdt, load_gdt};
+pub use gdt::{STATIC_GDT_PTR, USER_CODE_32_SELECTOR, USER_CODE_64_SELECTOR, USER_DATA_SELECTOR, create_new_gdt, load_gdt};
 use std::{println, printlnc};
 #[macro_use]
 pub mod handlers;
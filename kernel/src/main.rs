@@ -43,9 +43,11 @@ use limine::LIMINE_BOOTLOADER_REQUESTS;
 use task_runner::block_task;
 use vfs::ResolvedPath;
 
-const PRIME_FINDER: &[u8] = include_bytes!("../../assets/prime_finder");
-const TIME_PRINTER: &[u8] = include_bytes!("../../assets/time_printer");
-const FILE_READER: &[u8] = include_bytes!("../../assets/file_reader");
+const PRIME_FINDER: &[u8] = include_bytes!(env!("CARGO_BIN_FILE_PRIME_FINDER_prime_finder"));
+const TIME_PRINTER: &[u8] = include_bytes!(env!("CARGO_BIN_FILE_TIME_PRINTER_time_printer"));
+const FILE_READER: &[u8] = include_bytes!(env!("CARGO_BIN_FILE_FILE_READER_file_reader"));
+#[cfg(feature = "run_tests")]
+const COMPAT_TEST: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/compat_test"));
 
 #[unsafe(no_mangle)]
 extern "C" fn _start() -> ! {
@@ -69,8 +71,29 @@ extern "C" fn _start() -> ! {
 
     interrupts::init_interrupts();
 
-    let cmd_args = cmd_args::CmdArgs::new(str.to_str().expect("Invalid UTF-8 in cmdline"));
+    let cmd_args = match str.to_str() {
+        Ok(str) => cmd_args::CmdArgs::new(str).unwrap_or_else(|e| {
+            println!("Invalid kernel command line: {}, using defaults", e);
+            cmd_args::CmdArgs::default()
+        }),
+        Err(_) => {
+            println!("Kernel command line is not valid UTF-8, using defaults");
+            cmd_args::CmdArgs::default()
+        }
+    };
     println!("cmd_args: {:?}", cmd_args);
+    if let Some(value) = cmd_args.get("rfs.fsck") {
+        match drivers::rfs::MountCheck::parse(value) {
+            Some(check) => drivers::rfs::set_mount_check(check),
+            None => println!("Unknown rfs.fsck mode {}, expected off, check or repair", value),
+        }
+    }
+    if let Some(value) = cmd_args.get("rfs.atime") {
+        match drivers::rfs::AtimeMode::parse(value) {
+            Some(mode) => drivers::rfs::set_atime_mode(mode),
+            None => println!("Unknown rfs.atime mode {}, expected off, relatime or strict", value),
+        }
+    }
 
     acpi::read_tables();
 
@@ -81,14 +104,30 @@ extern "C" fn _start() -> ! {
     pci::enumerate_devices();
     vfs::init();
 
-    let res = block_task(Box::pin(vfs::mount_blkdev_partition(
-        cmd_args.root_partition,
-        ResolvedPath::root(),
-    )));
-    if let Err(e) = res {
-        println!("{}", e);
-        panic!("Failed to mount root partition");
+    let root_mounted = match &cmd_args.root {
+        Some(root) => {
+            let res = block_task(Box::pin(vfs::mount_root(
+                root,
+                cmd_args.root_fs_type.as_deref(),
+                cmd_args.read_only,
+            )));
+            if let Err(e) = &res {
+                println!("Failed to mount root {}: {}, using tmpfs as root", root, e);
+            }
+            res.is_ok()
+        }
+        None => {
+            println!("No root given on the command line, using tmpfs as root");
+            false
+        }
+    };
+    if !root_mounted {
+        block_task(Box::pin(vfs::mount_tmpfs(ResolvedPath::root()))).expect("Failed to mount tmpfs as root");
     }
+    block_task(Box::pin(vfs::mount_tmpfs(vfs::resolve_path("/tmp")))).expect("Failed to mount /tmp");
+
+    #[cfg(feature = "run_tests")]
+    tests::test_runner();
     //
     // let path = vfs::resolve_path("/");
     // let file_open_flags = FileFlags::new_with_flags(true, false, false, false);
@@ -100,7 +139,7 @@ extern "C" fn _start() -> ! {
     //     process_tasks();
     // }
 
-    proc::init();
+    proc::init(&cmd_args.init);
     //start first proc
     unsafe { core::arch::asm!("int 254") };
 
@@ -86,6 +86,14 @@ pub fn is_frame_allocated(addr: PhysAddr) -> bool {
     lock_w_info!(BUDDY_ALLOCATOR).is_frame_allocated(addr)
 }
 
+///Returns the number of frames in the physical address space and the number of free frames
+pub fn frame_stats() -> (u64, u64) {
+    let allocator = lock_w_info!(BUDDY_ALLOCATOR);
+    //every leaf of the tree starts out allocated, so reserved regions and holes count as used
+    let free_frames = allocator.binary_tree_size / 2 - allocator.allocated_pages;
+    (allocator.n_pages, free_frames)
+}
+
 pub fn print_state() {
     let allocator = lock_w_info!(BUDDY_ALLOCATOR);
     printlnc!((255, 200, 100), "Buddy Allocator state:");
@@ -2,7 +2,7 @@
 use super::ParseError;
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Elf64_Ehdr {
     pub e_ident: EIdent,
     pub e_type: u16,
@@ -21,21 +21,82 @@ pub struct Elf64_Ehdr {
 }
 
 impl Elf64_Ehdr {
-    pub(super) fn parse(data: &[u8]) -> Result<&Self, ParseError> {
+    ///Parses headers of both classes. 32 bit headers are widened to the 64 bit layout
+    pub(super) fn parse(data: &[u8]) -> Result<(Self, ElfClass), ParseError> {
+        if data.len() < core::mem::size_of::<EIdent>() {
+            return Err(ParseError::IncompleteData);
+        }
+        let class = EIdent::verify(data)?;
+        let header = match class {
+            ElfClass::Elf32 => Elf32_Ehdr::parse(data)?.widen(),
+            ElfClass::Elf64 => {
+                if data.len() < core::mem::size_of::<Self>() {
+                    return Err(ParseError::IncompleteData);
+                }
+                let header = unsafe { &*(data.as_ptr() as *const Elf64_Ehdr) };
+                if header.e_ehsize != core::mem::size_of::<Self>() as u16 {
+                    return Err(ParseError::Other);
+                }
+                header.clone()
+            }
+        };
+        Ok((header, class))
+    }
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct Elf32_Ehdr {
+    pub e_ident: EIdent,
+    pub e_type: u16,
+    pub e_machine: u16,
+    pub e_version: u32,
+    pub e_entry: u32,
+    pub e_phoff: u32,
+    pub e_shoff: u32,
+    pub e_flags: u32,
+    pub e_ehsize: u16,
+    pub e_phentsize: u16,
+    pub e_phnum: u16,
+    pub e_shentsize: u16,
+    pub e_shnum: u16,
+    pub e_shstrndx: u16,
+}
+
+impl Elf32_Ehdr {
+    fn parse(data: &[u8]) -> Result<&Self, ParseError> {
         if data.len() < core::mem::size_of::<Self>() {
             return Err(ParseError::IncompleteData);
         }
-        EIdent::verify(data)?;
-        let header = unsafe { &*(data.as_ptr() as *const Elf64_Ehdr) };
+        let header = unsafe { &*(data.as_ptr() as *const Elf32_Ehdr) };
         if header.e_ehsize != core::mem::size_of::<Self>() as u16 {
             return Err(ParseError::Other);
         }
         Ok(header)
     }
+
+    fn widen(&self) -> Elf64_Ehdr {
+        Elf64_Ehdr {
+            e_ident: self.e_ident.clone(),
+            e_type: self.e_type,
+            e_machine: self.e_machine,
+            e_version: self.e_version,
+            e_entry: self.e_entry as u64,
+            e_phoff: self.e_phoff as u64,
+            e_shoff: self.e_shoff as u64,
+            e_flags: self.e_flags,
+            e_ehsize: self.e_ehsize,
+            e_phentsize: self.e_phentsize,
+            e_phnum: self.e_phnum,
+            e_shentsize: self.e_shentsize,
+            e_shnum: self.e_shnum,
+            e_shstrndx: self.e_shstrndx,
+        }
+    }
 }
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct EIdent {
     /// (0x7f)ELF magic number
     pub ei_magic: [u8; 4],
@@ -55,24 +116,32 @@ pub struct EIdent {
 }
 
 impl EIdent {
-    fn verify(data: &[u8]) -> Result<(), ParseError> {
+    fn verify(data: &[u8]) -> Result<ElfClass, ParseError> {
         let header = unsafe { &*(data.as_ptr() as *const Self) };
         if header.ei_magic != [0x7f, b'E', b'L', b'F'] {
             return Err(ParseError::InvalidMagic);
         }
-        if header.ei_class != 2 {
-            return Err(ParseError::InvalidClass);
-        }
+        let class = match header.ei_class {
+            1 => ElfClass::Elf32,
+            2 => ElfClass::Elf64,
+            _ => return Err(ParseError::InvalidClass),
+        };
         if header.ei_data != 1 {
             return Err(ParseError::InvalidDataEncoding);
         }
         if header.ei_version != 1 {
             return Err(ParseError::InvalidVersion);
         }
-        Ok(())
+        Ok(class)
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfClass {
+    Elf32,
+    Elf64,
+}
+
 #[repr(u16)]
 pub enum EType {
     ET_NONE = 0,
@@ -82,6 +151,12 @@ pub enum EType {
     ET_CORE = 4,
 }
 
+#[repr(u16)]
+pub enum EMachine {
+    EM_386 = 3,
+    EM_X86_64 = 62,
+}
+
 #[repr(u8)]
 pub enum EiOsAbi {
     None = 0,
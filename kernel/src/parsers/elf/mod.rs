@@ -6,6 +6,7 @@ mod header;
 mod program_header;
 mod section;
 
+pub use header::{EMachine, EType, ElfClass};
 pub use program_header::PType;
 
 #[derive(Debug)]
@@ -22,21 +23,25 @@ pub enum ParseError {
 //parse further if needed
 #[derive(Debug)]
 pub struct ParsedElf<'a> {
-    pub header: &'a header::Elf64_Ehdr,
-    pub segments: Box<[(&'a program_header::Elf64_Phdr, &'a [u8])]>,
+    pub class: ElfClass,
+    ///32 bit headers are widened to the 64 bit layout
+    pub header: header::Elf64_Ehdr,
+    pub segments: Box<[(program_header::Elf64_Phdr, &'a [u8])]>,
 }
 
 pub fn parse<'a>(data: &'a [u8]) -> Result<ParsedElf<'a>, ParseError> {
-    let header = header::Elf64_Ehdr::parse(data)?;
-    let section_headers = section::get_section_table(data, header.e_shoff, header.e_shentsize, header.e_shnum)?;
-    let segment_headers = program_header::get_segment_table(data, header.e_phoff, header.e_phentsize, header.e_phnum)?;
-    let string_section_header = &section_headers[header.e_shstrndx as usize];
+    let (header, class) = header::Elf64_Ehdr::parse(data)?;
+    let section_headers = section::get_section_table(data, class, header.e_shoff, header.e_shentsize, header.e_shnum)?;
+    let segment_headers = program_header::get_segment_table(data, class, header.e_phoff, header.e_phentsize, header.e_phnum)?;
+    let string_section_header = section_headers
+        .get(header.e_shstrndx as usize)
+        .ok_or(ParseError::InvalidData)?;
     let start_shstr = string_section_header.sh_offset as usize;
     let end_shstr = start_shstr + string_section_header.sh_size as usize;
     let _shstrtab = &data[start_shstr..end_shstr];
 
     let mut segments = Vec::new();
-    for segment in segment_headers {
+    for segment in segment_headers.into_iter() {
         let start = segment.p_offset as usize;
         let end = start + segment.p_filesz as usize;
         if end > data.len() {
@@ -47,6 +52,7 @@ pub fn parse<'a>(data: &'a [u8]) -> Result<ParsedElf<'a>, ParseError> {
     }
 
     let parsed = ParsedElf {
+        class,
         header,
         segments: segments.into_boxed_slice(),
     };
@@ -1,10 +1,12 @@
 #![allow(non_camel_case_types)]
 
-use super::ParseError;
+use std::{boxed::Box, vec::Vec};
+
+use super::{ElfClass, ParseError};
 use bitfield::bitfield;
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Elf64_Phdr {
     pub p_type: u32,
     pub p_flags: PFlags,
@@ -21,17 +23,62 @@ pub struct Elf64_Phdr {
     pub p_align: u64,
 }
 
-pub(super) fn get_segment_table(data: &[u8], e_phoff: u64, e_phentsize: u16, e_phnum: u16) -> Result<&[Elf64_Phdr], ParseError> {
-    if e_phentsize as usize != core::mem::size_of::<Elf64_Phdr>() {
+#[repr(C)]
+#[derive(Debug)]
+pub struct Elf32_Phdr {
+    pub p_type: u32,
+    pub p_offset: u32,
+    pub p_vaddr: u32,
+    pub p_paddr: u32,
+    pub p_filesz: u32,
+    pub p_memsz: u32,
+    pub p_flags: u32,
+    pub p_align: u32,
+}
+
+impl Elf32_Phdr {
+    fn widen(&self) -> Elf64_Phdr {
+        Elf64_Phdr {
+            p_type: self.p_type,
+            p_flags: PFlags(self.p_flags),
+            p_offset: self.p_offset as u64,
+            p_vaddr: self.p_vaddr as u64,
+            p_paddr: self.p_paddr as u64,
+            p_filesz: self.p_filesz as u64,
+            p_memsz: self.p_memsz as u64,
+            p_align: self.p_align as u64,
+        }
+    }
+}
+
+///32 bit program headers are widened to the 64 bit layout
+pub(super) fn get_segment_table(
+    data: &[u8],
+    class: ElfClass,
+    e_phoff: u64,
+    e_phentsize: u16,
+    e_phnum: u16,
+) -> Result<Box<[Elf64_Phdr]>, ParseError> {
+    let entry_size = match class {
+        ElfClass::Elf32 => core::mem::size_of::<Elf32_Phdr>(),
+        ElfClass::Elf64 => core::mem::size_of::<Elf64_Phdr>(),
+    };
+    if e_phentsize as usize != entry_size {
         return Err(ParseError::InvalidData);
     }
-    if e_phoff as usize + (e_phentsize * e_phnum) as usize > data.len() {
+    if e_phoff as usize + (e_phentsize as usize * e_phnum as usize) > data.len() {
         return Err(ParseError::InvalidData);
     }
     unsafe {
-        let first_ptr = data.as_ptr().add(e_phoff as usize) as *const Elf64_Phdr;
-        let slice = core::slice::from_raw_parts(first_ptr, e_phnum as usize);
-        Ok(slice)
+        let first_ptr = data.as_ptr().add(e_phoff as usize);
+        let table = match class {
+            ElfClass::Elf32 => core::slice::from_raw_parts(first_ptr as *const Elf32_Phdr, e_phnum as usize)
+                .iter()
+                .map(Elf32_Phdr::widen)
+                .collect::<Vec<_>>(),
+            ElfClass::Elf64 => core::slice::from_raw_parts(first_ptr as *const Elf64_Phdr, e_phnum as usize).to_vec(),
+        };
+        Ok(table.into_boxed_slice())
     }
 }
 
@@ -50,6 +97,7 @@ pub enum PType {
 }
 
 bitfield! {
+    #[derive(Clone, Copy)]
     pub struct PFlags(u32);
     impl Debug;
     pub execute, _: 0;
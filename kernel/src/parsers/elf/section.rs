@@ -1,12 +1,14 @@
 #![allow(non_camel_case_types)]
 
+use std::{boxed::Box, vec::Vec};
+
 use bitfield::bitfield;
 
-use super::ParseError;
+use super::{ElfClass, ParseError};
 
 ///Section header struct
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Elf64_Shdr {
     ///index into string table
     pub sh_name: u32,
@@ -25,17 +27,66 @@ pub struct Elf64_Shdr {
     pub sh_entsize: u64,
 }
 
-pub(super) fn get_section_table(data: &[u8], e_shoff: u64, e_shentsize: u16, e_shnum: u16) -> Result<&[Elf64_Shdr], ParseError> {
-    if e_shentsize as usize != core::mem::size_of::<Elf64_Shdr>() {
+#[repr(C)]
+#[derive(Debug)]
+pub struct Elf32_Shdr {
+    pub sh_name: u32,
+    pub sh_type: u32,
+    pub sh_flags: u32,
+    pub sh_addr: u32,
+    pub sh_offset: u32,
+    pub sh_size: u32,
+    pub sh_link: u32,
+    pub sh_info: u32,
+    pub sh_addralign: u32,
+    pub sh_entsize: u32,
+}
+
+impl Elf32_Shdr {
+    fn widen(&self) -> Elf64_Shdr {
+        Elf64_Shdr {
+            sh_name: self.sh_name,
+            sh_type: self.sh_type,
+            sh_flags: self.sh_flags,
+            sh_addr: self.sh_addr as u64,
+            sh_offset: self.sh_offset as u64,
+            sh_size: self.sh_size as u64,
+            sh_link: self.sh_link,
+            sh_info: self.sh_info,
+            sh_addralign: self.sh_addralign as u64,
+            sh_entsize: self.sh_entsize as u64,
+        }
+    }
+}
+
+///32 bit section headers are widened to the 64 bit layout
+pub(super) fn get_section_table(
+    data: &[u8],
+    class: ElfClass,
+    e_shoff: u64,
+    e_shentsize: u16,
+    e_shnum: u16,
+) -> Result<Box<[Elf64_Shdr]>, ParseError> {
+    let entry_size = match class {
+        ElfClass::Elf32 => core::mem::size_of::<Elf32_Shdr>(),
+        ElfClass::Elf64 => core::mem::size_of::<Elf64_Shdr>(),
+    };
+    if e_shentsize as usize != entry_size {
         return Err(ParseError::InvalidData);
     }
-    if e_shoff as usize + (e_shentsize * e_shnum) as usize > data.len() {
+    if e_shoff as usize + (e_shentsize as usize * e_shnum as usize) > data.len() {
         return Err(ParseError::InvalidData);
     }
     unsafe {
-        let first_ptr = data.as_ptr().add(e_shoff as usize) as *const Elf64_Shdr;
-        let slice = core::slice::from_raw_parts(first_ptr, e_shnum as usize);
-        Ok(slice)
+        let first_ptr = data.as_ptr().add(e_shoff as usize);
+        let table = match class {
+            ElfClass::Elf32 => core::slice::from_raw_parts(first_ptr as *const Elf32_Shdr, e_shnum as usize)
+                .iter()
+                .map(Elf32_Shdr::widen)
+                .collect::<Vec<_>>(),
+            ElfClass::Elf64 => core::slice::from_raw_parts(first_ptr as *const Elf64_Shdr, e_shnum as usize).to_vec(),
+        };
+        Ok(table.into_boxed_slice())
     }
 }
 
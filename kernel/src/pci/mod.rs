@@ -30,7 +30,7 @@ pub fn enumerate_devices() {
             let ahci_disk = AhciController::new(device);
             let ports = ahci_disk.init();
             for port in ports {
-                block_task(Box::pin(crate::vfs::add_disk(Box::new(port))));
+                block_task(Box::pin(crate::vfs::add_disk(port)));
             }
         }
         printlnc!((51, 153, 10), "Device configured");
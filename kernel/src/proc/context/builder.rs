@@ -1,11 +1,13 @@
 use crate::interrupts::InterruptProcessorState;
 use crate::proc::process_data::CpuStateType;
 use crate::proc::PROCESS_ID_COUNTER;
+use crate::proc::loaders::ProcessLoadError;
 use crate::proc::ProcessData;
 use crate::proc::SCHEDULER;
 use std::lock_w_info;
 use std::string::ToString;
 use std::sync::arc::Arc;
+use std::{boxed::Box, vec::Vec};
 use std::{
     mem_utils::{self, VirtAddr, memset_physical_addr},
     println,
@@ -16,24 +18,29 @@ use crate::{
     proc::{MappedMemoryRegion, MemoryContext, Pid},
 };
 
-use super::info::ContextInfo;
+use super::info::{AuxvType, ContextInfo, MemoryRegionFlags};
 
 const DEFAULT_PROC_STACK_SIZE: usize = 0x4000; // 8KB
 
-pub fn create_process(context_info: &ContextInfo) -> Pid {
+pub fn create_process(context_info: &ContextInfo) -> Result<Pid, ProcessLoadError> {
+    //leave at least a page of the stack for the process itself
+    if startup_data_size(context_info) > DEFAULT_PROC_STACK_SIZE - 0x1000 {
+        return Err(ProcessLoadError::StartupDataTooLarge);
+    }
     let pid = Pid(PROCESS_ID_COUNTER.fetch_add(1, core::sync::atomic::Ordering::Relaxed));
     let is_32_bit = context_info.is_32_bit();
     let cmdline = context_info.cmdline().to_string().into_boxed_str();
     let rip = context_info.entry_point().0;
-    let memory_context = build_mem_context_for_new_proc(context_info);
+    let mut memory_context = build_mem_context_for_new_proc(context_info);
     let stack = memory_context
         .memory_regions
         .iter()
         .find(|region| (*region.name).eq("[stack]"))
         .unwrap();
-    let rsp = stack.base.0 + (stack.size_pages as u64 * 0x1000) - 16; //-16 just in case (ret val and other things are 0)
+    let stack_top = stack.base.0 + (stack.size_pages as u64 * 0x1000) - 16; //-16 just in case (ret val and other things are 0)
+    let rsp = push_startup_data(&mut memory_context.page_tree, context_info, stack_top);
 
-    let cpu_state = InterruptProcessorState::new(rip, rsp);
+    let cpu_state = InterruptProcessorState::new(rip, rsp, is_32_bit);
     let process_data = ProcessData::new(
         pid,
         is_32_bit,
@@ -45,7 +52,7 @@ pub fn create_process(context_info: &ContextInfo) -> Pid {
     let mut scheduler_lock = lock_w_info!(SCHEDULER);
     let scheduler = unsafe { scheduler_lock.assume_init_mut() };
     scheduler.accept_new_process(pid, process_data);
-    pid
+    Ok(pid)
 }
 
 pub fn build_generic_memory_context(context: &ContextInfo) -> MemoryContext {
@@ -69,21 +76,7 @@ pub fn build_generic_memory_context(context: &ContextInfo) -> MemoryContext {
     }
 
     for mem_init in context.mem_init() {
-        let first_page = mem_init.0.0 & (!0xfff);
-        let last_page = (mem_init.0.0 + mem_init.1.len() as u64) & (!0xfff); //inclusive
-        for page_addr in (first_page..=last_page).step_by(0x1000) {
-            let page = memory_tree.get_page_table_entry_mut(VirtAddr(page_addr)).unwrap();
-            let start_mem_addr = page_addr.max(mem_init.0.0);
-            let start_data_index = (start_mem_addr - mem_init.0.0) as usize;
-            let mem_offset = start_mem_addr & 0xFFF;
-            let end_data_index = mem_init.1.len().min(start_data_index + 0x1000 - mem_offset as usize);
-
-            let physical_addr = page.address();
-
-            unsafe {
-                mem_utils::memcopy_physical_buffer(physical_addr + mem_offset, &mem_init.1[start_data_index..end_data_index])
-            }
-        }
+        copy_to_page_tree(&mut memory_tree, mem_init.0, &mem_init.1);
     }
 
     MemoryContext {
@@ -93,10 +86,90 @@ pub fn build_generic_memory_context(context: &ContextInfo) -> MemoryContext {
             name: context.path().to_string().into_boxed_str(),
             base: VirtAddr(region.start().0),
             size_pages: region.size_pages() as u64,
+            flags: region.flags(),
         }).collect(),
     }
 }
 
+///Copies data to already mapped pages of a (possibly inactive) page tree
+fn copy_to_page_tree(memory_tree: &mut PageTree, start: VirtAddr, data: &[u8]) {
+    if data.is_empty() {
+        return;
+    }
+    let first_page = start.0 & (!0xfff);
+    let last_page = (start.0 + data.len() as u64 - 1) & (!0xfff); //inclusive
+    for page_addr in (first_page..=last_page).step_by(0x1000) {
+        let page = memory_tree
+            .get_page_table_entry_mut(VirtAddr(page_addr))
+            .expect("copying to an unmapped page");
+        let start_mem_addr = page_addr.max(start.0);
+        let start_data_index = (start_mem_addr - start.0) as usize;
+        let mem_offset = start_mem_addr & 0xFFF;
+        let end_data_index = data.len().min(start_data_index + 0x1000 - mem_offset as usize);
+
+        let physical_addr = page.address();
+
+        unsafe { mem_utils::memcopy_physical_buffer(physical_addr + mem_offset, &data[start_data_index..end_data_index]) }
+    }
+}
+
+///Upper bound on the number of bytes push_startup_data uses, including the 16 bytes left free at
+///the top and both alignment paddings
+fn startup_data_size(context: &ContextInfo) -> usize {
+    let strings_size: usize = context.argv().iter().chain(context.envp()).map(|string| string.len() + 1).sum();
+    //argc, argv, NULL, envp, NULL, auxv pairs and AT_NULL
+    let word_count = 1 + context.argv().len() + 1 + context.envp().len() + 1 + 2 * context.auxv().len() + 2;
+    let word_size = if context.is_32_bit() { 4 } else { 8 };
+    16 + strings_size + 16 + word_count * word_size + 16
+}
+
+///Lays out argc, argv, envp and the auxiliary vector on the new stack as described by the SysV
+///ABI (i386 ABI for 32 bit processes). Strings are placed at the very top. Returns the stack pointer
+///the process should start with, which points to argc and is 16 byte aligned
+fn push_startup_data(memory_tree: &mut PageTree, context: &ContextInfo, stack_top: u64) -> u64 {
+    let mut ptr = stack_top;
+
+    let mut push_strings = |strings: &[Box<str>]| -> Vec<u64> {
+        let mut pointers = Vec::with_capacity(strings.len());
+        for string in strings {
+            ptr -= string.len() as u64 + 1;
+            copy_to_page_tree(memory_tree, VirtAddr(ptr), string.as_bytes());
+            copy_to_page_tree(memory_tree, VirtAddr(ptr + string.len() as u64), &[0]);
+            pointers.push(ptr);
+        }
+        pointers
+    };
+    let argv = push_strings(context.argv());
+    let envp = push_strings(context.envp());
+    ptr &= !0xF;
+
+    let mut words: Vec<u64> = Vec::new();
+    words.push(argv.len() as u64);
+    words.extend_from_slice(&argv);
+    words.push(0);
+    words.extend_from_slice(&envp);
+    words.push(0);
+    for (aux_type, value) in context.auxv() {
+        words.push(*aux_type as u64);
+        words.push(*value);
+    }
+    words.push(AuxvType::AT_NULL as u64);
+    words.push(0);
+
+    //32 bit processes expect 32 bit pointers and auxv entries
+    let bytes: Vec<u8> = if context.is_32_bit() {
+        words.iter().flat_map(|word| (*word as u32).to_le_bytes()).collect()
+    } else {
+        words.iter().flat_map(|word| word.to_le_bytes()).collect()
+    };
+
+    //keep argc 16 byte aligned
+    ptr -= bytes.len() as u64;
+    ptr &= !0xF;
+    copy_to_page_tree(memory_tree, VirtAddr(ptr), &bytes);
+    ptr
+}
+
 pub fn build_mem_context_for_new_proc(context: &ContextInfo) -> MemoryContext {
     let mut generic_context = build_generic_memory_context(context);
     let stack_size_pages = DEFAULT_PROC_STACK_SIZE.div_ceil(0x1000) as u8; // convert to pages
@@ -155,6 +228,7 @@ pub fn add_stack(context: &mut MemoryContext, stack_size_pages: u8) {
         name: "[stack]".to_string().into_boxed_str(),
         base: VirtAddr(((top_page - stack_size_pages as u64) << 12) + 0x1000),
         size_pages: stack_size_pages as u64,
+        flags: MemoryRegionFlags(1), //writeable
     };
     context.memory_regions.push(stack);
 }
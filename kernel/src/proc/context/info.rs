@@ -1,5 +1,5 @@
 use bitfield::bitfield;
-use std::{boxed::Box, mem_utils::VirtAddr, vec::Vec};
+use std::{borrow::Cow, boxed::Box, mem_utils::VirtAddr, vec::Vec};
 
 use crate::proc::MemoryContext;
 
@@ -64,6 +64,20 @@ pub enum MemoryRegionError {
     StartNotPageAligned,
 }
 
+///Auxiliary vector entry types, placed on the initial stack after envp. Values follow the SysV ABI
+#[allow(non_camel_case_types)]
+#[repr(u64)]
+#[derive(Debug, Clone, Copy)]
+pub enum AuxvType {
+    AT_NULL = 0,
+    AT_PHDR = 3,
+    AT_PHENT = 4,
+    AT_PHNUM = 5,
+    AT_PAGESZ = 6,
+    AT_BASE = 7,
+    AT_ENTRY = 9,
+}
+
 ///Describes a memory context of a process. For it to be valid, mem_init regions all have to be
 ///included in the mem_regions
 #[derive(Debug)]
@@ -71,17 +85,22 @@ pub struct ContextInfo<'a> {
     is_32_bit: bool,
     ///Sorted by start address, no overlapping regions
     mem_regions: Box<[MemoryRegionDescriptor]>,
-    mem_init: Box<[(VirtAddr, &'a [u8])]>,
+    ///Data is owned when it doesn't come from the executable itself (like an interpreter)
+    mem_init: Box<[(VirtAddr, Cow<'a, [u8]>)]>,
     entry_point: VirtAddr,
     cmdline: Box<str>,
     path: Box<str>,
+    argv: Box<[Box<str>]>,
+    envp: Box<[Box<str>]>,
+    ///AT_NULL is appended when the stack is built, don't include it here
+    auxv: Box<[(AuxvType, u64)]>,
 }
 
 impl<'a> ContextInfo<'a> {
     pub fn new(
         is_32_bit: bool,
         mem_regions: &mut [MemoryRegionDescriptor],
-        mut mem_init: Box<[(VirtAddr, &'a [u8])]>,
+        mut mem_init: Box<[(VirtAddr, Cow<'a, [u8]>)]>,
         entry_point: VirtAddr,
         cmdline: Box<str>,
         path: Box<str>,
@@ -153,9 +172,43 @@ impl<'a> ContextInfo<'a> {
             entry_point,
             cmdline,
             path,
+            argv: Box::new([]),
+            envp: Box::new([]),
+            auxv: Box::new([]),
         })
     }
 
+    pub fn with_args(mut self, argv: Box<[Box<str>]>, envp: Box<[Box<str>]>) -> Self {
+        self.argv = argv;
+        self.envp = envp;
+        self
+    }
+
+    pub fn with_auxv(mut self, auxv: Box<[(AuxvType, u64)]>) -> Self {
+        self.auxv = auxv;
+        self
+    }
+
+    ///Copies any borrowed init data, so the context can outlive the data it was loaded from
+    pub fn into_owned(self) -> ContextInfo<'static> {
+        let mem_init = self
+            .mem_init
+            .into_iter()
+            .map(|(addr, data)| (addr, Cow::Owned(data.into_owned())))
+            .collect();
+        ContextInfo {
+            is_32_bit: self.is_32_bit,
+            mem_regions: self.mem_regions,
+            mem_init,
+            entry_point: self.entry_point,
+            cmdline: self.cmdline,
+            path: self.path,
+            argv: self.argv,
+            envp: self.envp,
+            auxv: self.auxv,
+        }
+    }
+
     pub fn is_32_bit(&self) -> bool {
         self.is_32_bit
     }
@@ -166,7 +219,7 @@ impl<'a> ContextInfo<'a> {
         &self.mem_regions
     }
 
-    pub fn mem_init(&self) -> &[(VirtAddr, &'a [u8])] {
+    pub fn mem_init(&self) -> &[(VirtAddr, Cow<'a, [u8]>)] {
         &self.mem_init
     }
 
@@ -185,6 +238,18 @@ impl<'a> ContextInfo<'a> {
     pub fn path(&self) -> &str {
         &self.path
     }
+
+    pub fn argv(&self) -> &[Box<str>] {
+        &self.argv
+    }
+
+    pub fn envp(&self) -> &[Box<str>] {
+        &self.envp
+    }
+
+    pub fn auxv(&self) -> &[(AuxvType, u64)] {
+        &self.auxv
+    }
 }
 
 #[derive(Debug)]
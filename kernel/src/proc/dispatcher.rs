@@ -40,6 +40,7 @@ pub(super) fn dispatch(new_proc: &ProcessData) -> ! {
 
     match cpu_state {
         CpuStateType::Interrupt(interrupt_frame) => return_interrupted(&interrupt_frame),
+        CpuStateType::Syscall((state, rsp)) if new_proc.is_32_bit() => return_syscalled_compat(&state, rsp),
         CpuStateType::Syscall((state, rsp)) => return_syscalled(&state, rsp),
         CpuStateType::None => panic!("Process with no CPU state dispatched (currently running)"),
     }
@@ -97,3 +98,24 @@ extern "C" fn return_syscalled(cpu_state: &SyscallCpuState, userspace_stack: u64
         "sysretq",
     )}
 }
+
+///Same as return_syscalled, but returns to compatibility mode
+#[naked]
+extern "C" fn return_syscalled_compat(cpu_state: &SyscallCpuState, userspace_stack: u64) -> ! {
+    //INFO: any kind of change here should be matched with the one in syscall.rs
+    unsafe { core::arch::naked_asm!(
+        //cpu_state in rdi
+        "mov rdx, [rdi + 8 * 0]",
+        "mov rax, [rdi + 8 * 1]",
+        "mov rcx, [rdi + 8 * 2]",
+        "mov r11, [rdi + 8 * 3]",
+        "mov r15, [rdi + 8 * 4]",
+        "mov r14, [rdi + 8 * 5]",
+        "mov r13, [rdi + 8 * 6]",
+        "mov r12, [rdi + 8 * 7]",
+        "mov rbp, [rdi + 8 * 8]",
+        "mov rbx, [rdi + 8 * 9]",
+        "mov rsp, rsi",
+        "sysret",
+    )}
+}
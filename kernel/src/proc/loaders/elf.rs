@@ -1,12 +1,19 @@
-use std::{boxed::Box, mem_utils::VirtAddr, println, vec::Vec};
+use std::{borrow::Cow, boxed::Box, ffi::CStr, mem_utils::VirtAddr, println, string::String, vec::Vec};
 
 use crate::{
     parsers::elf,
-    proc::context::info::{ContextInfo, MemoryRegionDescriptor, MemoryRegionFlags},
+    proc::context::info::{AuxvType, ContextInfo, MemoryRegionDescriptor, MemoryRegionFlags},
 };
 
 use super::{ProcessLoadError, ProcessLoader};
 
+///Where position independent executables are loaded
+const PIE_BASE: u64 = 0x40_0000;
+///Where the interpreter (dynamic linker) is loaded if it is position independent
+const INTERP_BASE: u64 = 0x7000_0000_0000;
+///Same as INTERP_BASE, but below the 3GiB limit of 32 bit processes
+const INTERP_BASE_32: u64 = 0x8000_0000;
+
 pub(super) fn proc_loader() -> ProcessLoader {
     ProcessLoader {
         is_this_type: is_elf,
@@ -18,37 +25,58 @@ fn is_elf(data: &[u8]) -> bool {
     data.len() >= 4 && &data[0..4] == b"\x7fELF"
 }
 
-fn load_elf_process(data: &[u8], path: Box<str>) -> Result<ContextInfo, super::ProcessLoadError> {
+fn parse_elf(data: &[u8]) -> Result<elf::ParsedElf<'_>, ProcessLoadError> {
     let parsed_elf = if data.as_ptr() as usize % 8 == 0 {
         elf::parse(data)
     } else {
         elf::parse_unaligned(data)
     };
 
-    let parsed_elf = match parsed_elf {
-        Ok(elf) => elf,
-        Err(e) => {
-            return match e {
-                elf::ParseError::InvalidMagic => Err(ProcessLoadError::InvalidFile),
-                elf::ParseError::InvalidClass => Err(ProcessLoadError::UnsupportedProcessFormat),
-                elf::ParseError::InvalidDataEncoding => Err(ProcessLoadError::UnsupportedProcessFormat),
-                elf::ParseError::InvalidVersion => Err(ProcessLoadError::InvalidFile),
-                elf::ParseError::IncompleteData => Err(ProcessLoadError::UnparseableFile),
-                elf::ParseError::InvalidData => Err(ProcessLoadError::UnparseableFile),
-                elf::ParseError::Other => Err(ProcessLoadError::UnparseableFile),
-            };
-        }
+    parsed_elf.map_err(|e| match e {
+        elf::ParseError::InvalidMagic => ProcessLoadError::InvalidFile,
+        elf::ParseError::InvalidClass => ProcessLoadError::UnsupportedProcessFormat,
+        elf::ParseError::InvalidDataEncoding => ProcessLoadError::UnsupportedProcessFormat,
+        elf::ParseError::InvalidVersion => ProcessLoadError::InvalidFile,
+        elf::ParseError::IncompleteData => ProcessLoadError::UnparseableFile,
+        elf::ParseError::InvalidData => ProcessLoadError::UnparseableFile,
+        elf::ParseError::Other => ProcessLoadError::UnparseableFile,
+    })
+}
+
+///Only native code can be loaded, 32 bit processes have to be i386 and 64 bit ones x86_64
+fn check_machine(parsed_elf: &elf::ParsedElf) -> Result<(), ProcessLoadError> {
+    let expected_machine = match parsed_elf.class {
+        elf::ElfClass::Elf32 => elf::EMachine::EM_386,
+        elf::ElfClass::Elf64 => elf::EMachine::EM_X86_64,
     };
+    if parsed_elf.header.e_machine != expected_machine as u16 {
+        return Err(ProcessLoadError::UnsupportedProcessFormat);
+    }
+    Ok(())
+}
 
-    let mut regions = Vec::new();
-    let mut regions_init = Vec::new();
+fn load_base(parsed_elf: &elf::ParsedElf, dyn_base: u64) -> u64 {
+    if parsed_elf.header.e_type == elf::EType::ET_DYN as u16 {
+        dyn_base
+    } else {
+        0
+    }
+}
 
+///Adds PT_LOAD segments to regions, shifted by base
+fn add_load_segments<'a>(
+    parsed_elf: &elf::ParsedElf<'a>,
+    base: u64,
+    regions: &mut Vec<MemoryRegionDescriptor>,
+    regions_init: &mut Vec<(VirtAddr, Cow<'a, [u8]>)>,
+) -> Result<(), ProcessLoadError> {
     for (segment, segment_data) in parsed_elf.segments.iter() {
         if segment.p_type != elf::PType::PT_LOAD as u32 {
             continue; // Only loadable segments
         }
-        let start = segment.p_vaddr & (!0xfff); // Align to page boundary
-        let start_extended = segment.p_vaddr - start;
+        let vaddr = segment.p_vaddr + base;
+        let start = vaddr & (!0xfff); // Align to page boundary
+        let start_extended = vaddr - start;
         let size = segment.p_memsz as usize + start_extended as usize;
         let mut flags = MemoryRegionFlags(0);
         flags.set_is_writeable(segment.p_flags.write());
@@ -63,23 +91,105 @@ fn load_elf_process(data: &[u8], path: Box<str>) -> Result<ContextInfo, super::P
             }
         }
         if !segment_data.is_empty() {
-            let init_region = (VirtAddr(start + start_extended), *segment_data);
+            let init_region = (VirtAddr(start + start_extended), Cow::Borrowed(*segment_data));
             regions_init.push(init_region);
         }
     }
+    Ok(())
+}
+
+///Address of the program headers in process memory, if they are mapped at all
+fn program_headers_addr(parsed_elf: &elf::ParsedElf, base: u64) -> Option<u64> {
+    let segments = &parsed_elf.segments;
+    if let Some((phdr, _)) = segments.iter().find(|(s, _)| s.p_type == elf::PType::PT_PHDR as u32) {
+        return Some(phdr.p_vaddr + base);
+    }
+    let e_phoff = parsed_elf.header.e_phoff;
+    segments
+        .iter()
+        .find(|(s, _)| s.p_type == elf::PType::PT_LOAD as u32 && s.p_offset <= e_phoff && e_phoff < s.p_offset + s.p_filesz)
+        .map(|(s, _)| s.p_vaddr + (e_phoff - s.p_offset) + base)
+}
+
+fn interpreter_path(parsed_elf: &elf::ParsedElf) -> Result<Option<String>, ProcessLoadError> {
+    let Some((_, interp)) = parsed_elf
+        .segments
+        .iter()
+        .find(|(s, _)| s.p_type == elf::PType::PT_INTER as u32)
+    else {
+        return Ok(None);
+    };
+    let path = CStr::from_bytes_until_nul(interp)
+        .ok()
+        .and_then(|path| path.to_str().ok())
+        .ok_or(ProcessLoadError::InvalidFile)?;
+    Ok(Some(path.into()))
+}
+
+fn load_elf_process(data: &[u8], path: Box<str>, argv: Box<[Box<str>]>, _depth: usize) -> Result<ContextInfo, ProcessLoadError> {
+    let parsed_elf = parse_elf(data)?;
+    check_machine(&parsed_elf)?;
+    let is_32_bit = parsed_elf.class == elf::ElfClass::Elf32;
+
+    let mut regions = Vec::new();
+    let mut regions_init = Vec::new();
+
+    let exec_base = load_base(&parsed_elf, PIE_BASE);
+    add_load_segments(&parsed_elf, exec_base, &mut regions, &mut regions_init)?;
 
     if regions.is_empty() {
         return Err(ProcessLoadError::InvalidFile);
     }
-    println!("rip will be set to {:#x}", parsed_elf.header.e_entry);
+
+    let program_entry = parsed_elf.header.e_entry + exec_base;
+    let mut auxv = Vec::new();
+    if let Some(phdr) = program_headers_addr(&parsed_elf, exec_base) {
+        auxv.push((AuxvType::AT_PHDR, phdr));
+    }
+    auxv.push((AuxvType::AT_PHENT, parsed_elf.header.e_phentsize as u64));
+    auxv.push((AuxvType::AT_PHNUM, parsed_elf.header.e_phnum as u64));
+    auxv.push((AuxvType::AT_PAGESZ, 0x1000));
+    auxv.push((AuxvType::AT_ENTRY, program_entry));
+
+    //the program is started through the interpreter, which finds the program through auxv
+    let entry_point = if let Some(interp_path) = interpreter_path(&parsed_elf)? {
+        let interp_data = super::read_file_from_vfs(&interp_path)?;
+        let interp_elf = parse_elf(&interp_data)?;
+        check_machine(&interp_elf)?;
+        if interp_elf.class != parsed_elf.class {
+            return Err(ProcessLoadError::UnsupportedProcessFormat);
+        }
+        if interpreter_path(&interp_elf)?.is_some() {
+            //interpreters must be self contained
+            return Err(ProcessLoadError::InvalidFile);
+        }
+        let interp_base = load_base(&interp_elf, if is_32_bit { INTERP_BASE_32 } else { INTERP_BASE });
+        let mut interp_init = Vec::new();
+        add_load_segments(&interp_elf, interp_base, &mut regions, &mut interp_init)?;
+        //interpreter data is dropped after this, so it has to be copied
+        regions_init.extend(
+            interp_init
+                .into_iter()
+                .map(|(addr, data)| (addr, Cow::Owned(data.into_owned()))),
+        );
+        auxv.push((AuxvType::AT_BASE, interp_base));
+        interp_elf.header.e_entry + interp_base
+    } else {
+        program_entry
+    };
+
+    println!("rip will be set to {:#x}", entry_point);
+    let cmdline = argv.join(" ").into_boxed_str();
     let context_info = ContextInfo::new(
-        false,
+        is_32_bit,
         &mut regions,
         regions_init.into_boxed_slice(),
-        VirtAddr(parsed_elf.header.e_entry),
-        "".into(),
+        VirtAddr(entry_point),
+        cmdline,
         path,
     );
 
-    context_info.map_err(|_| ProcessLoadError::InvalidFile)
+    context_info
+        .map(|context| context.with_args(argv, Box::new([])).with_auxv(auxv.into_boxed_slice()))
+        .map_err(|_| ProcessLoadError::InvalidFile)
 }
@@ -1,9 +1,15 @@
 use core::mem::MaybeUninit;
-use std::{boxed::Box, vec::Vec};
+use std::{boxed::Box, mem_utils::PhysAddr, vec::Vec};
 
 use super::context::info::ContextInfo;
+use crate::{
+    memory::physical_allocator,
+    task_runner::block_task,
+    vfs::{self, file::FileFlags},
+};
 
 mod elf;
+mod script;
 
 static mut PROCESS_LOADERS: MaybeUninit<Vec<ProcessLoader>> = MaybeUninit::uninit();
 
@@ -11,24 +17,79 @@ pub fn init_process_loaders() {
     unsafe {
         let proc_vec = PROCESS_LOADERS.write(Vec::new());
         proc_vec.push(elf::proc_loader());
+        proc_vec.push(script::proc_loader());
     }
 }
 
-pub(super) fn load_process(data: &[u8], path: Box<str>) -> Result<ContextInfo, ProcessLoadError> {
+///How many times loaders can hand the process over to another loader (script interpreters)
+const MAX_LOAD_DEPTH: usize = 4;
+
+pub(super) fn load_process(data: &[u8], path: Box<str>, argv: Box<[Box<str>]>) -> Result<ContextInfo, ProcessLoadError> {
+    load_process_at_depth(data, path, argv, 0)
+}
+
+fn load_process_at_depth(
+    data: &[u8],
+    path: Box<str>,
+    argv: Box<[Box<str>]>,
+    depth: usize,
+) -> Result<ContextInfo, ProcessLoadError> {
+    if depth > MAX_LOAD_DEPTH {
+        return Err(ProcessLoadError::RecursionLimit);
+    }
     unsafe {
         let loaders = PROCESS_LOADERS.assume_init_ref();
         for loader in loaders {
             if (loader.is_this_type)(data) {
-                return (loader.load_context)(data, path);
+                return (loader.load_context)(data, path, argv, depth);
             }
         }
     }
     Err(ProcessLoadError::UnsupportedProcessFormat)
 }
 
+///Reads the whole file at path. Blocks until the file is read, so it should only be used while
+///loading processes (interpreters, scripts,...)
+pub(super) fn read_file_from_vfs(path: &str) -> Result<Box<[u8]>, ProcessLoadError> {
+    let resolved_path = vfs::resolve_path(path);
+    block_task(Box::pin(async move {
+        let open_flags = FileFlags::new().with_read(true);
+        let mut file = vfs::open_file((&resolved_path).into(), None, open_flags)
+            .await
+            .map_err(|_| ProcessLoadError::FileNotFound)?;
+        let size = vfs::stat_file(&file).map_err(|_| ProcessLoadError::FileNotFound)?.size;
+        if size == 0 {
+            return Ok(Box::from([]));
+        }
+
+        let pages = size.div_ceil(4096);
+        let buffer_alloc = physical_allocator::allocate_contiguius_high(pages);
+        let buffers = (0..pages).map(|i| buffer_alloc + (i * 4096)).collect::<Vec<PhysAddr>>();
+        let read_result = vfs::read_file(&mut file, &buffers, size).await;
+
+        let mut data = Vec::new();
+        if let Ok(bytes_read) = read_result {
+            let src = std::mem_utils::translate_phys_virt_addr(buffer_alloc).0 as *const u8;
+            let bytes_read = bytes_read.min(size) as usize;
+            data.extend_from_slice(unsafe { core::slice::from_raw_parts(src, bytes_read) });
+        }
+        for i in 0..pages {
+            unsafe { physical_allocator::deallocate_frame(buffer_alloc + (i * 4096)) };
+        }
+
+        match read_result {
+            Ok(_) => Ok(data.into_boxed_slice()),
+            Err(_) => Err(ProcessLoadError::FileNotFound),
+        }
+    }))
+}
+
+///depth is the number of loaders that already handed the process over
+type LoadContextFn = fn(&[u8], path: Box<str>, argv: Box<[Box<str>]>, depth: usize) -> Result<ContextInfo, ProcessLoadError>;
+
 struct ProcessLoader {
     is_this_type: fn(&[u8]) -> bool,
-    load_context: fn(&[u8], path: Box<str>) -> Result<ContextInfo, ProcessLoadError>,
+    load_context: LoadContextFn,
     //potentially a post load hook
 }
 
@@ -36,5 +97,11 @@ struct ProcessLoader {
 pub enum ProcessLoadError {
     UnparseableFile,
     InvalidFile,
-    UnsupportedProcessFormat, //32 bit, different arch,...
+    UnsupportedProcessFormat, //different arch,...
+    ///A file needed for loading (interpreter, script,...) could not be read
+    FileNotFound,
+    ///Too many nested interpreters, most likely a script that runs itself
+    RecursionLimit,
+    ///argv, envp and auxv don't fit on the initial stack
+    StartupDataTooLarge,
 }
@@ -0,0 +1,61 @@
+use core::str;
+use std::{boxed::Box, vec::Vec};
+
+use crate::proc::context::info::ContextInfo;
+
+use super::{ProcessLoadError, ProcessLoader};
+
+///Longest "#!" line that is considered, same as linux
+const MAX_SHEBANG_LEN: usize = 256;
+
+pub(super) fn proc_loader() -> ProcessLoader {
+    ProcessLoader {
+        is_this_type: is_script,
+        load_context: load_script_process,
+    }
+}
+
+fn is_script(data: &[u8]) -> bool {
+    data.len() >= 2 && &data[0..2] == b"#!"
+}
+
+///Returns the interpreter and its optional argument. Everything after the interpreter is passed
+///as a single argument
+fn parse_shebang(data: &[u8]) -> Result<(&str, Option<&str>), ProcessLoadError> {
+    let line = &data[2..data.len().min(MAX_SHEBANG_LEN)];
+    let line_end = line.iter().position(|c| *c == b'\n').ok_or(ProcessLoadError::InvalidFile)?;
+    let line = str::from_utf8(&line[..line_end]).map_err(|_| ProcessLoadError::InvalidFile)?;
+    let line = line.trim_matches([' ', '\t', '\r']);
+
+    let (interpreter, argument) = match line.find([' ', '\t']) {
+        Some(split) => (&line[..split], Some(line[split..].trim_matches([' ', '\t']))),
+        None => (line, None),
+    };
+    if interpreter.is_empty() {
+        return Err(ProcessLoadError::InvalidFile);
+    }
+    Ok((interpreter, argument.filter(|arg| !arg.is_empty())))
+}
+
+fn load_script_process(
+    data: &[u8],
+    path: Box<str>,
+    argv: Box<[Box<str>]>,
+    depth: usize,
+) -> Result<ContextInfo, ProcessLoadError> {
+    let (interpreter, argument) = parse_shebang(data)?;
+    let interpreter_data = super::read_file_from_vfs(interpreter)?;
+
+    //interpreter [argument] script_path original_args...
+    let mut new_argv: Vec<Box<str>> = Vec::with_capacity(argv.len() + 2);
+    new_argv.push(interpreter.into());
+    if let Some(argument) = argument {
+        new_argv.push(argument.into());
+    }
+    new_argv.push(path);
+    new_argv.extend(argv.into_iter().skip(1));
+
+    //the interpreter data is dropped here, so the context can't borrow from it
+    super::load_process_at_depth(&interpreter_data, interpreter.into(), new_argv.into_boxed_slice(), depth + 1)
+        .map(ContextInfo::into_owned)
+}
@@ -5,6 +5,7 @@ use context::{
 use core::{mem::MaybeUninit, sync::atomic::AtomicU32};
 use scheduler::Scheduler;
 use std::{
+    borrow::Cow,
     boxed::Box,
     lock_w_info,
     mem_utils::{PhysAddr, VirtAddr},
@@ -25,7 +26,8 @@ mod scheduler;
 mod syscall;
 pub use context_switch::{context_switch, interrupt_context_switch};
 pub use process_data::{ProcessData, StackCpuStateData};
-pub use scheduler::save_and_release_current;
+pub use scheduler::{ProcessState, save_and_release_current};
+pub use syscall::compat_syscall_interrupt;
 
 static SCHEDULER: NoIntSpinlock<MaybeUninit<Scheduler>> = NoIntSpinlock::new(MaybeUninit::uninit());
 
@@ -49,13 +51,15 @@ pub(super) struct MemoryContext {
 }
 
 #[derive(Debug)]
-struct MappedMemoryRegion {
-    name: Box<str>,
-    base: VirtAddr,
-    size_pages: u64,
+pub struct MappedMemoryRegion {
+    pub name: Box<str>,
+    pub base: VirtAddr,
+    pub size_pages: u64,
+    pub flags: MemoryRegionFlags,
 }
 
-pub fn init() {
+///Loads init_path from the root filesystem as pid 1. If that fails, the embedded binaries are used
+pub fn init(init_path: &str) {
     // Initialize the scheduler
     let mut scheduler = lock_w_info!(SCHEDULER);
     *scheduler = MaybeUninit::new(Scheduler::new());
@@ -63,28 +67,55 @@ pub fn init() {
     create_fallback_process();
     loaders::init_process_loaders();
 
-    // let prime_finder = loaders::load_process(crate::PRIME_FINDER, "[prime_finder]".to_string().into_boxed_str())
+    match load_init_process(init_path) {
+        Ok(pid) => println!("Created init process {} with pid: {:?}", init_path, pid),
+        Err(e) => {
+            println!("Failed to load init process {}: {:?}, using embedded binaries", init_path, e);
+            load_embedded_processes();
+        }
+    }
+    #[cfg(feature = "run_tests")]
+    load_test_processes();
+
+    syscall::init();
+    set_proc_initialized();
+}
+
+fn load_init_process(init_path: &str) -> Result<Pid, loaders::ProcessLoadError> {
+    let data = loaders::read_file_from_vfs(init_path)?;
+    let context_info = loaders::load_process(&data, init_path.into(), Box::new([init_path.into()]))?;
+    create_process(&context_info)
+}
+
+fn load_embedded_processes() {
+
+    // let prime_finder = loaders::load_process(crate::PRIME_FINDER, "[prime_finder]".into(), Box::new(["[prime_finder]".into()]))
     //     .expect("Failed to load test executable prime finders");
     // for _i in 0..10 {
     //     let pid = create_process(&prime_finder);
     //     println!("Created process with pid: {:?}", pid);
     // }
     //
-    // let time_printer = loaders::load_process(crate::TIME_PRINTER, "[time_printer]".to_string().into_boxed_str())
+    // let time_printer = loaders::load_process(crate::TIME_PRINTER, "[time_printer]".into(), Box::new(["[time_printer]".into()]))
     //     .expect("Failed to load test executable time printer");
     // for _i in 0..10 {
     //     let pid = create_process(&time_printer);
     //     println!("Created process with pid: {:?}", pid);
     // }
 
-    let file_reader = loaders::load_process(crate::FILE_READER, "[file_reader]".to_string().into_boxed_str())
+    let file_reader = loaders::load_process(crate::FILE_READER, "[file_reader]".into(), Box::new(["[file_reader]".into()]))
         .expect("Failed to load test executable file reader");
-    let pid = create_process(&file_reader);
+    let pid = create_process(&file_reader).expect("Failed to create file reader process");
     println!("Created file reader process with pid: {:?}", pid);
+}
 
-
-    syscall::init();
-    set_proc_initialized();
+///Processes testing syscalls from user mode, started next to init. They report on /dev/tty
+#[cfg(feature = "run_tests")]
+fn load_test_processes() {
+    let compat_test = loaders::load_process(crate::COMPAT_TEST, "[compat_test]".into(), Box::new(["[compat_test]".into()]))
+        .expect("Failed to load the 32 bit compat test");
+    let pid = create_process(&compat_test).expect("Failed to create the compat test process");
+    println!("Created compat test process with pid: {:?}", pid);
 }
 
 pub fn init_ap() {
@@ -111,6 +142,19 @@ pub fn get_proc(pid: Pid) -> Option<Arc<ProcessData>> {
     scheduler.get_proc(pid)
 }
 
+///Pids of all processes known to the scheduler, in ascending order
+pub fn get_pids() -> Vec<Pid> {
+    let scheduler_lock = lock_w_info!(SCHEDULER);
+    let scheduler = unsafe { scheduler_lock.assume_init_ref() };
+    scheduler.pids()
+}
+
+pub fn get_proc_state(pid: Pid) -> Option<ProcessState> {
+    let scheduler_lock = lock_w_info!(SCHEDULER);
+    let scheduler = unsafe { scheduler_lock.assume_init_ref() };
+    scheduler.proc_state(pid)
+}
+
 //for now this only marks the process as stopping. If it was in running state before, return,
 //otherwise clear resources
 //Also return if it was in stopping state. Reason: stopping means it's either running and has been
@@ -159,13 +203,16 @@ pub fn create_fallback_process() {
     let fake_context = ContextInfo::new(
         false,
         &mut [code_region, data_region],
-        Box::new([(VirtAddr(0x1000), &code_init), (VirtAddr(0x2000), data_init)]),
+        Box::new([
+            (VirtAddr(0x1000), Cow::Borrowed(code_init.as_slice())),
+            (VirtAddr(0x2000), Cow::Borrowed(data_init.as_slice())),
+        ]),
         VirtAddr(0x1000),
         "fallback_process".to_string().into_boxed_str(),
         "[fallback_process]".to_string().into_boxed_str(),
     )
     .unwrap();
-    let pid = create_process(&fake_context);
+    let pid = create_process(&fake_context).expect("Failed to create fallback process");
     assert_eq!(pid.0, 0);
     let mut scheduler_lock = lock_w_info!(SCHEDULER);
     let scheduler = unsafe { scheduler_lock.assume_init_mut() };
@@ -17,7 +17,7 @@ use crate::{
     },
 };
 
-use super::{MemoryContext, Pid, syscall::SyscallCpuState};
+use super::{MappedMemoryRegion, MemoryContext, Pid, syscall::SyscallCpuState};
 
 ///Describes the process metadata like memory mapping, open files, etc.
 #[derive(Debug)]
@@ -88,12 +88,19 @@ impl ProcessData {
 
     pub fn set_syscall_return(&self, val: u64, err: u64) -> Result<(), ()> {
         let internal = &mut lock_w_info!(self.internal);
-        if let CpuStateType::Syscall((syscall_state, _)) = &mut internal.cpu_state {
-            syscall_state.rax = val;
-            syscall_state.rdx = err;
-            Ok(())
-        } else {
-            Err(())
+        match &mut internal.cpu_state {
+            CpuStateType::Syscall((syscall_state, _)) => {
+                syscall_state.rax = val;
+                syscall_state.rdx = err;
+                Ok(())
+            }
+            //32 bit processes can syscall through int 0x80
+            CpuStateType::Interrupt(interrupt_state) if self.is_32_bit => {
+                interrupt_state.rax = val;
+                interrupt_state.rdx = err;
+                Ok(())
+            }
+            _ => Err(()),
         }
     }
 
@@ -106,6 +113,18 @@ impl ProcessData {
         self.pid
     }
 
+    pub fn is_32_bit(&self) -> bool {
+        self.is_32_bit
+    }
+
+    pub fn cmdline(&self) -> &str {
+        &self.cmdline
+    }
+
+    pub fn memory_regions(&self) -> &[MappedMemoryRegion] {
+        &self.memory_context.get().memory_regions
+    }
+
     pub fn page_tree(&self) -> &PageTree {
         &self.memory_context.get().page_tree
     }
@@ -128,4 +147,9 @@ impl ProcessDataMutable {
     pub fn insert_file_handle(&mut self, fd: FileDescriptor, handle: FileHandle) {
         self.file_handles.insert(fd, handle);
     }
+
+    ///Handles taken out for an ongoing read or write are not included
+    pub fn file_handles(&self) -> impl Iterator<Item = (FileDescriptor, &FileHandle)> {
+        self.file_handles.iter().map(|(fd, handle)| (*fd, handle))
+    }
 }
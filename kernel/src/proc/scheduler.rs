@@ -19,6 +19,16 @@ pub enum SleepCondition {
     Event,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessState {
+    ///Currently executing on the cpu with this apic id
+    Running(u32),
+    Ready,
+    Sleeping,
+    ///Scheduled for removal
+    Stopping,
+}
+
 pub struct Scheduler {
     tasks: BTreeMap<Pid, Arc<ProcessData>>,
     sleeping_tasks: Vec<(Pid, SleepCondition)>,
@@ -131,6 +141,26 @@ impl Scheduler {
     pub fn get_proc(&mut self, pid: Pid) -> Option<Arc<ProcessData>> {
         self.tasks.get_mut(&pid).cloned()
     }
+
+    pub fn pids(&self) -> Vec<Pid> {
+        self.tasks.keys().copied().collect()
+    }
+
+    pub fn proc_state(&self, pid: Pid) -> Option<ProcessState> {
+        if !self.tasks.contains_key(&pid) {
+            return None;
+        }
+        if self.purge_queue.contains(&pid) {
+            return Some(ProcessState::Stopping);
+        }
+        if let Some((_, cpu)) = self.active_tasks.iter().find(|(p, _)| *p == pid) {
+            return Some(ProcessState::Running(*cpu));
+        }
+        if self.sleeping_tasks.iter().any(|(p, _)| *p == pid) {
+            return Some(ProcessState::Sleeping);
+        }
+        Some(ProcessState::Ready)
+    }
 }
 
 pub fn save_and_release_current(old_proc: &Arc<ProcessData>, on_stack_data: &StackCpuStateData, sleep: Option<SleepCondition>) {
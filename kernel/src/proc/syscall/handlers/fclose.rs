@@ -1,15 +1,24 @@
-use std::sync::arc::Arc;
+use std::{boxed::Box, error::ErrorCode, sync::arc::Arc};
 
-use crate::proc::{syscall::SyscallArgs, ProcessData};
+use crate::{proc::{syscall::SyscallArgs, ProcessData}, task_runner, vfs};
+
+use super::return_from_task;
 
 
 pub fn fclose(args: &mut SyscallArgs, proc: &Arc<ProcessData>) -> bool {
     let fd = args.arg1;
-    let mut proc_mut = proc.get_mutable();
-    if proc_mut.take_file_handle(fd).is_some() {
-        proc.set_syscall_return(0, 0).unwrap();
-    } else {
-        proc.set_syscall_return(u64::MAX, 1).unwrap();
-    }
-    false
+    let pid = proc.pid();
+    let Some(file_handle) = proc.get_mutable().take_file_handle(fd) else {
+        args.set_error(ErrorCode::BadFd);
+        return false;
+    };
+
+    let task = async move {
+        //cached writes reach the disk before fclose returns, the fd is gone even if they fail
+        let result = vfs::close_file(file_handle).await;
+        return_from_task(pid, result.map(|()| 0));
+    };
+
+    task_runner::add_task(Box::pin(task), Some(pid));
+    true
 }
@@ -1,4 +1,4 @@
-use std::{boxed::Box, string::ToString, sync::arc::Arc, vec::Vec};
+use std::{boxed::Box, error::ErrorCode, string::ToString, sync::arc::Arc, vec::Vec};
 
 use crate::{proc::{self, syscall::SyscallArgs, ProcessData}, task_runner, vfs::{self, file::FileFlags, InodeIdentifierChain}};
 
@@ -7,7 +7,7 @@ pub fn fopen(args: &mut SyscallArgs, proc: &Arc<ProcessData>) -> bool {
     let pid = proc.pid();
     let c_path = unsafe { core::ffi::c_str::CStr::from_ptr(args.arg1 as *const i8) };
     let Ok(path) = c_path.to_str() else {
-        args.syscall_number = u64::MAX;
+        args.set_error(ErrorCode::InvalidString);
         return false;
     };
     let path = path.to_string();
@@ -21,7 +21,7 @@ pub fn fopen(args: &mut SyscallArgs, proc: &Arc<ProcessData>) -> bool {
     } else {
         let proc_mut = proc.get_mutable();
         let Some(f_handle) = proc_mut.get_file_handle(fd) else {
-            args.syscall_number = u64::MAX;
+            args.set_error(ErrorCode::BadFd);
             return false;
         };
         let mut new_chain = Vec::from(f_handle.parent_chain.as_ref());
@@ -42,9 +42,9 @@ pub fn fopen(args: &mut SyscallArgs, proc: &Arc<ProcessData>) -> bool {
                 let f_descriptor = proc_lock.open_file_handle(handle);
                 proc_lock.set_syscall_return(f_descriptor, 0).unwrap();
             },
-            Err(_) => {
+            Err(e) => {
                 let proc_lock = proc.get();
-                proc_lock.set_syscall_return(u64::MAX, 1).unwrap();
+                proc_lock.set_syscall_return(u64::MAX, e.errno()).unwrap();
             }
         }
         proc::wake_process(pid)
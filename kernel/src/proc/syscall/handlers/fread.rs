@@ -1,4 +1,4 @@
-use std::{boxed::Box, mem_utils::PhysAddr, sync::arc::Arc, vec::Vec};
+use std::{boxed::Box, error::ErrorCode, mem_utils::PhysAddr, sync::arc::Arc, vec::Vec};
 
 use crate::{proc::{syscall::SyscallArgs, ProcessData}, task_runner};
 
@@ -20,7 +20,7 @@ pub fn fread(args: &mut SyscallArgs, proc: &Arc<ProcessData>) -> bool {
         if let Some(f_handle) = proc_mut.take_file_handle(fd) {
             f_handle
         } else {
-            args.syscall_number = u64::MAX;
+            args.set_error(ErrorCode::BadFd);
             return false;
         }
     };
@@ -35,17 +35,13 @@ pub fn fread(args: &mut SyscallArgs, proc: &Arc<ProcessData>) -> bool {
         let Some(proc) = crate::proc::get_proc(proc.pid()) else {
             return; //proc was killed
         };
-        if read_result.is_err() {
-            let proc_lock = proc.get();
-            proc_lock.set_syscall_return(u64::MAX, 1).unwrap();
-            return;
+        if let Ok(bytes_read) = read_result {
+            let bytes_read = bytes_read.min(size); //disk may have read more than necessary
+            //copy to user buffer, only what was read so the rest of it is left untouched
+            let dst = buffer_ptr;
+            let src = std::mem_utils::translate_phys_virt_addr(buffer_alloc).0 as *const u8;
+            unsafe { core::ptr::copy_nonoverlapping(src, dst, bytes_read as usize) };
         }
-        let bytes_read = unsafe { read_result.unwrap_unchecked() };
-        let bytes_read = bytes_read.min(size); //disk may have read more than necessary
-        //copy to user buffer
-        let dst = buffer_ptr;
-        let src = std::mem_utils::translate_phys_virt_addr(buffer_alloc).0 as *const u8;
-        unsafe { core::ptr::copy_nonoverlapping(src, dst, size as usize) };
 
         //free
         for i in 0..pages {
@@ -56,7 +52,10 @@ pub fn fread(args: &mut SyscallArgs, proc: &Arc<ProcessData>) -> bool {
         proc.get_mutable().insert_file_handle(fd, f_handle);
 
         //return
-        proc.set_syscall_return(bytes_read, 0).unwrap();
+        match read_result {
+            Ok(bytes_read) => proc.set_syscall_return(bytes_read.min(size), 0).unwrap(),
+            Err(e) => proc.set_syscall_return(u64::MAX, e.errno()).unwrap(),
+        }
         crate::proc::wake_process(proc.pid())
     };
 
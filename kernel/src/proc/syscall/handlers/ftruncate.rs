@@ -0,0 +1,26 @@
+use std::{boxed::Box, error::ErrorCode, sync::arc::Arc};
+
+use crate::{proc::{syscall::SyscallArgs, ProcessData}, task_runner, vfs};
+
+use super::return_from_task;
+
+pub fn ftruncate(args: &mut SyscallArgs, proc: &Arc<ProcessData>) -> bool {
+    let fd = args.arg1;
+    let size = args.arg2;
+    let pid = proc.pid();
+    let proc = proc.clone();
+    let Some(file_handle) = proc.get_mutable().take_file_handle(fd) else {
+        args.set_error(ErrorCode::BadFd);
+        return false;
+    };
+
+    let task = async move {
+        let result = vfs::truncate(&file_handle, size).await;
+        //return fd
+        proc.get_mutable().insert_file_handle(fd, file_handle);
+        return_from_task(pid, result.map(|()| 0));
+    };
+
+    task_runner::add_task(Box::pin(task), Some(pid));
+    true
+}
@@ -1,4 +1,4 @@
-use std::{boxed::Box, mem_utils::PhysAddr, sync::arc::Arc, vec::Vec};
+use std::{boxed::Box, error::ErrorCode, mem_utils::PhysAddr, sync::arc::Arc, vec::Vec};
 
 use crate::{proc::{syscall::SyscallArgs, ProcessData}, task_runner};
 
@@ -21,7 +21,7 @@ pub fn fwrite(args: &mut SyscallArgs, proc: &Arc<ProcessData>) -> bool {
         if let Some(f_handle) = proc_mut.take_file_handle(fd) {
             f_handle
         } else {
-            args.syscall_number = u64::MAX;
+            args.set_error(ErrorCode::BadFd);
             return false;
         }
     };
@@ -42,13 +42,6 @@ pub fn fwrite(args: &mut SyscallArgs, proc: &Arc<ProcessData>) -> bool {
         let Some(proc) = crate::proc::get_proc(proc.pid()) else {
             return; //proc was killed
         };
-        if write_result.is_err() {
-            let proc_lock = proc.get();
-            proc_lock.set_syscall_return(u64::MAX, 1).unwrap();
-            return;
-        }
-        let bytes_written = unsafe { write_result.unwrap_unchecked() };
-
         //free
         for i in 0..pages {
             unsafe { crate::memory::physical_allocator::deallocate_frame(buffer_alloc + (i * 4096)) };
@@ -58,7 +51,10 @@ pub fn fwrite(args: &mut SyscallArgs, proc: &Arc<ProcessData>) -> bool {
         proc.get_mutable().insert_file_handle(fd, f_handle);
 
         //return
-        proc.set_syscall_return(bytes_written, 0).unwrap();
+        match write_result {
+            Ok(bytes_written) => proc.set_syscall_return(bytes_written, 0).unwrap(),
+            Err(e) => proc.set_syscall_return(u64::MAX, e.errno()).unwrap(),
+        }
         crate::proc::wake_process(proc.pid())
     };
 
@@ -1,10 +1,10 @@
-use std::sync::arc::Arc;
+use std::{error::ErrorCode, sync::arc::Arc};
 
 use crate::proc::{syscall::SyscallArgs, ProcessData};
 
 
 //purely to catch bugs from processes, will always set error
-pub fn illegal(_args: &mut SyscallArgs, proc: &Arc<ProcessData>) -> bool {
-    proc.set_syscall_return(u64::MAX, 1).unwrap();
+pub fn illegal(args: &mut SyscallArgs, _proc: &Arc<ProcessData>) -> bool {
+    args.set_error(ErrorCode::UnsupportedOperation);
     false
 }
@@ -4,6 +4,15 @@ mod fread;
 mod fwrite;
 mod fclose;
 mod illegal;
+mod sync;
+mod mount;
+mod symlink;
+mod unlink;
+mod ftruncate;
+
+use std::{error::ErrorCode, string::{String, ToString}};
+
+use crate::proc::{self, Pid};
 
 pub use time::time;
 pub use fopen::fopen;
@@ -11,3 +20,28 @@ pub use fread::fread;
 pub use fwrite::fwrite;
 pub use fclose::fclose;
 pub use illegal::illegal;
+pub use sync::sync;
+pub use mount::{mount, umount};
+pub use symlink::symlink;
+pub use unlink::unlink;
+pub use ftruncate::ftruncate;
+
+fn read_c_str(ptr: u64) -> Option<String> {
+    let c_str = unsafe { core::ffi::c_str::CStr::from_ptr(ptr as *const i8) };
+    c_str.to_str().ok().map(|s| s.to_string())
+}
+
+///Sets the return value of a syscall that finished in a task and wakes the process. Nothing is set if the
+///process was killed or is not waiting in a syscall anymore
+fn return_from_task(pid: Pid, result: Result<u64, ErrorCode>) {
+    let Some(proc) = proc::get_proc(pid) else {
+        return;
+    };
+    let (value, errno) = match result {
+        Ok(value) => (value, 0),
+        Err(e) => (u64::MAX, e.errno()),
+    };
+    if proc.set_syscall_return(value, errno).is_ok() {
+        proc::wake_process(pid)
+    }
+}
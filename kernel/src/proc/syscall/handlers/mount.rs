@@ -0,0 +1,48 @@
+use std::{boxed::Box, error::ErrorCode, sync::arc::Arc};
+
+use crate::{proc::{syscall::SyscallArgs, ProcessData}, task_runner, vfs};
+
+use super::{read_c_str, return_from_task};
+
+pub fn mount(args: &mut SyscallArgs, proc: &Arc<ProcessData>) -> bool {
+    let pid = proc.pid();
+    let (Some(source), Some(target)) = (read_c_str(args.arg1), read_c_str(args.arg2)) else {
+        args.set_error(ErrorCode::InvalidString);
+        return false;
+    };
+    //0 detects the filesystem type
+    let fs_type = if args.arg3 == 0 {
+        None
+    } else {
+        let Some(fs_type) = read_c_str(args.arg3) else {
+            args.set_error(ErrorCode::InvalidString);
+            return false;
+        };
+        Some(fs_type)
+    };
+
+    let task = async move {
+        let result = vfs::mount(&source, fs_type.as_deref(), vfs::resolve_path(&target)).await;
+        return_from_task(pid, result.map(|()| 0));
+    };
+
+    task_runner::add_task(Box::pin(task), Some(pid));
+    true
+}
+
+pub fn umount(args: &mut SyscallArgs, proc: &Arc<ProcessData>) -> bool {
+    let pid = proc.pid();
+    let Some(target) = read_c_str(args.arg1) else {
+        args.set_error(ErrorCode::InvalidString);
+        return false;
+    };
+
+    let task = async move {
+        let target = vfs::resolve_path(&target);
+        let result = vfs::unmount((&target).into()).await;
+        return_from_task(pid, result.map(|()| 0));
+    };
+
+    task_runner::add_task(Box::pin(task), Some(pid));
+    true
+}
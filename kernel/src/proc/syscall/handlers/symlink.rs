@@ -0,0 +1,22 @@
+use std::{boxed::Box, error::ErrorCode, sync::arc::Arc};
+
+use crate::{proc::{syscall::SyscallArgs, ProcessData}, task_runner, vfs};
+
+use super::{read_c_str, return_from_task};
+
+pub fn symlink(args: &mut SyscallArgs, proc: &Arc<ProcessData>) -> bool {
+    let pid = proc.pid();
+    let (Some(target), Some(link_path)) = (read_c_str(args.arg1), read_c_str(args.arg2)) else {
+        args.set_error(ErrorCode::InvalidString);
+        return false;
+    };
+
+    let task = async move {
+        let link_path = vfs::resolve_path(&link_path);
+        let result = vfs::symlink((&link_path).into(), &target).await;
+        return_from_task(pid, result.map(|()| 0));
+    };
+
+    task_runner::add_task(Box::pin(task), Some(pid));
+    true
+}
@@ -0,0 +1,18 @@
+use std::{boxed::Box, sync::arc::Arc};
+
+use crate::{proc::{syscall::SyscallArgs, ProcessData}, task_runner, vfs};
+
+use super::return_from_task;
+
+
+pub fn sync(_args: &mut SyscallArgs, proc: &Arc<ProcessData>) -> bool {
+    let pid = proc.pid();
+
+    let task = async move {
+        let result = vfs::sync().await;
+        return_from_task(pid, result.map(|()| 0));
+    };
+
+    task_runner::add_task(Box::pin(task), Some(pid));
+    true
+}
@@ -0,0 +1,22 @@
+use std::{boxed::Box, error::ErrorCode, sync::arc::Arc};
+
+use crate::{proc::{syscall::SyscallArgs, ProcessData}, task_runner, vfs};
+
+use super::{read_c_str, return_from_task};
+
+pub fn unlink(args: &mut SyscallArgs, proc: &Arc<ProcessData>) -> bool {
+    let pid = proc.pid();
+    let Some(path) = read_c_str(args.arg1) else {
+        args.set_error(ErrorCode::InvalidString);
+        return false;
+    };
+
+    let task = async move {
+        let path = vfs::resolve_path(&path);
+        let result = vfs::unlink((&path).into()).await;
+        return_from_task(pid, result.map(|()| 0));
+    };
+
+    task_runner::add_task(Box::pin(task), Some(pid));
+    true
+}
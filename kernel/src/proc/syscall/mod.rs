@@ -1,5 +1,7 @@
-use super::{context_switch::no_ret_context_switch, process_data::StackCpuStateData, scheduler::{save_and_release_current, SleepCondition}};
-use crate::{interrupts::enable_interrupts, msr, proc::syscall};
+use std::{error::ErrorCode, mem_utils::VirtAddr, sync::arc::Arc};
+
+use super::{context_switch::no_ret_context_switch, process_data::StackCpuStateData, scheduler::{save_and_release_current, SleepCondition}, ProcessData};
+use crate::{interrupts::{enable_interrupts, InterruptProcessorState, USER_CODE_32_SELECTOR}, memory::paging::PageTree, msr, proc::syscall};
 
 mod handlers;
 
@@ -13,10 +15,11 @@ const MSR_EFER: u32 = 0xC000_0080;
 ///MSRs and more
 pub(super) fn init() {
     let syscall_cs_ss: u16 = 0x8;
-    let sysret_cs_ss: u16 = 0x10 | 0x3;
+    //sysret uses this for 32 bit code, +8 for data and +16 for 64 bit code
+    let sysret_cs_ss: u16 = USER_CODE_32_SELECTOR;
     let syscall_eip: u64 = 0; //unused
     let syscall_rip: u64 = handler_wrapper as *const fn() as u64;
-    let compat_rip: u64 = 0; //unused
+    let compat_rip: u64 = compat_handler_wrapper as *const fn() as u64;
     let syscall_flag_mask: u32 = 0x700;
 
     let mut star_reg = (sysret_cs_ss as u64) << 48;
@@ -96,8 +99,141 @@ extern "C" fn handler_wrapper() -> ! {
     )}
 }
 
-#[allow(unused_variables)]
+//i386 syscall abi (int 0x80):
+//parameters: ebx, ecx, edx, esi, edi, ebp
+//syscall number: eax
+//
+//syscall from compatibility mode (CSTAR) clobbers ecx, so the second parameter is passed in ebp
+//and the sixth one on top of the user stack, same as linux. Only AMD cpus support this, int 0x80
+//works everywhere
+#[naked]
+extern "C" fn compat_handler_wrapper() -> ! {
+    //INFO: must push the same layout as handler_wrapper, and any change should be matched with
+    //the one in dispatcher.rs
+    unsafe { core::arch::naked_asm!(
+        "swapgs",
+
+        "mov gs:[16], rcx", //save user rip to gsbase area
+        "mov cx, 0",
+        "mov ss, cx",
+        "mov rcx, gs:[16]", //get user rip from gsbase area
+
+        "mov gs:[16], rsp", //save user rsp to gsbase area
+        "mov rsp, gs:[8]", //get kernel rsp from gsbase area
+
+        "sub rsp, 8*8",
+        "mov [rsp + 8*7], rbx",
+        "mov [rsp + 8*6], rbp",
+        "mov [rsp + 8*5], r12",
+        "mov [rsp + 8*4], r13",
+        "mov [rsp + 8*3], r14",
+        "mov [rsp + 8*2], r15",
+        "mov [rsp + 8*1], r11", //rflags is in r11
+        "mov [rsp + 8*0], rcx", //return rip
+
+        //translate args, 32 bit moves zero the upper half
+        "sub rsp, 8*7",
+        "mov eax, eax",
+        "mov [rsp + 8*6], rax", //syscall number
+        "mov r9d, dword ptr gs:[16]", //user esp, compat_handler reads the 6th arg from it
+        "mov [rsp + 8*5], r9",
+        "mov r8d, edi",
+        "mov [rsp + 8*4], r8",
+        "mov r10d, esi",
+        "mov [rsp + 8*3], r10",
+        "mov edx, edx",
+        "mov [rsp + 8*2], rdx",
+        "mov esi, ebp",
+        "mov [rsp + 8*1], rsi",
+        "mov edi, ebx",
+        "mov [rsp + 8*0], rdi",
+
+        "mov rdi, rsp", //args rsp
+
+        "call {}",
+        sym compat_handler
+    )}
+}
+
+///Main handler of int 0x80, for 32 bit processes. Arguments are translated to the 64 bit layout
+///and the process returns with iret. 64 bit processes get ENOSYS, syscalls that finish in a task
+///can only return into the interrupt state of a 32 bit process
+pub extern "C" fn compat_syscall_interrupt(proc_data: &mut InterruptProcessorState) {
+    let locals = crate::acpi::cpu_locals::CpuLocals::get();
+    let curr_proc = locals.current_process.as_mut().expect("syscalled while no current process in locals");
+    //same as run_syscall, long syscalls must not hold off interrupts
+    enable_interrupts();
+
+    if !curr_proc.is_32_bit() {
+        proc_data.rax = u64::MAX;
+        proc_data.rdx = ErrorCode::NoSyscall.errno();
+        save_and_release_current(curr_proc, &StackCpuStateData::Interrupt(proc_data), None);
+        no_ret_context_switch();
+    }
+
+    let mut args = SyscallArgs {
+        arg1: proc_data.rbx & 0xFFFF_FFFF,
+        arg2: proc_data.rcx & 0xFFFF_FFFF,
+        arg3: proc_data.rdx & 0xFFFF_FFFF,
+        arg4: proc_data.rsi & 0xFFFF_FFFF,
+        arg5: proc_data.rdi & 0xFFFF_FFFF,
+        arg6: proc_data.rbp & 0xFFFF_FFFF,
+        syscall_number: proc_data.rax & 0xFFFF_FFFF,
+    };
+
+    let task_sleep = dispatch_syscall(&mut args, curr_proc);
+
+    //same as with syscall, where rax and rdx overlap with the syscall number and the 6th arg
+    proc_data.rax = args.syscall_number;
+    proc_data.rdx = args.arg6;
+
+    let sleep_cond = if task_sleep {
+        Some(SleepCondition::Event)
+    } else {
+        None
+    };
+
+    save_and_release_current(curr_proc, &StackCpuStateData::Interrupt(proc_data), sleep_cond);
+    no_ret_context_switch();
+}
+
 extern "C" fn handler(args_rsp: u64) -> ! {
+    run_syscall(args_rsp, None)
+}
+
+///The compat wrapper leaves the user esp in arg6. The 6th arg is only read from there if it is
+///mapped and user accessible, otherwise the syscall fails with EFAULT
+extern "C" fn compat_handler(args_rsp: u64) -> ! {
+    let args = unsafe { &mut *(args_rsp as *mut SyscallArgs) };
+    match read_user_u32(VirtAddr(args.arg6)) {
+        Some(arg6) => {
+            args.arg6 = arg6 as u64;
+            run_syscall(args_rsp, None)
+        }
+        None => run_syscall(args_rsp, Some(ErrorCode::BadAddress)),
+    }
+}
+
+///Reads a u32 from the current process' memory, None if any of its bytes are not mapped for user mode
+fn read_user_u32(addr: VirtAddr) -> Option<u32> {
+    let last_byte = addr.0.checked_add(3)?;
+    if !crate::memory::paging::is_user_mode(last_byte) {
+        return None;
+    }
+    let mut page_tree = PageTree::new(PageTree::get_level4_addr());
+    for page in [addr.0, last_byte] {
+        let entry = page_tree.get_page_table_entry_mut(VirtAddr(page))?;
+        if !entry.user_accessible() {
+            return None;
+        }
+    }
+    Some(unsafe { core::ptr::read_unaligned(addr.0 as *const u32) })
+}
+
+///Dispatches a syscall pushed by one of the wrappers, or fails it with early_error without
+///dispatching
+#[allow(unused_variables)]
+fn run_syscall(args_rsp: u64, early_error: Option<ErrorCode>) -> ! {
     //handle here
     // println!("Syscall called with args: {}, {}, {}, {}", arg1, arg2, arg3, arg4);
 
@@ -112,8 +248,28 @@ extern "C" fn handler(args_rsp: u64) -> ! {
     let curr_proc = locals.current_process.as_mut().expect("syscalled while no current process in locals");
     enable_interrupts();
 
+    let task_sleep = match early_error {
+        Some(error) => {
+            args.set_error(error);
+            false
+        }
+        None => dispatch_syscall(args, curr_proc),
+    };
+
+    let sleep_cond = if task_sleep {
+        Some(SleepCondition::Event)
+    } else {
+        None
+    };
+
+    save_and_release_current(curr_proc, &StackCpuStateData::Syscall(state), sleep_cond);
+    no_ret_context_switch();
+}
+
+///Returns whether the process should sleep until the syscall is done
+fn dispatch_syscall(args: &mut SyscallArgs, curr_proc: &Arc<ProcessData>) -> bool {
     #[allow(clippy::single_match)]
-    let task_sleep = match args.syscall_number {
+    match args.syscall_number {
         0 => syscall::handlers::illegal(args, curr_proc),
         1 => todo!("implement exit"),
         2 => todo!("implement exec"),
@@ -127,17 +283,14 @@ extern "C" fn handler(args_rsp: u64) -> ! {
         10 => todo!("implement munmap"),
         11 => todo!("implement sleep"),
         12 => syscall::handlers::time(args),
+        13 => syscall::handlers::sync(args, curr_proc),
+        14 => syscall::handlers::mount(args, curr_proc),
+        15 => syscall::handlers::umount(args, curr_proc),
+        16 => syscall::handlers::symlink(args, curr_proc),
+        17 => syscall::handlers::unlink(args, curr_proc),
+        18 => syscall::handlers::ftruncate(args, curr_proc),
         _ => {false}
-    };
-
-    let sleep_cond = if task_sleep {
-        Some(SleepCondition::Event)
-    } else {
-        None
-    };
-
-    save_and_release_current(curr_proc, &StackCpuStateData::Syscall(state), sleep_cond);
-    no_ret_context_switch();
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -165,3 +318,11 @@ struct SyscallArgs {
     arg6: u64,
     syscall_number: u64,
 }
+
+impl SyscallArgs {
+    ///Fails a syscall that returns right away, rax and rdx overlap with the syscall number and arg6
+    fn set_error(&mut self, error: ErrorCode) {
+        self.syscall_number = u64::MAX;
+        self.arg6 = error.errno();
+    }
+}
@@ -0,0 +1,208 @@
+//!A file mapped by pointers, like on filesystems written before extents, grows from inline data
+//!through 1, 2 and 3 levels of pointers and shrinks back. 3 levels start at 3.5 GiB, the space
+//!between the written ranges is filled with zeros by truncate
+#![cfg(feature = "run_tests")]
+use super::rfs_fixture;
+use crate::{
+    drivers::rfs,
+    memory::physical_allocator,
+    println,
+    task_runner::block_task,
+    vfs::{
+        self, InodeType,
+        file::{FileFlags, FileHandle},
+    },
+};
+use kernel_test::{kernel_test, kernel_test_mod};
+use rfs_layout::{BLOCK_POINTERS, INLINE_DATA_SIZE};
+use std::{
+    boxed::Box,
+    mem_utils::{PhysAddr, translate_phys_virt_addr},
+    vec::Vec,
+};
+kernel_test_mod!(crate::tests::A3_rfs_large_files);
+
+const MOUNTPOINT: &str = "/tmp/rfs_pointers";
+const FILE_PATH: &str = "/tmp/rfs_pointers/levels";
+const CHUNK_PAGES: u64 = 256;
+
+//biggest files with 1 and 2 levels of pointers
+const LEVEL_1_END: u64 = INLINE_DATA_SIZE * BLOCK_POINTERS as u64;
+const LEVEL_2_END: u64 = LEVEL_1_END * BLOCK_POINTERS as u64;
+//written from the start, past the end of 1 level
+const HEAD_SIZE: u64 = LEVEL_1_END + 512 * 1024;
+//the last block of 2 levels and the first of 3, the file ends in the middle of the block after
+const TAIL_START: u64 = LEVEL_2_END - 4096;
+const FILE_SIZE: u64 = LEVEL_2_END + 4096 + 8;
+
+fn expected_word(offset: u64) -> u64 {
+    offset.wrapping_mul(0x9E37_79B9_7F4A_7C15)
+}
+
+fn allocate_chunk() -> Vec<PhysAddr> {
+    (0..CHUNK_PAGES).map(|_| physical_allocator::allocate_frame()).collect()
+}
+
+fn free_chunk(chunk: Vec<PhysAddr>) {
+    for frame in chunk {
+        unsafe { physical_allocator::deallocate_frame(frame) };
+    }
+}
+
+fn word_at(chunk: &[PhysAddr], position: u64) -> *mut u64 {
+    let page = translate_phys_virt_addr(chunk[(position / 4096) as usize]);
+    (page.0 + position % 4096) as *mut u64
+}
+
+fn open_file(write: bool) -> Option<FileHandle> {
+    rfs_fixture::open(FILE_PATH, FileFlags::new_with_flags(true, write, false, false))
+}
+
+///Writes the pattern to `start..end`, start has to be page aligned
+fn write_pattern(handle: &mut FileHandle, chunk: &[PhysAddr], start: u64, end: u64) -> bool {
+    handle.position = start;
+    let mut offset = start;
+    while offset < end {
+        let len = u64::min(CHUNK_PAGES * 4096, end - offset);
+        for position in (0..len).step_by(8) {
+            unsafe { *word_at(chunk, position) = expected_word(offset + position) };
+        }
+        match block_task(Box::pin(vfs::write_file(handle, chunk, len))) {
+            Ok(written) if written == len => {}
+            res => {
+                println!("writing {} bytes at {} returned {:?}", len, offset, res);
+                return false;
+            }
+        }
+        offset += len;
+    }
+    true
+}
+
+///Checks `start..end` against the pattern, or against zeros if `zeros` is set
+fn check_range(handle: &mut FileHandle, chunk: &[PhysAddr], start: u64, end: u64, zeros: bool) -> bool {
+    handle.position = start;
+    let mut offset = start;
+    while offset < end {
+        let len = u64::min(CHUNK_PAGES * 4096, end - offset);
+        match block_task(Box::pin(vfs::read_file(handle, chunk, len))) {
+            Ok(read) if read == len => {}
+            res => {
+                println!("reading {} bytes at {} returned {:?}", len, offset, res);
+                return false;
+            }
+        }
+        //a partial word at the end is past the file in the pattern
+        for position in (0..len - len % 8).step_by(8) {
+            let word = unsafe { *word_at(chunk, position) };
+            let expected = if zeros { 0 } else { expected_word(offset + position) };
+            if word != expected {
+                println!("wrong data at {}: {:#x}, expected {:#x}", offset + position, word, expected);
+                return false;
+            }
+        }
+        offset += len;
+    }
+    true
+}
+
+fn check_size(handle: &FileHandle, size: u64) -> bool {
+    match vfs::stat_file(handle) {
+        Ok(inode) if inode.size == size => true,
+        res => {
+            println!("stat of {} returned {:?}, expected size {}", FILE_PATH, res, size);
+            false
+        }
+    }
+}
+
+fn truncate(size: u64) -> bool {
+    let Some(handle) = open_file(true) else {
+        return false;
+    };
+    let res = block_task(Box::pin(vfs::truncate(&handle, size)));
+    let closed = rfs_fixture::close(handle);
+    if let Err(e) = res {
+        println!("truncating {} to {} failed: {:?}", FILE_PATH, size, e);
+        return false;
+    }
+    closed
+}
+
+fn write_levels(chunk: &[PhysAddr]) -> bool {
+    if !rfs_fixture::mount_new(MOUNTPOINT) {
+        return false;
+    }
+    rfs::set_pointer_inodes(true);
+    let created = rfs_fixture::create(MOUNTPOINT, "levels", InodeType::new_file(0o644));
+    rfs::set_pointer_inodes(false);
+    if !created {
+        return false;
+    }
+    let Some(mut handle) = open_file(true) else {
+        return false;
+    };
+    //inline data moves into the first block, then the pointers into the first pointer block
+    let mut passed = write_pattern(&mut handle, chunk, 0, HEAD_SIZE);
+    if passed {
+        if let Err(e) = block_task(Box::pin(vfs::truncate(&handle, TAIL_START))) {
+            println!("growing {} to {} failed: {:?}", FILE_PATH, TAIL_START, e);
+            passed = false;
+        }
+    }
+    passed = passed && write_pattern(&mut handle, chunk, TAIL_START, FILE_SIZE);
+    rfs_fixture::close(handle) && passed
+}
+
+#[kernel_test]
+fn rfs_pointer_file_grows_through_levels() -> bool {
+    let chunk = allocate_chunk();
+    //remounting drops the cached pages, the data is read from the pointers on disk
+    let handle = if write_levels(&chunk) && rfs_fixture::remount(MOUNTPOINT) {
+        open_file(false)
+    } else {
+        None
+    };
+    let mut passed = false;
+    if let Some(mut handle) = handle {
+        passed = check_size(&handle, FILE_SIZE)
+            && check_range(&mut handle, &chunk, 0, HEAD_SIZE, false)
+            && check_range(&mut handle, &chunk, HEAD_SIZE, HEAD_SIZE + 4096, true)
+            && check_range(&mut handle, &chunk, LEVEL_2_END / 2, LEVEL_2_END / 2 + 4096, true)
+            && check_range(&mut handle, &chunk, TAIL_START - 4096, TAIL_START, true)
+            && check_range(&mut handle, &chunk, TAIL_START, FILE_SIZE, false);
+        rfs_fixture::close(handle);
+    }
+    free_chunk(chunk);
+    passed
+}
+
+#[kernel_test]
+//uses the file of rfs_pointer_file_grows_through_levels
+fn rfs_pointer_file_shrinks_through_levels() -> bool {
+    let chunk = allocate_chunk();
+    let mut passed = true;
+    //3 levels down to 1, then back to inline data
+    for size in [LEVEL_1_END - 100, 100] {
+        if !(truncate(size) && rfs_fixture::remount(MOUNTPOINT)) {
+            passed = false;
+            break;
+        }
+        let Some(mut handle) = open_file(false) else {
+            passed = false;
+            break;
+        };
+        let checked = check_size(&handle, size) && check_range(&mut handle, &chunk, 0, size, false);
+        rfs_fixture::close(handle);
+        if !checked {
+            passed = false;
+            break;
+        }
+    }
+    free_chunk(chunk);
+    if let Err(e) = block_task(Box::pin(vfs::unlink((&vfs::resolve_path(FILE_PATH)).into()))) {
+        println!("failed to unlink {}: {:?}", FILE_PATH, e);
+        passed = false;
+    }
+    passed && rfs_fixture::unmount(MOUNTPOINT)
+}
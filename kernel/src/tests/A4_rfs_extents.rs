@@ -0,0 +1,224 @@
+//!Files mapped by extents. A large file starts as inline data and grows into extents longer than
+//!one disk command and past a block group, reads cross those boundaries. Two files growing one block
+//!at a time in turns get a run per block each, more extents than fit into the inode block. The data
+//!is read back after a remount, from the extents on disk
+#![cfg(feature = "run_tests")]
+use super::rfs_fixture;
+use crate::{
+    memory::physical_allocator,
+    println,
+    task_runner::block_task,
+    vfs::{
+        self, InodeType,
+        file::{FileFlags, FileHandle},
+    },
+};
+use kernel_test::{kernel_test, kernel_test_mod};
+use rfs_layout::{GROUP_BLOCK_SIZE, INLINE_DATA_SIZE};
+use std::{
+    boxed::Box,
+    mem_utils::{PhysAddr, translate_phys_virt_addr},
+    vec::Vec,
+};
+kernel_test_mod!(crate::tests::A4_rfs_extents);
+
+const MOUNTPOINT: &str = "/tmp/rfs_extents";
+const LARGE_FILE: &str = "/tmp/rfs_extents/large";
+//more than a group, not page aligned so the last block is partial
+const LARGE_SIZE: u64 = (GROUP_BLOCK_SIZE + 8192) * 4096 + 3 * 1024 + 8;
+const CHUNK_PAGES: u64 = 256;
+//inline data, the first disk command of an extent, the blocks of one transaction and a group
+const BOUNDARIES: [u64; 5] = [
+    INLINE_DATA_SIZE,
+    248 * 4096,
+    4096 * 4096,
+    GROUP_BLOCK_SIZE * 4096,
+    LARGE_SIZE - 8,
+];
+const FILES: [(&str, &str); 2] = [("first", "/tmp/rfs_extents/first"), ("second", "/tmp/rfs_extents/second")];
+//more than the 298 extents of the inode block
+const BLOCKS: u64 = 400;
+
+fn expected_word(file: usize, offset: u64) -> u64 {
+    (offset ^ ((file as u64) << 56)).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+}
+
+fn word_at(frame: PhysAddr, position: u64) -> *mut u64 {
+    (translate_phys_virt_addr(frame).0 + position) as *mut u64
+}
+
+fn chunk_word_at(chunk: &[PhysAddr], position: u64) -> *mut u64 {
+    word_at(chunk[(position / 4096) as usize], position % 4096)
+}
+
+fn open_large(write: bool) -> Option<FileHandle> {
+    rfs_fixture::open(LARGE_FILE, FileFlags::new_with_flags(!write, write, false, false))
+}
+
+fn write_large_file(chunk: &[PhysAddr]) -> bool {
+    if !(rfs_fixture::mount_new(MOUNTPOINT) && rfs_fixture::create(MOUNTPOINT, "large", InodeType::new_file(0o644))) {
+        return false;
+    }
+    let Some(mut handle) = open_large(true) else {
+        return false;
+    };
+    let mut offset = 0;
+    let mut passed = true;
+    while offset < LARGE_SIZE {
+        let len = u64::min(CHUNK_PAGES * 4096, LARGE_SIZE - offset);
+        for position in (0..len).step_by(8) {
+            unsafe { *chunk_word_at(chunk, position) = expected_word(0, offset + position) };
+        }
+        match block_task(Box::pin(vfs::write_file(&mut handle, chunk, len))) {
+            Ok(written) if written == len => {}
+            res => {
+                println!("writing {} bytes at {} returned {:?}", len, offset, res);
+                passed = false;
+                break;
+            }
+        }
+        offset += len;
+    }
+    rfs_fixture::close(handle) && passed
+}
+
+fn read_large_file(handle: &mut FileHandle, chunk: &[PhysAddr]) -> bool {
+    if vfs::stat_file(handle).map(|inode| inode.size) != Ok(LARGE_SIZE) {
+        println!("size of {} is not {}", LARGE_FILE, LARGE_SIZE);
+        return false;
+    }
+    let mut offset = 0;
+    while offset < LARGE_SIZE {
+        let len = u64::min(CHUNK_PAGES * 4096, LARGE_SIZE - offset);
+        match block_task(Box::pin(vfs::read_file(handle, chunk, len))) {
+            Ok(read) if read == len => {}
+            res => {
+                println!("reading {} bytes at {} returned {:?}", len, offset, res);
+                return false;
+            }
+        }
+        for position in (0..len).step_by(8) {
+            let word = unsafe { *chunk_word_at(chunk, position) };
+            if word != expected_word(0, offset + position) {
+                println!("wrong data at {}: {:#x}", offset + position, word);
+                return false;
+            }
+        }
+        offset += len;
+    }
+    true
+}
+
+fn read_boundaries(handle: &mut FileHandle, frame: PhysAddr) -> bool {
+    let mut passed = true;
+    for boundary in BOUNDARIES {
+        let start = boundary - 8;
+        let len = u64::min(16, LARGE_SIZE - start);
+        handle.position = start;
+        if block_task(Box::pin(vfs::read_file(handle, &[frame], len))) != Ok(len) {
+            println!("failed to read {} bytes at {}", len, start);
+            passed = false;
+            continue;
+        }
+        for position in (0..len).step_by(8) {
+            let word = unsafe { *word_at(frame, position) };
+            if word != expected_word(0, start + position) {
+                println!("wrong data at {} near boundary {}: {:#x}", start + position, boundary, word);
+                passed = false;
+            }
+        }
+    }
+    passed
+}
+
+#[kernel_test]
+fn rfs_large_extent_file_read_back() -> bool {
+    let chunk: Vec<PhysAddr> = (0..CHUNK_PAGES).map(|_| physical_allocator::allocate_frame()).collect();
+    //remounting drops the cached pages
+    let handle = if write_large_file(&chunk) && rfs_fixture::remount(MOUNTPOINT) {
+        open_large(false)
+    } else {
+        None
+    };
+    let mut passed = false;
+    if let Some(mut handle) = handle {
+        passed = read_large_file(&mut handle, &chunk) && read_boundaries(&mut handle, chunk[0]);
+        rfs_fixture::close(handle);
+    }
+    for frame in chunk {
+        unsafe { physical_allocator::deallocate_frame(frame) };
+    }
+    passed
+}
+
+fn write_interleaved(frame: PhysAddr) -> bool {
+    //the partition is still mounted by rfs_large_extent_file_read_back
+    let mut handles = Vec::new();
+    for (name, path) in FILES {
+        if !rfs_fixture::create(MOUNTPOINT, name, InodeType::new_file(0o644)) {
+            return false;
+        }
+        let Some(handle) = rfs_fixture::open(path, FileFlags::new_with_flags(false, true, false, false)) else {
+            return false;
+        };
+        handles.push(handle);
+    }
+
+    let mut passed = true;
+    'blocks: for block in 0..BLOCKS {
+        for (file, handle) in handles.iter_mut().enumerate() {
+            for position in (0..4096).step_by(8) {
+                unsafe { *word_at(frame, position) = expected_word(file, block * 4096 + position) };
+            }
+            if block_task(Box::pin(vfs::write_file(handle, &[frame], 4096))) != Ok(4096) {
+                println!("failed to write block {} of {}", block, FILES[file].0);
+                passed = false;
+                break 'blocks;
+            }
+            //written back right away, so the files get their blocks in turns
+            if let Err(e) = block_task(Box::pin(vfs::sync())) {
+                println!("failed to write back block {} of {}: {:?}", block, FILES[file].0, e);
+                passed = false;
+                break 'blocks;
+            }
+        }
+    }
+    for handle in handles {
+        passed &= rfs_fixture::close(handle);
+    }
+    passed
+}
+
+#[kernel_test]
+fn rfs_interleaved_files_read_back() -> bool {
+    let frame = physical_allocator::allocate_frame();
+    //remounting drops the cached pages
+    let mut passed = write_interleaved(frame) && rfs_fixture::remount(MOUNTPOINT);
+    for (file, (name, path)) in FILES.iter().enumerate() {
+        if !passed {
+            break;
+        }
+        let Some(mut handle) = rfs_fixture::open(path, FileFlags::new_with_flags(true, false, false, false)) else {
+            passed = false;
+            break;
+        };
+        for block in 0..BLOCKS {
+            if block_task(Box::pin(vfs::read_file(&mut handle, &[frame], 4096))) != Ok(4096) {
+                println!("failed to read block {} of {}", block, name);
+                passed = false;
+                break;
+            }
+            let position = (0..4096)
+                .step_by(8)
+                .find(|position| unsafe { *word_at(frame, *position) } != expected_word(file, block * 4096 + position));
+            if let Some(position) = position {
+                println!("wrong data at {} of {}", block * 4096 + position, name);
+                passed = false;
+                break;
+            }
+        }
+        rfs_fixture::close(handle);
+    }
+    unsafe { physical_allocator::deallocate_frame(frame) };
+    passed && rfs_fixture::unmount(MOUNTPOINT)
+}
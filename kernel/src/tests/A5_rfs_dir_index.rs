@@ -0,0 +1,75 @@
+//!A directory gets enough entries for a name index with more than one node. Every name is looked up
+//!after a remount, through the index on disk since the inode cache starts out empty, a missing name
+//!isn't found and an existing one can't be created again
+#![cfg(feature = "run_tests")]
+use super::rfs_fixture;
+use crate::{
+    println,
+    task_runner::block_task,
+    vfs::{self, InodeType, file::FileFlags},
+};
+use kernel_test::{kernel_test, kernel_test_mod};
+use std::{boxed::Box, error::ErrorCode, format};
+kernel_test_mod!(crate::tests::A5_rfs_dir_index);
+
+const MOUNTPOINT: &str = "/tmp/rfs_dir_index";
+const DIR: &str = "/tmp/rfs_dir_index/many";
+//more than the keys of one btree node
+const ENTRIES: u32 = 1000;
+
+fn create_entries() -> bool {
+    if !(rfs_fixture::mount_new(MOUNTPOINT) && rfs_fixture::create(MOUNTPOINT, "many", InodeType::new_dir(0o755))) {
+        return false;
+    }
+    let Some(mut dir_handle) = rfs_fixture::open_dir(DIR) else {
+        return false;
+    };
+    for i in 0..ENTRIES {
+        let name = format!("entry_{}", i);
+        if let Err(e) = block_task(Box::pin(vfs::create_file(&mut dir_handle, &name, InodeType::new_file(0o644)))) {
+            println!("failed to create {}: {:?}", name, e);
+            return false;
+        }
+    }
+    true
+}
+
+#[kernel_test]
+fn rfs_large_dir_lookup() -> bool {
+    //remounting drops the cached inodes
+    if !(create_entries() && rfs_fixture::remount(MOUNTPOINT)) {
+        return false;
+    }
+    let Some(mut dir_handle) = rfs_fixture::open_dir(DIR) else {
+        return false;
+    };
+    for i in (0..ENTRIES).rev() {
+        let path = vfs::resolve_path(&format!("{}/entry_{}", DIR, i));
+        let flags = FileFlags::new_with_flags(true, false, false, false);
+        match block_task(Box::pin(vfs::open_file((&path).into(), None, flags))) {
+            Ok(handle) => {
+                rfs_fixture::close(handle);
+            }
+            Err(e) => {
+                println!("failed to look up entry_{}: {:?}", i, e);
+                return false;
+            }
+        }
+    }
+    let missing = vfs::resolve_path(&format!("{}/entry_{}", DIR, ENTRIES));
+    let flags = FileFlags::new_with_flags(true, false, false, false);
+    if block_task(Box::pin(vfs::open_file((&missing).into(), None, flags))).is_ok() {
+        println!("found entry_{}, which was never created", ENTRIES);
+        return false;
+    }
+    let existing = block_task(Box::pin(vfs::create_file(
+        &mut dir_handle,
+        "entry_0",
+        InodeType::new_file(0o644),
+    )));
+    if existing != Err(ErrorCode::Exists) {
+        println!("creating entry_0 again returned {:?}", existing);
+        return false;
+    }
+    rfs_fixture::unmount(MOUNTPOINT)
+}
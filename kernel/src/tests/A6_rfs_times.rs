@@ -0,0 +1,102 @@
+//!A new file gets its access, modification and change times when it's written, and a read moves
+//!the access time on. The times are checked after a remount each time, so they come from disk
+#![cfg(feature = "run_tests")]
+use super::rfs_fixture;
+use crate::{
+    memory::physical_allocator,
+    println,
+    task_runner::block_task,
+    vfs::{self, InodeType, file::FileFlags},
+};
+use kernel_test::{kernel_test, kernel_test_mod};
+use std::{
+    boxed::Box,
+    mem_utils::PhysAddr,
+    time::{Instant, UNIX_EPOCH},
+};
+kernel_test_mod!(crate::tests::A6_rfs_times);
+
+const MOUNTPOINT: &str = "/tmp/rfs_times";
+const FILE: &str = "/tmp/rfs_times/stamped";
+
+fn now() -> u32 {
+    Instant::now().duration_since(UNIX_EPOCH).as_secs() as u32
+}
+
+fn remount_and_stat() -> Option<vfs::Inode> {
+    if !rfs_fixture::remount(MOUNTPOINT) {
+        return None;
+    }
+    let handle = rfs_fixture::open(FILE, FileFlags::new_with_flags(true, false, false, false))?;
+    let inode = vfs::stat_file(&handle).ok();
+    rfs_fixture::close(handle);
+    inode
+}
+
+fn create_and_write(frame: PhysAddr) -> bool {
+    if !(rfs_fixture::mount_new(MOUNTPOINT) && rfs_fixture::create(MOUNTPOINT, "stamped", InodeType::new_file(0o644))) {
+        return false;
+    }
+    let Some(mut handle) = rfs_fixture::open(FILE, FileFlags::new_with_flags(false, true, false, false)) else {
+        return false;
+    };
+    let written = block_task(Box::pin(vfs::write_file(&mut handle, &[frame], 100)));
+    //written back here, which is when rfs sets the times
+    let closed = rfs_fixture::close(handle);
+    if written != Ok(100) {
+        println!("failed to write {}: {:?}", FILE, written);
+        return false;
+    }
+    closed
+}
+
+fn read(frame: PhysAddr) -> bool {
+    let Some(mut handle) = rfs_fixture::open(FILE, FileFlags::new_with_flags(true, false, false, false)) else {
+        return false;
+    };
+    let read = block_task(Box::pin(vfs::read_file(&mut handle, &[frame], 100)));
+    rfs_fixture::close(handle);
+    if read != Ok(100) {
+        println!("failed to read {}: {:?}", FILE, read);
+        return false;
+    }
+    true
+}
+
+#[kernel_test]
+fn rfs_times_survive_remount() -> bool {
+    let start = now();
+    let frame = physical_allocator::allocate_frame();
+    let passed = create_and_write(frame);
+    let written = if passed { remount_and_stat() } else { None };
+    let Some(written) = written else {
+        unsafe { physical_allocator::deallocate_frame(frame) };
+        return false;
+    };
+    let end = now();
+    let in_range = |time: u32| (start..=end).contains(&time);
+    if !(in_range(written.access_time) && in_range(written.modification_time) && in_range(written.stat_change_time)) {
+        println!(
+            "times {} {} {} are outside of {}..={}",
+            written.access_time, written.modification_time, written.stat_change_time, start, end
+        );
+        unsafe { physical_allocator::deallocate_frame(frame) };
+        return false;
+    }
+
+    //the access time isn't newer than the modification time, so even relatime updates it
+    let passed = read(frame);
+    unsafe { physical_allocator::deallocate_frame(frame) };
+    let read = if passed { remount_and_stat() } else { None };
+    let Some(read) = read else {
+        return false;
+    };
+    if read.modification_time != written.modification_time || read.access_time < written.modification_time {
+        println!(
+            "after a read access time is {} and modification time {}, was {}",
+            read.access_time, read.modification_time, written.modification_time
+        );
+        return false;
+    }
+    rfs_fixture::unmount(MOUNTPOINT)
+}
@@ -0,0 +1,80 @@
+//!Links are created in the tmpfs at /tmp, so this runs without a disk. Relative and absolute
+//!targets have to end at the same inode, and a pair of links pointing at each other has to hit
+//!the follow limit instead of looping
+#![cfg(feature = "run_tests")]
+use crate::{
+    println,
+    task_runner::block_task,
+    vfs::{self, InodeIdentifier, InodeType, file::FileFlags},
+};
+use kernel_test::{kernel_test, kernel_test_mod};
+use std::{boxed::Box, error::ErrorCode};
+kernel_test_mod!(crate::tests::A7_symlinks);
+
+const DIR: &str = "/tmp/symlinks";
+
+fn open(path: &str) -> Result<InodeIdentifier, ErrorCode> {
+    let path = vfs::resolve_path(path);
+    let flags = FileFlags::new_with_flags(true, false, false, false);
+    let handle = block_task(Box::pin(vfs::open_file((&path).into(), None, flags)))?;
+    let inode = handle.inode;
+    let _ = block_task(Box::pin(vfs::close_file(handle)));
+    Ok(inode)
+}
+
+fn create(parent: &str, name: &str, inode_type: InodeType) -> bool {
+    let path = vfs::resolve_path(parent);
+    let flags = FileFlags::new_with_flags(true, true, false, true);
+    let Ok(mut handle) = block_task(Box::pin(vfs::open_file((&path).into(), None, flags))) else {
+        println!("failed to open {}", parent);
+        return false;
+    };
+    let result = block_task(Box::pin(vfs::create_file(&mut handle, name, inode_type)));
+    let _ = block_task(Box::pin(vfs::close_file(handle)));
+    if let Err(e) = result {
+        println!("failed to create {}/{}: {:?}", parent, name, e);
+        return false;
+    }
+    true
+}
+
+fn symlink(link_path: &str, target: &str) -> bool {
+    let link_path = vfs::resolve_path(link_path);
+    if let Err(e) = block_task(Box::pin(vfs::symlink((&link_path).into(), target))) {
+        println!("failed to create a link to {}: {:?}", target, e);
+        return false;
+    }
+    true
+}
+
+#[kernel_test]
+fn symlinks_resolve_and_stop_at_loops() -> bool {
+    if !(create("/tmp", "symlinks", InodeType::new_dir(0o755)) && create(DIR, "target", InodeType::new_file(0o644))) {
+        return false;
+    }
+    if !(symlink("/tmp/symlinks/relative", "target")
+        && symlink("/tmp/symlinks/absolute", "/tmp/symlinks/target")
+        && symlink("/tmp/symlinks/loop_a", "loop_b")
+        && symlink("/tmp/symlinks/loop_b", "./loop_a"))
+    {
+        return false;
+    }
+
+    let Ok(target) = open("/tmp/symlinks/target") else {
+        println!("failed to open the target");
+        return false;
+    };
+    for link in ["/tmp/symlinks/relative", "/tmp/symlinks/absolute"] {
+        let resolved = open(link);
+        if resolved != Ok(target) {
+            println!("{} resolved to {:?} instead of {:?}", link, resolved, target);
+            return false;
+        }
+    }
+    let looped = open("/tmp/symlinks/loop_a");
+    if looped != Err(ErrorCode::SymlinkLoop) {
+        println!("a symlink loop resolved to {:?}", looped);
+        return false;
+    }
+    true
+}
@@ -0,0 +1,55 @@
+//!Metadata that doesn't match its checksum is never used. A byte of the root inode is flipped while
+//!the partition isn't mounted, mounting then fails with FileSystemInconsistency where the root
+//!inode is read. Once the byte is flipped back the partition mounts again
+#![cfg(feature = "run_tests")]
+use super::rfs_fixture;
+use crate::{drivers::disk::MountedPartition, memory::physical_allocator, println, task_runner::block_task, vfs};
+use kernel_test::{kernel_test, kernel_test_mod};
+use rfs_layout::{BLOCK_SIZE_SECTORS, ROOT_INODE_BLOCK};
+use std::{
+    boxed::Box,
+    error::ErrorCode,
+    mem_utils::{PhysAddr, translate_phys_virt_addr},
+};
+kernel_test_mod!(crate::tests::A8_rfs_checksums);
+
+const MOUNTPOINT: &str = "/tmp/rfs_checksums";
+//any byte of the block changes its checksum
+const FLIPPED_BYTE: u64 = 16;
+
+///Flips a byte of the root inode block on the disk, doing it twice restores the block
+fn flip_root_inode(partition: &MountedPartition, frame: PhysAddr) {
+    let sector = ROOT_INODE_BLOCK as usize * BLOCK_SIZE_SECTORS;
+    block_task(Box::pin(partition.read(sector, BLOCK_SIZE_SECTORS, &[frame])));
+    unsafe { *((translate_phys_virt_addr(frame).0 + FLIPPED_BYTE) as *mut u8) ^= 0xFF };
+    block_task(Box::pin(partition.write(sector, BLOCK_SIZE_SECTORS, &[frame])));
+}
+
+#[kernel_test]
+fn rfs_checksum_mismatch_fails() -> bool {
+    //mounted once so everything before this test is on the disk
+    if !(rfs_fixture::mount_new(MOUNTPOINT) && rfs_fixture::unmount(MOUNTPOINT)) {
+        return false;
+    }
+    let Some(partition) = rfs_fixture::raw_partition() else {
+        return false;
+    };
+    let frame = physical_allocator::allocate_frame();
+    flip_root_inode(&partition, frame);
+    let mounted = block_task(Box::pin(vfs::mount(
+        rfs_fixture::TEST_PARTITION,
+        Some("rfs"),
+        vfs::resolve_path(MOUNTPOINT),
+    )));
+    //restored either way, the other tests use the partition too
+    flip_root_inode(&partition, frame);
+    unsafe { physical_allocator::deallocate_frame(frame) };
+    if mounted != Err(ErrorCode::FileSystemInconsistency) {
+        println!("mounting with a corrupted root inode gave {:?}", mounted);
+        if mounted.is_ok() {
+            rfs_fixture::unmount(MOUNTPOINT);
+        }
+        return false;
+    }
+    rfs_fixture::mount(MOUNTPOINT) && rfs_fixture::unmount(MOUNTPOINT)
+}
@@ -0,0 +1,72 @@
+;32 bit process started after the kernel tests, checks syscalls through int 0x80 from
+;compatibility mode. time writes through pointers that are zero extended, fopen and fwrite finish
+;in a task and return into the interrupt state. The result is written to /dev/tty
+;nasm -f elf32, linked with ld -m elf_i386
+bits 32
+
+section .data
+tty_path: db "/dev/tty", 0
+ok_msg: db "compat_test: int 0x80 syscalls [ok]", 10
+ok_len equ $ - ok_msg
+err_msg: db "compat_test: int 0x80 syscalls [err]", 10
+err_len equ $ - err_msg
+
+section .bss
+seconds: resq 1
+nanoseconds: resq 1
+tty_fd: resd 1
+
+section .text
+global _start
+_start:
+    ;unused arguments are 0, ebp is the 6th one
+    xor ebp, ebp
+    xor esi, esi
+    xor edi, edi
+
+    ;fopen(path, no dir, write)
+    mov eax, 4
+    mov ebx, tty_path
+    xor ecx, ecx
+    mov edx, 2
+    int 0x80
+    test edx, edx
+    jnz .done ;nowhere to report to
+    mov [tty_fd], eax
+
+    ;time(&seconds, &nanoseconds), the upper halves stay 0 until 2106
+    mov eax, 12
+    mov ebx, seconds
+    mov ecx, nanoseconds
+    xor edx, edx
+    int 0x80
+    test edx, edx
+    jnz .failed
+    cmp dword [seconds], 0
+    je .failed
+    cmp dword [seconds + 4], 0
+    jne .failed
+    cmp dword [nanoseconds], 1000000000
+    jae .failed
+
+    mov ecx, ok_msg
+    mov edx, ok_len
+    jmp .report
+.failed:
+    mov ecx, err_msg
+    mov edx, err_len
+.report:
+    ;fwrite(fd, message, len)
+    mov eax, 7
+    mov ebx, [tty_fd]
+    int 0x80
+
+    ;fclose(fd)
+    mov eax, 5
+    mov ebx, [tty_fd]
+    int 0x80
+.done:
+    pause
+    jmp .done
+
+section .note.GNU-stack noalloc noexec nowrite progbits
@@ -2,7 +2,14 @@
 mod A0_trivial;
 mod A1_log_2_rounded_up;
 mod A2_vec;
+mod A3_rfs_large_files;
+mod A4_rfs_extents;
+mod A5_rfs_dir_index;
+mod A6_rfs_times;
+mod A7_symlinks;
+mod A8_rfs_checksums;
 mod memory_utils;
+mod rfs_fixture;
 
 #[cfg(feature = "run_tests")]
 static mut FREE_SPACE: [u8; 1032] = [0; 1032];
@@ -0,0 +1,98 @@
+//!The `rfs_test` partition the rfs tests run on. make_disk.sh creates it empty, every test module
+//!mounts it at a directory of its own in /tmp
+#![cfg(feature = "run_tests")]
+use crate::{
+    drivers::disk::MountedPartition,
+    println,
+    task_runner::block_task,
+    vfs::{
+        self, InodeType,
+        file::{FileFlags, FileHandle},
+    },
+};
+use std::boxed::Box;
+
+pub(super) const TEST_PARTITION: &str = "PARTLABEL=rfs_test";
+
+pub(super) fn mount(mountpoint: &str) -> bool {
+    if let Err(e) = block_task(Box::pin(vfs::mount(
+        TEST_PARTITION,
+        Some("rfs"),
+        vfs::resolve_path(mountpoint),
+    ))) {
+        println!("failed to mount {}: {:?}", TEST_PARTITION, e);
+        return false;
+    }
+    true
+}
+
+pub(super) fn unmount(mountpoint: &str) -> bool {
+    if block_task(Box::pin(vfs::unmount((&vfs::resolve_path(mountpoint)).into()))).is_err() {
+        println!("failed to unmount {}", mountpoint);
+        return false;
+    }
+    true
+}
+
+///The test partition itself, to change blocks beneath the filesystem while it isn't mounted
+pub(super) fn raw_partition() -> Option<MountedPartition> {
+    match vfs::raw_partition(TEST_PARTITION) {
+        Ok(partition) => Some(partition),
+        Err(e) => {
+            println!("failed to find {}: {:?}", TEST_PARTITION, e);
+            None
+        }
+    }
+}
+
+///Unmounts and mounts again, which drops the cached inodes and pages
+pub(super) fn remount(mountpoint: &str) -> bool {
+    unmount(mountpoint) && mount(mountpoint)
+}
+
+pub(super) fn open(path: &str, flags: FileFlags) -> Option<FileHandle> {
+    let resolved = vfs::resolve_path(path);
+    match block_task(Box::pin(vfs::open_file((&resolved).into(), None, flags))) {
+        Ok(handle) => Some(handle),
+        Err(e) => {
+            println!("failed to open {}: {:?}", path, e);
+            None
+        }
+    }
+}
+
+///Closes the handle, false if its cached data couldn't be written back
+pub(super) fn close(handle: FileHandle) -> bool {
+    if let Err(e) = block_task(Box::pin(vfs::close_file(handle))) {
+        println!("failed to write back on close: {:?}", e);
+        return false;
+    }
+    true
+}
+
+///Opened to create entries in
+pub(super) fn open_dir(path: &str) -> Option<FileHandle> {
+    open(path, FileFlags::new_with_flags(true, true, false, true))
+}
+
+pub(super) fn create(parent: &str, name: &str, inode_type: InodeType) -> bool {
+    let Some(mut handle) = open_dir(parent) else {
+        return false;
+    };
+    let result = block_task(Box::pin(vfs::create_file(&mut handle, name, inode_type)));
+    let closed = close(handle);
+    if let Err(e) = result {
+        println!("failed to create {}/{}: {:?}", parent, name, e);
+        return false;
+    }
+    closed
+}
+
+///Creates the mountpoint, which has to be directly in /tmp, and mounts the partition on it
+pub(super) fn mount_new(mountpoint: &str) -> bool {
+    let Some(name) = mountpoint.strip_prefix("/tmp/") else {
+        println!("{} is not in /tmp", mountpoint);
+        return false;
+    };
+    create("/tmp", name, InodeType::new_dir(0o755)) && mount(mountpoint)
+}
@@ -0,0 +1,150 @@
+//!/dev, a flat directory of device nodes. Drivers add nodes with [`register_device_node`]
+
+use std::{
+    boxed::Box,
+    collections::btree_map::BTreeMap,
+    lock_w_info,
+    mem_utils::PhysAddr,
+    sync::{arc::Arc, no_int_spinlock::NoIntSpinlock},
+    vec::Vec,
+};
+
+use crate::{
+    drivers::disk::DirEntry,
+    vfs::{DeviceId, Inode, InodeIndex, InodeType, ROOT_INODE_INDEX},
+};
+
+use super::VfsAdapterTrait;
+
+static DEVICE_REGISTRY: NoIntSpinlock<DeviceRegistry> = NoIntSpinlock::new(DeviceRegistry::new());
+
+///A character or block device that can be opened through /dev
+#[async_trait::async_trait]
+pub trait DeviceNode: core::fmt::Debug + Send + Sync {
+    fn is_block_device(&self) -> bool;
+    ///Size in bytes, 0 for character devices
+    fn size(&self) -> u64;
+    ///Offset must be page aligned. Returns the number of bytes read
+    async fn read(&self, offset: u64, size: u64, buffer: &[PhysAddr]) -> u64;
+    ///Offset must be page aligned. Returns the number of bytes written
+    async fn write(&self, offset: u64, size: u64, buffer: &[PhysAddr]) -> u64;
+}
+
+struct DeviceRegistry {
+    nodes: BTreeMap<InodeIndex, (Box<str>, Arc<dyn DeviceNode>)>,
+    next_inode: InodeIndex,
+}
+
+impl DeviceRegistry {
+    const fn new() -> Self {
+        DeviceRegistry {
+            nodes: BTreeMap::new(),
+            next_inode: ROOT_INODE_INDEX + 1,
+        }
+    }
+
+    fn find(&self, name: &str) -> Option<InodeIndex> {
+        self.nodes
+            .iter()
+            .find(|(_, (node_name, _))| **node_name == *name)
+            .map(|(inode, _)| *inode)
+    }
+}
+
+///Adds a node at /dev/name, replacing any node with the same name. Returns its inode index
+pub fn register_device_node(name: &str, node: Arc<dyn DeviceNode>) -> InodeIndex {
+    let mut registry = lock_w_info!(DEVICE_REGISTRY);
+    let inode = registry.find(name).unwrap_or_else(|| {
+        let inode = registry.next_inode;
+        registry.next_inode += 1;
+        inode
+    });
+    registry.nodes.insert(inode, (name.into(), node));
+    inode
+}
+
+pub fn unregister_device_node(name: &str) {
+    let mut registry = lock_w_info!(DEVICE_REGISTRY);
+    if let Some(inode) = registry.find(name) {
+        registry.nodes.remove(&inode);
+    }
+}
+
+fn get_device_node(inode: InodeIndex) -> Option<Arc<dyn DeviceNode>> {
+    lock_w_info!(DEVICE_REGISTRY).nodes.get(&inode).map(|(_, node)| node.clone())
+}
+
+#[derive(Debug)]
+pub struct DevAdapter {
+    device_id: DeviceId,
+}
+
+impl DevAdapter {
+    pub fn new(device_id: DeviceId) -> Self {
+        DevAdapter { device_id }
+    }
+
+    fn get_inode(&self, inode: InodeIndex) -> Inode {
+        let (type_mode, size) = if inode == ROOT_INODE_INDEX {
+            (InodeType::new_dir(0o755), 0)
+        } else {
+            match get_device_node(inode) {
+                Some(node) if node.is_block_device() => (InodeType::new_block_device(0o660), node.size()),
+                Some(node) => (InodeType::new_char_device(0o666), node.size()),
+                None => (InodeType::new_file(0), 0),
+            }
+        };
+        Inode {
+            index: inode,
+            device: self.device_id,
+            type_mode,
+            link_cnt: 1,
+            uid: 0,
+            gid: 0,
+            size,
+            access_time: 0,
+            modification_time: 0,
+            stat_change_time: 0,
+            preferred_block_size: 512,
+            blocks: 0,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl VfsAdapterTrait for DevAdapter {
+    async fn read(&self, inode: InodeIndex, offset_bytes: u64, size_bytes: u64, buffer: &[PhysAddr]) -> u64 {
+        match get_device_node(inode) {
+            Some(node) => node.read(offset_bytes, size_bytes, buffer).await,
+            None => 0,
+        }
+    }
+
+    async fn read_dir(&self, inode: InodeIndex) -> Box<[DirEntry]> {
+        if inode != ROOT_INODE_INDEX {
+            return Box::new([]);
+        }
+        let registry = lock_w_info!(DEVICE_REGISTRY);
+        let entries: Vec<DirEntry> = registry
+            .nodes
+            .iter()
+            .map(|(inode, (name, _))| DirEntry {
+                inode: *inode,
+                name: name.clone(),
+            })
+            .collect();
+        entries.into_boxed_slice()
+    }
+
+    async fn write(&self, inode: InodeIndex, offset: u64, size: u64, buffer: &[PhysAddr]) -> (Inode, u64) {
+        let written = match get_device_node(inode) {
+            Some(node) => node.write(offset, size, buffer).await,
+            None => 0,
+        };
+        (self.get_inode(inode), written)
+    }
+
+    async fn stat(&self, inode: InodeIndex) -> Inode {
+        self.get_inode(inode)
+    }
+}
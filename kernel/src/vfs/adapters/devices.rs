@@ -0,0 +1,289 @@
+//!Device nodes provided by the kernel itself: null, zero, urandom, tty and raw disks
+
+use core::arch::asm;
+use std::{
+    boxed::Box,
+    lock_w_info,
+    mem_utils::{PhysAddr, translate_phys_virt_addr},
+    print,
+    sync::{arc::Arc, no_int_spinlock::NoIntSpinlock},
+    vec::Vec,
+};
+
+use crate::{clocks, cpuid, drivers::disk::BlockDevice};
+
+use super::DeviceNode;
+
+///Largest number of sectors sent to a disk in one request
+const MAX_SECTORS_PER_REQUEST: u64 = 128;
+
+///Calls f on the first size bytes of the buffer, one page at a time. Returns the number of bytes
+///passed to f
+fn for_each_page(buffer: &[PhysAddr], size: u64, mut f: impl FnMut(&mut [u8])) -> u64 {
+    let mut done = 0;
+    for page in buffer {
+        if done == size {
+            break;
+        }
+        let len = (size - done).min(4096);
+        let ptr = translate_phys_virt_addr(*page).0 as *mut u8;
+        f(unsafe { core::slice::from_raw_parts_mut(ptr, len as usize) });
+        done += len;
+    }
+    done
+}
+
+///Reads return end of file, writes are discarded
+#[derive(Debug)]
+pub struct NullDevice;
+
+#[async_trait::async_trait]
+impl DeviceNode for NullDevice {
+    fn is_block_device(&self) -> bool {
+        false
+    }
+
+    fn size(&self) -> u64 {
+        0
+    }
+
+    async fn read(&self, _offset: u64, _size: u64, _buffer: &[PhysAddr]) -> u64 {
+        0
+    }
+
+    async fn write(&self, _offset: u64, size: u64, _buffer: &[PhysAddr]) -> u64 {
+        size
+    }
+}
+
+///Reads return zeroes, writes are discarded
+#[derive(Debug)]
+pub struct ZeroDevice;
+
+#[async_trait::async_trait]
+impl DeviceNode for ZeroDevice {
+    fn is_block_device(&self) -> bool {
+        false
+    }
+
+    fn size(&self) -> u64 {
+        0
+    }
+
+    async fn read(&self, _offset: u64, size: u64, buffer: &[PhysAddr]) -> u64 {
+        for_each_page(buffer, size, |page| page.fill(0))
+    }
+
+    async fn write(&self, _offset: u64, size: u64, _buffer: &[PhysAddr]) -> u64 {
+        size
+    }
+}
+
+///Uses rdrand if the cpu has it, otherwise a xorshift generator seeded from the uptime. Not suitable
+///for cryptography without rdrand. Writes are discarded
+#[derive(Debug)]
+pub struct RandomDevice {
+    has_rdrand: bool,
+    state: NoIntSpinlock<u64>,
+}
+
+impl RandomDevice {
+    pub fn new() -> Self {
+        let has_rdrand = cpuid::get_cpuid_leaf(1).is_some_and(|leaf| leaf.ecx & (1 << 30) != 0);
+        let seed = clocks::uptime().as_nanos() as u64;
+        RandomDevice {
+            has_rdrand,
+            //xorshift state must not be 0
+            state: NoIntSpinlock::new(seed | 1),
+        }
+    }
+
+    fn rdrand() -> Option<u64> {
+        //rdrand can fail if the hardware generator is busy, intel recommends 10 retries
+        for _ in 0..10 {
+            let value: u64;
+            let success: u8;
+            unsafe {
+                asm!(
+                    "rdrand {value}",
+                    "setc {success}",
+                    value = out(reg) value,
+                    success = out(reg_byte) success,
+                    options(nomem, nostack)
+                );
+            }
+            if success != 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    fn next(&self, state: &mut u64) -> u64 {
+        if self.has_rdrand {
+            if let Some(value) = Self::rdrand() {
+                return value;
+            }
+        }
+        //xorshift64*
+        *state ^= *state >> 12;
+        *state ^= *state << 25;
+        *state ^= *state >> 27;
+        state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+}
+
+#[async_trait::async_trait]
+impl DeviceNode for RandomDevice {
+    fn is_block_device(&self) -> bool {
+        false
+    }
+
+    fn size(&self) -> u64 {
+        0
+    }
+
+    async fn read(&self, _offset: u64, size: u64, buffer: &[PhysAddr]) -> u64 {
+        let mut state = lock_w_info!(self.state);
+        for_each_page(buffer, size, |page| {
+            for chunk in page.chunks_mut(8) {
+                let value = self.next(&mut state).to_le_bytes();
+                chunk.copy_from_slice(&value[..chunk.len()]);
+            }
+        })
+    }
+
+    async fn write(&self, _offset: u64, size: u64, _buffer: &[PhysAddr]) -> u64 {
+        size
+    }
+}
+
+#[derive(Debug)]
+pub struct TtyDevice {
+    buffered_input: NoIntSpinlock<Vec<u8>>,
+    ready_input: NoIntSpinlock<Vec<u8>>,
+}
+
+impl TtyDevice {
+    pub fn new() -> Self {
+        TtyDevice {
+            buffered_input: NoIntSpinlock::new(Vec::new()),
+            ready_input: NoIntSpinlock::new(Vec::new()),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl DeviceNode for TtyDevice {
+    fn is_block_device(&self) -> bool {
+        false
+    }
+
+    fn size(&self) -> u64 {
+        lock_w_info!(self.ready_input).len() as u64
+    }
+
+    async fn read(&self, _offset: u64, mut size_bytes: u64, buffer: &[PhysAddr]) -> u64 {
+        let mut ready_input = lock_w_info!(self.ready_input);
+        let mut block = 0;
+        let mut read_size = 0;
+        loop {
+            if size_bytes == 0 || ready_input.is_empty() {
+                break;
+            }
+            let size_to_read = size_bytes.min(4096).min(ready_input.len() as u64);
+            let Some(phys_ptr) = buffer.get(block as usize) else {
+                break;
+            };
+            let ptr = translate_phys_virt_addr(*phys_ptr).0 as *mut u8;
+            let slice = unsafe { core::slice::from_raw_parts_mut(ptr, size_to_read as usize) };
+            slice.copy_from_slice(&ready_input[..size_to_read as usize]);
+            ready_input.drain(..size_to_read as usize);
+            block += 1;
+            size_bytes -= size_to_read;
+            read_size += size_to_read;
+        }
+        read_size
+    }
+
+    async fn write(&self, _offset: u64, size: u64, buffer: &[PhysAddr]) -> u64 {
+        for i in 0..(size / 4096) {
+            let Some(phys_ptr) = buffer.get(i as usize) else {
+                return size;
+            };
+            let ptr = translate_phys_virt_addr(*phys_ptr).0 as *const u8;
+            let str = unsafe { core::str::from_raw_parts(ptr, 4096) };
+            print!("{}", str);
+        }
+        let Some(phys_ptr) = buffer.last() else {
+            return size;
+        };
+        let ptr = translate_phys_virt_addr(*phys_ptr).0 as *const u8;
+        let str = unsafe { core::str::from_raw_parts(ptr, (size % 4096) as usize) };
+        print!("{}", str);
+
+        size
+    }
+}
+
+///A whole disk or one partition of it. Transfers whole sectors only, a partial sector at the end
+///of a write is not written
+#[derive(Debug)]
+pub struct RawBlockDevice {
+    disk: Arc<dyn BlockDevice + Send>,
+    start_sector: u64,
+    size_sectors: u64,
+}
+
+impl RawBlockDevice {
+    pub fn new(disk: Arc<dyn BlockDevice + Send>, start_sector: u64, size_sectors: u64) -> Self {
+        RawBlockDevice {
+            disk,
+            start_sector,
+            size_sectors,
+        }
+    }
+
+    ///Splits the transfer into requests the disk accepts. Returns the number of sectors transferred
+    async fn transfer(&self, offset: u64, sectors: u64, buffer: &[PhysAddr], write: bool) -> u64 {
+        let first_sector = offset / 512;
+        if first_sector >= self.size_sectors {
+            return 0;
+        }
+        let sectors = sectors.min(self.size_sectors - first_sector).min(buffer.len() as u64 * 8);
+        let mut done = 0;
+        while done < sectors {
+            let count = (sectors - done).min(MAX_SECTORS_PER_REQUEST);
+            let pages = &buffer[(done / 8) as usize..(done + count).div_ceil(8) as usize];
+            let sector = (self.start_sector + first_sector + done) as usize;
+            if write {
+                self.disk.write(sector, count as usize, pages).await;
+            } else {
+                self.disk.read(sector, count as usize, pages).await;
+            }
+            done += count;
+        }
+        sectors
+    }
+}
+
+#[async_trait::async_trait]
+impl DeviceNode for RawBlockDevice {
+    fn is_block_device(&self) -> bool {
+        true
+    }
+
+    fn size(&self) -> u64 {
+        self.size_sectors * 512
+    }
+
+    async fn read(&self, offset: u64, size: u64, buffer: &[PhysAddr]) -> u64 {
+        //the rest of the last sector lands in the same buffer page, past size
+        let sectors = self.transfer(offset, size.div_ceil(512), buffer, false).await;
+        (sectors * 512).min(size)
+    }
+
+    async fn write(&self, offset: u64, size: u64, buffer: &[PhysAddr]) -> u64 {
+        self.transfer(offset, size / 512, buffer, true).await * 512
+    }
+}
@@ -1,15 +1,17 @@
 use core::{fmt::Debug, sync::atomic::AtomicU32};
-use std::{boxed::Box, mem_utils::PhysAddr};
+use std::{boxed::Box, error::ErrorCode, mem_utils::PhysAddr};
 
 use crate::drivers::disk::DirEntry;
 
 use super::{filesystem_trait::FileSystem, DeviceDetails, DeviceId, Inode, InodeIndex, Vfs};
 
+mod dev_adapter;
+mod devices;
 mod proc_adapter;
-mod tty_adapter;
 
+pub use dev_adapter::{DevAdapter, DeviceNode, register_device_node, unregister_device_node};
+pub use devices::{NullDevice, RandomDevice, RawBlockDevice, TtyDevice, ZeroDevice};
 pub use proc_adapter::ProcAdapter;
-pub use tty_adapter::TtyAdapter;
 use uuid::Uuid;
 
 #[derive(Debug)]
@@ -50,28 +52,33 @@ pub trait VfsAdapterTrait: Debug + Send + Sync {
 
 #[async_trait::async_trait]
 impl<T: VfsAdapterTrait> FileSystem for T {
-    async fn read(&self, inode: InodeIndex, offset_bytes: u64, size_bytes: u64, buffer: &[PhysAddr]) -> u64 {
-        VfsAdapterTrait::read(self, inode, offset_bytes, size_bytes, buffer).await
+    fn uses_page_cache(&self) -> bool {
+        false
     }
 
-    async fn read_dir(&self, inode: InodeIndex) -> Box<[DirEntry]> {
-        VfsAdapterTrait::read_dir(self, inode).await
+    async fn read(&self, inode: InodeIndex, offset_bytes: u64, size_bytes: u64, buffer: &[PhysAddr]) -> Result<u64, ErrorCode> {
+        Ok(VfsAdapterTrait::read(self, inode, offset_bytes, size_bytes, buffer).await)
     }
 
-    async fn write(&self, inode: InodeIndex, offset: u64, size: u64, buffer: &[PhysAddr]) -> (Inode, u64) {
-        VfsAdapterTrait::write(self, inode, offset, size, buffer).await
+    async fn read_dir(&self, inode: InodeIndex) -> Result<Box<[DirEntry]>, ErrorCode> {
+        Ok(VfsAdapterTrait::read_dir(self, inode).await)
     }
 
-    async fn stat(&self, inode: InodeIndex) -> Inode {
-        VfsAdapterTrait::stat(self, inode).await
+    async fn write(&self, inode: InodeIndex, offset: u64, size: u64, buffer: &[PhysAddr]) -> Result<(Inode, u64), ErrorCode> {
+        Ok(VfsAdapterTrait::write(self, inode, offset, size, buffer).await)
+    }
+
+    async fn stat(&self, inode: InodeIndex) -> Result<Inode, ErrorCode> {
+        Ok(VfsAdapterTrait::stat(self, inode).await)
     }
 
     async fn unmount(&self) {
         unreachable!()
     }
 
-    async fn set_stat(&self, _inode_index: InodeIndex, _inode_data: Inode) {
-        unreachable!()
+    //adapters have a fixed structure
+    async fn set_stat(&self, _inode_index: InodeIndex, _inode_data: Inode) -> Result<(), ErrorCode> {
+        Err(ErrorCode::UnsupportedOperation)
     }
 
     async fn create(
@@ -81,23 +88,34 @@ impl<T: VfsAdapterTrait> FileSystem for T {
         _type_mode: super::InodeType,
         _uid: u16,
         _gid: u16,
-    ) -> (Inode, Inode) {
-        unreachable!()
+    ) -> Result<(Inode, Inode), ErrorCode> {
+        Err(ErrorCode::UnsupportedOperation)
     }
 
-    async fn unlink(&self, _parent_inode: InodeIndex, _name: &str) {
-        unreachable!()
+    async fn symlink(
+        &self,
+        _name: &str,
+        _parent_dir: InodeIndex,
+        _target: &str,
+        _uid: u16,
+        _gid: u16,
+    ) -> Result<(Inode, Inode), ErrorCode> {
+        Err(ErrorCode::UnsupportedOperation)
     }
 
-    async fn link(&self, _inode: InodeIndex, _parent_dir: InodeIndex, _name: &str) -> Inode {
-        unreachable!()
+    async fn unlink(&self, _parent_inode: InodeIndex, _name: &str) -> Result<(), ErrorCode> {
+        Err(ErrorCode::UnsupportedOperation)
     }
 
-    async fn truncate(&self, _inode: InodeIndex, _size: u64) {
-        unreachable!()
+    async fn link(&self, _inode: InodeIndex, _parent_dir: InodeIndex, _name: &str) -> Result<Inode, ErrorCode> {
+        Err(ErrorCode::UnsupportedOperation)
     }
 
-    async fn rename(&self, _inode: InodeIndex, _parent_inode: InodeIndex, _name: &str) {
-        unreachable!()
+    async fn truncate(&self, _inode: InodeIndex, _size: u64) -> Result<(), ErrorCode> {
+        Err(ErrorCode::UnsupportedOperation)
+    }
+
+    async fn rename(&self, _inode: InodeIndex, _parent_inode: InodeIndex, _name: &str) -> Result<(), ErrorCode> {
+        Err(ErrorCode::UnsupportedOperation)
     }
 }
@@ -1,59 +1,344 @@
-use crate::vfs::InodeType;
+//!/proc, a read-only view of the running system. File contents are generated on every read
+
+use core::fmt::Write;
+use std::{
+    boxed::Box,
+    mem_utils::{PhysAddr, translate_phys_virt_addr},
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use crate::{
+    acpi::cpu_locals::CpuLocals,
+    clocks, cpuid,
+    drivers::disk::DirEntry,
+    memory::physical_allocator,
+    proc::{self, Pid, ProcessData, ProcessState},
+    vfs::{
+        self, DeviceId, Inode, InodeIndex, InodeType, ROOT_INODE_INDEX,
+        file::{FileDescriptor, FileHandle},
+    },
+};
 
 use super::VfsAdapterTrait;
-use std::boxed::Box;
+
+//inode layout: bits 32..64 hold pid + 1 for per process entries and 0 for global ones, bits 24..32
+//the entry kind and bits 0..24 the file descriptor of fd/ entries
+const PID_SHIFT: u64 = 32;
+const KIND_SHIFT: u64 = 24;
+const KIND_MASK: u64 = 0xFF;
+const FD_MASK: u64 = (1 << KIND_SHIFT) - 1;
+
+const MEMINFO_INODE: InodeIndex = 3;
+const CPUINFO_INODE: InodeIndex = 4;
+const MOUNTS_INODE: InodeIndex = 5;
+const UPTIME_INODE: InodeIndex = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ProcEntry {
+    Root,
+    MemInfo,
+    CpuInfo,
+    Mounts,
+    Uptime,
+    ProcessDir(Pid),
+    Cmdline(Pid),
+    Status(Pid),
+    Maps(Pid),
+    FdDir(Pid),
+    Fd(Pid, FileDescriptor),
+}
+
+const GLOBAL_FILES: [(&str, ProcEntry); 4] = [
+    ("meminfo", ProcEntry::MemInfo),
+    ("cpuinfo", ProcEntry::CpuInfo),
+    ("mounts", ProcEntry::Mounts),
+    ("uptime", ProcEntry::Uptime),
+];
+
+impl ProcEntry {
+    fn from_inode(inode: InodeIndex) -> Option<Self> {
+        let pid_part = inode >> PID_SHIFT;
+        if pid_part == 0 {
+            return match inode {
+                ROOT_INODE_INDEX => Some(ProcEntry::Root),
+                MEMINFO_INODE => Some(ProcEntry::MemInfo),
+                CPUINFO_INODE => Some(ProcEntry::CpuInfo),
+                MOUNTS_INODE => Some(ProcEntry::Mounts),
+                UPTIME_INODE => Some(ProcEntry::Uptime),
+                _ => None,
+            };
+        }
+        let pid = Pid(u32::try_from(pid_part - 1).ok()?);
+        match (inode >> KIND_SHIFT) & KIND_MASK {
+            0 => Some(ProcEntry::ProcessDir(pid)),
+            1 => Some(ProcEntry::Cmdline(pid)),
+            2 => Some(ProcEntry::Status(pid)),
+            3 => Some(ProcEntry::Maps(pid)),
+            4 => Some(ProcEntry::FdDir(pid)),
+            5 => Some(ProcEntry::Fd(pid, inode & FD_MASK)),
+            _ => None,
+        }
+    }
+
+    fn inode(&self) -> InodeIndex {
+        let process_inode = |pid: Pid, kind: u64| ((pid.0 as u64 + 1) << PID_SHIFT) | (kind << KIND_SHIFT);
+        match *self {
+            ProcEntry::Root => ROOT_INODE_INDEX,
+            ProcEntry::MemInfo => MEMINFO_INODE,
+            ProcEntry::CpuInfo => CPUINFO_INODE,
+            ProcEntry::Mounts => MOUNTS_INODE,
+            ProcEntry::Uptime => UPTIME_INODE,
+            ProcEntry::ProcessDir(pid) => process_inode(pid, 0),
+            ProcEntry::Cmdline(pid) => process_inode(pid, 1),
+            ProcEntry::Status(pid) => process_inode(pid, 2),
+            ProcEntry::Maps(pid) => process_inode(pid, 3),
+            ProcEntry::FdDir(pid) => process_inode(pid, 4),
+            ProcEntry::Fd(pid, fd) => process_inode(pid, 5) | fd,
+        }
+    }
+
+    fn is_dir(&self) -> bool {
+        matches!(self, ProcEntry::Root | ProcEntry::ProcessDir(_) | ProcEntry::FdDir(_))
+    }
+
+    fn dir_entry(&self, name: &str) -> DirEntry {
+        DirEntry {
+            inode: self.inode(),
+            name: name.into(),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct ProcAdapter {
-    device_id: crate::vfs::DeviceId,
+    device_id: DeviceId,
 }
 
 impl ProcAdapter {
-    pub fn new(device_id: crate::vfs::DeviceId) -> Self {
+    pub fn new(device_id: DeviceId) -> Self {
         ProcAdapter { device_id }
     }
-}
 
-#[async_trait::async_trait]
-impl VfsAdapterTrait for ProcAdapter {
-    async fn read(
-        &self,
-        _inode: crate::vfs::InodeIndex,
-        _offset_bytes: u64,
-        _size_bytes: u64,
-        _buffer: &[std::mem_utils::PhysAddr],
-    ) -> u64 {
-        todo!()
-    }
-
-    async fn read_dir(&self, _inode: crate::vfs::InodeIndex) -> std::boxed::Box<[crate::drivers::disk::DirEntry]> {
-        todo!()
+    fn list_dir(entry: ProcEntry) -> Vec<DirEntry> {
+        match entry {
+            ProcEntry::Root => {
+                let mut entries: Vec<DirEntry> = GLOBAL_FILES.iter().map(|(name, file)| file.dir_entry(name)).collect();
+                for pid in proc::get_pids() {
+                    entries.push(ProcEntry::ProcessDir(pid).dir_entry(&pid.0.to_string()));
+                }
+                entries
+            }
+            ProcEntry::ProcessDir(pid) if proc::get_proc(pid).is_some() => Vec::from([
+                ProcEntry::Cmdline(pid).dir_entry("cmdline"),
+                ProcEntry::Status(pid).dir_entry("status"),
+                ProcEntry::Maps(pid).dir_entry("maps"),
+                ProcEntry::FdDir(pid).dir_entry("fd"),
+            ]),
+            ProcEntry::FdDir(pid) => {
+                let Some(process) = proc::get_proc(pid) else {
+                    return Vec::new();
+                };
+                let process_mut = process.get_mutable();
+                process_mut
+                    .file_handles()
+                    .filter(|(fd, _)| *fd <= FD_MASK)
+                    .map(|(fd, _)| ProcEntry::Fd(pid, fd).dir_entry(&fd.to_string()))
+                    .collect()
+            }
+            _ => Vec::new(),
+        }
     }
 
-    async fn write(
-        &self,
-        _inode: crate::vfs::InodeIndex,
-        _offset: u64,
-        _size: u64,
-        _buffer: &[std::mem_utils::PhysAddr],
-    ) -> (crate::vfs::Inode, u64) {
-        todo!()
+    ///Returns None for directories and entries of processes that no longer exist
+    fn generate(entry: ProcEntry) -> Option<String> {
+        let mut out = String::new();
+        let res = match entry {
+            ProcEntry::MemInfo => write_meminfo(&mut out),
+            ProcEntry::CpuInfo => write_cpuinfo(&mut out),
+            ProcEntry::Mounts => write_mounts(&mut out),
+            ProcEntry::Uptime => {
+                let uptime = clocks::uptime();
+                writeln!(out, "{}.{:02}", uptime.as_secs(), uptime.subsec_millis() / 10)
+            }
+            ProcEntry::Cmdline(pid) => writeln!(out, "{}", proc::get_proc(pid)?.cmdline()),
+            ProcEntry::Status(pid) => write_status(&mut out, &*proc::get_proc(pid)?, proc::get_proc_state(pid)?),
+            ProcEntry::Maps(pid) => write_maps(&mut out, &*proc::get_proc(pid)?),
+            ProcEntry::Fd(pid, fd) => {
+                let process = proc::get_proc(pid)?;
+                let process_mut = process.get_mutable();
+                write_fd(&mut out, process_mut.get_file_handle(fd)?)
+            }
+            ProcEntry::Root | ProcEntry::ProcessDir(_) | ProcEntry::FdDir(_) => return None,
+        };
+        res.expect("writing to a String can't fail");
+        Some(out)
     }
 
-    async fn stat(&self, inode: crate::vfs::InodeIndex) -> crate::vfs::Inode {
-        crate::vfs::Inode {
+    fn get_inode(&self, inode: InodeIndex) -> Inode {
+        let entry = ProcEntry::from_inode(inode);
+        let is_dir = entry.is_some_and(|entry| entry.is_dir());
+        let size = entry.and_then(Self::generate).map_or(0, |content| content.len() as u64);
+        Inode {
             index: inode,
             device: self.device_id,
-            type_mode: InodeType::new_dir(0o755),
-            link_cnt: 1,
+            type_mode: if is_dir {
+                InodeType::new_dir(0o555)
+            } else {
+                InodeType::new_file(0o444)
+            },
+            link_cnt: if is_dir { 2 } else { 1 },
             uid: 0,
             gid: 0,
-            size: 0,
+            size,
             access_time: 0,
             modification_time: 0,
             stat_change_time: 0,
             preferred_block_size: 512,
-            blocks: u32::MAX,
+            blocks: 0,
+        }
+    }
+}
+
+fn write_meminfo(out: &mut String) -> core::fmt::Result {
+    let (total_frames, free_frames) = physical_allocator::frame_stats();
+    writeln!(out, "MemTotal:\t{} kB", total_frames * 4)?;
+    writeln!(out, "MemFree:\t{} kB", free_frames * 4)?;
+    writeln!(out, "MemUsed:\t{} kB", total_frames.saturating_sub(free_frames) * 4)
+}
+
+fn write_cpuinfo(out: &mut String) -> core::fmt::Result {
+    let vendor = cpuid::get_manufacturer_id();
+    let vendor = core::str::from_utf8(&vendor).unwrap_or("unknown");
+    let brand = cpuid::get_brand_string();
+    let brand = brand
+        .as_ref()
+        .and_then(|brand| core::str::from_utf8(brand).ok())
+        .map_or("unknown", |brand| {
+            brand.trim_matches(|c: char| c == '\0' || c.is_whitespace())
+        });
+    let (family, model, stepping) = cpuid::get_family_model_stepping();
+    let running: Vec<(u32, Pid)> = proc::get_pids()
+        .into_iter()
+        .filter_map(|pid| match proc::get_proc_state(pid) {
+            Some(ProcessState::Running(cpu)) => Some((cpu, pid)),
+            _ => None,
+        })
+        .collect();
+
+    for locals in CpuLocals::all() {
+        writeln!(out, "processor\t: {}", locals.processor_id)?;
+        writeln!(out, "apic id\t\t: {}", locals.apic_id)?;
+        writeln!(out, "vendor_id\t: {}", vendor)?;
+        writeln!(out, "cpu family\t: {}", family)?;
+        writeln!(out, "model\t\t: {}", model)?;
+        writeln!(out, "stepping\t: {}", stepping)?;
+        writeln!(out, "model name\t: {}", brand)?;
+        match running.iter().find(|(cpu, _)| *cpu == locals.apic_id as u32) {
+            Some((_, pid)) => writeln!(out, "running pid\t: {}", pid.0)?,
+            None => writeln!(out, "running pid\t: -")?,
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+fn write_mounts(out: &mut String) -> core::fmt::Result {
+    for mount in vfs::get_mounts() {
+        writeln!(
+            out,
+            "{} /{} {} {}",
+            mount.source,
+            mount.mountpoint.inner().join("/"),
+            mount.fs_type,
+            if mount.read_only { "ro" } else { "rw" }
+        )?;
+    }
+    Ok(())
+}
+
+fn write_status(out: &mut String, process: &ProcessData, state: ProcessState) -> core::fmt::Result {
+    let name = process.cmdline().split(' ').next().unwrap_or_default();
+    let vm_size_pages: u64 = process.memory_regions().iter().map(|region| region.size_pages).sum();
+    let fd_count = process.get_mutable().file_handles().count();
+
+    writeln!(out, "Name:\t{}", name)?;
+    writeln!(out, "Pid:\t{}", process.pid().0)?;
+    match state {
+        ProcessState::Running(cpu) => writeln!(out, "State:\tR (running on cpu {})", cpu)?,
+        ProcessState::Ready => writeln!(out, "State:\tR (ready)")?,
+        ProcessState::Sleeping => writeln!(out, "State:\tS (sleeping)")?,
+        ProcessState::Stopping => writeln!(out, "State:\tX (stopping)")?,
+    }
+    writeln!(out, "Arch:\t{}", if process.is_32_bit() { "i386" } else { "x86_64" })?;
+    writeln!(out, "Fds:\t{}", fd_count)?;
+    writeln!(out, "VmSize:\t{} kB", vm_size_pages * 4)
+}
+
+fn write_maps(out: &mut String, process: &ProcessData) -> core::fmt::Result {
+    for region in process.memory_regions() {
+        let end = region.base.0 + region.size_pages * 0x1000;
+        let write = if region.flags.is_writeable() { 'w' } else { '-' };
+        let execute = if region.flags.is_executable() { 'x' } else { '-' };
+        writeln!(
+            out,
+            "{:016x}-{:016x} r{}{}p {}",
+            region.base.0, end, write, execute, region.name
+        )?;
+    }
+    Ok(())
+}
+
+fn write_fd(out: &mut String, handle: &FileHandle) -> core::fmt::Result {
+    let flags = handle.file_flags;
+    writeln!(out, "pos:\t{}", handle.position)?;
+    writeln!(
+        out,
+        "flags:\t{}{}{}{}",
+        if flags.read() { 'r' } else { '-' },
+        if flags.write() { 'w' } else { '-' },
+        if flags.append() { 'a' } else { '-' },
+        if flags.dir() { 'd' } else { '-' },
+    )?;
+    writeln!(out, "dev:\t{}", handle.inode.device_id.0)?;
+    writeln!(out, "inode:\t{}", handle.inode.index)
+}
+
+///Copies data to the start of the buffer pages, returns the number of bytes copied
+fn copy_to_buffer(data: &[u8], buffer: &[PhysAddr]) -> u64 {
+    let mut copied = 0;
+    for (chunk, page) in data.chunks(4096).zip(buffer) {
+        let ptr = translate_phys_virt_addr(*page).0 as *mut u8;
+        unsafe { core::ptr::copy_nonoverlapping(chunk.as_ptr(), ptr, chunk.len()) };
+        copied += chunk.len() as u64;
+    }
+    copied
+}
+
+#[async_trait::async_trait]
+impl VfsAdapterTrait for ProcAdapter {
+    async fn read(&self, inode: InodeIndex, offset_bytes: u64, size_bytes: u64, buffer: &[PhysAddr]) -> u64 {
+        let Some(content) = ProcEntry::from_inode(inode).and_then(Self::generate) else {
+            return 0;
+        };
+        let start = (offset_bytes as usize).min(content.len());
+        let end = start.saturating_add(size_bytes as usize).min(content.len());
+        copy_to_buffer(&content.as_bytes()[start..end], buffer)
+    }
+
+    async fn read_dir(&self, inode: InodeIndex) -> Box<[DirEntry]> {
+        match ProcEntry::from_inode(inode) {
+            Some(entry) => Self::list_dir(entry).into_boxed_slice(),
+            None => Box::new([]),
         }
     }
+
+    async fn write(&self, inode: InodeIndex, _offset: u64, _size: u64, _buffer: &[PhysAddr]) -> (Inode, u64) {
+        //procfs is read-only
+        (self.get_inode(inode), 0)
+    }
+
+    async fn stat(&self, inode: InodeIndex) -> Inode {
+        self.get_inode(inode)
+    }
 }
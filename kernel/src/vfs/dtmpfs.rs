@@ -5,6 +5,7 @@
 use std::{
     boxed::Box,
     collections::btree_map::BTreeMap,
+    error::ErrorCode,
     lock_w_info,
     string::{String, ToString},
     sync::arc::Arc,
@@ -43,6 +44,10 @@ impl DtmpfsFactory {
 
 #[async_trait::async_trait]
 impl FileSystemFactory for DtmpfsFactory {
+    fn name(&self) -> &'static str {
+        "dtmpfs"
+    }
+
     async fn mount(&self, _partition: crate::drivers::disk::MountedPartition) -> Arc<dyn FileSystem + Send> {
         let mut inodes = BTreeMap::new();
         inodes.insert(ROOT_INODE_INDEX, DtmpfsNode { children: Vec::new() });
@@ -59,34 +64,49 @@ impl FileSystemFactory for DtmpfsFactory {
 
 #[async_trait::async_trait]
 impl FileSystem for Dtmpfs {
+    fn uses_page_cache(&self) -> bool {
+        false
+    }
+
     async fn unmount(&self) {}
 
-    async fn read(&self, _inode: InodeIndex, _offset_bytes: u64, _size_bytes: u64, _buffer: &[std::mem_utils::PhysAddr]) -> u64 {
-        panic!("Reading is not supported in dtmpfs");
+    async fn read(
+        &self,
+        _inode: InodeIndex,
+        _offset_bytes: u64,
+        _size_bytes: u64,
+        _buffer: &[std::mem_utils::PhysAddr],
+    ) -> Result<u64, ErrorCode> {
+        Err(ErrorCode::IsDirectory)
     }
 
-    async fn read_dir(&self, inode: InodeIndex) -> std::boxed::Box<[crate::drivers::disk::DirEntry]> {
+    async fn read_dir(&self, inode: InodeIndex) -> Result<std::boxed::Box<[crate::drivers::disk::DirEntry]>, ErrorCode> {
         let inner = lock_w_info!(self.global_lock);
         let mut entries = Vec::new();
-        if let Some(node) = inner.inodes.get(&inode) {
-            for (name, child_inode) in &node.children {
-                entries.push(crate::drivers::disk::DirEntry {
-                    name: name.clone().into_boxed_str(),
-                    inode: *child_inode,
-                });
-            }
+        let node = inner.inodes.get(&inode).ok_or(ErrorCode::InodeNotPresent)?;
+        for (name, child_inode) in &node.children {
+            entries.push(crate::drivers::disk::DirEntry {
+                name: name.clone().into_boxed_str(),
+                inode: *child_inode,
+            });
         }
         drop(inner);
-        entries.into_boxed_slice()
+        Ok(entries.into_boxed_slice())
     }
 
-    async fn write(&self, _inode: InodeIndex, _offset: u64, _size: u64, _buffer: &[std::mem_utils::PhysAddr]) -> (super::Inode, u64) {
-        panic!("Writing is not supported in dtmpfs");
+    async fn write(
+        &self,
+        _inode: InodeIndex,
+        _offset: u64,
+        _size: u64,
+        _buffer: &[std::mem_utils::PhysAddr],
+    ) -> Result<(super::Inode, u64), ErrorCode> {
+        Err(ErrorCode::IsDirectory)
     }
 
-    async fn stat(&self, inode: InodeIndex) -> super::Inode {
+    async fn stat(&self, inode: InodeIndex) -> Result<super::Inode, ErrorCode> {
         unsafe {
-            super::Inode {
+            Ok(super::Inode {
                 index: inode,
                 device: DeviceId(0),
                 type_mode: InodeType::new_dir(0o755), //rwxr-xr-x
@@ -99,12 +119,12 @@ impl FileSystem for Dtmpfs {
                 access_time: 0,
                 modification_time: 0,
                 stat_change_time: 0,
-            }
+            })
         }
     }
 
-    async fn set_stat(&self, _inode_index: InodeIndex, _inode_data: super::Inode) {
-        panic!("Setting stat is not supported in dtmpfs");
+    async fn set_stat(&self, _inode_index: InodeIndex, _inode_data: super::Inode) -> Result<(), ErrorCode> {
+        Err(ErrorCode::UnsupportedOperation)
     }
 
     async fn create(
@@ -114,45 +134,60 @@ impl FileSystem for Dtmpfs {
         _type_mode: super::InodeType,
         _uid: u16,
         _gid: u16,
-    ) -> (super::Inode, super::Inode) {
+    ) -> Result<(super::Inode, super::Inode), ErrorCode> {
         let mut inner = lock_w_info!(self.global_lock);
+        let parent_inode = inner.inodes.get(&parent_dir).ok_or(ErrorCode::InodeNotPresent)?;
+        if parent_inode.children.iter().any(|(n, _)| n == name) {
+            return Err(ErrorCode::Exists);
+        }
         let inode_index = inner.inode_index;
         inner.inode_index += 1;
         inner.inodes.insert(inode_index, DtmpfsNode { children: Vec::new() });
-
-        let Some(parent_inode) = inner.inodes.get_mut(&parent_dir) else {
-            panic!("Parent directory inode {} not found", parent_dir);
-        };
-
+        let parent_inode = inner.inodes.get_mut(&parent_dir).expect("checked above");
         parent_inode.children.push((name.to_string(), inode_index));
         drop(inner);
-        (self.stat(parent_dir).await, self.stat(inode_index).await)
+        Ok((self.stat(inode_index).await?, self.stat(parent_dir).await?))
+    }
+
+    async fn symlink(
+        &self,
+        _name: &str,
+        _parent_dir: InodeIndex,
+        _target: &str,
+        _uid: u16,
+        _gid: u16,
+    ) -> Result<(super::Inode, super::Inode), ErrorCode> {
+        Err(ErrorCode::UnsupportedOperation)
     }
 
-    async fn unlink(&self, parent_inode: InodeIndex, name: &str) {
+    async fn unlink(&self, parent_inode: InodeIndex, name: &str) -> Result<(), ErrorCode> {
         let mut inner = lock_w_info!(self.global_lock);
-        if let Some(parent_node) = inner.inodes.get_mut(&parent_inode) {
-            parent_node.children.retain(|(n, _)| n != name);
-        }
+        let parent_node = inner.inodes.get_mut(&parent_inode).ok_or(ErrorCode::InodeNotPresent)?;
+        let position = parent_node
+            .children
+            .iter()
+            .position(|(n, _)| n == name)
+            .ok_or(ErrorCode::NoEntry)?;
+        parent_node.children.remove(position);
+        Ok(())
     }
 
-    async fn link(&self, _inode: InodeIndex, _parent_dir: InodeIndex, _name: &str) -> super::Inode {
-        panic!("Linking is not supported in dtmpfs");
+    async fn link(&self, _inode: InodeIndex, _parent_dir: InodeIndex, _name: &str) -> Result<super::Inode, ErrorCode> {
+        Err(ErrorCode::UnsupportedOperation)
     }
 
-    async fn truncate(&self, _inode: InodeIndex, _size: u64) {
-        panic!("Truncating is not supported in dtmpfs");
+    async fn truncate(&self, _inode: InodeIndex, _size: u64) -> Result<(), ErrorCode> {
+        Err(ErrorCode::IsDirectory)
     }
 
-    async fn rename(&self, inode: InodeIndex, parent_inode: InodeIndex, name: &str) {
+    async fn rename(&self, inode: InodeIndex, parent_inode: InodeIndex, name: &str) -> Result<(), ErrorCode> {
         let mut inner = lock_w_info!(self.global_lock);
-        let Some(parent_node) = inner.inodes.get_mut(&parent_inode) else {
-            return;
-        };
+        let parent_node = inner.inodes.get_mut(&parent_inode).ok_or(ErrorCode::InodeNotPresent)?;
         if let Some((_, child_inode)) = parent_node.children.iter_mut().find(|(n, _)| n == name) {
             *child_inode = inode;
         } else {
             parent_node.children.push((name.to_string(), inode));
         }
+        Ok(())
     }
 }
@@ -1,9 +1,14 @@
 use bitfield::bitfield;
+use std::{collections::btree_map::BTreeMap, lock_w_info, sync::no_int_spinlock::NoIntSpinlock};
 
-use super::{InodeIdentifier, InodeIdentifierChain};
+use super::{DeviceId, InodeIdentifier, InodeIdentifierChain, InodeIndex};
 
 pub type FileDescriptor = u64;
 
+///Number of live file handles per inode. A device with open files can't be unmounted, and an
+///unlinked inode is only freed once it is closed
+static OPEN_FILES: NoIntSpinlock<BTreeMap<InodeIdentifier, usize>> = NoIntSpinlock::new(BTreeMap::new());
+
 #[derive(Debug)]
 pub struct FileHandle {
     pub inode: InodeIdentifier,
@@ -12,6 +17,43 @@ pub struct FileHandle {
     pub file_flags: FileFlags,
 }
 
+impl FileHandle {
+    pub(super) fn new(inode: InodeIdentifier, parent_chain: InodeIdentifierChain, file_flags: FileFlags) -> Self {
+        *lock_w_info!(OPEN_FILES).entry(inode).or_insert(0) += 1;
+        FileHandle {
+            inode,
+            parent_chain,
+            position: 0,
+            file_flags,
+        }
+    }
+}
+
+impl Drop for FileHandle {
+    fn drop(&mut self) {
+        let mut open_files = lock_w_info!(OPEN_FILES);
+        if let Some(count) = open_files.get_mut(&self.inode) {
+            *count -= 1;
+            if *count == 0 {
+                open_files.remove(&self.inode);
+            }
+        }
+    }
+}
+
+pub(super) fn has_open_files(device: DeviceId) -> bool {
+    let first = InodeIdentifier { device_id: device, index: 0 };
+    let last = InodeIdentifier {
+        device_id: device,
+        index: InodeIndex::MAX,
+    };
+    lock_w_info!(OPEN_FILES).range(first..=last).next().is_some()
+}
+
+pub fn is_open(inode: InodeIdentifier) -> bool {
+    lock_w_info!(OPEN_FILES).contains_key(&inode)
+}
+
 bitfield! {
     #[derive(Clone, Copy, PartialEq, Eq)]
     pub struct FileFlags(u8);
@@ -20,8 +62,11 @@ bitfield! {
     pub write, set_write: 1;
     pub append, set_append: 2;
     //bit 3 is create, not a file bit
-    //bit 4 is truncate, not a file bit
+    ///cut the file to 0 bytes when it is opened for writing, cleared from the handle
+    pub truncate, set_truncate: 4;
     pub dir, set_dir: 5;
+    ///open a symlink in the last component itself instead of its target. Reading it returns the target
+    pub no_follow, set_no_follow: 6;
 }
 
 impl FileFlags {
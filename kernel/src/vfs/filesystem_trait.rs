@@ -1,5 +1,5 @@
 use core::fmt::Debug;
-use std::{sync::arc::Arc, boxed::Box, mem_utils::PhysAddr};
+use std::{sync::arc::Arc, boxed::Box, error::ErrorCode, mem_utils::PhysAddr};
 
 use crate::drivers::disk::{DirEntry, MountedPartition};
 
@@ -8,24 +8,54 @@ use super::{Inode, InodeIndex, InodeType};
 
 #[async_trait::async_trait]
 pub trait FileSystemFactory: Send + Sync {
+    ///Filesystem type as shown in /proc/mounts
+    fn name(&self) -> &'static str;
     async fn mount(&self, partition: MountedPartition) -> Arc<dyn FileSystem + Send>;
 }
 
 #[async_trait::async_trait]
 pub trait FileSystem: Debug + Send + Sync {
+    ///false if the data is already in memory, or generated on every read
+    fn uses_page_cache(&self) -> bool;
     async fn unmount(&self);
     ///Offset must be page aligned
-    async fn read(&self, inode: InodeIndex, offset_bytes: u64, size_bytes: u64, buffer: &[PhysAddr]) -> u64;
-    async fn read_dir(&self, inode: InodeIndex) -> Box<[DirEntry]>;
+    async fn read(&self, inode: InodeIndex, offset_bytes: u64, size_bytes: u64, buffer: &[PhysAddr]) -> Result<u64, ErrorCode>;
+    async fn read_dir(&self, inode: InodeIndex) -> Result<Box<[DirEntry]>, ErrorCode>;
+    ///Inode of the entry called name. Filesystems that can find an entry without listing the
+    ///whole directory override this
+    async fn lookup(&self, dir: InodeIndex, name: &str) -> Result<InodeIndex, ErrorCode> {
+        self.read_dir(dir)
+            .await?
+            .iter()
+            .find(|entry| *entry.name == *name)
+            .map(|entry| entry.inode)
+            .ok_or(ErrorCode::NoEntry)
+    }
     ///Offset must be page aligned. Returns the new inode
-    async fn write(&self, inode: InodeIndex, offset: u64, size: u64, buffer: &[PhysAddr]) -> (Inode, u64);
-    async fn stat(&self, inode: InodeIndex) -> Inode;
-    async fn set_stat(&self, inode_index: InodeIndex, inode_data: Inode);
-    ///returns the new parent inode in the first field and the new inode in the second
-    async fn create(&self, name: &str, parent_dir: InodeIndex, type_mode: InodeType, uid: u16, gid: u16) -> (Inode, Inode);
-    async fn unlink(&self, parent_inode: InodeIndex, name: &str);
+    async fn write(&self, inode: InodeIndex, offset: u64, size: u64, buffer: &[PhysAddr]) -> Result<(Inode, u64), ErrorCode>;
+    async fn stat(&self, inode: InodeIndex) -> Result<Inode, ErrorCode>;
+    async fn set_stat(&self, inode_index: InodeIndex, inode_data: Inode) -> Result<(), ErrorCode>;
+    ///returns the new inode in the first field and the new parent inode in the second
+    async fn create(
+        &self,
+        name: &str,
+        parent_dir: InodeIndex,
+        type_mode: InodeType,
+        uid: u16,
+        gid: u16,
+    ) -> Result<(Inode, Inode), ErrorCode>;
+    ///Creates a symlink pointing to target, which is at most 4096 bytes. Returns the same as create
+    async fn symlink(
+        &self,
+        name: &str,
+        parent_dir: InodeIndex,
+        target: &str,
+        uid: u16,
+        gid: u16,
+    ) -> Result<(Inode, Inode), ErrorCode>;
+    async fn unlink(&self, parent_inode: InodeIndex, name: &str) -> Result<(), ErrorCode>;
     ///returns the new parent inode
-    async fn link(&self, inode: InodeIndex, parent_dir: InodeIndex, name: &str) -> Inode;
-    async fn truncate(&self, inode: InodeIndex, size: u64);
-    async fn rename(&self, inode: InodeIndex, parent_inode: InodeIndex, name: &str);
+    async fn link(&self, inode: InodeIndex, parent_dir: InodeIndex, name: &str) -> Result<Inode, ErrorCode>;
+    async fn truncate(&self, inode: InodeIndex, size: u64) -> Result<(), ErrorCode>;
+    async fn rename(&self, inode: InodeIndex, parent_inode: InodeIndex, name: &str) -> Result<(), ErrorCode>;
 }
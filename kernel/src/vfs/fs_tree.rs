@@ -3,12 +3,16 @@ use std::vec;
 use std::{
     boxed::Box,
     collections::btree_map::BTreeMap,
-    lock_w_info, printlnc,
+    lock_w_info,
+    mem_utils::translate_phys_virt_addr,
+    printlnc,
     sync::no_int_spinlock::{NoIntSpinlock, NoIntSpinlockGuard},
     vec::Vec,
 };
 
-use super::{DeviceId, Inode, InodeIdentifier, InodeIdentifierChain, ResolvedPathBorrowed, VFS};
+use crate::memory::physical_allocator;
+
+use super::{DeviceId, Inode, InodeIdentifier, InodeIdentifierChain, ResolvedPathBorrowed, VFS, resolve_path};
 
 pub(super) static INODE_CACHE: NoIntSpinlock<InodeCache> = NoIntSpinlock::new(InodeCache::new());
 
@@ -54,23 +58,39 @@ pub fn get_inode(inode_index: InodeIdentifier) -> Option<Inode> {
     cache.inodes.get(&inode_index).map(|(inode, _)| inode).cloned()
 }
 
+///Returns the root of the filesystem mounted on top of inode, or inode itself if nothing is mounted there
+fn follow_mounts(cache: &InodeCache, mut inode: InodeIdentifier) -> InodeIdentifier {
+    //each mount can be crossed at most once, anything longer is a loop
+    for _ in 0..=cache.mount_points.len() {
+        match cache.mount_points.get(&inode) {
+            Some(mount_point) => inode = *mount_point,
+            None => return inode,
+        }
+    }
+    printlnc!((0, 255, 255), "Detected mount loop at inode {:?}\n", inode);
+    inode
+}
+
+///".." never goes above from, or above root if from is not set
 pub async fn get_unmount_inodes(
     path: ResolvedPathBorrowed<'_>,
     from: Option<InodeIdentifier>,
 ) -> Result<(InodeIdentifier, InodeIdentifier), ErrorCode> {
     let mut cache = Some(lock_w_info!(INODE_CACHE));
-    let mut current = from.unwrap_or(cache.as_ref().expect("is some").root);
+    let mut chain = vec![from.unwrap_or(cache.as_ref().expect("is some").root)];
     for component in path.iter() {
-        while let Some(mount_point) = cache.as_ref().expect("is some").mount_points.get(&current) {
-            if *mount_point == current {
-                printlnc!((0, 0, 255), "Detected mount loop at inode {:?}\n", current);
-                break;
+        if **component == *".." {
+            if chain.len() > 1 {
+                chain.pop();
             }
-            current = *mount_point;
+            continue;
         }
+        let current = follow_mounts(cache.as_ref().expect("is some"), *chain.last().expect("chain can't be empty"));
+        *chain.last_mut().expect("chain can't be empty") = current;
         let child = find_child_no_mounts(current, component, &mut cache).await?;
-        current = child;
+        chain.push(child);
     }
+    let mut current = *chain.last().expect("chain can't be empty");
     let mut old = current;
     while let Some(mount_point) = cache.as_ref().expect("is some").mount_points.get(&current) {
         if *mount_point == current {
@@ -86,9 +106,15 @@ pub async fn get_unmount_inodes(
     Ok((old, current))
 }
 
+///Same limit as linux
+const MAX_SYMLINK_FOLLOWS: usize = 40;
+
+///".." is resolved against the chain, so it crosses mount points and stays at root when already there.
+///Symlinks are followed in every component, the last one only if follow_last is set
 pub async fn get_inode_chain(
     path: ResolvedPathBorrowed<'_>,
     from: Option<InodeIdentifierChain>,
+    follow_last: bool,
 ) -> Result<(InodeIdentifier, InodeIdentifierChain), ErrorCode> {
     let mut cache_lock = Some(lock_w_info!(INODE_CACHE));
     let mut current = from
@@ -97,33 +123,47 @@ pub async fn get_inode_chain(
     if current.is_empty() {
         current = vec![cache_lock.as_ref().expect("is some").root];
     }
-    for component in path.iter() {
-        if **component == *".." {
+    //reversed, so the target of a symlink can be pushed in front of the rest
+    let mut remaining: Vec<Box<str>> = path.iter().rev().cloned().collect();
+    let mut symlinks_followed = 0;
+    while let Some(component) = remaining.pop() {
+        if *component == *".." {
             if current.len() > 1 {
                 current.pop();
             }
             continue;
         }
 
-        let current_last = *current.last().expect("current can't be empty");
-        while let Some(mount_point) = cache_lock.as_ref().expect("is some").mount_points.get(&current_last) {
-            if *mount_point == current_last {
-                printlnc!((0, 255, 255), "Detected mount loop at inode {:?}\n", current);
-                break;
+        //the chain keeps the mounted root in place of the directory it covers, so ".." from a mount root
+        //goes to the parent of the covered directory
+        let current_last = follow_mounts(
+            cache_lock.as_ref().expect("is some"),
+            *current.last().expect("current can't be empty"),
+        );
+        *current.last_mut().expect("current can't be empty") = current_last;
+
+        let child = find_child_no_mounts(current_last, &component, &mut cache_lock).await?;
+        if follow_last || !remaining.is_empty() {
+            if let Some(target) = read_symlink(child, &mut cache_lock).await? {
+                symlinks_followed += 1;
+                if symlinks_followed > MAX_SYMLINK_FOLLOWS {
+                    return Err(ErrorCode::SymlinkLoop);
+                }
+                if target.starts_with('/') {
+                    current = vec![cache_lock.as_ref().expect("is some").root];
+                }
+                //relative targets start from the directory containing the link
+                remaining.extend(resolve_path(&target).take().into_vec().into_iter().rev());
+                continue;
             }
-            *current.last_mut().expect("current can't be empty") = *mount_point;
         }
-
-        let child = find_child_no_mounts(*current.last().expect("current can't be empty"), component, &mut cache_lock).await?;
         current.push(child);
     }
-    if let Some(mount_point) = cache_lock
-        .expect("is some")
-        .mount_points
-        .get(current.last().expect("current can't be empty"))
-    {
-        *current.last_mut().expect("current can't be empty") = *mount_point;
-    }
+    let last = follow_mounts(
+        &cache_lock.expect("is some"),
+        *current.last().expect("current can't be empty"),
+    );
+    *current.last_mut().expect("current can't be empty") = last;
     let file = *current.last().expect("current can't be empty");
     if current.len() > 1 {
         current.pop();
@@ -132,24 +172,52 @@ pub async fn get_inode_chain(
     Ok((file, current.into_boxed_slice()))
 }
 
-async fn find_child_no_mounts(
-    current: InodeIdentifier,
-    f_name: &str,
+///Returns the target if the inode is a symlink. Leaves the cache locked
+async fn read_symlink(
+    inode_id: InodeIdentifier,
     cache: &mut Option<NoIntSpinlockGuard<'_, InodeCache>>,
-) -> Result<InodeIdentifier, ErrorCode> {
-    let current_node = cache
+) -> Result<Option<Box<str>>, ErrorCode> {
+    let (inode, _) = cache
         .as_ref()
         .expect("is some")
         .inodes
-        .get(&current)
+        .get(&inode_id)
         .ok_or(ErrorCode::InodeNotPresent)?;
-    let child = current_node.1.children.iter().find(|(name, _)| **name == *f_name);
-    if let Some(child) = child {
-        return Ok(child.1);
+    if !inode.type_mode.is_symlink() {
+        return Ok(None);
     }
-    // If the child is not found, we need to load the directory
-    load_dir(current, cache).await?;
-    // After loading, we check again
+    if inode.size > 4096 {
+        return Err(ErrorCode::FileSystemInconsistency);
+    }
+    let size = inode.size;
+    let vfs = lock_w_info!(VFS);
+    let device_details = vfs.devices.get(&inode_id.device_id).ok_or(ErrorCode::NoEntry)?;
+    let fs = vfs
+        .mounted_filesystems
+        .get(&device_details.partition)
+        .ok_or(ErrorCode::NoEntry)?
+        .clone();
+    drop(vfs);
+    drop(cache.take()); //drop lock
+
+    let frame = physical_allocator::allocate_frame();
+    let target = fs.read(inode_id.index, 0, size, &[frame]).await.and_then(|read| {
+        let bytes = unsafe { core::slice::from_raw_parts(translate_phys_virt_addr(frame).0 as *const u8, read as usize) };
+        core::str::from_utf8(bytes)
+            .map(Box::from)
+            .map_err(|_| ErrorCode::InvalidString)
+    });
+    unsafe { physical_allocator::deallocate_frame(frame) };
+
+    *cache = Some(lock_w_info!(INODE_CACHE)); //get lock back
+    target.map(Some)
+}
+
+async fn find_child_no_mounts(
+    current: InodeIdentifier,
+    f_name: &str,
+    cache: &mut Option<NoIntSpinlockGuard<'_, InodeCache>>,
+) -> Result<InodeIdentifier, ErrorCode> {
     let current_node = cache
         .as_ref()
         .expect("is some")
@@ -160,10 +228,16 @@ async fn find_child_no_mounts(
     if let Some(child) = child {
         return Ok(child.1);
     }
-    Err(ErrorCode::InodeNotPresent)
+    // If the child is not cached, the filesystem looks it up
+    load_child(current, f_name, cache).await
 }
 
-async fn load_dir(current: InodeIdentifier, cache: &mut Option<NoIntSpinlockGuard<'_, InodeCache>>) -> Result<(), ErrorCode> {
+///Adds the child called name to the cache, without listing the whole directory
+async fn load_child(
+    current: InodeIdentifier,
+    f_name: &str,
+    cache: &mut Option<NoIntSpinlockGuard<'_, InodeCache>>,
+) -> Result<InodeIdentifier, ErrorCode> {
     let inode = cache
         .as_ref()
         .expect("is some")
@@ -181,36 +255,36 @@ async fn load_dir(current: InodeIdentifier, cache: &mut Option<NoIntSpinlockGuar
     drop(vfs);
     drop(cache.take()); //drop lock
 
-    let dir = fs.read_dir(current.index).await;
-
-    let mut children = Vec::new();
-    if dir.is_empty() {
-        return Ok(());
-    }
-    for dir_entry in dir.iter() {
-        drop(cache.take()); //drop in loop
-        let inode = fs.stat(dir_entry.inode).await;
-        let inode_index = InodeIdentifier {
-            device_id: inode.device,
-            index: inode.index,
-        };
-        *cache = Some(lock_w_info!(INODE_CACHE)); //get lock back
-        cache
-            .as_mut()
-            .expect("is some")
-            .inodes
-            .insert(inode_index, (inode, FsTreeNode { children: Vec::new() }));
-        children.push((dir_entry.name.clone(), inode_index));
+    let inode = match fs.lookup(current.index, f_name).await {
+        Ok(child) => fs.stat(child).await,
+        Err(e) => Err(e),
+    };
+    *cache = Some(lock_w_info!(INODE_CACHE)); //callers expect the lock back
+    let inode = match inode {
+        Ok(inode) => inode,
+        Err(ErrorCode::NoEntry) => return Err(ErrorCode::InodeNotPresent),
+        Err(e) => return Err(e),
+    };
+    let inode_index = InodeIdentifier {
+        device_id: inode.device,
+        index: inode.index,
+    };
+    let cache = cache.as_mut().expect("is some");
+    //another link may have cached it already, with its children
+    match cache.inodes.get_mut(&inode_index) {
+        Some((cached, _)) => *cached = inode,
+        None => {
+            cache.inodes.insert(inode_index, (inode, FsTreeNode { children: Vec::new() }));
+        }
     }
     cache
-        .as_mut()
-        .expect("is some")
         .inodes
         .get_mut(&current)
         .ok_or(ErrorCode::InodeNotPresent)?
         .1
-        .children = children;
-    Ok(())
+        .children
+        .push((Box::from(f_name), inode_index));
+    Ok(inode_index)
 }
 
 pub fn update_inode(cache_num: InodeIdentifier, inode: Inode) -> Result<(), ErrorCode> {
@@ -237,6 +311,20 @@ pub fn insert_inode(parent_cache_num: InodeIdentifier, name: Box<str>, inode: In
     Ok(())
 }
 
+///Removes the entry called name from the cached children of parent. The inode stays cached, other
+///links and open handles may still refer to it
+pub fn remove_child(parent_cache_num: InodeIdentifier, name: &str) {
+    let mut cache = lock_w_info!(INODE_CACHE);
+    if let Some((_, parent)) = cache.inodes.get_mut(&parent_cache_num) {
+        parent.children.retain(|(child_name, _)| **child_name != *name);
+    }
+}
+
+///Drops an inode that has no links and no open handles left
+pub fn forget_inode(cache_num: InodeIdentifier) {
+    lock_w_info!(INODE_CACHE).inodes.remove(&cache_num);
+}
+
 ///parent_cache_num refers to the mountpoint itself, on top of which the new inode will be mounted
 pub fn mount_inode(parent_cache_num: InodeIdentifier, inode: Inode) {
     let mut cache = lock_w_info!(INODE_CACHE);
@@ -34,6 +34,15 @@ const TEST: u32 = 0o4000;
 pub struct InodeType(u32);
 
 impl InodeType {
+    pub fn from_bits(bits: u32) -> Self {
+        InodeType(bits)
+    }
+
+    ///Type and permission bits, as in st_mode
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+
     pub fn get_flags(&self) -> InodeFlags {
         InodeFlags(self.0 & PERM_MASK)
     }
@@ -73,6 +82,18 @@ impl InodeType {
     pub fn new_file(perms: u32) -> Self {
         InodeType(perms)
     }
+
+    pub fn new_symlink(perms: u32) -> Self {
+        InodeType(0o120000 | perms)
+    }
+
+    pub fn new_char_device(perms: u32) -> Self {
+        InodeType(0o20000 | perms)
+    }
+
+    pub fn new_block_device(perms: u32) -> Self {
+        InodeType(0o60000 | perms)
+    }
 }
 
 //unused for now, we don't need permissions
@@ -1,6 +1,6 @@
 use dtmpfs::DtmpfsFactory;
 use std::{
-    boxed::Box, collections::btree_map::BTreeMap, lock_w_info, sync::arc::Arc, sync::no_int_spinlock::NoIntSpinlock, vec::Vec,
+    boxed::Box, collections::{btree_map::BTreeMap, btree_set::BTreeSet}, lock_w_info, sync::arc::Arc, sync::no_int_spinlock::NoIntSpinlock, vec::Vec,
 };
 use uuid::Uuid;
 
@@ -16,10 +16,14 @@ mod filesystem_trait;
 mod fs_tree;
 mod inode;
 mod operations;
+mod page_cache;
 mod path;
+mod tmpfs;
+pub use adapters::{register_device_node, unregister_device_node};
 pub use filesystem_trait::{FileSystem, FileSystemFactory};
 pub use inode::*;
 pub use operations::*;
+pub use page_cache::sync;
 pub use path::*;
 
 //0 is unknown, 1 is bad blocks, 2 is root
@@ -57,7 +61,7 @@ pub type InodeIdentifierChain = Box<[InodeIdentifier]>;
 
 pub struct Vfs {
     ///Map from disk guid to disk object (driver) and a list of partition guids
-    disks: BTreeMap<Uuid, (Box<dyn BlockDevice + Send>, Vec<Uuid>)>,
+    disks: BTreeMap<Uuid, (Arc<dyn BlockDevice + Send>, Vec<Uuid>)>,
     ///maps from filesystem type uuid to filesystem driver factory
     filesystem_driver_factories: BTreeMap<Uuid, Arc<dyn FileSystemFactory + Send>>,
     ///maps from partition uuid to filesystem driver
@@ -68,6 +72,25 @@ pub struct Vfs {
     devices: BTreeMap<DeviceId, DeviceDetails>,
     ///counts devices
     device_counter: u64,
+    ///mounted filesystems in mount order
+    mounts: Vec<MountInfo>,
+    ///partitions mounted read only, their files can't be opened for writing
+    read_only: BTreeSet<Uuid>,
+    ///maps from disk guid to the names of its nodes in /dev
+    disk_nodes: BTreeMap<Uuid, Vec<Box<str>>>,
+    ///counts disks, used for naming their /dev nodes
+    disk_counter: u64,
+    ///inodes whose last link was removed while they were open, dropped from the caches on the last close
+    unlinked_open: BTreeSet<InodeIdentifier>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MountInfo {
+    ///partition uuid, or the adapter name for filesystems without a partition
+    pub source: Box<str>,
+    pub mountpoint: ResolvedPath,
+    pub fs_type: &'static str,
+    pub read_only: bool,
 }
 
 impl Vfs {
@@ -79,6 +102,11 @@ impl Vfs {
             available_partitions: BTreeMap::new(),
             devices: BTreeMap::new(),
             device_counter: 1,
+            mounts: Vec::new(),
+            read_only: BTreeSet::new(),
+            disk_nodes: BTreeMap::new(),
+            disk_counter: 0,
+            unlinked_open: BTreeSet::new(),
         }
     }
 
@@ -89,10 +117,20 @@ impl Vfs {
     }
 }
 
+pub fn get_mounts() -> Vec<MountInfo> {
+    lock_w_info!(VFS).mounts.clone()
+}
+
 pub fn init() {
     let mut vfs = lock_w_info!(VFS);
     vfs.filesystem_driver_factories
         .insert(RfsFactory::UUID, Arc::new(RfsFactory {}));
     vfs.filesystem_driver_factories
         .insert(DtmpfsFactory::UUID, Arc::new(DtmpfsFactory {}));
+    drop(vfs);
+
+    register_device_node("null", Arc::new(adapters::NullDevice));
+    register_device_node("zero", Arc::new(adapters::ZeroDevice));
+    register_device_node("urandom", Arc::new(adapters::RandomDevice::new()));
+    register_device_node("tty", Arc::new(adapters::TtyDevice::new()));
 }
@@ -1,31 +1,47 @@
 use std::{
-    boxed::Box, error::ErrorCode, lock_w_info, mem_utils::PhysAddr, printlnc, string::{String, ToString}, sync::{arc::Arc, no_int_spinlock::NoIntSpinlockGuard}, vec::Vec
+    boxed::Box, error::ErrorCode, format, lock_w_info, mem_utils::{PhysAddr, memset_physical_addr, translate_phys_virt_addr}, printlnc, string::ToString, sync::{arc::Arc, no_int_spinlock::NoIntSpinlockGuard}, time::{Instant, UNIX_EPOCH}, vec::Vec
 };
 
+use core::str::FromStr;
 use uuid::Uuid;
 
-use crate::drivers::{
-    disk::{BlockDevice, DirEntry, MountedPartition, PartitionSchemeDriver},
-    gpt::GPTDriver,
+use crate::{
+    drivers::{
+        disk::{BlockDevice, DirEntry, MountedPartition, PartitionSchemeDriver},
+        gpt::GPTDriver,
+    },
+    memory::physical_allocator,
 };
 
 use super::{
-    file::{FileFlags, FileHandle}, filesystem_trait::FileSystem, fs_tree::{self}, resolve_path, DeviceDetails, InodeIdentifierChain, InodeType, ResolvedPath, ResolvedPathBorrowed, Vfs, ROOT_INODE_INDEX, VFS, VFS_ADAPTER_DEVICE
+    adapters::RawBlockDevice, file::{self, FileFlags, FileHandle}, filesystem_trait::FileSystem, fs_tree::{self}, page_cache, resolve_path, tmpfs::Tmpfs, DeviceDetails, DeviceId, Inode, InodeIdentifierChain, InodeType, MountInfo, ResolvedPath, ResolvedPathBorrowed, Vfs, ROOT_INODE_INDEX, VFS, VFS_ADAPTER_DEVICE, register_device_node, unregister_device_node
 };
 
-pub async fn add_disk(mut disk: Box<dyn BlockDevice + Send>) {
+pub async fn add_disk<D: BlockDevice + Send + 'static>(mut disk: D) {
     //for now only GPT
     let gpt_driver = GPTDriver {};
-    let guid = gpt_driver.guid(&mut *disk).await;
-    let partitions = gpt_driver.partitions(&mut *disk).await;
+    let guid = gpt_driver.guid(&mut disk).await;
+    let partitions = gpt_driver.partitions(&mut disk).await;
     let partition_guids: Vec<Uuid> = partitions.iter().map(|(guid, _)| *guid).collect();
+    //device nodes keep their own reference, so an open node outlives the removed disk
+    let disk: Arc<dyn BlockDevice + Send> = Arc::new(disk);
 
     let mut vfs = lock_w_info!(VFS);
+    let disk_name = format!("disk{}", vfs.disk_counter);
+    vfs.disk_counter += 1;
+    let mut nodes = Vec::from([(
+        disk_name.clone().into_boxed_str(),
+        RawBlockDevice::new(disk.clone(), 0, disk.sector_count() as u64),
+    )]);
 
-    vfs.disks.insert(guid, (disk, partition_guids));
+    vfs.disks.insert(guid, (disk.clone(), partition_guids));
 
-    for partition in partitions {
+    for (i, partition) in partitions.into_iter().enumerate() {
         let device = partition.1.device;
+        nodes.push((
+            format!("{}p{}", disk_name, i + 1).into_boxed_str(),
+            RawBlockDevice::new(disk.clone(), partition.1.start_sector as u64, partition.1.size_sectors as u64),
+        ));
         vfs.available_partitions.insert(partition.0, partition.1);
         vfs.devices.insert(
             device,
@@ -35,11 +51,20 @@ pub async fn add_disk(mut disk: Box<dyn BlockDevice + Send>) {
             },
         );
     }
+    vfs.disk_nodes.insert(guid, nodes.iter().map(|(name, _)| name.clone()).collect());
+    drop(vfs);
+
+    for (name, node) in nodes {
+        register_device_node(&name, Arc::new(node));
+    }
 }
 
 //called after unmounting all partitions or when it was forcibly removed
 fn remove_disk(uuid: Uuid) {
     let mut vfs = lock_w_info!(VFS);
+    for name in vfs.disk_nodes.remove(&uuid).unwrap_or_default() {
+        unregister_device_node(&name);
+    }
     let Some(partitions) = vfs.disks.remove(&uuid) else {
         //slow path
         remove_disk_slow(uuid, vfs);
@@ -90,7 +115,104 @@ fn remove_disk_slow(uuid: Uuid, mut vfs: NoIntSpinlockGuard<'_, Vfs>) {
 }
 
 pub async fn mount_blkdev_partition(part_id: Uuid, mountpoint: ResolvedPath) -> Result<(), ErrorCode> {
-    let mut vfs = lock_w_info!(VFS);
+    mount_partition(part_id, mountpoint, part_id.to_string().into_boxed_str(), false).await
+}
+
+///Mounts the root filesystem while booting, source and fs_type are the same as for [`mount`]
+pub async fn mount_root(source: &str, fs_type: Option<&str>, read_only: bool) -> Result<(), ErrorCode> {
+    if fs_type == Some("tmpfs") {
+        return mount_tmpfs(ResolvedPath::root()).await;
+    }
+    let part_id = find_mountable_partition(source, fs_type)?;
+    mount_partition(part_id, ResolvedPath::root(), source.into(), read_only).await
+}
+
+///Mounts source at mountpoint. Source is a partition given as `PARTUUID=<uuid>`, `PARTLABEL=<gpt name>`,
+///a plain partition uuid or a device node like `/dev/disk0p1`. The filesystem type is detected from the
+///partition, if fs_type is set it has to match. A fs_type of "tmpfs" mounts a new tmpfs and ignores source
+pub async fn mount(source: &str, fs_type: Option<&str>, mountpoint: ResolvedPath) -> Result<(), ErrorCode> {
+    //root is only replaced while booting
+    if mountpoint.inner().is_empty() {
+        return Err(ErrorCode::Busy);
+    }
+    let (inode, _) = fs_tree::get_inode_chain((&mountpoint).into(), None, true).await?;
+    if !fs_tree::get_inode(inode).ok_or(ErrorCode::InodeNotPresent)?.type_mode.is_dir() {
+        return Err(ErrorCode::NotDirectory);
+    }
+    if fs_type == Some("tmpfs") {
+        return mount_tmpfs(mountpoint).await;
+    }
+    let part_id = find_mountable_partition(source, fs_type)?;
+    mount_partition(part_id, mountpoint, source.into(), false).await
+}
+
+///Finds the partition of source and checks that it isn't mounted yet and has the right type
+fn find_mountable_partition(source: &str, fs_type: Option<&str>) -> Result<Uuid, ErrorCode> {
+    let vfs = lock_w_info!(VFS);
+    let part_id = find_partition(&vfs, source)?;
+    if vfs.mounted_filesystems.contains_key(&part_id) {
+        return Err(ErrorCode::Busy);
+    }
+    if let Some(fs_type) = fs_type {
+        let partition = vfs.available_partitions.get(&part_id).ok_or(ErrorCode::NoEntry)?;
+        let factory = vfs
+            .filesystem_driver_factories
+            .get(&partition.fs_type)
+            .ok_or(ErrorCode::UnsupportedFilesystem)?;
+        if factory.name() != fs_type {
+            return Err(ErrorCode::UnsupportedFilesystem);
+        }
+    }
+    Ok(part_id)
+}
+
+fn find_partition(vfs: &Vfs, source: &str) -> Result<Uuid, ErrorCode> {
+    if let Some(label) = source.strip_prefix("PARTLABEL=") {
+        return vfs
+            .available_partitions
+            .iter()
+            .find(|(_, partition)| partition.name == label)
+            .map(|(part_id, _)| *part_id)
+            .ok_or(ErrorCode::NoEntry);
+    }
+    if let Some(node) = source.strip_prefix("/dev/") {
+        for (disk, names) in vfs.disk_nodes.iter() {
+            let Some(position) = names.iter().position(|name| **name == *node) else {
+                continue;
+            };
+            //the first node is the whole disk, the others are its partitions in order
+            if position == 0 {
+                return Err(ErrorCode::InvalidArgument);
+            }
+            let partitions = &vfs.disks.get(disk).ok_or(ErrorCode::InternalFSError)?.1;
+            return partitions.get(position - 1).copied().ok_or(ErrorCode::InternalFSError);
+        }
+        return Err(ErrorCode::NoEntry);
+    }
+    let uuid = source.strip_prefix("PARTUUID=").unwrap_or(source);
+    let part_id = Uuid::from_str(uuid).map_err(|_| ErrorCode::InvalidArgument)?;
+    if !vfs.available_partitions.contains_key(&part_id) {
+        return Err(ErrorCode::NoEntry);
+    }
+    Ok(part_id)
+}
+
+///The partition of source, in any format mount takes. Lets the tests change a partition beneath
+///its filesystem, the disk must stay present while it is used
+#[cfg(feature = "run_tests")]
+pub fn raw_partition(source: &str) -> Result<MountedPartition, ErrorCode> {
+    let vfs = lock_w_info!(VFS);
+    let part_id = find_partition(&vfs, source)?;
+    let partition = vfs.available_partitions.get(&part_id).ok_or(ErrorCode::NoEntry)?.clone();
+    let drive_id = vfs.devices.get(&partition.device).ok_or(ErrorCode::InternalFSError)?.drive;
+    let disk = vfs.disks.get(&drive_id).ok_or(ErrorCode::NoEntry)?;
+    let disk: &'static dyn BlockDevice = unsafe { &*(disk.0.get() as *const dyn BlockDevice) };
+    Ok(MountedPartition::new(disk, partition))
+}
+
+///Mounts a partition with the filesystem detected from its type. Source is recorded in the mount table
+async fn mount_partition(part_id: Uuid, mountpoint: ResolvedPath, source: Box<str>, read_only: bool) -> Result<(), ErrorCode> {
+    let vfs = lock_w_info!(VFS);
     let Some(partition) = vfs.available_partitions.get(&part_id) else {
         return Err(ErrorCode::NoEntry);
     };
@@ -100,11 +222,11 @@ pub async fn mount_blkdev_partition(part_id: Uuid, mountpoint: ResolvedPath) ->
         return Err(ErrorCode::InternalFSError);
     };
     let drive_id = device_detail.drive;
-    let Some(disk) = vfs.disks.get_mut(&drive_id) else {
+    let Some(disk) = vfs.disks.get(&drive_id) else {
         return Err(ErrorCode::NoEntry);
     };
-    let disk = &raw mut *disk.0;
-    let cloned_disk: &'static mut dyn BlockDevice = unsafe { &mut *disk };
+    //disks with mounted partitions are not removed
+    let cloned_disk: &'static dyn BlockDevice = unsafe { &*(disk.0.get() as *const dyn BlockDevice) };
 
     let Some(fs_factory) = vfs.filesystem_driver_factories.get(&partition.fs_type).cloned() else {
         return Err(ErrorCode::UnsupportedFilesystem);
@@ -116,7 +238,13 @@ pub async fn mount_blkdev_partition(part_id: Uuid, mountpoint: ResolvedPath) ->
         partition,
     };
     let fs = fs_factory.mount(mounted_partition).await;
-    if let Err(e) = mount_filesystem(mountpoint, fs.clone(), part_id).await {
+    let mount_info = MountInfo {
+        source,
+        mountpoint,
+        fs_type: fs_factory.name(),
+        read_only,
+    };
+    if let Err(e) = mount_filesystem(mount_info, fs.clone(), part_id).await {
         fs.unmount().await;
         Err(e)
     } else {
@@ -124,59 +252,128 @@ pub async fn mount_blkdev_partition(part_id: Uuid, mountpoint: ResolvedPath) ->
     }
 }
 
-async fn mount_filesystem(mountpoint: ResolvedPath, fs: Arc<dyn FileSystem + Send>, part_id: Uuid) -> Result<(), ErrorCode> {
-    let root = mountpoint.inner().is_empty();
+///Mounts a new, empty tmpfs. Used for /tmp and as the root when there is no disk
+pub async fn mount_tmpfs(mountpoint: ResolvedPath) -> Result<(), ErrorCode> {
+    let mut vfs = lock_w_info!(VFS);
+    let (device, device_details) = VFS_ADAPTER_DEVICE.allocate_device(&mut vfs);
+    drop(vfs);
+
+    let fs: Arc<dyn FileSystem + Send> = Arc::new(Tmpfs::new(device));
+    let mount_info = MountInfo {
+        source: "tmpfs".into(),
+        mountpoint,
+        fs_type: "tmpfs",
+        read_only: false,
+    };
+    if let Err(e) = mount_filesystem(mount_info, fs.clone(), device_details.partition).await {
+        fs.unmount().await;
+        Err(e)
+    } else {
+        Ok(())
+    }
+}
+
+async fn mount_filesystem(mount_info: MountInfo, fs: Arc<dyn FileSystem + Send>, part_id: Uuid) -> Result<(), ErrorCode> {
+    let root = mount_info.mountpoint.inner().is_empty();
     if root {
         //mounting root
-        mount_new_root(&fs).await;
+        mount_new_root(&fs, mount_info.read_only).await?;
         let fs: Arc<dyn FileSystem + Send> = fs;
         let mut vfs = lock_w_info!(VFS);
         vfs.mounted_filesystems.insert(part_id, fs);
+        if mount_info.read_only {
+            vfs.read_only.insert(part_id);
+        }
+        //everything else was mounted on top of the old root
+        vfs.mounts.clear();
+        vfs.mounts.push(mount_info);
         mount_vfs_adapters(vfs).await;
     } else {
-        let fs_root_inode = fs.stat(ROOT_INODE_INDEX).await;
+        let fs_root_inode = fs.stat(ROOT_INODE_INDEX).await?;
         //we disallow the mounting of root failing so no checks :3
-        let (inode, _parent_inode_chain) = fs_tree::get_inode_chain((&mountpoint).into(), None).await?;
+        let (inode, _parent_inode_chain) = fs_tree::get_inode_chain((&mount_info.mountpoint).into(), None, true).await?;
         fs_tree::mount_inode(inode, fs_root_inode);
         let fs: Arc<dyn FileSystem + Send> = fs;
         let mut vfs = lock_w_info!(VFS);
         vfs.mounted_filesystems.insert(part_id, fs);
+        if mount_info.read_only {
+            vfs.read_only.insert(part_id);
+        }
+        vfs.mounts.push(mount_info);
     }
 
     Ok(())
 }
 
-async fn mount_new_root(fs: &Arc<dyn FileSystem + Send>) {
-    let inode = fs.stat(ROOT_INODE_INDEX).await;
+///Makes `fs` the root and creates the directories the kernel mounts on. A read only root isn't
+///written to, it fails with [`ErrorCode::ReadOnlyFilesystem`] if one of them is missing
+async fn mount_new_root(fs: &Arc<dyn FileSystem + Send>, read_only: bool) -> Result<(), ErrorCode> {
+    let inode = fs.stat(ROOT_INODE_INDEX).await?;
     let inode_index = inode.index;
     fs_tree::init(inode);
 
     //root checks
-    let root_dirs = fs.read_dir(inode_index).await;
-    let required_dirs = ["tty", "proc"];
+    let root_dirs = fs.read_dir(inode_index).await?;
+    let required_dirs = ["dev", "proc", "tmp"];
     for required_dir in required_dirs.iter() {
         if !root_dirs.iter().any(|entry| entry.name.as_ref() == *required_dir) {
+            if read_only {
+                return Err(ErrorCode::ReadOnlyFilesystem);
+            }
             //create the required directory
             fs.create(required_dir, ROOT_INODE_INDEX, InodeType::new_dir(0o755), 0, 0)
-                .await;
+                .await?;
         }
     }
 
+    Ok(())
 }
 
 async fn mount_vfs_adapters(mut vfs: NoIntSpinlockGuard<'_, Vfs>) {
     let proc_dev = VFS_ADAPTER_DEVICE.allocate_device(&mut vfs);
-    let tty_dev = VFS_ADAPTER_DEVICE.allocate_device(&mut vfs);
+    let dev_dev = VFS_ADAPTER_DEVICE.allocate_device(&mut vfs);
     drop(vfs);
 
     let proc_adapter: Arc<dyn FileSystem + Send> = Arc::new(crate::vfs::adapters::ProcAdapter::new(proc_dev.0));
-    let tty_adapter: Arc<dyn FileSystem + Send> = Arc::new(crate::vfs::adapters::TtyAdapter::new(tty_dev.0));
-    Box::pin(mount_filesystem(resolve_path("/tty"), tty_adapter, tty_dev.1.partition)).await.expect("Failed to mount /tty");
-    Box::pin(mount_filesystem(resolve_path("/proc"), proc_adapter, proc_dev.1.partition)).await.expect("Failed to mount /proc");
+    let dev_adapter: Arc<dyn FileSystem + Send> = Arc::new(crate::vfs::adapters::DevAdapter::new(dev_dev.0));
+    let dev_mount = MountInfo {
+        source: "dev".into(),
+        mountpoint: resolve_path("/dev"),
+        fs_type: "devfs",
+        read_only: false,
+    };
+    let proc_mount = MountInfo {
+        source: "proc".into(),
+        mountpoint: resolve_path("/proc"),
+        fs_type: "proc",
+        read_only: false,
+    };
+    Box::pin(mount_filesystem(dev_mount, dev_adapter, dev_dev.1.partition)).await.expect("Failed to mount /dev");
+    Box::pin(mount_filesystem(proc_mount, proc_adapter, proc_dev.1.partition)).await.expect("Failed to mount /proc");
 }
 
+///Fails with [`ErrorCode::Busy`] while the filesystem has open files or other filesystems are mounted
+///below it
 pub async fn unmount(path: ResolvedPathBorrowed<'_>) -> Result<(), ErrorCode> {
+    if path.is_empty() {
+        return Err(ErrorCode::Busy);
+    }
     let inodes = fs_tree::get_unmount_inodes(path, None).await?;
+    //stays mounted if the cached data can't be written
+    page_cache::flush_device(inodes.1.device_id).await?;
+    let mut vfs = lock_w_info!(VFS);
+    let has_submounts = vfs.mounts.iter().any(|mount| {
+        let mountpoint = mount.mountpoint.inner();
+        mountpoint.len() > path.len() && mountpoint.starts_with(path.inner())
+    });
+    if has_submounts || file::has_open_files(inodes.1.device_id) {
+        return Err(ErrorCode::Busy);
+    }
+    //only the topmost of stacked mounts goes away
+    if let Some(position) = vfs.mounts.iter().rposition(|mount| mount.mountpoint.inner() == path.inner()) {
+        vfs.mounts.remove(position);
+    }
+    drop(vfs);
     let last_part_mount = fs_tree::unmount_inode(inodes.0);
     if last_part_mount {
         let mut vfs = lock_w_info!(VFS);
@@ -187,6 +384,9 @@ pub async fn unmount(path: ResolvedPathBorrowed<'_>) -> Result<(), ErrorCode> {
         let Some(partition) = vfs.mounted_filesystems.remove(&partition_id) else {
             return Ok(());
         };
+        vfs.read_only.remove(&partition_id);
+        drop(vfs);
+        page_cache::drop_device(inodes.1.device_id).await;
         partition.unmount().await;
     }
     Ok(())
@@ -197,27 +397,46 @@ pub async fn open_file(
     from: Option<InodeIdentifierChain>,
     mut open_mode: FileFlags,
 ) -> Result<FileHandle, ErrorCode> {
-    let (inode_index, inode_chain) = fs_tree::get_inode_chain(path, from).await?;
+    let (inode_index, inode_chain) = fs_tree::get_inode_chain(path, from, !open_mode.no_follow()).await?;
     let inode = fs_tree::get_inode(inode_index).ok_or(ErrorCode::InodeNotPresent)?;
+    if open_mode.write() && is_read_only(inode.device) {
+        return Err(ErrorCode::ReadOnlyFilesystem);
+    }
     open_mode.set_dir(inode.type_mode.is_dir());
+    let cut = open_mode.truncate() && open_mode.write() && inode.type_mode.is_file();
+    open_mode.set_truncate(false);
     //TODO: check permissions
-    Ok(FileHandle {
-        inode: inode_index,
-        parent_chain: inode_chain,
-        position: 0,
-        file_flags: open_mode,
-    })
+    let handle = FileHandle::new(inode_index, inode_chain, open_mode);
+    if cut {
+        truncate(&handle, 0).await?;
+    }
+    Ok(handle)
+}
+
+///Whether the partition of the device was mounted read only
+pub fn is_read_only(device: DeviceId) -> bool {
+    let vfs = lock_w_info!(VFS);
+    vfs.devices
+        .get(&device)
+        .is_some_and(|device_details| vfs.read_only.contains(&device_details.partition))
 }
 
-pub async fn get_dir_entries(file_handle: &FileHandle) -> Result<Box<[DirEntry]>, String> {
-    let inode = fs_tree::get_inode(file_handle.inode).ok_or("Inode not found")?;
+pub fn stat_file(file_handle: &FileHandle) -> Result<Inode, ErrorCode> {
+    fs_tree::get_inode(file_handle.inode).ok_or(ErrorCode::InodeNotPresent)
+}
+
+pub async fn get_dir_entries(file_handle: &FileHandle) -> Result<Box<[DirEntry]>, ErrorCode> {
+    let inode = fs_tree::get_inode(file_handle.inode).ok_or(ErrorCode::InodeNotPresent)?;
+    if !inode.type_mode.is_dir() {
+        return Err(ErrorCode::NotDirectory);
+    }
     let mut vfs = lock_w_info!(VFS);
-    let device_details = vfs.devices.get(&inode.device).ok_or("Device not found")?;
+    let device_details = vfs.devices.get(&inode.device).ok_or(ErrorCode::NoEntry)?;
     let partition_id = device_details.partition;
-    let fs = vfs.mounted_filesystems.get_mut(&partition_id).ok_or("FS not found")?;
+    let fs = vfs.mounted_filesystems.get_mut(&partition_id).ok_or(ErrorCode::NotMounted)?;
     let fs = fs.clone();
     drop(vfs);
-    Ok(fs.read_dir(file_handle.inode.index).await)
+    fs.read_dir(file_handle.inode.index).await
 }
 
 pub async fn create_file(parent_dir: &mut FileHandle, name: &str, inode_type: InodeType) -> Result<(), ErrorCode> {
@@ -235,22 +454,158 @@ pub async fn create_file(parent_dir: &mut FileHandle, name: &str, inode_type: In
     let fs = vfs.mounted_filesystems.get_mut(&partition_id).ok_or(ErrorCode::InodeNotPresent)?;
     let fs = fs.clone();
     drop(vfs);
-    let (file_inode, parent_inode) = fs.create(name, parent_inode.index, inode_type, 0, 0).await;
+    match fs.lookup(parent_inode.index, name).await {
+        Ok(_) => return Err(ErrorCode::Exists),
+        Err(ErrorCode::NoEntry) => {}
+        Err(e) => return Err(e),
+    }
+    let (file_inode, parent_inode) = fs.create(name, parent_inode.index, inode_type, 0, 0).await?;
     fs_tree::update_inode(parent_dir.inode, parent_inode)?;
     fs_tree::insert_inode(parent_dir.inode, name.to_string().into_boxed_str(), file_inode)?;
     Ok(())
 }
 
-pub async fn write_file(file_handle: &mut FileHandle, content: &[PhysAddr], size: u64) -> Result<u64, String> {
+///Creates a symlink called name in parent_dir pointing to target. The target is not checked
+pub async fn create_symlink(parent_dir: &mut FileHandle, name: &str, target: &str) -> Result<(), ErrorCode> {
+    if !parent_dir.file_flags.write() {
+        return Err(ErrorCode::InsufficientPermissions);
+    }
+    if !parent_dir.file_flags.dir() {
+        return Err(ErrorCode::UnsupportedOperation);
+    }
+    if target.is_empty() || target.len() > 4096 {
+        return Err(ErrorCode::InvalidString);
+    }
+
+    let parent_inode = fs_tree::get_inode(parent_dir.inode).ok_or(ErrorCode::InodeNotPresent)?;
+    let mut vfs = lock_w_info!(VFS);
+    let device_details = vfs.devices.get(&parent_inode.device).ok_or(ErrorCode::InodeNotPresent)?;
+    let partition_id = device_details.partition;
+    let fs = vfs.mounted_filesystems.get_mut(&partition_id).ok_or(ErrorCode::InodeNotPresent)?;
+    let fs = fs.clone();
+    drop(vfs);
+    match fs.lookup(parent_inode.index, name).await {
+        Ok(_) => return Err(ErrorCode::Exists),
+        Err(ErrorCode::NoEntry) => {}
+        Err(e) => return Err(e),
+    }
+    let (link_inode, parent_inode) = fs.symlink(name, parent_inode.index, target, 0, 0).await?;
+    fs_tree::update_inode(parent_dir.inode, parent_inode)?;
+    fs_tree::insert_inode(parent_dir.inode, name.to_string().into_boxed_str(), link_inode)?;
+    Ok(())
+}
+
+///Creates a symlink at link_path pointing to target, in a directory that has to exist
+pub async fn symlink(link_path: ResolvedPathBorrowed<'_>, target: &str) -> Result<(), ErrorCode> {
+    let (mut parent_dir, name) = open_parent(link_path).await?;
+    let result = create_symlink(&mut parent_dir, name, target).await;
+    let closed = close_file(parent_dir).await;
+    result.and(closed)
+}
+
+///Removes the entry at path, directories have to be empty. The cached pages of the last link are
+///written back while the blocks still belong to it and dropped, an open file keeps them until it is closed
+pub async fn unlink(path: ResolvedPathBorrowed<'_>) -> Result<(), ErrorCode> {
+    let (parent_dir, name) = open_parent(path).await?;
+    let result = unlink_from(&parent_dir, name, path).await;
+    let closed = close_file(parent_dir).await;
+    result.and(closed)
+}
+
+async fn unlink_from(parent_dir: &FileHandle, name: &str, path: ResolvedPathBorrowed<'_>) -> Result<(), ErrorCode> {
+    let (child, _) = fs_tree::get_inode_chain(path, None, false).await?;
+    if child.device_id != parent_dir.inode.device_id {
+        //a filesystem is mounted on it
+        return Err(ErrorCode::Busy);
+    }
+    let child_inode = fs_tree::get_inode(child).ok_or(ErrorCode::InodeNotPresent)?;
+    let last_link = child_inode.link_cnt <= 1 || child_inode.type_mode.is_dir();
+
+    let mut vfs = lock_w_info!(VFS);
+    let device_details = vfs.devices.get(&child.device_id).ok_or(ErrorCode::NoEntry)?;
+    let partition_id = device_details.partition;
+    let fs = vfs.mounted_filesystems.get_mut(&partition_id).ok_or(ErrorCode::NotMounted)?;
+    let fs = fs.clone();
+    drop(vfs);
+
+    if last_link {
+        //pages that fail stay dirty, they are dropped below or written back on the last close
+        let _ = page_cache::flush_inode(child).await;
+    }
+    fs.unlink(parent_dir.inode.index, name).await?;
+    fs_tree::update_inode(parent_dir.inode, fs.stat(parent_dir.inode.index).await?)?;
+    fs_tree::remove_child(parent_dir.inode, name);
+    if !last_link {
+        let mut child_inode = child_inode;
+        child_inode.link_cnt -= 1;
+        return fs_tree::update_inode(child, child_inode);
+    }
+
+    let mut vfs = lock_w_info!(VFS);
+    if file::is_open(child) {
+        vfs.unlinked_open.insert(child);
+    } else {
+        drop(vfs);
+        page_cache::invalidate(child, 0);
+        fs_tree::forget_inode(child);
+    }
+    Ok(())
+}
+
+///Opens the directory containing the last component of path for writing. Returns it with the last component
+async fn open_parent<'a>(path: ResolvedPathBorrowed<'a>) -> Result<(FileHandle, &'a str), ErrorCode> {
+    let Some((name, parent)) = path.split_last() else {
+        return Err(ErrorCode::InvalidArgument);
+    };
+    if name == ".." {
+        return Err(ErrorCode::InvalidArgument);
+    }
+    let dir_flags = FileFlags::new_with_flags(true, true, false, true);
+    let parent_dir = open_file(parent, None, dir_flags).await?;
+    if !parent_dir.file_flags.dir() {
+        return Err(ErrorCode::NotDirectory);
+    }
+    Ok((parent_dir, name))
+}
+
+///Cuts or extends the file to size bytes, new bytes read as zeros. Cached pages past size are
+///dropped after writing back the rest, so nothing is written into blocks the filesystem frees
+pub async fn truncate(file_handle: &FileHandle, size: u64) -> Result<(), ErrorCode> {
     if !file_handle.file_flags.write() {
-        return Err("File opened in read-only mode".to_string());
+        return Err(ErrorCode::BadFd);
     }
+    let inode = fs_tree::get_inode(file_handle.inode).ok_or(ErrorCode::InodeNotPresent)?;
+    if inode.type_mode.is_dir() {
+        return Err(ErrorCode::IsDirectory);
+    }
+    let mut vfs = lock_w_info!(VFS);
+    let device_details = vfs.devices.get(&inode.device).ok_or(ErrorCode::NoEntry)?;
+    let partition_id = device_details.partition;
+    let fs = vfs.mounted_filesystems.get_mut(&partition_id).ok_or(ErrorCode::NotMounted)?;
+    let fs = fs.clone();
+    drop(vfs);
 
-    let inode = fs_tree::get_inode(file_handle.inode).ok_or("Inode not found")?;
+    //the filesystem only knows the size of written back data
+    page_cache::flush_inode(file_handle.inode).await?;
+    page_cache::invalidate(file_handle.inode, size);
+    fs.truncate(inode.index, size).await?;
+    fs_tree::update_inode(file_handle.inode, fs.stat(inode.index).await?)
+}
+
+///Offset and size can be anything, content holds the data starting at its first byte
+pub async fn write_file(file_handle: &mut FileHandle, content: &[PhysAddr], size: u64) -> Result<u64, ErrorCode> {
+    if !file_handle.file_flags.write() {
+        return Err(ErrorCode::BadFd);
+    }
+
+    let inode = fs_tree::get_inode(file_handle.inode).ok_or(ErrorCode::InodeNotPresent)?;
+    if inode.type_mode.is_dir() {
+        return Err(ErrorCode::IsDirectory);
+    }
     let mut vfs = lock_w_info!(VFS);
-    let device_details = vfs.devices.get(&inode.device).ok_or("Device not found")?;
+    let device_details = vfs.devices.get(&inode.device).ok_or(ErrorCode::NoEntry)?;
     let partition_id = device_details.partition;
-    let fs = vfs.mounted_filesystems.get_mut(&partition_id).ok_or("FS not found")?;
+    let fs = vfs.mounted_filesystems.get_mut(&partition_id).ok_or(ErrorCode::NotMounted)?;
     let fs = fs.clone();
     drop(vfs);
 
@@ -260,32 +615,164 @@ pub async fn write_file(file_handle: &mut FileHandle, content: &[PhysAddr], size
         file_handle.position
     };
 
-    let res = fs.write(inode.index, offset, size, content).await;
+    let (new_inode, written) = if fs.uses_page_cache() {
+        let written = page_cache::write(&fs, file_handle.inode, inode.size, offset, size, content).await?;
+        let mut new_inode = inode.clone();
+        new_inode.size = inode.size.max(offset + written);
+        //the filesystem sets its times when the pages are written back, the cache shows them right away
+        let now = Instant::now().duration_since(UNIX_EPOCH).as_secs() as u32;
+        new_inode.modification_time = now;
+        new_inode.stat_change_time = now;
+        (new_inode, written)
+    } else {
+        write_bounced(&fs, &inode, offset, size, content).await?
+    };
+    fs_tree::update_inode(file_handle.inode, new_inode)?;
 
     if !file_handle.file_flags.append() {
-        file_handle.position += size;
+        file_handle.position += written;
     }
 
-    Ok(res.1)
+    Ok(written)
 }
 
-pub async fn read_file(file_handle: &mut FileHandle, buffer: &[PhysAddr], size: u64) -> Result<u64, String> {
+///Offset and size can be anything, the data is placed starting at the first byte of buffer
+pub async fn read_file(file_handle: &mut FileHandle, buffer: &[PhysAddr], size: u64) -> Result<u64, ErrorCode> {
     if !file_handle.file_flags.read() {
-        return Err("File opened in write-only mode".to_string());
+        return Err(ErrorCode::BadFd);
     }
 
-    let inode = fs_tree::get_inode(file_handle.inode).ok_or("Inode not found")?;
+    let inode = fs_tree::get_inode(file_handle.inode).ok_or(ErrorCode::InodeNotPresent)?;
+    if inode.type_mode.is_dir() {
+        return Err(ErrorCode::IsDirectory);
+    }
     let mut vfs = lock_w_info!(VFS);
-    let device_details = vfs.devices.get(&inode.device).ok_or("Device not found")?;
+    let device_details = vfs.devices.get(&inode.device).ok_or(ErrorCode::NoEntry)?;
     let partition_id = device_details.partition;
-    let fs = vfs.mounted_filesystems.get_mut(&partition_id).ok_or("FS not found")?;
+    let fs = vfs.mounted_filesystems.get_mut(&partition_id).ok_or(ErrorCode::NotMounted)?;
     let fs = fs.clone();
     drop(vfs);
 
     let offset = file_handle.position;
 
-    let bytes_read = fs.read(inode.index, offset, size, buffer).await;
+    let bytes_read = if fs.uses_page_cache() {
+        page_cache::read(&fs, file_handle.inode, inode.size, offset, size, buffer).await?
+    } else {
+        read_bounced(&fs, &inode, offset, size, buffer).await?
+    };
 
     file_handle.position += bytes_read;
     Ok(bytes_read)
 }
+
+///Writes back cached data of the file if it was opened for writing. The handle is closed even if
+///that fails, the pages stay dirty and the error is returned. The last close of an unlinked file
+///drops it from the caches, the filesystem reclaims its blocks after that
+pub async fn close_file(file_handle: FileHandle) -> Result<(), ErrorCode> {
+    let written_back = if file_handle.file_flags.write() {
+        page_cache::flush_inode(file_handle.inode).await
+    } else {
+        Ok(())
+    };
+    let inode = file_handle.inode;
+    drop(file_handle);
+
+    let mut vfs = lock_w_info!(VFS);
+    if file::is_open(inode) || !vfs.unlinked_open.remove(&inode) {
+        return written_back;
+    }
+    drop(vfs);
+    page_cache::invalidate(inode, 0);
+    fs_tree::forget_inode(inode);
+    written_back
+}
+
+///Copies len bytes between lists of pages, starting at byte src_pos of src and byte dst_pos of dst
+pub(super) fn copy_pages(src: &[PhysAddr], src_pos: u64, dst: &[PhysAddr], dst_pos: u64, len: u64) {
+    let mut done = 0;
+    while done < len {
+        let (src_pos, dst_pos) = (src_pos + done, dst_pos + done);
+        let chunk = (len - done).min(4096 - src_pos % 4096).min(4096 - dst_pos % 4096);
+        let src_ptr = translate_phys_virt_addr(src[(src_pos / 4096) as usize]).0 + src_pos % 4096;
+        let dst_ptr = translate_phys_virt_addr(dst[(dst_pos / 4096) as usize]).0 + dst_pos % 4096;
+        unsafe { core::ptr::copy_nonoverlapping(src_ptr as *const u8, dst_ptr as *mut u8, chunk as usize) };
+        done += chunk;
+    }
+}
+
+fn allocate_bounce_pages(count: u64) -> Vec<PhysAddr> {
+    (0..count)
+        .map(|_| {
+            let frame = physical_allocator::allocate_frame();
+            unsafe { memset_physical_addr(frame, 0, 4096) };
+            frame
+        })
+        .collect()
+}
+
+fn free_bounce_pages(pages: Vec<PhysAddr>) {
+    for page in pages {
+        unsafe { physical_allocator::deallocate_frame(page) };
+    }
+}
+
+///Filesystems only take page aligned offsets, an unaligned read goes through bounce pages that
+///start at the page containing offset. Character devices have no offsets and are read directly
+async fn read_bounced(
+    fs: &Arc<dyn FileSystem + Send>,
+    inode: &Inode,
+    offset: u64,
+    size: u64,
+    buffer: &[PhysAddr],
+) -> Result<u64, ErrorCode> {
+    let head = offset % 4096;
+    if head == 0 || inode.type_mode.is_char_device() {
+        return fs.read(inode.index, offset, size, buffer).await;
+    }
+    let bounce = allocate_bounce_pages((head + size).div_ceil(4096));
+    let read = fs.read(inode.index, offset - head, head + size, &bounce).await;
+    let read = read.map(|read| read.saturating_sub(head).min(size));
+    if let Ok(read) = read {
+        copy_pages(&bounce, head, buffer, 0, read);
+    }
+    free_bounce_pages(bounce);
+    read
+}
+
+///Partially written pages are read first and written back whole, so filesystems only see page
+///aligned offsets and never lose the data around the written range
+async fn write_bounced(
+    fs: &Arc<dyn FileSystem + Send>,
+    inode: &Inode,
+    offset: u64,
+    size: u64,
+    content: &[PhysAddr],
+) -> Result<(Inode, u64), ErrorCode> {
+    let head = offset % 4096;
+    let end = offset + size;
+    //a partial last page only matters if there is file data after it
+    if inode.type_mode.is_char_device() || (head == 0 && (end % 4096 == 0 || end >= inode.size)) {
+        return fs.write(inode.index, offset, size, content).await;
+    }
+    let start = offset - head;
+    let pages = (head + size).div_ceil(4096);
+    let bounce = allocate_bounce_pages(pages);
+    let result: Result<(Inode, u64), ErrorCode> = async {
+        if head != 0 && start < inode.size {
+            fs.read(inode.index, start, 4096, &bounce[..1]).await?;
+        }
+        let last_start = start + (pages - 1) * 4096;
+        if end % 4096 != 0 && (pages > 1 || head == 0) && last_start < inode.size {
+            fs.read(inode.index, last_start, 4096, &bounce[(pages - 1) as usize..])
+                .await?;
+        }
+        copy_pages(content, 0, &bounce, head, size);
+        //the old data after the written range goes back too, up to the end of the file or last page
+        let write_size = (head + size).max(inode.size.saturating_sub(start).min(pages * 4096));
+        fs.write(inode.index, start, write_size, &bounce).await
+    }
+    .await;
+    free_bounce_pages(bounce);
+    let (new_inode, written) = result?;
+    Ok((new_inode, written.saturating_sub(head).min(size)))
+}
@@ -0,0 +1,400 @@
+//!Caches file data in whole pages, keyed by inode and page index. A miss loads a few pages ahead,
+//!writes only dirty the cached pages. Dirty pages are written back on close, sync, eviction and
+//!under memory pressure, contiguous pages of a file in one filesystem write. Pages that fail to be
+//!written back stay dirty and the error is returned to close and sync. Truncate and unlink drop the pages that no longer belong to the file, so
+//!they are never written back into freed blocks. Filesystems that keep their data in memory anyway
+//!opt out through [`FileSystem::uses_page_cache`]
+
+use std::{
+    collections::{btree_map::BTreeMap, btree_set::BTreeSet},
+    error::ErrorCode,
+    lock_w_info,
+    mem_utils::{PhysAddr, memset_physical_addr, translate_phys_virt_addr},
+    printlnc,
+    sync::{arc::Arc, no_int_spinlock::NoIntSpinlock},
+    vec::Vec,
+};
+
+use crate::memory::physical_allocator;
+
+use super::{DeviceId, FileSystem, InodeIdentifier, operations::copy_pages};
+
+///Pages loaded after the one that missed
+const READ_AHEAD_PAGES: u64 = 15;
+
+static PAGE_CACHE: NoIntSpinlock<PageCache> = NoIntSpinlock::new(PageCache::new());
+
+///inode and page index in the file
+type PageKey = (InodeIdentifier, u64);
+
+struct CachedPage {
+    frame: PhysAddr,
+    ///bytes of the page that belong to the file, only these are written back
+    len: u64,
+    dirty: bool,
+    last_used: u64,
+}
+
+///Copies of contiguous dirty pages of a file, written back with one filesystem write
+struct WriteBackRun {
+    fs: Arc<dyn FileSystem + Send>,
+    inode: InodeIdentifier,
+    first_page: u64,
+    ///bytes from the start of the first page, only the last page may be partial
+    len: u64,
+    frames: Vec<PhysAddr>,
+}
+
+struct PageCache {
+    pages: BTreeMap<PageKey, CachedPage>,
+    ///maps from last use to page, the first entry is the least recently used one
+    lru: BTreeMap<u64, PageKey>,
+    clock: u64,
+    ///needed to write back pages of other files when evicting
+    filesystems: BTreeMap<DeviceId, Arc<dyn FileSystem + Send>>,
+}
+
+impl PageCache {
+    const fn new() -> Self {
+        PageCache {
+            pages: BTreeMap::new(),
+            lru: BTreeMap::new(),
+            clock: 0,
+            filesystems: BTreeMap::new(),
+        }
+    }
+
+    fn touch(&mut self, key: PageKey) -> Option<&mut CachedPage> {
+        let page = self.pages.get_mut(&key)?;
+        self.lru.remove(&page.last_used);
+        self.clock += 1;
+        page.last_used = self.clock;
+        self.lru.insert(self.clock, key);
+        Some(page)
+    }
+
+    fn insert(&mut self, key: PageKey, frame: PhysAddr, len: u64, dirty: bool) {
+        self.clock += 1;
+        self.lru.insert(self.clock, key);
+        let page = CachedPage {
+            frame,
+            len,
+            dirty,
+            last_used: self.clock,
+        };
+        self.pages.insert(key, page);
+    }
+
+    fn remove(&mut self, key: PageKey) -> Option<CachedPage> {
+        let page = self.pages.remove(&key)?;
+        self.lru.remove(&page.last_used);
+        Some(page)
+    }
+
+    ///Number of pages that have to go before incoming new ones are added
+    fn excess(&self, incoming: usize) -> usize {
+        let (total, free) = physical_allocator::frame_stats();
+        let limit = (total / 8) as usize;
+        let mut excess = (self.pages.len() + incoming).saturating_sub(limit);
+        //under memory pressure give back a quarter of the cache
+        if free < total / 32 {
+            excess = excess.max(self.pages.len() / 4);
+        }
+        excess.min(self.pages.len())
+    }
+}
+
+///Reads size bytes at any offset into buffer, starting at its first byte. Never reads past file_size
+pub(super) async fn read(
+    fs: &Arc<dyn FileSystem + Send>,
+    inode: InodeIdentifier,
+    file_size: u64,
+    offset: u64,
+    size: u64,
+    buffer: &[PhysAddr],
+) -> Result<u64, ErrorCode> {
+    let size = size.min(file_size.saturating_sub(offset));
+    let mut done = 0;
+    while done < size {
+        let position = offset + done;
+        let key = (inode, position / 4096);
+        let in_page = position % 4096;
+        let len = (size - done).min(4096 - in_page);
+        if !copy_from_cache(key, in_page, buffer, done, len) {
+            let count = (file_size.div_ceil(4096) - key.1).min(READ_AHEAD_PAGES + 1);
+            load(fs, key, count, file_size).await?;
+            if !copy_from_cache(key, in_page, buffer, done, len) {
+                //evicted right after loading, the cache is too small to hold it
+                let bounce = [physical_allocator::allocate_frame()];
+                unsafe { memset_physical_addr(bounce[0], 0, 4096) };
+                let read = fs.read(inode.index, key.1 * 4096, (in_page + len).min(4096), &bounce).await;
+                copy_pages(&bounce, in_page, buffer, done, len);
+                unsafe { physical_allocator::deallocate_frame(bounce[0]) };
+                read?;
+            }
+        }
+        done += len;
+    }
+    Ok(done)
+}
+
+///Writes size bytes from the start of buffer at any offset into the cache. The data reaches the
+///filesystem on write back
+pub(super) async fn write(
+    fs: &Arc<dyn FileSystem + Send>,
+    inode: InodeIdentifier,
+    file_size: u64,
+    offset: u64,
+    size: u64,
+    buffer: &[PhysAddr],
+) -> Result<u64, ErrorCode> {
+    make_room((offset % 4096 + size).div_ceil(4096) as usize).await;
+    lock_w_info!(PAGE_CACHE)
+        .filesystems
+        .entry(inode.device_id)
+        .or_insert_with(|| fs.clone());
+    let mut done = 0;
+    while done < size {
+        let position = offset + done;
+        let key = (inode, position / 4096);
+        let in_page = position % 4096;
+        let len = (size - done).min(4096 - in_page);
+        //the rest of a partially written page has to be read first
+        let keeps_old_data = len < 4096 && key.1 * 4096 < file_size;
+        if !copy_to_cache(key, in_page, buffer, done, len, !keeps_old_data) {
+            load(fs, key, 1, file_size).await?;
+            if !copy_to_cache(key, in_page, buffer, done, len, false) {
+                //evicted right after loading, the cache is too small to hold it
+                let bounce = [physical_allocator::allocate_frame()];
+                unsafe { memset_physical_addr(bounce[0], 0, 4096) };
+                let page_len = file_size.saturating_sub(key.1 * 4096).min(4096);
+                let mut result = Ok(0);
+                if page_len != 0 {
+                    result = fs.read(inode.index, key.1 * 4096, page_len, &bounce).await;
+                }
+                if result.is_ok() {
+                    copy_pages(buffer, done, &bounce, in_page, len);
+                    result = fs
+                        .write(inode.index, key.1 * 4096, page_len.max(in_page + len), &bounce)
+                        .await
+                        .map(|(_, written)| written);
+                }
+                unsafe { physical_allocator::deallocate_frame(bounce[0]) };
+                result?;
+            }
+        }
+        done += len;
+    }
+    Ok(done)
+}
+
+///Writes back the dirty pages of an inode
+pub(super) async fn flush_inode(inode: InodeIdentifier) -> Result<(), ErrorCode> {
+    write_back_where(|key| key.0 == inode).await
+}
+
+///Writes back the dirty pages of a device
+pub(super) async fn flush_device(device: DeviceId) -> Result<(), ErrorCode> {
+    write_back_where(|key| key.0.device_id == device).await
+}
+
+///Drops the cached pages of an inode from from_offset on without writing them back, the page
+///containing from_offset is cut there. Called before the filesystem frees the blocks behind them
+pub(super) fn invalidate(inode: InodeIdentifier, from_offset: u64) {
+    let mut cache = lock_w_info!(PAGE_CACHE);
+    let first = (inode, from_offset.div_ceil(4096));
+    let keys: Vec<PageKey> = cache.pages.range(first..=(inode, u64::MAX)).map(|(key, _)| *key).collect();
+    for key in keys {
+        if let Some(page) = cache.remove(key) {
+            unsafe { physical_allocator::deallocate_frame(page.frame) };
+        }
+    }
+    let in_page = from_offset % 4096;
+    if in_page == 0 {
+        return;
+    }
+    if let Some(page) = cache.pages.get_mut(&(inode, from_offset / 4096)) {
+        page.len = page.len.min(in_page);
+        unsafe { memset_physical_addr(page.frame + in_page, 0, (4096 - in_page) as usize) };
+    }
+}
+
+///Writes back every dirty page
+pub async fn sync() -> Result<(), ErrorCode> {
+    write_back_where(|_| true).await
+}
+
+///Writes back and drops every page of a device. Called before its filesystem is unmounted, pages
+///that still fail to be written back are lost
+pub(super) async fn drop_device(device: DeviceId) {
+    if let Err(e) = flush_device(device).await {
+        printlnc!((255, 0, 0), "page cache: dropping dirty pages of device {:?}: {}", device, e);
+    }
+    let mut cache = lock_w_info!(PAGE_CACHE);
+    let keys: Vec<PageKey> = cache.pages.keys().filter(|key| key.0.device_id == device).copied().collect();
+    for key in keys {
+        if let Some(page) = cache.remove(key) {
+            unsafe { physical_allocator::deallocate_frame(page.frame) };
+        }
+    }
+    cache.filesystems.remove(&device);
+}
+
+///Copies len bytes at in_page of the cached page to buffer_pos in buffer
+fn copy_from_cache(key: PageKey, in_page: u64, buffer: &[PhysAddr], buffer_pos: u64, len: u64) -> bool {
+    let mut cache = lock_w_info!(PAGE_CACHE);
+    let Some(page) = cache.touch(key) else {
+        return false;
+    };
+    copy_pages(&[page.frame], in_page, buffer, buffer_pos, len);
+    true
+}
+
+///Copies len bytes at buffer_pos in buffer to in_page of the cached page and marks it dirty. A
+///missing page is only created if create is set, otherwise returns false
+fn copy_to_cache(key: PageKey, in_page: u64, buffer: &[PhysAddr], buffer_pos: u64, len: u64, create: bool) -> bool {
+    let mut cache = lock_w_info!(PAGE_CACHE);
+    let frame = match cache.touch(key) {
+        Some(page) => {
+            page.len = page.len.max(in_page + len);
+            page.dirty = true;
+            page.frame
+        }
+        None if create => {
+            let frame = physical_allocator::allocate_frame();
+            unsafe { memset_physical_addr(frame, 0, 4096) };
+            cache.insert(key, frame, in_page + len, true);
+            frame
+        }
+        None => return false,
+    };
+    copy_pages(buffer, buffer_pos, &[frame], in_page, len);
+    true
+}
+
+///Loads count pages starting at first. Pages that are already cached are kept as they are
+async fn load(fs: &Arc<dyn FileSystem + Send>, first: PageKey, count: u64, file_size: u64) -> Result<(), ErrorCode> {
+    let (inode, first_page) = first;
+    let offset = first_page * 4096;
+    let size = (count * 4096).min(file_size.saturating_sub(offset));
+    //filesystems expect exactly as many frames as the size needs
+    let count = size.div_ceil(4096);
+    make_room(count as usize).await;
+    let frames: Vec<PhysAddr> = (0..count).map(|_| physical_allocator::allocate_frame()).collect();
+    let read = match fs.read(inode.index, offset, size, &frames).await {
+        Ok(read) => read,
+        Err(e) => {
+            for frame in frames {
+                unsafe { physical_allocator::deallocate_frame(frame) };
+            }
+            return Err(e);
+        }
+    };
+
+    let mut cache = lock_w_info!(PAGE_CACHE);
+    cache.filesystems.entry(inode.device_id).or_insert_with(|| fs.clone());
+    for (i, frame) in frames.into_iter().enumerate() {
+        let key = (inode, first_page + i as u64);
+        let page_start = i as u64 * 4096;
+        if page_start >= size || cache.pages.contains_key(&key) {
+            unsafe { physical_allocator::deallocate_frame(frame) };
+            continue;
+        }
+        //filesystems may leave garbage after the end of the data, and a short read is a hole
+        let valid = read.saturating_sub(page_start).min(4096);
+        unsafe { memset_physical_addr(frame + valid, 0, (4096 - valid) as usize) };
+        cache.insert(key, frame, (size - page_start).min(4096), false);
+    }
+    Ok(())
+}
+
+///Evicts the least recently used pages until incoming new ones fit
+async fn make_room(incoming: usize) {
+    let cache = lock_w_info!(PAGE_CACHE);
+    let excess = cache.excess(incoming);
+    if excess == 0 {
+        return;
+    }
+    let victims: BTreeSet<PageKey> = cache.lru.values().take(excess).copied().collect();
+    drop(cache);
+
+    //pages that fail stay dirty and cached, close and sync report the error
+    let _ = write_back_where(|key| victims.contains(key)).await;
+    let mut cache = lock_w_info!(PAGE_CACHE);
+    for key in victims {
+        //pages dirtied again during write back have to stay
+        if cache.pages.get(&key).is_some_and(|page| !page.dirty) {
+            let page = cache.remove(key).expect("checked above");
+            unsafe { physical_allocator::deallocate_frame(page.frame) };
+        }
+    }
+}
+
+///Writes back the dirty pages matching filter. They stay cached and are clean afterwards, unless
+///writing them failed. Returns the first error
+async fn write_back_where(filter: impl Fn(&PageKey) -> bool) -> Result<(), ErrorCode> {
+    let mut cache = lock_w_info!(PAGE_CACHE);
+    let PageCache { pages, filesystems, .. } = &mut *cache;
+    let mut runs: Vec<WriteBackRun> = Vec::new();
+    for (key, page) in pages.iter_mut().filter(|(key, page)| page.dirty && filter(key)) {
+        let Some(fs) = filesystems.get(&key.0.device_id) else {
+            continue;
+        };
+        //written from a copy, so the page can be used and dirtied again in the meantime
+        let snapshot = physical_allocator::allocate_frame();
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                translate_phys_virt_addr(page.frame).0 as *const u8,
+                translate_phys_virt_addr(snapshot).0 as *mut u8,
+                4096,
+            )
+        };
+        page.dirty = false;
+        match runs.last_mut() {
+            //only a full page can be followed by another one
+            Some(run) if run.inode == key.0 && run.first_page + run.frames.len() as u64 == key.1 && run.len % 4096 == 0 => {
+                run.frames.push(snapshot);
+                run.len += page.len;
+            }
+            _ => runs.push(WriteBackRun {
+                fs: fs.clone(),
+                inode: key.0,
+                first_page: key.1,
+                len: page.len,
+                frames: Vec::from([snapshot]),
+            }),
+        }
+    }
+    drop(cache);
+
+    //in page order, so files only ever grow while writing back
+    let mut result = Ok(());
+    for run in runs {
+        let written = run
+            .fs
+            .write(run.inode.index, run.first_page * 4096, run.len, &run.frames)
+            .await;
+        if let Err(e) = written {
+            printlnc!(
+                (255, 0, 0),
+                "page cache: failed to write back pages {}.. of inode {}: {}",
+                run.first_page,
+                run.inode.index,
+                e
+            );
+            //pages dropped in the meantime were truncated away
+            let mut cache = lock_w_info!(PAGE_CACHE);
+            for page in 0..run.frames.len() as u64 {
+                if let Some(page) = cache.pages.get_mut(&(run.inode, run.first_page + page)) {
+                    page.dirty = true;
+                }
+            }
+            drop(cache);
+            result = result.and(Err(e));
+        }
+        for frame in run.frames {
+            unsafe { physical_allocator::deallocate_frame(frame) };
+        }
+    }
+    result
+}
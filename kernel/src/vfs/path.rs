@@ -1,7 +1,8 @@
 use std::{boxed::Box, vec::Vec};
 
 ///A wrapper type for path, that have been resolved to a list of path components
-///That is, the path starts from root and does not contain any "." or ".." components
+///That is, the path does not contain any "." components. ".." is kept, it is resolved against the inode
+///chain during lookup since its meaning depends on mounts and symlinks
 #[repr(transparent)]
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct ResolvedPath(Box<[Box<str>]>);
@@ -52,7 +53,7 @@ impl ResolvedPath {
     }
 }
 
-impl ResolvedPathBorrowed<'_> {
+impl<'a> ResolvedPathBorrowed<'a> {
     pub fn index(&self, range: core::ops::Range<usize>) -> ResolvedPathBorrowed<'_> {
         ResolvedPathBorrowed(&self.0[range])
     }
@@ -76,6 +77,11 @@ impl ResolvedPathBorrowed<'_> {
     pub fn inner(&self) -> &[Box<str>] {
         self.0
     }
+
+    ///Last component and the path of its parent, None for root
+    pub fn split_last(&self) -> Option<(&'a str, ResolvedPathBorrowed<'a>)> {
+        self.0.split_last().map(|(last, parent)| (last.as_ref(), ResolvedPathBorrowed(parent)))
+    }
 }
 
 pub fn resolve_path(path: &str) -> ResolvedPath {
@@ -0,0 +1,385 @@
+//!A RAM backed filesystem. File data is kept in physical frames, nothing survives an unmount.
+//!Used for /tmp and as the root filesystem when no disk is available
+
+use std::{
+    boxed::Box,
+    collections::btree_map::BTreeMap,
+    error::ErrorCode,
+    lock_w_info,
+    mem_utils::{PhysAddr, memset_physical_addr, translate_phys_virt_addr},
+    sync::no_int_spinlock::NoIntSpinlock,
+    time::{Instant, UNIX_EPOCH},
+    vec::Vec,
+};
+
+use crate::{drivers::disk::DirEntry, memory::physical_allocator};
+
+use super::{DeviceId, FileSystem, Inode, InodeIndex, InodeType, ROOT_INODE_INDEX};
+
+#[derive(Debug)]
+pub(super) struct Tmpfs {
+    global_lock: NoIntSpinlock<TmpfsInner>,
+}
+
+#[derive(Debug)]
+struct TmpfsInner {
+    nodes: BTreeMap<InodeIndex, TmpfsNode>,
+    next_inode: InodeIndex,
+}
+
+#[derive(Debug)]
+struct TmpfsNode {
+    inode: Inode,
+    data: TmpfsData,
+}
+
+#[derive(Debug)]
+enum TmpfsData {
+    ///maps from page index to the frame holding it. Missing pages are holes and read as zeroes
+    File(BTreeMap<u64, PhysAddr>),
+    Dir(Vec<(Box<str>, InodeIndex)>),
+}
+
+fn now() -> u32 {
+    Instant::now().duration_since(UNIX_EPOCH).as_secs() as u32
+}
+
+impl Tmpfs {
+    pub fn new(device: DeviceId) -> Self {
+        let mut nodes = BTreeMap::new();
+        let root = TmpfsNode::new(ROOT_INODE_INDEX, device, InodeType::new_dir(0o755), 0, 0);
+        nodes.insert(ROOT_INODE_INDEX, root);
+        Tmpfs {
+            global_lock: NoIntSpinlock::new(TmpfsInner {
+                nodes,
+                next_inode: ROOT_INODE_INDEX + 1,
+            }),
+        }
+    }
+}
+
+impl TmpfsNode {
+    fn new(index: InodeIndex, device: DeviceId, type_mode: InodeType, uid: u16, gid: u16) -> Self {
+        let time = now();
+        let data = if type_mode.is_dir() {
+            TmpfsData::Dir(Vec::new())
+        } else {
+            TmpfsData::File(BTreeMap::new())
+        };
+        TmpfsNode {
+            inode: Inode {
+                index,
+                device,
+                type_mode,
+                link_cnt: 1,
+                uid,
+                gid,
+                size: 0,
+                access_time: time,
+                modification_time: time,
+                stat_change_time: time,
+                preferred_block_size: 4096,
+                blocks: 0,
+            },
+            data,
+        }
+    }
+
+    fn touch_modified(&mut self) {
+        let time = now();
+        self.inode.modification_time = time;
+        self.inode.stat_change_time = time;
+    }
+
+    ///Frees all pages at or after first_page
+    fn free_pages_from(&mut self, first_page: u64) {
+        let TmpfsData::File(pages) = &mut self.data else {
+            return;
+        };
+        for (_, frame) in pages.split_off(&first_page) {
+            unsafe { physical_allocator::deallocate_frame(frame) };
+        }
+        self.inode.blocks = pages.len() as u32 * 8;
+    }
+}
+
+impl TmpfsInner {
+    fn dir_entries(&mut self, dir: InodeIndex) -> Result<&mut Vec<(Box<str>, InodeIndex)>, ErrorCode> {
+        match &mut self.nodes.get_mut(&dir).ok_or(ErrorCode::InodeNotPresent)?.data {
+            TmpfsData::Dir(entries) => Ok(entries),
+            TmpfsData::File(_) => Err(ErrorCode::NotDirectory),
+        }
+    }
+
+    ///Adds an entry to dir and updates the directory size, which counts entries
+    fn add_entry(&mut self, dir: InodeIndex, name: &str, inode: InodeIndex) -> Result<(), ErrorCode> {
+        let entries = self.dir_entries(dir)?;
+        if entries.iter().any(|(entry_name, _)| **entry_name == *name) {
+            return Err(ErrorCode::Exists);
+        }
+        entries.push((name.into(), inode));
+        let count = entries.len() as u64;
+        let parent = self.nodes.get_mut(&dir).expect("checked above");
+        parent.inode.size = count;
+        parent.touch_modified();
+        Ok(())
+    }
+
+    ///Drops one link to inode and frees it once nothing refers to it anymore
+    fn drop_link(&mut self, inode: InodeIndex) {
+        let Some(node) = self.nodes.get_mut(&inode) else {
+            return;
+        };
+        node.inode.link_cnt = node.inode.link_cnt.saturating_sub(1);
+        node.inode.stat_change_time = now();
+        if node.inode.link_cnt == 0 {
+            node.free_pages_from(0);
+            self.nodes.remove(&inode);
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl FileSystem for Tmpfs {
+    fn uses_page_cache(&self) -> bool {
+        false
+    }
+
+    async fn unmount(&self) {
+        let mut inner = lock_w_info!(self.global_lock);
+        for node in inner.nodes.values_mut() {
+            node.free_pages_from(0);
+        }
+        inner.nodes.clear();
+    }
+
+    async fn read(&self, inode: InodeIndex, offset_bytes: u64, size_bytes: u64, buffer: &[PhysAddr]) -> Result<u64, ErrorCode> {
+        let mut inner = lock_w_info!(self.global_lock);
+        let node = inner.nodes.get_mut(&inode).ok_or(ErrorCode::InodeNotPresent)?;
+        let TmpfsData::File(pages) = &node.data else {
+            return Err(ErrorCode::IsDirectory);
+        };
+        let size = size_bytes.min(node.inode.size.saturating_sub(offset_bytes));
+        let first_page = offset_bytes / 4096;
+        let mut done = 0;
+        for (i, dst) in buffer.iter().enumerate() {
+            if done == size {
+                break;
+            }
+            let len = (size - done).min(4096);
+            match pages.get(&(first_page + i as u64)) {
+                Some(src) => unsafe {
+                    core::ptr::copy_nonoverlapping(
+                        translate_phys_virt_addr(*src).0 as *const u8,
+                        translate_phys_virt_addr(*dst).0 as *mut u8,
+                        len as usize,
+                    )
+                },
+                None => unsafe { memset_physical_addr(*dst, 0, len as usize) },
+            }
+            done += len;
+        }
+        node.inode.access_time = now();
+        Ok(done)
+    }
+
+    async fn read_dir(&self, inode: InodeIndex) -> Result<Box<[DirEntry]>, ErrorCode> {
+        let mut inner = lock_w_info!(self.global_lock);
+        let entries = inner.dir_entries(inode)?;
+        Ok(entries
+            .iter()
+            .map(|(name, inode)| DirEntry {
+                inode: *inode,
+                name: name.clone(),
+            })
+            .collect())
+    }
+
+    async fn write(&self, inode: InodeIndex, offset: u64, size: u64, buffer: &[PhysAddr]) -> Result<(Inode, u64), ErrorCode> {
+        let mut inner = lock_w_info!(self.global_lock);
+        let node = inner.nodes.get_mut(&inode).ok_or(ErrorCode::InodeNotPresent)?;
+        let TmpfsData::File(pages) = &mut node.data else {
+            return Err(ErrorCode::IsDirectory);
+        };
+        let first_page = offset / 4096;
+        let mut done = 0;
+        for (i, src) in buffer.iter().enumerate() {
+            if done == size {
+                break;
+            }
+            let len = (size - done).min(4096);
+            let dst = *pages.entry(first_page + i as u64).or_insert_with(|| {
+                let frame = physical_allocator::allocate_frame();
+                unsafe { memset_physical_addr(frame, 0, 4096) };
+                frame
+            });
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    translate_phys_virt_addr(*src).0 as *const u8,
+                    translate_phys_virt_addr(dst).0 as *mut u8,
+                    len as usize,
+                )
+            };
+            done += len;
+        }
+        node.inode.blocks = pages.len() as u32 * 8;
+        node.inode.size = node.inode.size.max(offset + done);
+        node.touch_modified();
+        Ok((node.inode.clone(), done))
+    }
+
+    async fn stat(&self, inode: InodeIndex) -> Result<Inode, ErrorCode> {
+        let inner = lock_w_info!(self.global_lock);
+        let node = inner.nodes.get(&inode).ok_or(ErrorCode::InodeNotPresent)?;
+        Ok(node.inode.clone())
+    }
+
+    async fn set_stat(&self, inode_index: InodeIndex, inode_data: Inode) -> Result<(), ErrorCode> {
+        let mut inner = lock_w_info!(self.global_lock);
+        let node = inner.nodes.get_mut(&inode_index).ok_or(ErrorCode::InodeNotPresent)?;
+        //size, links and blocks are owned by the filesystem
+        node.inode.type_mode = inode_data.type_mode;
+        node.inode.uid = inode_data.uid;
+        node.inode.gid = inode_data.gid;
+        node.inode.access_time = inode_data.access_time;
+        node.inode.modification_time = inode_data.modification_time;
+        node.inode.stat_change_time = now();
+        Ok(())
+    }
+
+    async fn create(
+        &self,
+        name: &str,
+        parent_dir: InodeIndex,
+        type_mode: InodeType,
+        uid: u16,
+        gid: u16,
+    ) -> Result<(Inode, Inode), ErrorCode> {
+        let mut inner = lock_w_info!(self.global_lock);
+        let device = inner.nodes.get(&parent_dir).ok_or(ErrorCode::InodeNotPresent)?.inode.device;
+        let inode_index = inner.next_inode;
+        let node = TmpfsNode::new(inode_index, device, type_mode, uid, gid);
+        let inode = node.inode.clone();
+        inner.add_entry(parent_dir, name, inode_index)?;
+        inner.next_inode += 1;
+        inner.nodes.insert(inode_index, node);
+        let parent_inode = inner.nodes.get(&parent_dir).expect("checked above").inode.clone();
+        Ok((inode, parent_inode))
+    }
+
+    async fn symlink(
+        &self,
+        name: &str,
+        parent_dir: InodeIndex,
+        target: &str,
+        uid: u16,
+        gid: u16,
+    ) -> Result<(Inode, Inode), ErrorCode> {
+        let (inode, parent_inode) = self.create(name, parent_dir, InodeType::new_symlink(0o777), uid, gid).await?;
+        let mut inner = lock_w_info!(self.global_lock);
+        //removed again while being created
+        let node = inner.nodes.get_mut(&inode.index).ok_or(ErrorCode::NoEntry)?;
+        let TmpfsData::File(pages) = &mut node.data else {
+            unreachable!("symlinks are stored like files");
+        };
+        //the target is stored as the file content
+        let frame = physical_allocator::allocate_frame();
+        let len = target.len().min(4096);
+        unsafe {
+            memset_physical_addr(frame, 0, 4096);
+            core::ptr::copy_nonoverlapping(target.as_ptr(), translate_phys_virt_addr(frame).0 as *mut u8, len);
+        }
+        pages.insert(0, frame);
+        node.inode.size = len as u64;
+        node.inode.blocks = 8;
+        Ok((node.inode.clone(), parent_inode))
+    }
+
+    async fn unlink(&self, parent_inode: InodeIndex, name: &str) -> Result<(), ErrorCode> {
+        let mut inner = lock_w_info!(self.global_lock);
+        let entries = inner.dir_entries(parent_inode)?;
+        let position = entries
+            .iter()
+            .position(|(entry_name, _)| **entry_name == *name)
+            .ok_or(ErrorCode::NoEntry)?;
+        let target = entries[position].1;
+        let target_is_full_dir = inner
+            .nodes
+            .get(&target)
+            .is_some_and(|node| matches!(&node.data, TmpfsData::Dir(children) if !children.is_empty()));
+        if target_is_full_dir {
+            return Err(ErrorCode::DirectoryNotEmpty);
+        }
+
+        let entries = inner.dir_entries(parent_inode).expect("checked above");
+        entries.remove(position);
+        let count = entries.len() as u64;
+        let parent = inner.nodes.get_mut(&parent_inode).expect("checked above");
+        parent.inode.size = count;
+        parent.touch_modified();
+        inner.drop_link(target);
+        Ok(())
+    }
+
+    async fn link(&self, inode: InodeIndex, parent_dir: InodeIndex, name: &str) -> Result<Inode, ErrorCode> {
+        let mut inner = lock_w_info!(self.global_lock);
+        if !inner.nodes.contains_key(&inode) {
+            return Err(ErrorCode::InodeNotPresent);
+        }
+        inner.add_entry(parent_dir, name, inode)?;
+        let node = inner.nodes.get_mut(&inode).expect("checked above");
+        node.inode.link_cnt += 1;
+        node.inode.stat_change_time = now();
+        Ok(inner
+            .nodes
+            .get(&parent_dir)
+            .expect("add_entry checks the parent")
+            .inode
+            .clone())
+    }
+
+    async fn truncate(&self, inode: InodeIndex, size: u64) -> Result<(), ErrorCode> {
+        let mut inner = lock_w_info!(self.global_lock);
+        let node = inner.nodes.get_mut(&inode).ok_or(ErrorCode::InodeNotPresent)?;
+        if node.inode.type_mode.is_dir() {
+            return Err(ErrorCode::IsDirectory);
+        }
+        if size < node.inode.size {
+            node.free_pages_from(size.div_ceil(4096));
+            //the tail of the last page has to read as zeroes if the file grows again
+            let tail = size % 4096;
+            if let TmpfsData::File(pages) = &node.data {
+                if let Some(last) = pages.get(&(size / 4096)).filter(|_| tail != 0) {
+                    unsafe { memset_physical_addr(*last + PhysAddr(tail), 0, (4096 - tail) as usize) };
+                }
+            }
+        }
+        node.inode.size = size;
+        node.touch_modified();
+        Ok(())
+    }
+
+    async fn rename(&self, inode: InodeIndex, parent_inode: InodeIndex, name: &str) -> Result<(), ErrorCode> {
+        let mut inner = lock_w_info!(self.global_lock);
+        let entries = inner.dir_entries(parent_inode)?;
+        //an existing entry with the new name is replaced
+        let replaced = entries
+            .iter()
+            .position(|(entry_name, entry_inode)| **entry_name == *name && *entry_inode != inode);
+        let replaced_inode = replaced.map(|position| entries.remove(position).1);
+        if let Some(entry) = entries.iter_mut().find(|(_, entry_inode)| *entry_inode == inode) {
+            entry.0 = name.into();
+        }
+        let count = entries.len() as u64;
+        let parent = inner.nodes.get_mut(&parent_inode).expect("checked above");
+        parent.inode.size = count;
+        parent.touch_modified();
+        if let Some(node) = inner.nodes.get_mut(&inode) {
+            node.inode.stat_change_time = now();
+        }
+        if let Some(replaced_inode) = replaced_inode {
+            inner.drop_link(replaced_inode);
+        }
+        Ok(())
+    }
+}
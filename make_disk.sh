@@ -26,3 +26,14 @@ xorriso -as mkisofs -b "/limine-bios-cd.bin" \
         "iso/" -o kernel_build_files/image.iso
 
 limine bios-install kernel_build_files/image.iso
+
+#----------test disk, the same every time it is built----------
+#root gets the content of assets/disk_root if it exists, rfs_test is left empty for the kernel tests.
+#It needs 4 GiB for a file with 3 levels of pointers, the image is sparse
+cargo run -q --release -p rfs_tool -- mkfs assets/ahci_disk.img root:64 rfs_test:4096
+if [ -d assets/disk_root ]; then
+    for entry in assets/disk_root/*; do
+        cargo run -q --release -p rfs_tool -- -p root insert assets/ahci_disk.img "$entry" /
+    done
+fi
+cargo run -q --release -p rfs_tool -- -p root fsck assets/ahci_disk.img
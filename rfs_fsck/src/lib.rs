@@ -0,0 +1,1279 @@
+//!Consistency checker for rfs, used by the kernel at mount time and by rfs-tool. Walks the inode
+//!tree, the pointers or extents of every inode and the directories with their name indexes, then
+//!compares what it found with the block and inode bitmaps. Problems are reported, and repaired where
+//!the fix is unambiguous. On filesystems with checksums every metadata block read is verified, and
+//!every block written gets its checksum updated
+#![no_std]
+extern crate alloc;
+
+use alloc::{
+    collections::{btree_map::BTreeMap, btree_set::BTreeSet},
+    string::String,
+    vec,
+    vec::Vec,
+};
+use core::{fmt, mem::size_of};
+
+use rfs_layout::{
+    BLOCK_POINTERS, BLOCK_SIZE, ChecksumBlock, DirEntry, Extent, FEATURE_CHECKSUMS, GROUP_BLOCK_SIZE, INLINE_DATA_SIZE,
+    INODE_EXTENTS, INODE_POINTERS, Inode, InodeBitmask, Key, NODE_EXTENTS, NODE_KEYS, ROOT_INODE_INDEX, SUPERBLOCK_BLOCK,
+    SuperBlock, TABLE_CHECKSUMS, checksum_table_blocks, crc32c, extent_depth_for, name_hash, next_hash, ptr_levels_for,
+};
+
+pub type Block = [u8; BLOCK_SIZE];
+
+///Blocks of the filesystem, block 0 being the first block of the partition
+#[allow(async_fn_in_trait)]
+pub trait Disk {
+    type Error;
+    fn blocks(&self) -> u32;
+    async fn read_block(&mut self, block: u32, buffer: &mut Block) -> Result<(), Self::Error>;
+    async fn write_block(&mut self, block: u32, buffer: &Block) -> Result<(), Self::Error>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    ///Superblock points outside of the partition, nothing else can be checked
+    BadSuperblock,
+    ///Root inode is not in the inode tree
+    MissingRoot,
+    ///Btree node with keys out of order or missing children
+    BadNode {
+        block: u32,
+    },
+    ///Inode in the inode tree more than once
+    DuplicateInode {
+        inode: u32,
+    },
+    ///Pointer of an inode outside of the partition, or 0
+    BadPointer {
+        inode: u32,
+        pointer: u32,
+    },
+    ///Levels of pointers or depth of the extent tree don't match the size
+    BadSize {
+        inode: u32,
+    },
+    ///Extents of a level of the extent tree leave holes, overlap or don't cover the file
+    BadExtents {
+        inode: u32,
+    },
+    ///Block used by an inode that is already used by another one or by metadata
+    DoubleAllocated {
+        block: u32,
+        inode: u32,
+    },
+    ///Directory entry of an inode that doesn't exist
+    DanglingEntry {
+        directory: u32,
+        name: String,
+        inode: u32,
+    },
+    ///Directory size is not a multiple of the entry size
+    BadDirectorySize {
+        directory: u32,
+    },
+    ///Name index of a directory is malformed or doesn't find every entry
+    BadDirectoryIndex {
+        directory: u32,
+    },
+    WrongLinkCount {
+        inode: u32,
+        stored: u16,
+        found: u16,
+    },
+    ///Inode no directory entry points to
+    UnreachableInode {
+        inode: u32,
+    },
+    ///Inode in the inode tree that is free in the inode bitmap
+    InodeMarkedFree {
+        inode: u32,
+    },
+    ///Inode taken in the inode bitmap that is not in the inode tree
+    UnusedInodeMarked {
+        inode: u32,
+    },
+    ///Blocks `first..first + count` are in use but marked free
+    BlocksMarkedFree {
+        first: u32,
+        count: u32,
+    },
+    ///Blocks `first..first + count` are marked used but nothing uses them
+    OrphanedBlocks {
+        first: u32,
+        count: u32,
+    },
+    ///Metadata block that doesn't match its checksum, or a block of the checksum table that doesn't
+    ///match its own
+    BadChecksum {
+        block: u32,
+    },
+}
+
+impl Problem {
+    ///Whether check repairs this problem when asked to
+    pub fn repairable(&self) -> bool {
+        match self {
+            Problem::DoubleAllocated { inode, .. } => *inode != METADATA,
+            Problem::DanglingEntry { .. }
+            | Problem::BadDirectorySize { .. }
+            | Problem::BadDirectoryIndex { .. }
+            | Problem::WrongLinkCount { .. }
+            | Problem::InodeMarkedFree { .. }
+            | Problem::UnusedInodeMarked { .. }
+            | Problem::BlocksMarkedFree { .. }
+            | Problem::OrphanedBlocks { .. }
+            | Problem::BadChecksum { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::BadSuperblock => write!(f, "superblock points outside of the partition"),
+            Problem::MissingRoot => write!(f, "root inode is missing from the inode tree"),
+            Problem::BadNode { block } => write!(f, "inode tree node at block {} is malformed", block),
+            Problem::DuplicateInode { inode } => write!(f, "inode {} is in the inode tree more than once", inode),
+            Problem::BadPointer { inode, pointer } => write!(f, "inode {} points to invalid block {}", inode, pointer),
+            Problem::BadSize { inode } => write!(f, "inode {} has pointer levels that don't match its size", inode),
+            Problem::BadExtents { inode } => write!(f, "extents of inode {} don't map its blocks in order", inode),
+            Problem::DoubleAllocated { block, inode } if *inode == METADATA => {
+                write!(f, "block {} is used by metadata more than once", block)
+            }
+            Problem::DoubleAllocated { block, inode } => write!(f, "block {} of inode {} is already in use", block, inode),
+            Problem::DanglingEntry { directory, name, inode } => {
+                write!(
+                    f,
+                    "entry {} of directory {} points to missing inode {}",
+                    name, directory, inode
+                )
+            }
+            Problem::BadDirectorySize { directory } => write!(f, "directory {} has a partial entry", directory),
+            Problem::BadDirectoryIndex { directory } => write!(f, "name index of directory {} is broken", directory),
+            Problem::WrongLinkCount { inode, stored, found } => {
+                write!(
+                    f,
+                    "inode {} has link count {}, but {} entries point to it",
+                    inode, stored, found
+                )
+            }
+            Problem::UnreachableInode { inode } => write!(f, "inode {} is not in any directory", inode),
+            Problem::InodeMarkedFree { inode } => write!(f, "inode {} is in use but marked free", inode),
+            Problem::UnusedInodeMarked { inode } => write!(f, "inode {} is marked used but doesn't exist", inode),
+            Problem::BlocksMarkedFree { first, count } => {
+                write!(f, "blocks {}..{} are in use but marked free", first, first + count)
+            }
+            Problem::OrphanedBlocks { first, count } => {
+                write!(f, "blocks {}..{} are marked used but nothing uses them", first, first + count)
+            }
+            Problem::BadChecksum { block } => write!(f, "block {} doesn't match its checksum", block),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub problems: Vec<Problem>,
+    ///Repairs were written to the disk
+    pub repaired: bool,
+}
+
+impl Report {
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+///Checks the filesystem and repairs what can be repaired if `repair` is set. Copying a block used
+///twice can leave the blocks under it used twice too, so repairs run until a check finds nothing
+///more to copy
+pub async fn check<D: Disk>(disk: &mut D, repair: bool) -> Result<Report, D::Error> {
+    let mut report = Report::default();
+    //one pass for each level of pointers, and the first one
+    for _ in 0..4 {
+        let mut checker = Checker::new(disk);
+        checker.run().await?;
+        let copies_needed = !checker.duplicates.is_empty();
+        if repair && checker.problems.iter().any(Problem::repairable) {
+            checker.repair().await?;
+            report.repaired = true;
+        }
+        //later passes find again what can't be repaired
+        for problem in checker.problems.drain(..) {
+            if !report.problems.contains(&problem) {
+                report.problems.push(problem);
+            }
+        }
+        if !repair || !copies_needed {
+            break;
+        }
+    }
+    Ok(report)
+}
+
+///Outcome of [`enable_checksums`]
+#[derive(Debug)]
+pub enum Upgrade {
+    Enabled,
+    AlreadyEnabled,
+    ///The filesystem has to be repaired first, checksums would cover the broken metadata
+    NotClean(Report),
+    ///No free run of blocks is big enough for the checksum table
+    NoSpace,
+}
+
+///Upgrades a filesystem formatted without checksums. The checksum table is allocated and filled
+///with the checksums of every block in use, and the superblock enabling them is written last, so
+///an interrupted upgrade only leaves orphaned blocks behind
+pub async fn enable_checksums<D: Disk>(disk: &mut D) -> Result<Upgrade, D::Error> {
+    let mut checker = Checker::new(disk);
+    checker.run().await?;
+    if checker.checksums.is_some() {
+        return Ok(Upgrade::AlreadyEnabled);
+    }
+    if !checker.problems.is_empty() {
+        return Ok(Upgrade::NotClean(Report {
+            problems: checker.problems,
+            repaired: false,
+        }));
+    }
+    let table_blocks = checksum_table_blocks(checker.blocks);
+    let Some(table_start) = checker.allocate_run(METADATA, table_blocks) else {
+        return Ok(Upgrade::NoSpace);
+    };
+    for group in 0..(checker.blocks as u64).div_ceil(GROUP_BLOCK_SIZE) {
+        let first = (group * GROUP_BLOCK_SIZE) as u32;
+        let last = u64::min((group + 1) * GROUP_BLOCK_SIZE, checker.blocks as u64) as u32;
+        let run = u32::max(first, table_start)..u32::min(last, table_start + table_blocks);
+        if run.is_empty() {
+            continue;
+        }
+        let mut bitmap = checker.read(first).await?;
+        for block in run {
+            let bit = block - first;
+            bitmap[bit as usize / 8] |= 1 << (bit % 8);
+        }
+        checker.write(first, &bitmap).await?;
+    }
+
+    let mut superblock_content = checker.read(SUPERBLOCK_BLOCK).await?;
+    checker.superblock.features |= FEATURE_CHECKSUMS;
+    checker.superblock.checksum_table = table_start;
+    store(&mut superblock_content, &checker.superblock);
+    let mut checksums = vec![0; checker.blocks as usize];
+    for block in 0..checker.blocks {
+        let is_table = (table_start..table_start + table_blocks).contains(&block);
+        if checker.owners[block as usize] == FREE || is_table {
+            continue;
+        }
+        checksums[block as usize] = if block == SUPERBLOCK_BLOCK {
+            crc32c(&superblock_content)
+        } else {
+            crc32c(&checker.read(block).await?)
+        };
+    }
+    checker.checksums = Some(checksums);
+    checker.dirty_table_blocks = (0..table_blocks).collect();
+    checker.write_checksum_table().await?;
+    checker.disk.write_block(SUPERBLOCK_BLOCK, &superblock_content).await?;
+    Ok(Upgrade::Enabled)
+}
+
+///Owner of blocks that are not data or pointers of an inode
+const METADATA: u32 = u32::MAX;
+const FREE: u32 = 0;
+const DIR_ENTRY_SIZE: usize = size_of::<DirEntry>();
+
+///Where a pointer to a block is stored
+#[derive(Debug, Clone, Copy)]
+struct PointerLocation {
+    block: u32,
+    offset: usize,
+    inode: u32,
+    ///Blocks starting at the pointed to one, more than 1 for extents
+    length: u32,
+}
+
+#[derive(Debug, Default)]
+struct InodeInfo {
+    block: u32,
+    link_count: u16,
+    is_dir: bool,
+    size: u64,
+    ///Pointers were readable, data_blocks and pointer_blocks are complete
+    complete: bool,
+    ///Only kept for directories
+    data_blocks: Vec<u32>,
+    pointer_blocks: Vec<u32>,
+    references: u16,
+    ///Entries to keep when the directory is rewritten
+    new_entries: Option<Vec<u8>>,
+    ///Nodes of the name index of a directory
+    index_blocks: Vec<u32>,
+    ///Keys of the name index, None if there is none or it is malformed
+    index_keys: Option<Vec<Key>>,
+    ///Index is dropped on repair
+    drop_index: bool,
+}
+
+///Keys and nodes of a btree, the inode tree or a directory index
+struct BtreeContent {
+    keys: Vec<Key>,
+    ///Nodes claimed for the owner
+    nodes: Vec<u32>,
+    ///Nodes that are malformed or already used
+    bad_nodes: Vec<u32>,
+}
+
+struct Checker<'a, D: Disk> {
+    disk: &'a mut D,
+    blocks: u32,
+    superblock: SuperBlock,
+    ///Inode using each block, METADATA or FREE
+    owners: Vec<u32>,
+    inodes: BTreeMap<u32, InodeInfo>,
+    ///Inodes taken in the inode bitmap
+    marked_inodes: BTreeSet<u32>,
+    inode_bitmask_blocks: Vec<u32>,
+    ///Pointers to blocks that were already used, copies are made on repair
+    duplicates: Vec<PointerLocation>,
+    problems: Vec<Problem>,
+    ///Block allocation for repairs starts searching here
+    next_free: u32,
+    ///Checksum of every block, None on filesystems without checksums
+    checksums: Option<Vec<u32>>,
+    ///Blocks of the checksum table, relative to its start, changed since it was read
+    dirty_table_blocks: BTreeSet<u32>,
+}
+
+impl<'a, D: Disk> Checker<'a, D> {
+    fn new(disk: &'a mut D) -> Self {
+        let blocks = disk.blocks();
+        Self {
+            disk,
+            blocks,
+            superblock: SuperBlock {
+                inode_tree: 0,
+                inode_bitmask: 0,
+                journal_start: 0,
+                journal_blocks: 0,
+                features: 0,
+                checksum_table: 0,
+            },
+            owners: vec![FREE; blocks as usize],
+            inodes: BTreeMap::new(),
+            marked_inodes: BTreeSet::new(),
+            inode_bitmask_blocks: Vec::new(),
+            duplicates: Vec::new(),
+            problems: Vec::new(),
+            next_free: 0,
+            checksums: None,
+            dirty_table_blocks: BTreeSet::new(),
+        }
+    }
+
+    async fn read(&mut self, block: u32) -> Result<Block, D::Error> {
+        let mut buffer = [0; BLOCK_SIZE];
+        self.disk.read_block(block, &mut buffer).await?;
+        Ok(buffer)
+    }
+
+    ///read for metadata, which is verified if the filesystem has checksums. The content is
+    ///returned either way, the structure is still checked
+    async fn read_metadata(&mut self, block: u32) -> Result<Block, D::Error> {
+        let content = self.read(block).await?;
+        self.verify(block, &content);
+        Ok(content)
+    }
+
+    fn verify(&mut self, block: u32, content: &Block) {
+        let Some(checksums) = &self.checksums else {
+            return;
+        };
+        if checksums[block as usize] != crc32c(content) {
+            self.problems.push(Problem::BadChecksum { block });
+        }
+    }
+
+    ///Writes a block and updates its checksum, the checksum table is written at the end of repair
+    async fn write(&mut self, block: u32, content: &Block) -> Result<(), D::Error> {
+        self.disk.write_block(block, content).await?;
+        self.set_checksum(block, content);
+        Ok(())
+    }
+
+    fn set_checksum(&mut self, block: u32, content: &Block) {
+        if let Some(checksums) = &mut self.checksums {
+            checksums[block as usize] = crc32c(content);
+            self.dirty_table_blocks.insert(block / TABLE_CHECKSUMS as u32);
+        }
+    }
+
+    ///Reads the whole checksum table and claims its blocks
+    async fn load_checksum_table(&mut self) -> Result<(), D::Error> {
+        let mut checksums = Vec::with_capacity(self.blocks as usize);
+        for table_block in 0..checksum_table_blocks(self.blocks) {
+            let block = self.superblock.checksum_table + table_block;
+            self.claim(block, METADATA, None);
+            let table: ChecksumBlock = load(&self.read(block).await?);
+            if table.checksum != table.own_checksum() {
+                self.problems.push(Problem::BadChecksum { block });
+                self.dirty_table_blocks.insert(table_block);
+            }
+            checksums.extend_from_slice(&table.checksums);
+        }
+        checksums.truncate(self.blocks as usize);
+        self.checksums = Some(checksums);
+        Ok(())
+    }
+
+    async fn write_checksum_table(&mut self) -> Result<(), D::Error> {
+        let Some(checksums) = &self.checksums else {
+            return Ok(());
+        };
+        let mut table = ChecksumBlock {
+            checksums: [0; TABLE_CHECKSUMS],
+            checksum: 0,
+        };
+        let mut writes = Vec::new();
+        for table_block in self.dirty_table_blocks.iter() {
+            let first = *table_block as usize * TABLE_CHECKSUMS;
+            let slots = &checksums[first..usize::min(first + TABLE_CHECKSUMS, checksums.len())];
+            table.checksums.fill(0);
+            table.checksums[..slots.len()].copy_from_slice(slots);
+            table.checksum = table.own_checksum();
+            let mut content = [0; BLOCK_SIZE];
+            store(&mut content, &table);
+            writes.push((self.superblock.checksum_table + table_block, content));
+        }
+        for (block, content) in writes {
+            self.disk.write_block(block, &content).await?;
+        }
+        self.dirty_table_blocks.clear();
+        Ok(())
+    }
+
+    ///Records the owner of a block, false if it is invalid or already used
+    fn claim(&mut self, block: u32, owner: u32, location: Option<PointerLocation>) -> bool {
+        if block >= self.blocks {
+            return false;
+        }
+        if self.owners[block as usize] == FREE {
+            self.owners[block as usize] = owner;
+            return true;
+        }
+        self.problems.push(Problem::DoubleAllocated { block, inode: owner });
+        if let Some(location) = location {
+            self.duplicates.push(location);
+        }
+        false
+    }
+
+    ///claim for the blocks of an extent. The free ones are claimed even if others are already used,
+    ///so the copy made on repair can't overlap them
+    fn claim_run(&mut self, first: u32, length: u32, owner: u32, location: PointerLocation) -> bool {
+        if first == 0 || first as u64 + length as u64 > self.blocks as u64 {
+            self.problems.push(Problem::BadPointer {
+                inode: owner,
+                pointer: first,
+            });
+            return false;
+        }
+        let mut used = None;
+        for block in first..first + length {
+            if self.owners[block as usize] == FREE {
+                self.owners[block as usize] = owner;
+            } else if used.is_none() {
+                used = Some(block);
+            }
+        }
+        let Some(block) = used else {
+            return true;
+        };
+        self.problems.push(Problem::DoubleAllocated { block, inode: owner });
+        self.duplicates.push(location);
+        false
+    }
+
+    async fn run(&mut self) -> Result<(), D::Error> {
+        let superblock = self.read(SUPERBLOCK_BLOCK).await?;
+        self.superblock = load(&superblock);
+        let journal_end = self.superblock.journal_start as u64 + self.superblock.journal_blocks as u64;
+        let has_checksums = self.superblock.features & FEATURE_CHECKSUMS != 0;
+        let table_end = self.superblock.checksum_table as u64 + checksum_table_blocks(self.blocks) as u64;
+        if self.superblock.inode_tree >= self.blocks
+            || self.superblock.inode_bitmask >= self.blocks
+            || journal_end > self.blocks as u64
+            || (has_checksums && table_end > self.blocks as u64)
+        {
+            self.problems.push(Problem::BadSuperblock);
+            return Ok(());
+        }
+        if has_checksums {
+            self.load_checksum_table().await?;
+            self.verify(SUPERBLOCK_BLOCK, &superblock);
+        }
+
+        for group in 0..(self.blocks as u64).div_ceil(GROUP_BLOCK_SIZE) {
+            self.claim((group * GROUP_BLOCK_SIZE) as u32, METADATA, None);
+        }
+        self.claim(SUPERBLOCK_BLOCK, METADATA, None);
+        for block in self.superblock.journal_start..journal_end as u32 {
+            self.claim(block, METADATA, None);
+        }
+
+        self.check_inode_bitmask().await?;
+        self.check_inode_tree().await?;
+        if !self.inodes.contains_key(&ROOT_INODE_INDEX) {
+            self.problems.push(Problem::MissingRoot);
+        }
+        let indexes = self.inodes.keys().copied().collect::<Vec<_>>();
+        for index in indexes.iter() {
+            self.check_inode(*index).await?;
+        }
+        //link counts are only known once every directory could be read
+        let links_known = self.inodes.values().all(|info| info.complete || !info.is_dir);
+        for index in indexes.iter() {
+            if self.inodes[index].is_dir && self.inodes[index].complete {
+                self.check_directory(*index).await?;
+            }
+        }
+        if links_known {
+            self.check_link_counts();
+        }
+        for index in indexes.iter() {
+            if !self.marked_inodes.contains(index) {
+                self.problems.push(Problem::InodeMarkedFree { inode: *index });
+            }
+        }
+        for index in self.marked_inodes.iter() {
+            if *index >= ROOT_INODE_INDEX && !self.inodes.contains_key(index) {
+                self.problems.push(Problem::UnusedInodeMarked { inode: *index });
+            }
+        }
+        self.check_block_bitmaps().await
+    }
+
+    async fn check_inode_bitmask(&mut self) -> Result<(), D::Error> {
+        let per_block = 8 * InodeBitmask::new().inodes.len() as u32;
+        let mut block = self.superblock.inode_bitmask;
+        let mut chain_index = 0;
+        while block != 0 && self.claim(block, METADATA, None) {
+            self.inode_bitmask_blocks.push(block);
+            let bitmask: InodeBitmask = load(&self.read_metadata(block).await?);
+            for bit in 0..per_block as usize {
+                if bitmask.get(bit) {
+                    self.marked_inodes.insert(chain_index * per_block + bit as u32);
+                }
+            }
+            chain_index += 1;
+            block = bitmask.next_ptr;
+        }
+        Ok(())
+    }
+
+    async fn check_inode_tree(&mut self) -> Result<(), D::Error> {
+        let tree = self.read_btree(self.superblock.inode_tree, METADATA).await?;
+        for block in tree.bad_nodes {
+            self.problems.push(Problem::BadNode { block });
+        }
+        for key in tree.keys {
+            if self.inodes.contains_key(&key.index) {
+                self.problems.push(Problem::DuplicateInode { inode: key.index });
+                continue;
+            }
+            let info = InodeInfo {
+                block: key.inode_block,
+                ..Default::default()
+            };
+            self.inodes.insert(key.index, info);
+        }
+        Ok(())
+    }
+
+    ///Claims the nodes of a btree for owner and collects the keys, also those of malformed nodes
+    async fn read_btree(&mut self, root: u32, owner: u32) -> Result<BtreeContent, D::Error> {
+        let mut content = BtreeContent {
+            keys: Vec::new(),
+            nodes: Vec::new(),
+            bad_nodes: Vec::new(),
+        };
+        //node, keys of the node have to be above and below these
+        let mut stack = vec![(root, 0, u32::MAX)];
+        while let Some((block, lower, upper)) = stack.pop() {
+            if block == 0 || !self.claim(block, owner, None) {
+                content.bad_nodes.push(block);
+                continue;
+            }
+            content.nodes.push(block);
+            let node = self.read_metadata(block).await?;
+            let keys = (0..NODE_KEYS)
+                .map(|i| load::<Key>(&node[i * size_of::<Key>()..]))
+                .take_while(|key| key.index != 0)
+                .collect::<Vec<_>>();
+            let children = (0..=NODE_KEYS)
+                .map(|i| load::<u32>(&node[NODE_KEYS * size_of::<Key>() + i * 4..]))
+                .collect::<Vec<_>>();
+            let sorted = keys.windows(2).all(|pair| pair[0].index < pair[1].index);
+            let in_range = keys.iter().all(|key| key.index > lower && key.index < upper);
+            let is_leaf = children[0] == 0;
+            let children_valid = is_leaf || children[..=keys.len()].iter().all(|child| *child != 0);
+            if !sorted || !in_range || !children_valid {
+                content.bad_nodes.push(block);
+            }
+            if !is_leaf && children_valid {
+                for (i, child) in children[..=keys.len()].iter().enumerate() {
+                    let child_lower = if i == 0 { lower } else { keys[i - 1].index };
+                    let child_upper = keys.get(i).map(|key| key.index).unwrap_or(upper);
+                    stack.push((*child, child_lower, child_upper));
+                }
+            }
+            content.keys.extend(keys);
+        }
+        Ok(content)
+    }
+
+    ///Claims the inode block and every block the inode points to
+    async fn check_inode(&mut self, index: u32) -> Result<(), D::Error> {
+        let inode_block = self.inodes[&index].block;
+        if !self.claim(inode_block, index, None) {
+            if inode_block >= self.blocks {
+                self.problems.push(Problem::BadPointer {
+                    inode: index,
+                    pointer: inode_block,
+                });
+            }
+            return Ok(());
+        }
+        let content = self.read_metadata(inode_block).await?;
+        let inode: Inode = load(&content);
+        let size = inode.size.size();
+        let levels = inode.size.ptr_levels() as u32;
+        let is_dir = inode.inode_type_mode.is_dir();
+        let levels_valid = if inode.size.extents() {
+            levels <= 3 && (levels == 0 || size > INLINE_DATA_SIZE) && size.div_ceil(BLOCK_SIZE as u64) <= u32::MAX as u64
+        } else {
+            levels == ptr_levels_for(size)
+        };
+        if !levels_valid {
+            self.problems.push(Problem::BadSize { inode: index });
+            return Ok(());
+        }
+
+        let mut complete = true;
+        let mut data_blocks = Vec::new();
+        let mut pointer_blocks = Vec::new();
+        if inode.size.extents() {
+            if size > INLINE_DATA_SIZE {
+                (complete, data_blocks, pointer_blocks) = self.check_extents(index, &content, size, levels, is_dir).await?;
+            }
+        } else if levels > 0 {
+            let data_count = size.div_ceil(BLOCK_SIZE as u64);
+            let top_count = data_count.div_ceil(u64::pow(BLOCK_POINTERS as u64, levels - 1)) as usize;
+            //pointers of the current level, with where they are stored
+            let mut pointers = (0..usize::min(top_count, INODE_POINTERS))
+                .map(|i| {
+                    let offset = 512 + i * 4;
+                    let location = PointerLocation {
+                        block: inode_block,
+                        offset,
+                        inode: index,
+                        length: 1,
+                    };
+                    (load::<u32>(&content[offset..]), location)
+                })
+                .collect::<Vec<_>>();
+            for level in (1..=levels).rev() {
+                let mut lower = Vec::new();
+                let mut lower_left = data_count.div_ceil(u64::pow(BLOCK_POINTERS as u64, level.saturating_sub(2))) as usize;
+                for (pointer, location) in pointers {
+                    let count = usize::min(lower_left, BLOCK_POINTERS);
+                    lower_left -= count;
+                    if pointer == 0 || pointer >= self.blocks {
+                        self.problems.push(Problem::BadPointer { inode: index, pointer });
+                        complete = false;
+                        continue;
+                    }
+                    if !self.claim(pointer, index, Some(location)) {
+                        complete = false;
+                        continue;
+                    }
+                    if level == 1 {
+                        if is_dir {
+                            data_blocks.push(pointer);
+                        }
+                        continue;
+                    }
+                    pointer_blocks.push(pointer);
+                    let pointer_block = self.read_metadata(pointer).await?;
+                    for i in 0..count {
+                        let location = PointerLocation {
+                            block: pointer,
+                            offset: i * 4,
+                            inode: index,
+                            length: 1,
+                        };
+                        lower.push((load::<u32>(&pointer_block[i * 4..]), location));
+                    }
+                }
+                pointers = lower;
+            }
+        }
+
+        let mut index_blocks = Vec::new();
+        let mut index_keys = None;
+        let mut drop_index = false;
+        if is_dir && inode.size.indexed() {
+            let tree = self.read_btree(inode.index_root, index).await?;
+            if tree.bad_nodes.is_empty() {
+                index_keys = Some(tree.keys);
+            } else {
+                self.problems.push(Problem::BadDirectoryIndex { directory: index });
+                drop_index = true;
+            }
+            index_blocks = tree.nodes;
+        }
+
+        let info = self.inodes.get_mut(&index).expect("inode is in the tree");
+        info.link_count = inode.link_count;
+        info.is_dir = is_dir;
+        info.size = size;
+        info.complete = complete;
+        if is_dir {
+            info.data_blocks = data_blocks;
+            info.pointer_blocks = pointer_blocks;
+            info.drop_index = drop_index;
+            info.index_blocks = index_blocks;
+            info.index_keys = index_keys;
+        }
+        Ok(())
+    }
+
+    ///Claims the nodes and data runs of an extent tree, returns whether all of them could be
+    ///claimed, the data blocks of directories and the nodes
+    async fn check_extents(
+        &mut self,
+        index: u32,
+        content: &Block,
+        size: u64,
+        depth: u32,
+        is_dir: bool,
+    ) -> Result<(bool, Vec<u32>, Vec<u32>), D::Error> {
+        let inode_block = self.inodes[&index].block;
+        let data_count = size.div_ceil(BLOCK_SIZE as u64);
+        let mut complete = true;
+        let mut data_blocks = Vec::new();
+        let mut nodes = Vec::new();
+        //extents of the current level, with where their block is stored
+        let Some(mut extents) = read_extents(content, 512, INODE_EXTENTS, inode_block, index) else {
+            self.problems.push(Problem::BadExtents { inode: index });
+            return Ok((false, data_blocks, nodes));
+        };
+        for level in (0..=depth).rev() {
+            //a node that couldn't be read leaves a hole below it. The blocks are claimed either way, so
+            //a repair doesn't free them
+            if complete && !covers(extents.iter().map(|(extent, _)| extent), data_count) {
+                self.problems.push(Problem::BadExtents { inode: index });
+                complete = false;
+            }
+            let mut lower = Vec::new();
+            for (extent, location) in extents {
+                if level == 0 {
+                    if !self.claim_run(extent.block, extent.length, index, location) {
+                        complete = false;
+                    } else if is_dir {
+                        data_blocks.extend(extent.block..extent.block + extent.length);
+                    }
+                    continue;
+                }
+                if extent.block == 0 || extent.block >= self.blocks {
+                    self.problems.push(Problem::BadPointer {
+                        inode: index,
+                        pointer: extent.block,
+                    });
+                    complete = false;
+                    continue;
+                }
+                if !self.claim(extent.block, index, Some(PointerLocation { length: 1, ..location })) {
+                    complete = false;
+                    continue;
+                }
+                nodes.push(extent.block);
+                let node = self.read_metadata(extent.block).await?;
+                match read_extents(&node, 0, NODE_EXTENTS, extent.block, index) {
+                    Some(node_extents) => lower.extend(node_extents),
+                    None => {
+                        self.problems.push(Problem::BadExtents { inode: index });
+                        complete = false;
+                    }
+                }
+            }
+            extents = lower;
+        }
+        Ok((complete, data_blocks, nodes))
+    }
+
+    async fn read_file(&mut self, index: u32) -> Result<Vec<u8>, D::Error> {
+        let info = &self.inodes[&index];
+        let (inode_block, size, data_blocks) = (info.block, info.size as usize, info.data_blocks.clone());
+        let mut content = Vec::with_capacity(size);
+        if data_blocks.is_empty() {
+            let block = self.read(inode_block).await?;
+            content.extend_from_slice(&block[512..512 + usize::min(size, BLOCK_SIZE - 512)]);
+        }
+        for data_block in data_blocks {
+            content.extend_from_slice(&self.read(data_block).await?);
+        }
+        content.truncate(size);
+        Ok(content)
+    }
+
+    async fn check_directory(&mut self, index: u32) -> Result<(), D::Error> {
+        let content = self.read_file(index).await?;
+        let mut rewrite = false;
+        if content.len() % DIR_ENTRY_SIZE != 0 {
+            self.problems.push(Problem::BadDirectorySize { directory: index });
+            rewrite = true;
+        }
+        let mut kept = Vec::with_capacity(content.len());
+        for entry_bytes in content.chunks_exact(DIR_ENTRY_SIZE) {
+            let entry: DirEntry = load(entry_bytes);
+            match self.inodes.get_mut(&entry.inode) {
+                Some(child) => {
+                    child.references = child.references.saturating_add(1);
+                    kept.extend_from_slice(entry_bytes);
+                }
+                None => {
+                    self.problems.push(Problem::DanglingEntry {
+                        directory: index,
+                        name: String::from_utf8_lossy(entry.name()).into(),
+                        inode: entry.inode,
+                    });
+                    rewrite = true;
+                }
+            }
+        }
+        let info = self.inodes.get_mut(&index).expect("inode is in the tree");
+        let index_valid = match info.index_keys.take() {
+            Some(keys) => index_finds_entries(&keys, &content),
+            None => true,
+        };
+        if !index_valid {
+            self.problems.push(Problem::BadDirectoryIndex { directory: index });
+            info.drop_index = true;
+        }
+        if rewrite {
+            info.new_entries = Some(kept);
+        }
+        Ok(())
+    }
+
+    fn check_link_counts(&mut self) {
+        for (index, info) in self.inodes.iter() {
+            if *index == ROOT_INODE_INDEX {
+                continue;
+            }
+            if info.references == 0 {
+                self.problems.push(Problem::UnreachableInode { inode: *index });
+            } else if info.references != info.link_count {
+                self.problems.push(Problem::WrongLinkCount {
+                    inode: *index,
+                    stored: info.link_count,
+                    found: info.references,
+                });
+            }
+        }
+    }
+
+    async fn check_block_bitmaps(&mut self) -> Result<(), D::Error> {
+        let mut marked_free: Option<(u32, u32)> = None;
+        let mut orphaned: Option<(u32, u32)> = None;
+        for group in 0..(self.blocks as u64).div_ceil(GROUP_BLOCK_SIZE) {
+            let bitmap = self.read_metadata((group * GROUP_BLOCK_SIZE) as u32).await?;
+            for bit in 0..GROUP_BLOCK_SIZE {
+                let block = group * GROUP_BLOCK_SIZE + bit;
+                if block >= self.blocks as u64 {
+                    break;
+                }
+                let block = block as u32;
+                let marked = bitmap[bit as usize / 8] & (1 << (bit % 8)) != 0;
+                let used = self.owners[block as usize] != FREE;
+                if used && !marked {
+                    extend_run(&mut marked_free, block, &mut self.problems, |first, count| {
+                        Problem::BlocksMarkedFree { first, count }
+                    });
+                } else if !used && marked {
+                    extend_run(&mut orphaned, block, &mut self.problems, |first, count| {
+                        Problem::OrphanedBlocks { first, count }
+                    });
+                }
+            }
+        }
+        if let Some((first, count)) = marked_free {
+            self.problems.push(Problem::BlocksMarkedFree { first, count });
+        }
+        if let Some((first, count)) = orphaned {
+            self.problems.push(Problem::OrphanedBlocks { first, count });
+        }
+        Ok(())
+    }
+
+    fn allocate(&mut self, owner: u32) -> Option<u32> {
+        self.allocate_run(owner, 1)
+    }
+
+    ///First of `length` free blocks in a row. Block 0 is never free, so runs don't wrap around
+    fn allocate_run(&mut self, owner: u32, length: u32) -> Option<u32> {
+        let mut found = 0;
+        for i in 0..self.blocks {
+            let block = (self.next_free + i) % self.blocks;
+            if self.owners[block as usize] != FREE {
+                found = 0;
+                continue;
+            }
+            found += 1;
+            if found == length {
+                let first = block + 1 - length;
+                self.owners[first as usize..=block as usize].fill(owner);
+                self.next_free = block + 1;
+                return Some(first);
+            }
+        }
+        None
+    }
+
+    ///Makes the changes the found problems need. Block bitmaps are rewritten from the owners last,
+    ///after copies and rewritten directories changed them, then the checksum table
+    async fn repair(&mut self) -> Result<(), D::Error> {
+        for location in core::mem::take(&mut self.duplicates) {
+            let Some(copy) = self.allocate_run(location.inode, location.length) else {
+                break;
+            };
+            let mut holder = self.read(location.block).await?;
+            let original = load::<u32>(&holder[location.offset..]);
+            for i in 0..location.length {
+                let content = self.read(original + i).await?;
+                self.write(copy + i, &content).await?;
+            }
+            holder[location.offset..location.offset + 4].copy_from_slice(&copy.to_le_bytes());
+            self.write(location.block, &holder).await?;
+        }
+
+        let rewrites = self
+            .inodes
+            .iter_mut()
+            .filter_map(|(index, info)| info.new_entries.take().map(|entries| (*index, entries)))
+            .collect::<Vec<_>>();
+        for (index, entries) in rewrites {
+            self.rewrite_file(index, &entries).await?;
+        }
+        //rewritten directories dropped theirs already
+        let dropped_indexes = self
+            .inodes
+            .iter()
+            .filter(|(_, info)| info.drop_index)
+            .map(|(index, _)| *index)
+            .collect::<Vec<_>>();
+        for index in dropped_indexes {
+            let inode_block = self.inodes[&index].block;
+            let mut content = self.read(inode_block).await?;
+            let mut inode: Inode = load(&content);
+            self.drop_index(index, &mut inode);
+            store(&mut content, &inode);
+            self.write(inode_block, &content).await?;
+        }
+
+        let link_counts = self
+            .problems
+            .iter()
+            .filter_map(|problem| match problem {
+                Problem::WrongLinkCount { inode, found, .. } => Some((*inode, *found)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        for (index, link_count) in link_counts {
+            let inode_block = self.inodes[&index].block;
+            let mut content = self.read(inode_block).await?;
+            let mut inode: Inode = load(&content);
+            inode.link_count = link_count;
+            store(&mut content, &inode);
+            self.write(inode_block, &content).await?;
+        }
+
+        let per_block = 8 * InodeBitmask::new().inodes.len() as u32;
+        for (chain_index, block) in self.inode_bitmask_blocks.clone().into_iter().enumerate() {
+            let mut content = self.read(block).await?;
+            let mut bitmask: InodeBitmask = load(&content);
+            for bit in 0..per_block {
+                let index = chain_index as u32 * per_block + bit;
+                if self.inodes.contains_key(&index) {
+                    bitmask.set(bit as usize);
+                } else if index >= ROOT_INODE_INDEX {
+                    bitmask.clear(bit as usize);
+                }
+            }
+            store(&mut content, &bitmask);
+            self.write(block, &content).await?;
+        }
+
+        for group in 0..(self.blocks as u64).div_ceil(GROUP_BLOCK_SIZE) {
+            let bitmap_block = (group * GROUP_BLOCK_SIZE) as u32;
+            let mut bitmap = self.read(bitmap_block).await?;
+            for bit in 0..GROUP_BLOCK_SIZE {
+                let block = group * GROUP_BLOCK_SIZE + bit;
+                //blocks past the end of the partition stay taken
+                let used = block >= self.blocks as u64 || self.owners[block as usize] != FREE;
+                if used {
+                    bitmap[bit as usize / 8] |= 1 << (bit % 8);
+                } else {
+                    bitmap[bit as usize / 8] &= !(1 << (bit % 8));
+                }
+            }
+            self.write(bitmap_block, &bitmap).await?;
+        }
+
+        //blocks that were rewritten above have their checksum updated already
+        let bad_checksums = self
+            .problems
+            .iter()
+            .filter_map(|problem| match problem {
+                Problem::BadChecksum { block } => Some(*block),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        for block in bad_checksums {
+            let table_start = self.superblock.checksum_table;
+            if !(table_start..table_start + checksum_table_blocks(self.blocks)).contains(&block) {
+                let content = self.read(block).await?;
+                self.set_checksum(block, &content);
+            }
+        }
+        self.write_checksum_table().await
+    }
+
+    ///Replaces the content of a directory whose blocks are known, with the same layout the kernel
+    ///uses: inline data if it fits, otherwise extents or as few levels of pointers as possible,
+    ///whichever the inode already uses
+    async fn rewrite_file(&mut self, index: u32, content: &[u8]) -> Result<(), D::Error> {
+        let info = self.inodes.get_mut(&index).expect("inode is in the tree");
+        let inode_block = info.block;
+        let old_blocks = info
+            .data_blocks
+            .drain(..)
+            .chain(info.pointer_blocks.drain(..))
+            .collect::<Vec<_>>();
+        for block in old_blocks {
+            self.owners[block as usize] = FREE;
+        }
+
+        let mut inode_content = self.read(inode_block).await?;
+        let mut inode: Inode = load(&inode_content);
+        //positions of the entries change, the kernel builds a new index when the directory grows
+        self.drop_index(index, &mut inode);
+        inode_content[512..].fill(0);
+        let levels = if content.len() as u64 <= INLINE_DATA_SIZE {
+            inode_content[512..512 + content.len()].copy_from_slice(content);
+            Some(0)
+        } else {
+            let mut data_blocks = Vec::new();
+            for chunk in content.chunks(BLOCK_SIZE) {
+                let Some(block) = self.allocate(index) else {
+                    return Ok(());
+                };
+                let mut data = [0; BLOCK_SIZE];
+                data[..chunk.len()].copy_from_slice(chunk);
+                self.write(block, &data).await?;
+                data_blocks.push(block);
+            }
+            if inode.size.extents() {
+                self.write_extents(index, &data_blocks, &mut inode_content).await?
+            } else {
+                let levels = ptr_levels_for(content.len() as u64);
+                self.write_pointers(index, data_blocks, levels, &mut inode_content).await?
+            }
+        };
+        let Some(levels) = levels else {
+            return Ok(());
+        };
+        inode.size.set_size(content.len() as u64);
+        inode.size.set_ptr_levels(levels as u64);
+        store(&mut inode_content, &inode);
+        self.write(inode_block, &inode_content).await
+    }
+
+    ///Frees the nodes of the name index of a directory and clears it from the inode
+    fn drop_index(&mut self, index: u32, inode: &mut Inode) {
+        let info = self.inodes.get_mut(&index).expect("inode is in the tree");
+        info.drop_index = false;
+        for block in info.index_blocks.drain(..) {
+            self.owners[block as usize] = FREE;
+        }
+        inode.size.set_indexed(false);
+        inode.index_root = 0;
+    }
+
+    ///Writes pointer blocks over the data blocks and the top pointers into the inode block.
+    ///Returns the levels of pointers again, None if no block was left for a pointer block
+    async fn write_pointers(
+        &mut self,
+        index: u32,
+        mut pointers: Vec<u32>,
+        levels: u32,
+        inode_content: &mut Block,
+    ) -> Result<Option<u32>, D::Error> {
+        for _ in 1..levels {
+            let mut upper = Vec::new();
+            for chunk in pointers.chunks(BLOCK_POINTERS) {
+                let Some(block) = self.allocate(index) else {
+                    return Ok(None);
+                };
+                let mut data = [0; BLOCK_SIZE];
+                for (i, pointer) in chunk.iter().enumerate() {
+                    data[i * 4..i * 4 + 4].copy_from_slice(&pointer.to_le_bytes());
+                }
+                self.write(block, &data).await?;
+                upper.push(block);
+            }
+            pointers = upper;
+        }
+        for (i, pointer) in pointers.iter().enumerate() {
+            inode_content[512 + i * 4..516 + i * 4].copy_from_slice(&pointer.to_le_bytes());
+        }
+        Ok(Some(levels))
+    }
+
+    ///Maps the data blocks with extents, blocks in a row sharing one, and writes the extent tree
+    ///the way the kernel does. Returns its depth, None if no block was left for a node
+    async fn write_extents(
+        &mut self,
+        index: u32,
+        data_blocks: &[u32],
+        inode_content: &mut Block,
+    ) -> Result<Option<u32>, D::Error> {
+        let mut extents: Vec<Extent> = Vec::new();
+        for (file_block, block) in data_blocks.iter().enumerate() {
+            match extents.last_mut() {
+                Some(last) if last.block + last.length == *block => last.length += 1,
+                _ => extents.push(Extent {
+                    file_block: file_block as u32,
+                    block: *block,
+                    length: 1,
+                }),
+            }
+        }
+        let depth = extent_depth_for(extents.len());
+        for _ in 0..depth {
+            let mut upper = Vec::new();
+            for chunk in extents.chunks(NODE_EXTENTS) {
+                let Some(block) = self.allocate(index) else {
+                    return Ok(None);
+                };
+                let mut node = [0; BLOCK_SIZE];
+                store_extents(&mut node, chunk);
+                self.write(block, &node).await?;
+                upper.push(Extent {
+                    file_block: chunk[0].file_block,
+                    block,
+                    length: chunk.iter().map(|extent| extent.length).sum(),
+                });
+            }
+            extents = upper;
+        }
+        store_extents(&mut inode_content[512..], &extents);
+        Ok(Some(depth))
+    }
+}
+
+///Extends the run of blocks ending right before `block`, or starts a new one
+fn extend_run(run: &mut Option<(u32, u32)>, block: u32, problems: &mut Vec<Problem>, problem: fn(u32, u32) -> Problem) {
+    match run {
+        Some((first, count)) if *first + *count == block => *count += 1,
+        _ => {
+            if let Some((first, count)) = run.replace((block, 1)) {
+                problems.push(problem(first, count));
+            }
+        }
+    }
+}
+
+///Extents stored at `offset` of a block behind their count, with where the block of each is
+///stored. None if there are more than `max`
+///Whether the keys of a directory index are exactly its entries, each one found by probing from the
+///hash of its name like the kernel does
+fn index_finds_entries(keys: &[Key], content: &[u8]) -> bool {
+    let positions = keys
+        .iter()
+        .map(|key| (key.index, key.inode_block as usize))
+        .collect::<BTreeMap<_, _>>();
+    if positions.len() != content.len() / DIR_ENTRY_SIZE {
+        return false;
+    }
+    content
+        .chunks_exact(DIR_ENTRY_SIZE)
+        .enumerate()
+        .all(|(position, entry_bytes)| {
+            let entry: DirEntry = load(entry_bytes);
+            let mut hash = name_hash(entry.name());
+            //every probed hash is taken, so this ends at a free one at the latest
+            loop {
+                match positions.get(&hash) {
+                    Some(found) if *found == position => return true,
+                    Some(_) => hash = next_hash(hash),
+                    None => return false,
+                }
+            }
+        })
+}
+
+fn read_extents(content: &Block, offset: usize, max: usize, block: u32, inode: u32) -> Option<Vec<(Extent, PointerLocation)>> {
+    let count = load::<u32>(&content[offset..]) as usize;
+    if count > max {
+        return None;
+    }
+    let extents = (0..count)
+        .map(|i| {
+            let extent_offset = offset + 4 + i * size_of::<Extent>();
+            let extent: Extent = load(&content[extent_offset..]);
+            let location = PointerLocation {
+                block,
+                offset: extent_offset + 4,
+                inode,
+                length: extent.length,
+            };
+            (extent, location)
+        })
+        .collect();
+    Some(extents)
+}
+
+///Whether the extents map file blocks `0..count` in order, each of them once
+fn covers<'a>(extents: impl Iterator<Item = &'a Extent>, count: u64) -> bool {
+    let mut next = 0;
+    for extent in extents {
+        if extent.length == 0 || extent.file_block as u64 != next {
+            return false;
+        }
+        next += extent.length as u64;
+    }
+    next == count
+}
+
+fn store_extents(bytes: &mut [u8], extents: &[Extent]) {
+    store(bytes, &(extents.len() as u32));
+    for (i, extent) in extents.iter().enumerate() {
+        store(&mut bytes[4 + i * size_of::<Extent>()..], extent);
+    }
+}
+
+fn load<T>(bytes: &[u8]) -> T {
+    assert!(bytes.len() >= size_of::<T>());
+    unsafe { (bytes.as_ptr() as *const T).read_unaligned() }
+}
+
+fn store<T>(bytes: &mut [u8], value: &T) {
+    let value = unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) };
+    bytes[..value.len()].copy_from_slice(value);
+}
@@ -0,0 +1,457 @@
+//!On-disk structures of rfs, shared by the kernel driver and the host tools. Everything here is
+//!read and written as raw memory, so field order and sizes are the disk format
+#![no_std]
+use bitfield::bitfield;
+
+pub const BLOCK_SIZE: usize = 4096;
+pub const BLOCK_SIZE_SECTORS: usize = 8;
+///Blocks described by one group bitmap, which is the first block of the group
+pub const GROUP_BLOCK_SIZE: u64 = 4096 * 8;
+///Longest name that fits into a [`DirEntry`]
+pub const MAX_NAME_LEN: usize = 128;
+///GPT partition type of rfs partitions
+pub const PARTITION_TYPE_GUID: u128 = 0xb1b3b44dbece44dfba0e964a35a05a16;
+pub const ROOT_INODE_INDEX: u32 = 2;
+
+///Blocks at fixed positions of a freshly formatted filesystem. Only the superblock can't move
+pub const SUPERBLOCK_BLOCK: u32 = 1;
+pub const ROOT_NODE_BLOCK: u32 = 2;
+pub const ROOT_INODE_BLOCK: u32 = 3;
+pub const INODE_BITMASK_BLOCK: u32 = 4;
+///First block of the journal, right after the inode bitmask
+pub const JOURNAL_START: u32 = 5;
+///Blocks reserved for the journal by format, including the descriptor
+pub const JOURNAL_BLOCKS: u32 = 256;
+
+///Bytes of file data that fit into the inode block, after the inode sector
+pub const INLINE_DATA_SIZE: u64 = 512 * 7;
+///Pointers that fit into the inode block, after the inode sector
+pub const INODE_POINTERS: usize = 512 / 4 * 7;
+///Pointers in a pointer block
+pub const BLOCK_POINTERS: usize = 1024;
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct SuperBlock {
+    ///Block index of the inode tree
+    pub inode_tree: u32,
+    ///Block index of the inode bitmask
+    pub inode_bitmask: u32,
+    ///Block index of the journal, 0 on filesystems without one
+    pub journal_start: u32,
+    ///Number of blocks in the journal
+    pub journal_blocks: u32,
+    ///Optional features in use, see [`FEATURE_CHECKSUMS`]. 0 on filesystems formatted before
+    ///features existed
+    pub features: u32,
+    ///First block of the checksum table, only valid with [`FEATURE_CHECKSUMS`]
+    pub checksum_table: u32,
+}
+
+///Metadata blocks are covered by the checksum table
+pub const FEATURE_CHECKSUMS: u32 = 1;
+
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub inode: u32,
+    pub name: [u8; MAX_NAME_LEN],
+}
+
+impl DirEntry {
+    pub fn new(inode: u32, name: &[u8]) -> Self {
+        let mut entry = Self {
+            inode,
+            name: [0; MAX_NAME_LEN],
+        };
+        entry.name[..name.len()].copy_from_slice(name);
+        entry
+    }
+
+    ///Name without the padding
+    pub fn name(&self) -> &[u8] {
+        let len = self.name.iter().position(|byte| *byte == 0).unwrap_or(MAX_NAME_LEN);
+        &self.name[..len]
+    }
+}
+
+//1 inode per block, contains the file if it's small enough, otherwise pointers to blocks, pointers
+//  to pointers, etc. File or pointers start at next sector
+#[repr(C)]
+#[derive(Debug)]
+pub struct Inode {
+    pub size: InodeSize,
+    pub inode_type_mode: InodeMode,
+    pub link_count: u16,
+    pub uid: u16,
+    pub gid: u16,
+    ///Padding before the times, always 0 so an inode is stored the same way every time
+    pub reserved: u16,
+    pub access_time: u32,
+    pub modification_time: u32,
+    pub stat_change_time: u32,
+    ///Root node of the name index of a directory, only valid if [`InodeSize::indexed`] is set
+    pub index_root: u32,
+    ///Padding up to the alignment of size, always 0 like reserved
+    pub reserved_tail: u32,
+}
+
+bitfield! {
+    pub struct InodeSize(u64);
+    impl Debug;
+    ///Size in bytes. Block length is size / 4096 rounded up
+    pub size, set_size: 50, 0;
+    ///number of levels of pointers. 0 means the file is small enough to fit in
+    ///the inode block, 1 means pointers to blocks, 2 means pointers to pointers to blocks, etc
+    pub ptr_levels, set_ptr_levels: 63, 62;
+    ///data is mapped by extents instead of pointers, ptr_levels is then the depth of the extent
+    ///tree. Small files keep their data inline either way
+    pub extents, set_extents: 61;
+    ///directory has a name index, see [`name_hash`]
+    pub indexed, set_indexed: 60;
+}
+
+impl InodeSize {
+    pub fn new(raw: u64) -> Self {
+        Self(raw)
+    }
+
+    pub fn raw(&self) -> u64 {
+        self.0
+    }
+
+    ///Empty file mapped by extents, which all new inodes are
+    pub fn new_extents() -> Self {
+        let mut size = Self(0);
+        size.set_extents(true);
+        size
+    }
+
+    ///Data is in the inode block, there are no pointers or extents
+    pub fn is_inline(&self) -> bool {
+        self.size() <= INLINE_DATA_SIZE
+    }
+}
+
+///File type and permissions, same bits as st_mode
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InodeMode(pub u32);
+
+impl InodeMode {
+    const FILE_TYPE_MASK: u32 = 0xF000;
+
+    pub fn new_dir(perms: u32) -> Self {
+        Self(0o40000 | perms)
+    }
+
+    pub fn new_file(perms: u32) -> Self {
+        Self(0o100000 | perms)
+    }
+
+    pub fn new_symlink(perms: u32) -> Self {
+        Self(0o120000 | perms)
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.0 & Self::FILE_TYPE_MASK == 0o40000
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.0 & Self::FILE_TYPE_MASK == 0o120000
+    }
+
+    pub fn perms(&self) -> u32 {
+        self.0 & 0o7777
+    }
+}
+
+///Levels of pointers needed for a file of the given size, 0 if it fits into the inode block
+pub fn ptr_levels_for(size: u64) -> u32 {
+    let mut max_file_size = INLINE_DATA_SIZE;
+    let mut levels = 0;
+    while max_file_size < size {
+        max_file_size *= BLOCK_POINTERS as u64;
+        levels += 1;
+    }
+    levels
+}
+
+///Extents that fit into the inode block, in place of the inline data
+pub const INODE_EXTENTS: usize = 298;
+///Extents in an extent tree node
+pub const NODE_EXTENTS: usize = 341;
+
+///File blocks `file_block..file_block + length` are stored in blocks `block..block + length`. Above
+///the leaves of an extent tree, `block` is a node and `length` the number of file blocks under it
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Extent {
+    pub file_block: u32,
+    pub block: u32,
+    pub length: u32,
+}
+
+impl Extent {
+    ///File block right after the extent
+    pub fn end(&self) -> u32 {
+        self.file_block + self.length
+    }
+}
+
+///Top of the extent tree, in the inode block. Extents are sorted and leave no holes
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct InodeExtents {
+    pub count: u32,
+    pub extents: [Extent; INODE_EXTENTS],
+}
+
+///Takes up exactly 1 block
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct ExtentNode {
+    pub count: u32,
+    pub extents: [Extent; NODE_EXTENTS],
+}
+
+///Depth of the extent tree holding the given number of extents, 0 if they fit into the inode block
+pub fn extent_depth_for(count: usize) -> u32 {
+    let mut max_count = INODE_EXTENTS;
+    let mut depth = 0;
+    while max_count < count {
+        max_count *= NODE_EXTENTS;
+        depth += 1;
+    }
+    depth
+}
+
+///Directories get a name index once they have this many entries, more than fit into a block
+pub const INDEXED_DIR_ENTRIES: u64 = 32;
+///Btree keys below this are empty or reserved
+const FIRST_HASH: u32 = 3;
+
+///Key of a name in a directory index. The index is a btree like the inode tree, each [`Key`] holds
+///the hash of a name and the position of its entry in the directory. A name whose hash is taken
+///gets the next free one, see [`next_hash`]
+pub fn name_hash(name: &[u8]) -> u32 {
+    //FNV-1a
+    let hash = name
+        .iter()
+        .fold(0x811C9DC5u32, |hash, byte| (hash ^ *byte as u32).wrapping_mul(0x01000193));
+    u32::max(hash, FIRST_HASH)
+}
+
+///Hash to try after `hash`, wrapping around to the first one
+pub fn next_hash(hash: u32) -> u32 {
+    if hash == u32::MAX { FIRST_HASH } else { hash + 1 }
+}
+
+//max size: block size
+pub struct GroupHeader {
+    pub bitmask: [u8; 4096],
+}
+
+impl Default for GroupHeader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GroupHeader {
+    pub fn new() -> Self {
+        Self { bitmask: [0; 4096] }
+    }
+
+    pub fn find_empty(&self) -> Option<usize> {
+        for (i, byte) in self.bitmask.iter().enumerate() {
+            if *byte != u8::MAX {
+                for j in 0..8 {
+                    if (byte & (1 << j)) == 0 {
+                        return Some(i * 8 + j);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    pub fn get(&self, index: usize) -> bool {
+        self.bitmask[index / 8] & (1 << (index % 8)) != 0
+    }
+
+    pub fn set(&mut self, index: usize) {
+        let byte = index / 8;
+        let bit = index % 8;
+        self.bitmask[byte] |= 1 << bit;
+    }
+
+    pub fn clear(&mut self, index: usize) {
+        let byte = index / 8;
+        let bit = index % 8;
+        self.bitmask[byte] &= !(1 << bit);
+    }
+}
+
+//can fit 32736 (0x7FE0) inodes
+#[repr(C)]
+pub struct InodeBitmask {
+    pub inodes: [u8; 4092],
+    pub next_ptr: u32,
+}
+
+impl Default for InodeBitmask {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InodeBitmask {
+    pub fn new() -> Self {
+        Self {
+            inodes: [0; 4092],
+            next_ptr: 0,
+        }
+    }
+
+    pub fn find_empty(&self) -> Option<usize> {
+        for (i, byte) in self.inodes.iter().enumerate() {
+            if *byte != u8::MAX {
+                for j in 0..8 {
+                    if (byte & (1 << j)) == 0 {
+                        return Some(i * 8 + j);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    pub fn get(&self, index: usize) -> bool {
+        self.inodes[index / 8] & (1 << (index % 8)) != 0
+    }
+
+    pub fn set(&mut self, index: usize) {
+        let byte = index / 8;
+        let bit = index % 8;
+        self.inodes[byte] |= 1 << bit;
+    }
+
+    pub fn clear(&mut self, index: usize) {
+        let byte = index / 8;
+        let bit = index % 8;
+        self.inodes[byte] &= !(1 << bit);
+    }
+}
+
+///Keys in a btree node. A node takes exactly one block, the keys followed by one more child than
+///there are keys. Unused keys have index 0, a leaf has no children
+pub const NODE_KEYS: usize = 341;
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct Key {
+    pub index: u32,
+    pub inode_block: u32,
+}
+
+impl Key {
+    pub fn empty() -> Self {
+        Self {
+            index: 0,
+            inode_block: 0,
+        }
+    }
+}
+
+///Takes up exactly 1 block
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct BtreeNode {
+    pub keys: [Key; NODE_KEYS],
+    pub children: [u32; NODE_KEYS + 1],
+}
+
+pub const DESCRIPTOR_MAGIC: u32 = 0x4C4E524A; //JRNL
+pub const COMMIT_MAGIC: u32 = 0x544D4D43; //CMMT
+///Blocks a journal descriptor can list
+pub const MAX_TARGETS: usize = 1020;
+
+///First block of the journal. The new content of each target follows in the next blocks, then
+///a [`CommitRecord`] in the first sector after them
+#[repr(C)]
+pub struct Descriptor {
+    pub magic: u32,
+    ///0 once the transaction reached its location
+    pub block_count: u32,
+    pub sequence: u64,
+    pub targets: [u32; MAX_TARGETS],
+}
+
+#[repr(C)]
+pub struct CommitRecord {
+    pub magic: u32,
+    pub _reserved: u32,
+    pub sequence: u64,
+}
+
+///Checksums in a block of the checksum table
+pub const TABLE_CHECKSUMS: usize = BLOCK_SIZE / 4 - 1;
+
+///The checksum table holds a CRC32C of every block of the partition, in order. Only the checksums
+///of metadata blocks are kept up to date, file data isn't covered. Takes up exactly 1 block
+#[repr(C)]
+pub struct ChecksumBlock {
+    pub checksums: [u32; TABLE_CHECKSUMS],
+    ///Checksum of the table block itself, see [`ChecksumBlock::own_checksum`]
+    pub checksum: u32,
+}
+
+impl ChecksumBlock {
+    ///CRC32C of the checksums held by the table block, which is also the CRC32C of all but the
+    ///last 4 bytes of the block
+    pub fn own_checksum(&self) -> u32 {
+        !self
+            .checksums
+            .iter()
+            .fold(!0, |crc, checksum| crc32c_update(crc, &checksum.to_le_bytes()))
+    }
+}
+
+///Blocks the checksum table of a partition with `blocks` blocks takes up
+pub fn checksum_table_blocks(blocks: u32) -> u32 {
+    blocks.div_ceil(TABLE_CHECKSUMS as u32)
+}
+
+///Block of the checksum table, relative to its start, and the slot in it holding the checksum of
+///`block`
+pub fn checksum_slot(block: u32) -> (u32, usize) {
+    (block / TABLE_CHECKSUMS as u32, block as usize % TABLE_CHECKSUMS)
+}
+
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0x82F63B78 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+fn crc32c_update(crc: u32, bytes: &[u8]) -> u32 {
+    bytes.iter().fold(crc, |crc, byte| {
+        CRC32C_TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+///CRC32C (Castagnoli) of `bytes`
+pub fn crc32c(bytes: &[u8]) -> u32 {
+    !crc32c_update(!0, bytes)
+}
@@ -0,0 +1,196 @@
+//!Just enough GPT to create disk images and find partitions on them. Guids are stored as plain
+//!big endian bytes, which is how the kernel reads them
+
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom, Write},
+};
+
+use crate::{Error, Result};
+
+const SECTOR_SIZE: u64 = 512;
+const ENTRY_COUNT: u64 = 128;
+const ENTRY_SIZE: u64 = 128;
+const ENTRY_SECTORS: u64 = ENTRY_COUNT * ENTRY_SIZE / SECTOR_SIZE;
+const HEADER_SIZE: usize = 92;
+///Partitions start at 1 MiB boundaries
+const ALIGNMENT_SECTORS: u64 = 2048;
+
+#[derive(Debug, Clone)]
+pub struct GptPartition {
+    pub type_guid: u128,
+    pub unique_guid: u128,
+    pub start_lba: u64,
+    ///Inclusive
+    pub end_lba: u64,
+    pub name: String,
+}
+
+impl GptPartition {
+    pub fn size_sectors(&self) -> u64 {
+        self.end_lba - self.start_lba + 1
+    }
+}
+
+///Writes a protective MBR, both GPT headers and the partition entries. Partitions get consecutive
+///aligned ranges of the given sizes, the file is resized to fit them. Guids are derived from the
+///names, so the same arguments always give the same image
+pub fn create(file: &mut File, partitions: &[(String, u128, u64)]) -> Result<Vec<GptPartition>> {
+    let mut entries = Vec::new();
+    let mut next_lba = ALIGNMENT_SECTORS;
+    for (name, type_guid, size_sectors) in partitions {
+        if name.encode_utf16().count() > 36 {
+            return Err(Error::InvalidArgument(format!(
+                "partition name {} is longer than 36 characters",
+                name
+            )));
+        }
+        entries.push(GptPartition {
+            type_guid: *type_guid,
+            unique_guid: guid_from_name(name),
+            start_lba: next_lba,
+            end_lba: next_lba + size_sectors - 1,
+            name: name.clone(),
+        });
+        next_lba = (next_lba + size_sectors).next_multiple_of(ALIGNMENT_SECTORS);
+    }
+    let disk_sectors = next_lba + ENTRY_SECTORS + 1;
+    file.set_len(disk_sectors * SECTOR_SIZE)?;
+
+    let mut mbr = [0u8; SECTOR_SIZE as usize];
+    let record = &mut mbr[446..462];
+    record[1..4].copy_from_slice(&[0x00, 0x02, 0x00]);
+    record[4] = 0xEE;
+    record[5..8].copy_from_slice(&[0xFF, 0xFF, 0xFF]);
+    record[8..12].copy_from_slice(&1u32.to_le_bytes());
+    record[12..16].copy_from_slice(&(u64::min(disk_sectors - 1, u32::MAX as u64) as u32).to_le_bytes());
+    mbr[510] = 0x55;
+    mbr[511] = 0xAA;
+    write_at(file, 0, &mbr)?;
+
+    let mut entry_array = vec![0u8; (ENTRY_COUNT * ENTRY_SIZE) as usize];
+    for (entry, partition) in entry_array.chunks_mut(ENTRY_SIZE as usize).zip(&entries) {
+        entry[0..16].copy_from_slice(&partition.type_guid.to_be_bytes());
+        entry[16..32].copy_from_slice(&partition.unique_guid.to_be_bytes());
+        entry[32..40].copy_from_slice(&partition.start_lba.to_le_bytes());
+        entry[40..48].copy_from_slice(&partition.end_lba.to_le_bytes());
+        for (i, unit) in partition.name.encode_utf16().enumerate() {
+            entry[56 + i * 2..58 + i * 2].copy_from_slice(&unit.to_le_bytes());
+        }
+    }
+    let entries_crc = crc32(&entry_array);
+    let names = entries.iter().map(|partition| partition.name.as_str()).collect::<Vec<_>>();
+    let disk_guid = guid_from_name(&names.join("/"));
+    let last_lba = disk_sectors - 1;
+    let backup_entries_lba = last_lba - ENTRY_SECTORS;
+
+    write_at(file, 2 * SECTOR_SIZE, &entry_array)?;
+    write_at(file, backup_entries_lba * SECTOR_SIZE, &entry_array)?;
+    let primary_header = header(1, last_lba, 2, disk_guid, last_lba, entries_crc);
+    write_at(file, SECTOR_SIZE, &primary_header)?;
+    let backup_header = header_with(&primary_header, last_lba, 1, backup_entries_lba);
+    write_at(file, last_lba * SECTOR_SIZE, &backup_header)?;
+    Ok(entries)
+}
+
+///Partitions listed in the primary GPT header
+pub fn read(file: &mut File) -> Result<Vec<GptPartition>> {
+    let mut header = [0u8; SECTOR_SIZE as usize];
+    read_at(file, SECTOR_SIZE, &mut header)?;
+    if &header[0..8] != b"EFI PART" {
+        return Err(Error::InvalidImage("no GPT header".into()));
+    }
+    let entries_lba = u64::from_le_bytes(header[72..80].try_into().expect("8 bytes"));
+    let entry_count = u32::from_le_bytes(header[80..84].try_into().expect("4 bytes")) as usize;
+    let entry_size = u32::from_le_bytes(header[84..88].try_into().expect("4 bytes")) as usize;
+    if entry_size < ENTRY_SIZE as usize {
+        return Err(Error::InvalidImage(format!("GPT entries of {} bytes", entry_size)));
+    }
+    let mut entry_array = vec![0u8; entry_count * entry_size];
+    read_at(file, entries_lba * SECTOR_SIZE, &mut entry_array)?;
+
+    let mut partitions = Vec::new();
+    for entry in entry_array.chunks(entry_size) {
+        let type_guid = u128::from_be_bytes(entry[0..16].try_into().expect("16 bytes"));
+        if type_guid == 0 {
+            continue;
+        }
+        let name = entry[56..128]
+            .chunks(2)
+            .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+            .take_while(|unit| *unit != 0)
+            .collect::<Vec<_>>();
+        partitions.push(GptPartition {
+            type_guid,
+            unique_guid: u128::from_be_bytes(entry[16..32].try_into().expect("16 bytes")),
+            start_lba: u64::from_le_bytes(entry[32..40].try_into().expect("8 bytes")),
+            end_lba: u64::from_le_bytes(entry[40..48].try_into().expect("8 bytes")),
+            name: String::from_utf16_lossy(&name),
+        });
+    }
+    Ok(partitions)
+}
+
+fn header(this_lba: u64, alternate_lba: u64, entries_lba: u64, disk_guid: u128, last_lba: u64, entries_crc: u32) -> Vec<u8> {
+    let mut header = vec![0u8; SECTOR_SIZE as usize];
+    header[0..8].copy_from_slice(b"EFI PART");
+    header[8..12].copy_from_slice(&0x0001_0000u32.to_le_bytes());
+    header[12..16].copy_from_slice(&(HEADER_SIZE as u32).to_le_bytes());
+    header[24..32].copy_from_slice(&this_lba.to_le_bytes());
+    header[32..40].copy_from_slice(&alternate_lba.to_le_bytes());
+    header[40..48].copy_from_slice(&(2 + ENTRY_SECTORS).to_le_bytes());
+    header[48..56].copy_from_slice(&(last_lba - ENTRY_SECTORS - 1).to_le_bytes());
+    header[56..72].copy_from_slice(&disk_guid.to_be_bytes());
+    header[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+    header[80..84].copy_from_slice(&(ENTRY_COUNT as u32).to_le_bytes());
+    header[84..88].copy_from_slice(&(ENTRY_SIZE as u32).to_le_bytes());
+    header[88..92].copy_from_slice(&entries_crc.to_le_bytes());
+    let crc = crc32(&header[..HEADER_SIZE]);
+    header[16..20].copy_from_slice(&crc.to_le_bytes());
+    header
+}
+
+///Copy of a header at another location, with its crc updated
+fn header_with(header: &[u8], this_lba: u64, alternate_lba: u64, entries_lba: u64) -> Vec<u8> {
+    let mut header = header.to_vec();
+    header[16..20].fill(0);
+    header[24..32].copy_from_slice(&this_lba.to_le_bytes());
+    header[32..40].copy_from_slice(&alternate_lba.to_le_bytes());
+    header[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+    let crc = crc32(&header[..HEADER_SIZE]);
+    header[16..20].copy_from_slice(&crc.to_le_bytes());
+    header
+}
+
+///Version 4 style guid from a FNV-1a hash of the name
+fn guid_from_name(name: &str) -> u128 {
+    let mut hash: u128 = 0x6c62272e07bb014262b821756295c58d;
+    for byte in name.bytes() {
+        hash ^= byte as u128;
+        hash = hash.wrapping_mul(0x0000000001000000000000000000013B);
+    }
+    (hash & !(0xF << 76) & !(0x3 << 62)) | (0x4 << 76) | (0x2 << 62)
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = u32::MAX;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+pub(crate) fn read_at(file: &mut File, offset: u64, buffer: &mut [u8]) -> Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(buffer)?;
+    Ok(())
+}
+
+pub(crate) fn write_at(file: &mut File, offset: u64, buffer: &[u8]) -> Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(buffer)?;
+    Ok(())
+}
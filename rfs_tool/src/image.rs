@@ -0,0 +1,879 @@
+//!An rfs filesystem in a partition of a disk image. Works on whole files at a time, which is all
+//!building and inspecting images needs. Group bitmaps are kept in memory and written by close, so
+//!is the checksum table. Metadata read is verified against it, file data isn't covered
+
+use std::{
+    collections::BTreeSet,
+    fs::File,
+    future::Future,
+    mem::size_of,
+    pin::pin,
+    task::{Context, Poll, Waker},
+};
+
+use rfs_layout::{
+    BLOCK_POINTERS, BLOCK_SIZE, BLOCK_SIZE_SECTORS, BtreeNode, COMMIT_MAGIC, ChecksumBlock, CommitRecord, DESCRIPTOR_MAGIC,
+    Descriptor, DirEntry, Extent, FEATURE_CHECKSUMS, GROUP_BLOCK_SIZE, GroupHeader, INDEXED_DIR_ENTRIES, INLINE_DATA_SIZE,
+    INODE_BITMASK_BLOCK, INODE_EXTENTS, INODE_POINTERS, Inode, InodeBitmask, InodeMode, InodeSize, JOURNAL_BLOCKS, JOURNAL_START,
+    Key, MAX_NAME_LEN, MAX_TARGETS, NODE_EXTENTS, NODE_KEYS, PARTITION_TYPE_GUID, ROOT_INODE_BLOCK, ROOT_INODE_INDEX,
+    ROOT_NODE_BLOCK, SUPERBLOCK_BLOCK, SuperBlock, TABLE_CHECKSUMS, checksum_table_blocks, crc32c, extent_depth_for, name_hash,
+    next_hash, ptr_levels_for,
+};
+
+use crate::{
+    Error, Result,
+    gpt::{self, GptPartition, read_at, write_at},
+};
+
+type Block = Box<[u8; BLOCK_SIZE]>;
+
+pub struct RfsImage {
+    file: File,
+    ///Byte offset of the partition in the image
+    offset: u64,
+    blocks: u32,
+    superblock: SuperBlock,
+    groups: Vec<GroupHeader>,
+    dirty_groups: Vec<bool>,
+    ///Where the search for a free block starts, so files end up contiguous
+    next_free: u32,
+    ///Checksum of every block, None on filesystems without checksums
+    checksums: Option<Vec<u32>>,
+    ///Blocks of the checksum table, relative to its start, that close has to write
+    dirty_table_blocks: BTreeSet<u32>,
+}
+
+impl RfsImage {
+    ///Formats the partition with the same layout as `Rfs::format_partition` in the kernel
+    pub fn format(file: File, partition: &GptPartition) -> Result<Self> {
+        let blocks = (partition.size_sectors() / BLOCK_SIZE_SECTORS as u64) as u32;
+        if blocks < JOURNAL_START + JOURNAL_BLOCKS {
+            return Err(Error::InvalidArgument(format!("partition {} is too small", partition.name)));
+        }
+        let group_count = (blocks as u64).div_ceil(GROUP_BLOCK_SIZE) as usize;
+        let mut groups = Vec::with_capacity(group_count);
+        for i in 0..group_count {
+            let mut group = GroupHeader::new();
+            //the bitmap is the first block of its group
+            group.set(0);
+            let group_blocks = u64::min(blocks as u64 - i as u64 * GROUP_BLOCK_SIZE, GROUP_BLOCK_SIZE);
+            for block in group_blocks..GROUP_BLOCK_SIZE {
+                group.set(block as usize);
+            }
+            groups.push(group);
+        }
+        //the checksum table follows the journal
+        let checksum_table = JOURNAL_START + JOURNAL_BLOCKS;
+        let table_blocks = checksum_table_blocks(blocks);
+        if (checksum_table + table_blocks) as u64 > u64::min(GROUP_BLOCK_SIZE, blocks as u64) {
+            return Err(Error::InvalidArgument(format!(
+                "partition {} is too small or too big for its checksum table",
+                partition.name
+            )));
+        }
+        for block in 0..(checksum_table + table_blocks) {
+            groups[0].set(block as usize);
+        }
+
+        let mut image = Self {
+            file,
+            offset: partition.start_lba * 512,
+            blocks,
+            superblock: SuperBlock {
+                inode_tree: ROOT_NODE_BLOCK,
+                inode_bitmask: INODE_BITMASK_BLOCK,
+                journal_start: JOURNAL_START,
+                journal_blocks: JOURNAL_BLOCKS,
+                features: FEATURE_CHECKSUMS,
+                checksum_table,
+            },
+            groups,
+            dirty_groups: vec![true; group_count],
+            next_free: 0,
+            checksums: Some(vec![0; blocks as usize]),
+            dirty_table_blocks: (0..table_blocks).collect(),
+        };
+        image.write_superblock()?;
+
+        let mut root_node = empty_node();
+        root_node.keys[0] = Key {
+            index: ROOT_INODE_INDEX,
+            inode_block: ROOT_INODE_BLOCK,
+        };
+        image.write_node(ROOT_NODE_BLOCK, &root_node)?;
+
+        let root_inode = Inode {
+            size: InodeSize::new_extents(),
+            inode_type_mode: InodeMode::new_dir(0o755),
+            link_count: 0,
+            uid: 0,
+            gid: 0,
+            reserved: 0,
+            access_time: 0,
+            modification_time: 0,
+            stat_change_time: 0,
+            index_root: 0,
+            reserved_tail: 0,
+        };
+        let mut block = zeroed_block();
+        store(&mut block[..], &root_inode);
+        image.write_block(ROOT_INODE_BLOCK, &block)?;
+
+        //indexes 0, 1 and root are used
+        let mut bitmask = InodeBitmask::new();
+        for index in 0..=ROOT_INODE_INDEX {
+            bitmask.set(index as usize);
+        }
+        let mut block = zeroed_block();
+        store(&mut block[..], &bitmask);
+        image.write_block(INODE_BITMASK_BLOCK, &block)?;
+
+        //an empty descriptor means there is nothing to replay
+        image.write_block(JOURNAL_START, &zeroed_block())?;
+        Ok(image)
+    }
+
+    ///Opens the rfs partition with the given label, or the only one if there is no label. A
+    ///complete transaction left in the journal is replayed, like the kernel does on mount
+    pub fn open(file: File, label: Option<&str>) -> Result<Self> {
+        let mut image = Self::open_unverified(file, label)?;
+        image.read_block(SUPERBLOCK_BLOCK)?;
+        for i in 0..image.groups.len() {
+            image.read_block((i as u64 * GROUP_BLOCK_SIZE) as u32)?;
+        }
+        Ok(image)
+    }
+
+    ///open for fsck, which reports blocks that don't match their checksum instead of failing
+    pub fn open_for_check(file: File, label: Option<&str>) -> Result<Self> {
+        Self::open_unverified(file, label)
+    }
+
+    fn open_unverified(mut file: File, label: Option<&str>) -> Result<Self> {
+        let mut partitions = gpt::read(&mut file)?
+            .into_iter()
+            .filter(|partition| partition.type_guid == PARTITION_TYPE_GUID)
+            .filter(|partition| label.is_none_or(|label| partition.name == label))
+            .collect::<Vec<_>>();
+        let partition = match (partitions.len(), label) {
+            (1, _) => partitions.remove(0),
+            (0, Some(label)) => return Err(Error::InvalidImage(format!("no rfs partition named {}", label))),
+            (0, None) => return Err(Error::InvalidImage("no rfs partition".into())),
+            _ => {
+                return Err(Error::InvalidArgument(
+                    "more than one rfs partition, pick one by its label".into(),
+                ));
+            }
+        };
+
+        let blocks = (partition.size_sectors() / BLOCK_SIZE_SECTORS as u64) as u32;
+        let group_count = (blocks as u64).div_ceil(GROUP_BLOCK_SIZE) as usize;
+        let mut image = Self {
+            file,
+            offset: partition.start_lba * 512,
+            blocks,
+            superblock: SuperBlock {
+                inode_tree: 0,
+                inode_bitmask: 0,
+                journal_start: 0,
+                journal_blocks: 0,
+                features: 0,
+                checksum_table: 0,
+            },
+            groups: Vec::with_capacity(group_count),
+            dirty_groups: vec![false; group_count],
+            next_free: 0,
+            checksums: None,
+            dirty_table_blocks: BTreeSet::new(),
+        };
+        image.superblock = load(&image.read_raw(SUPERBLOCK_BLOCK)?[..]);
+        image.replay_journal()?;
+        image.reload()?;
+        Ok(image)
+    }
+
+    ///Reads the superblock, the checksum table and the group bitmaps again, after something else
+    ///wrote them: the journal replay, fsck or the checksum upgrade
+    fn reload(&mut self) -> Result<()> {
+        self.superblock = load(&self.read_raw(SUPERBLOCK_BLOCK)?[..]);
+        self.checksums = None;
+        if self.superblock.features & FEATURE_CHECKSUMS != 0 {
+            let mut checksums = Vec::with_capacity(self.blocks as usize);
+            for table_block in 0..checksum_table_blocks(self.blocks) {
+                let table: ChecksumBlock = load(&self.read_raw(self.superblock.checksum_table + table_block)?[..]);
+                checksums.extend_from_slice(&table.checksums);
+            }
+            checksums.truncate(self.blocks as usize);
+            self.checksums = Some(checksums);
+        }
+        self.groups.clear();
+        for i in 0..self.dirty_groups.len() {
+            let group = self.read_raw((i as u64 * GROUP_BLOCK_SIZE) as u32)?;
+            self.groups.push(GroupHeader { bitmask: *group });
+        }
+        Ok(())
+    }
+
+    ///Writes the changed group bitmaps, then the checksum table
+    pub fn close(mut self) -> Result<()> {
+        for i in 0..self.groups.len() {
+            if self.dirty_groups[i] {
+                let bitmask = Box::new(self.groups[i].bitmask);
+                self.write_block((i as u64 * GROUP_BLOCK_SIZE) as u32, &bitmask)?;
+            }
+        }
+        if let Some(checksums) = &self.checksums {
+            let mut writes = Vec::new();
+            for table_block in self.dirty_table_blocks.iter() {
+                let first = *table_block as usize * TABLE_CHECKSUMS;
+                let slots = &checksums[first..usize::min(first + TABLE_CHECKSUMS, checksums.len())];
+                let mut table = ChecksumBlock {
+                    checksums: [0; TABLE_CHECKSUMS],
+                    checksum: 0,
+                };
+                table.checksums[..slots.len()].copy_from_slice(slots);
+                table.checksum = table.own_checksum();
+                let mut content = zeroed_block();
+                store(&mut content[..], &table);
+                writes.push((self.superblock.checksum_table + table_block, content));
+            }
+            for (block, content) in writes {
+                self.write_raw(block, &content)?;
+            }
+        }
+        self.file.sync_all()?;
+        Ok(())
+    }
+
+    fn replay_journal(&mut self) -> Result<()> {
+        let start = self.superblock.journal_start;
+        if start == 0 {
+            return Ok(());
+        }
+        let mut block = self.read_raw(start)?;
+        let mut descriptor: Descriptor = load(&block[..]);
+        let count = descriptor.block_count as usize;
+        if descriptor.magic != DESCRIPTOR_MAGIC || count == 0 || count > MAX_TARGETS {
+            return Ok(());
+        }
+        let commit: CommitRecord = load(&self.read_raw(start + count as u32 + 1)?[..]);
+        if commit.magic != COMMIT_MAGIC || commit.sequence != descriptor.sequence {
+            //the transaction never completed, nothing of it reached its location
+            return Ok(());
+        }
+        for (i, target) in descriptor.targets[..count].iter().enumerate() {
+            let content = self.read_raw(start + 1 + i as u32)?;
+            self.write_raw(*target, &content)?;
+        }
+        descriptor.block_count = 0;
+        store(&mut block[..], &descriptor);
+        self.write_raw(start, &block)
+    }
+
+    ///Reads metadata, which has to match its checksum
+    fn read_block(&mut self, block: u32) -> Result<Block> {
+        let content = self.read_raw(block)?;
+        let checksum = crc32c(&content[..]);
+        if self
+            .checksums
+            .as_ref()
+            .is_some_and(|checksums| checksums[block as usize] != checksum)
+        {
+            return Err(Error::InvalidImage(format!("block {} doesn't match its checksum", block)));
+        }
+        Ok(content)
+    }
+
+    ///Reads file data or the journal, which have no checksums
+    fn read_raw(&mut self, block: u32) -> Result<Block> {
+        if block >= self.blocks {
+            return Err(Error::InvalidImage(format!("block {} is outside of the partition", block)));
+        }
+        let mut content = zeroed_block();
+        read_at(
+            &mut self.file,
+            self.offset + block as u64 * BLOCK_SIZE as u64,
+            &mut content[..],
+        )?;
+        Ok(content)
+    }
+
+    ///Writes a block and updates its checksum
+    fn write_block(&mut self, block: u32, content: &[u8; BLOCK_SIZE]) -> Result<()> {
+        self.write_raw(block, content)?;
+        if let Some(checksums) = &mut self.checksums {
+            checksums[block as usize] = crc32c(content);
+            self.dirty_table_blocks.insert(block / TABLE_CHECKSUMS as u32);
+        }
+        Ok(())
+    }
+
+    fn write_raw(&mut self, block: u32, content: &[u8; BLOCK_SIZE]) -> Result<()> {
+        if block >= self.blocks {
+            return Err(Error::InvalidImage(format!("block {} is outside of the partition", block)));
+        }
+        write_at(&mut self.file, self.offset + block as u64 * BLOCK_SIZE as u64, content)
+    }
+
+    fn write_superblock(&mut self) -> Result<()> {
+        let mut block = zeroed_block();
+        store(&mut block[..], &self.superblock);
+        self.write_block(SUPERBLOCK_BLOCK, &block)
+    }
+
+    fn allocate_block(&mut self) -> Result<u32> {
+        for i in 0..self.blocks {
+            let block = (self.next_free + i) % self.blocks;
+            let group = (block as u64 / GROUP_BLOCK_SIZE) as usize;
+            let bit = (block as u64 % GROUP_BLOCK_SIZE) as usize;
+            if !self.groups[group].get(bit) {
+                self.groups[group].set(bit);
+                self.dirty_groups[group] = true;
+                self.next_free = block + 1;
+                return Ok(block);
+            }
+        }
+        Err(Error::NoSpace)
+    }
+
+    fn free_block(&mut self, block: u32) {
+        let group = (block as u64 / GROUP_BLOCK_SIZE) as usize;
+        self.groups[group].clear((block as u64 % GROUP_BLOCK_SIZE) as usize);
+        self.dirty_groups[group] = true;
+    }
+
+    fn allocate_inode(&mut self) -> Result<u32> {
+        let per_block = 8 * InodeBitmask::new().inodes.len() as u32;
+        let mut bitmask_block = self.superblock.inode_bitmask;
+        let mut chain_index = 0;
+        loop {
+            let mut block = self.read_block(bitmask_block)?;
+            let mut bitmask: InodeBitmask = load(&block[..]);
+            if let Some(bit) = bitmask.find_empty() {
+                bitmask.set(bit);
+                store(&mut block[..], &bitmask);
+                self.write_block(bitmask_block, &block)?;
+                return Ok(chain_index * per_block + bit as u32);
+            }
+            chain_index += 1;
+            if bitmask.next_ptr == 0 {
+                let new_block = self.allocate_block()?;
+                bitmask.next_ptr = new_block;
+                store(&mut block[..], &bitmask);
+                self.write_block(bitmask_block, &block)?;
+
+                let mut new_bitmask = InodeBitmask::new();
+                new_bitmask.set(0);
+                let mut block = zeroed_block();
+                store(&mut block[..], &new_bitmask);
+                self.write_block(new_block, &block)?;
+                return Ok(chain_index * per_block);
+            }
+            bitmask_block = bitmask.next_ptr;
+        }
+    }
+
+    fn read_node(&mut self, block: u32) -> Result<Box<BtreeNode>> {
+        Ok(Box::new(load(&self.read_block(block)?[..])))
+    }
+
+    fn write_node(&mut self, block: u32, node: &BtreeNode) -> Result<()> {
+        let mut content = zeroed_block();
+        store(&mut content[..], node);
+        self.write_block(block, &content)
+    }
+
+    fn find_inode_block(&mut self, index: u32) -> Result<u32> {
+        self.find_key(self.superblock.inode_tree, index)?
+            .ok_or_else(|| Error::InvalidImage(format!("inode {} is not in the inode tree", index)))
+    }
+
+    ///Value of the key in the btree at `root_block`, the inode tree or a directory index
+    fn find_key(&mut self, root_block: u32, index: u32) -> Result<Option<u32>> {
+        let mut node_block = root_block;
+        loop {
+            let node = self.read_node(node_block)?;
+            let count = key_count(&node);
+            let position = node.keys[..count].iter().position(|key| key.index >= index).unwrap_or(count);
+            if position < count && node.keys[position].index == index {
+                return Ok(Some(node.keys[position].inode_block));
+            }
+            if node.children[position] == 0 {
+                return Ok(None);
+            }
+            node_block = node.children[position];
+        }
+    }
+
+    fn insert_inode_key(&mut self, key: Key) -> Result<()> {
+        let root_block = self.insert_key(self.superblock.inode_tree, key)?;
+        if root_block != self.superblock.inode_tree {
+            self.superblock.inode_tree = root_block;
+            self.write_superblock()?;
+        }
+        Ok(())
+    }
+
+    ///Nodes that are full are split on the way down, the same as the kernel splits them. Returns
+    ///the root, which is new if the old one was full
+    fn insert_key(&mut self, root_block: u32, key: Key) -> Result<u32> {
+        let root = self.read_node(root_block)?;
+        if key_count(&root) < NODE_KEYS {
+            self.insert_key_non_full(root_block, root, key)?;
+            return Ok(root_block);
+        }
+        let new_root_block = self.allocate_block()?;
+        let mut new_root = empty_node();
+        new_root.children[0] = root_block;
+        self.split_child(&mut new_root, 0)?;
+        self.write_node(new_root_block, &new_root)?;
+        self.insert_key_non_full(new_root_block, new_root, key)?;
+        Ok(new_root_block)
+    }
+
+    fn insert_key_non_full(&mut self, mut node_block: u32, mut node: Box<BtreeNode>, key: Key) -> Result<()> {
+        loop {
+            let count = key_count(&node);
+            let mut position = node.keys[..count]
+                .iter()
+                .position(|node_key| node_key.index > key.index)
+                .unwrap_or(count);
+            if node.children[0] == 0 {
+                node.keys.copy_within(position..count, position + 1);
+                node.keys[position] = key;
+                return self.write_node(node_block, &node);
+            }
+            let child = self.read_node(node.children[position])?;
+            if key_count(&child) == NODE_KEYS {
+                self.split_child(&mut node, position)?;
+                self.write_node(node_block, &node)?;
+                if key.index > node.keys[position].index {
+                    position += 1;
+                }
+            }
+            node_block = node.children[position];
+            node = self.read_node(node_block)?;
+        }
+    }
+
+    ///Splits the full child at `position`, the middle key moves into the parent. Writes both
+    ///halves, but not the parent
+    fn split_child(&mut self, parent: &mut BtreeNode, position: usize) -> Result<()> {
+        let middle = NODE_KEYS / 2;
+        let left_block = parent.children[position];
+        let mut left = self.read_node(left_block)?;
+        let right_block = self.allocate_block()?;
+        let mut right = empty_node();
+        right.keys[..NODE_KEYS - middle - 1].copy_from_slice(&left.keys[middle + 1..]);
+        right.children[..NODE_KEYS - middle].copy_from_slice(&left.children[middle + 1..]);
+        let middle_key = left.keys[middle];
+        left.keys[middle..].fill(Key::empty());
+        left.children[middle + 1..].fill(0);
+
+        let count = key_count(parent);
+        parent.keys.copy_within(position..count, position + 1);
+        parent.children.copy_within(position + 1..count + 1, position + 2);
+        parent.keys[position] = middle_key;
+        parent.children[position + 1] = right_block;
+
+        self.write_node(left_block, &left)?;
+        self.write_node(right_block, &right)
+    }
+
+    pub fn stat(&mut self, index: u32) -> Result<Inode> {
+        let inode_block = self.find_inode_block(index)?;
+        Ok(load(&self.read_block(inode_block)?[..]))
+    }
+
+    ///Data blocks of a file in order, and the pointer blocks or extent tree nodes leading to them
+    fn file_blocks(&mut self, inode_block: &[u8; BLOCK_SIZE], inode: &Inode) -> Result<(Vec<u32>, Vec<u32>)> {
+        let levels = inode.size.ptr_levels() as u32;
+        let end = inode.size.size().div_ceil(BLOCK_SIZE as u64) as usize;
+        let mut data_blocks = Vec::with_capacity(end);
+        let mut pointer_blocks = Vec::new();
+        if inode.size.extents() {
+            if !inode.size.is_inline() {
+                let mut extents = extents_of(&inode_block[512..], INODE_EXTENTS)?;
+                for _ in 0..levels {
+                    let mut lower = Vec::new();
+                    for extent in extents {
+                        pointer_blocks.push(extent.block);
+                        lower.extend(extents_of(&self.read_block(extent.block)?[..], NODE_EXTENTS)?);
+                    }
+                    extents = lower;
+                }
+                for extent in extents {
+                    data_blocks.extend(extent.block..extent.block + extent.length);
+                }
+                data_blocks.truncate(end);
+            }
+        } else if levels > 0 {
+            let pointers = pointers_of(&inode_block[512..]);
+            self.collect_blocks(levels, &pointers, end, &mut data_blocks, &mut pointer_blocks)?;
+        }
+        Ok((data_blocks, pointer_blocks))
+    }
+
+    fn collect_blocks(
+        &mut self,
+        level: u32,
+        pointers: &[u32],
+        end: usize,
+        data_blocks: &mut Vec<u32>,
+        pointer_blocks: &mut Vec<u32>,
+    ) -> Result<()> {
+        for pointer in pointers {
+            if data_blocks.len() >= end {
+                break;
+            }
+            if level == 1 {
+                data_blocks.push(*pointer);
+                continue;
+            }
+            pointer_blocks.push(*pointer);
+            let lower = pointers_of(&self.read_block(*pointer)?[..]);
+            self.collect_blocks(level - 1, &lower, end, data_blocks, pointer_blocks)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_file(&mut self, index: u32) -> Result<Vec<u8>> {
+        let inode_block = self.find_inode_block(index)?;
+        let block = self.read_block(inode_block)?;
+        let inode: Inode = load(&block[..]);
+        let size = inode.size.size() as usize;
+        let inline = if inode.size.extents() {
+            inode.size.is_inline()
+        } else {
+            inode.size.ptr_levels() == 0
+        };
+        if inline {
+            if size as u64 > INLINE_DATA_SIZE {
+                return Err(Error::InvalidImage(format!("inode {} is too big for inline data", index)));
+            }
+            return Ok(block[512..512 + size].to_vec());
+        }
+        let (data_blocks, _) = self.file_blocks(&block, &inode)?;
+        let mut content = Vec::with_capacity(data_blocks.len() * BLOCK_SIZE);
+        for data_block in data_blocks {
+            content.extend_from_slice(&self.read_raw(data_block)?[..]);
+        }
+        content.truncate(size);
+        Ok(content)
+    }
+
+    ///Replaces the content of a file, freeing the blocks of the old one. The layout of the inode,
+    ///extents or pointers, stays the same
+    pub fn write_file(&mut self, index: u32, content: &[u8]) -> Result<()> {
+        let inode_block = self.find_inode_block(index)?;
+        let mut block = self.read_block(inode_block)?;
+        let mut inode: Inode = load(&block[..]);
+        let levels = if inode.size.extents() {
+            0
+        } else {
+            ptr_levels_for(content.len() as u64)
+        };
+        if levels > 3 || content.len().div_ceil(BLOCK_SIZE) > u32::MAX as usize {
+            return Err(Error::InvalidArgument(format!(
+                "{} bytes don't fit into a file",
+                content.len()
+            )));
+        }
+        let (data_blocks, pointer_blocks) = self.file_blocks(&block, &inode)?;
+        for old_block in data_blocks.into_iter().chain(pointer_blocks) {
+            self.free_block(old_block);
+        }
+
+        block[512..].fill(0);
+        let levels = if content.len() as u64 <= INLINE_DATA_SIZE {
+            block[512..512 + content.len()].copy_from_slice(content);
+            0
+        } else if inode.size.extents() {
+            self.write_extents(content, &mut block)?
+        } else {
+            let mut pointers = Vec::with_capacity(content.len().div_ceil(BLOCK_SIZE));
+            for chunk in content.chunks(BLOCK_SIZE) {
+                let data_block = self.allocate_block()?;
+                let mut data = zeroed_block();
+                data[..chunk.len()].copy_from_slice(chunk);
+                self.write_block(data_block, &data)?;
+                pointers.push(data_block);
+            }
+            for _ in 1..levels {
+                let mut upper = Vec::with_capacity(pointers.len().div_ceil(BLOCK_POINTERS));
+                for chunk in pointers.chunks(BLOCK_POINTERS) {
+                    let pointer_block = self.allocate_block()?;
+                    let mut data = zeroed_block();
+                    store_pointers(&mut data[..], chunk);
+                    self.write_block(pointer_block, &data)?;
+                    upper.push(pointer_block);
+                }
+                pointers = upper;
+            }
+            assert!(pointers.len() <= INODE_POINTERS);
+            store_pointers(&mut block[512..], &pointers);
+            levels
+        };
+        inode.size.set_size(content.len() as u64);
+        inode.size.set_ptr_levels(levels as u64);
+        store(&mut block[..], &inode);
+        self.write_block(inode_block, &block)
+    }
+
+    ///Writes the data blocks of a file mapped by extents and the extent tree over them, with the
+    ///top of the tree into the inode block. Returns the depth of the tree
+    fn write_extents(&mut self, content: &[u8], inode_block: &mut [u8; BLOCK_SIZE]) -> Result<u32> {
+        let mut extents: Vec<Extent> = Vec::new();
+        for (file_block, chunk) in content.chunks(BLOCK_SIZE).enumerate() {
+            let data_block = self.allocate_block()?;
+            let mut data = zeroed_block();
+            data[..chunk.len()].copy_from_slice(chunk);
+            self.write_block(data_block, &data)?;
+            match extents.last_mut() {
+                Some(last) if last.block + last.length == data_block => last.length += 1,
+                _ => extents.push(Extent {
+                    file_block: file_block as u32,
+                    block: data_block,
+                    length: 1,
+                }),
+            }
+        }
+        let depth = extent_depth_for(extents.len());
+        for _ in 0..depth {
+            let mut upper = Vec::with_capacity(extents.len().div_ceil(NODE_EXTENTS));
+            for chunk in extents.chunks(NODE_EXTENTS) {
+                let node_block = self.allocate_block()?;
+                let mut node = zeroed_block();
+                store_extents(&mut node[..], chunk);
+                self.write_block(node_block, &node)?;
+                upper.push(Extent {
+                    file_block: chunk[0].file_block,
+                    block: node_block,
+                    length: chunk.iter().map(|extent| extent.length).sum(),
+                });
+            }
+            extents = upper;
+        }
+        store_extents(&mut inode_block[512..], &extents);
+        Ok(depth)
+    }
+
+    pub fn read_dir(&mut self, index: u32) -> Result<Vec<(String, u32)>> {
+        if !self.stat(index)?.inode_type_mode.is_dir() {
+            return Err(Error::NotDirectory(format!("inode {}", index)));
+        }
+        let content = self.read_file(index)?;
+        Ok(content
+            .chunks_exact(size_of::<DirEntry>())
+            .map(|chunk| {
+                let entry: DirEntry = load(chunk);
+                (String::from_utf8_lossy(entry.name()).into_owned(), entry.inode)
+            })
+            .collect())
+    }
+
+    ///Inode of an absolute path
+    pub fn lookup(&mut self, path: &str) -> Result<u32> {
+        let mut index = ROOT_INODE_INDEX;
+        for component in path.split('/').filter(|component| !component.is_empty()) {
+            if !self.stat(index)?.inode_type_mode.is_dir() {
+                return Err(Error::NotDirectory(path.into()));
+            }
+            index = self
+                .read_dir(index)?
+                .into_iter()
+                .find(|(name, _)| name == component)
+                .ok_or_else(|| Error::NoEntry(path.into()))?
+                .1;
+        }
+        Ok(index)
+    }
+
+    ///Creates an empty inode and links it into the parent directory
+    pub fn create(&mut self, parent: u32, name: &str, mode: InodeMode) -> Result<u32> {
+        if name.len() > MAX_NAME_LEN {
+            return Err(Error::NameTooLong(name.into()));
+        }
+        if name.is_empty() || name.contains('/') {
+            return Err(Error::InvalidArgument(format!("\"{}\" is not a valid name", name)));
+        }
+        if self.read_dir(parent)?.iter().any(|(entry_name, _)| entry_name == name) {
+            return Err(Error::AlreadyExists(name.into()));
+        }
+
+        let inode_block = self.allocate_block()?;
+        let index = self.allocate_inode()?;
+        let inode = Inode {
+            size: InodeSize::new_extents(),
+            inode_type_mode: mode,
+            link_count: 1,
+            uid: 0,
+            gid: 0,
+            reserved: 0,
+            access_time: 0,
+            modification_time: 0,
+            stat_change_time: 0,
+            index_root: 0,
+            reserved_tail: 0,
+        };
+        let mut block = zeroed_block();
+        store(&mut block[..], &inode);
+        self.write_block(inode_block, &block)?;
+        self.insert_inode_key(Key { index, inode_block })?;
+
+        let parent_inode = self.stat(parent)?;
+        let mut content = self.read_file(parent)?;
+        let position = (content.len() / size_of::<DirEntry>()) as u32;
+        content.extend_from_slice(bytes_of(&DirEntry::new(index, name.as_bytes())));
+        self.write_file(parent, &content)?;
+        //same as the kernel, directories get an index once they are big enough
+        if parent_inode.size.indexed() {
+            let root = self.index_insert(parent_inode.index_root, name.as_bytes(), position)?;
+            self.set_dir_index_root(parent, root)?;
+        } else if position as u64 + 1 >= INDEXED_DIR_ENTRIES {
+            let mut root = self.allocate_block()?;
+            self.write_node(root, &empty_node())?;
+            for (position, chunk) in content.chunks_exact(size_of::<DirEntry>()).enumerate() {
+                let entry: DirEntry = load(chunk);
+                root = self.index_insert(root, entry.name(), position as u32)?;
+            }
+            self.set_dir_index_root(parent, root)?;
+        }
+        Ok(index)
+    }
+
+    ///Adds the entry at `position` to a directory index under the first free hash from the one of
+    ///its name. Returns the root of the index
+    fn index_insert(&mut self, root: u32, name: &[u8], position: u32) -> Result<u32> {
+        let mut hash = name_hash(name);
+        while self.find_key(root, hash)?.is_some() {
+            hash = next_hash(hash);
+        }
+        self.insert_key(
+            root,
+            Key {
+                index: hash,
+                inode_block: position,
+            },
+        )
+    }
+
+    ///Marks the directory as indexed by the btree at root
+    fn set_dir_index_root(&mut self, dir: u32, root: u32) -> Result<()> {
+        let inode_block = self.find_inode_block(dir)?;
+        let mut block = self.read_block(inode_block)?;
+        let mut inode: Inode = load(&block[..]);
+        inode.size.set_indexed(true);
+        inode.index_root = root;
+        store(&mut block[..], &inode);
+        self.write_block(inode_block, &block)
+    }
+
+    ///Runs the same check as the kernel does on mount, see rfs_fsck
+    pub fn check(&mut self, repair: bool) -> Result<rfs_fsck::Report> {
+        let report = block_on(rfs_fsck::check(self, repair))?;
+        if report.repaired {
+            //repairs rewrite group bitmaps and checksums behind the cached ones
+            self.reload()?;
+        }
+        Ok(report)
+    }
+
+    ///Adds checksums to a filesystem formatted without them, see rfs_fsck::enable_checksums
+    pub fn enable_checksums(&mut self) -> Result<rfs_fsck::Upgrade> {
+        let upgrade = block_on(rfs_fsck::enable_checksums(self))?;
+        self.reload()?;
+        Ok(upgrade)
+    }
+}
+
+impl rfs_fsck::Disk for RfsImage {
+    type Error = Error;
+
+    fn blocks(&self) -> u32 {
+        self.blocks
+    }
+
+    //rfs_fsck verifies and updates checksums itself
+    async fn read_block(&mut self, block: u32, buffer: &mut rfs_fsck::Block) -> Result<()> {
+        buffer.copy_from_slice(&self.read_raw(block)?[..]);
+        Ok(())
+    }
+
+    async fn write_block(&mut self, block: u32, buffer: &rfs_fsck::Block) -> Result<()> {
+        self.write_raw(block, buffer)
+    }
+}
+
+///Nothing here ever waits, the futures of rfs_fsck are ready the first time they are polled
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let mut context = Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+    }
+}
+
+fn zeroed_block() -> Block {
+    Box::new([0; BLOCK_SIZE])
+}
+
+fn empty_node() -> Box<BtreeNode> {
+    Box::new(BtreeNode {
+        keys: [Key::empty(); NODE_KEYS],
+        children: [0; NODE_KEYS + 1],
+    })
+}
+
+fn key_count(node: &BtreeNode) -> usize {
+    node.keys.iter().position(|key| key.index == 0).unwrap_or(NODE_KEYS)
+}
+
+fn pointers_of(bytes: &[u8]) -> Vec<u32> {
+    bytes
+        .chunks_exact(4)
+        .map(|pointer| u32::from_le_bytes([pointer[0], pointer[1], pointer[2], pointer[3]]))
+        .collect()
+}
+
+fn store_pointers(bytes: &mut [u8], pointers: &[u32]) {
+    for (slot, pointer) in bytes.chunks_exact_mut(4).zip(pointers) {
+        slot.copy_from_slice(&pointer.to_le_bytes());
+    }
+}
+
+///Extents stored behind their count, at most `max` of them
+fn extents_of(bytes: &[u8], max: usize) -> Result<Vec<Extent>> {
+    let count = load::<u32>(bytes) as usize;
+    if count > max {
+        return Err(Error::InvalidImage(format!(
+            "{} extents don't fit where they are stored",
+            count
+        )));
+    }
+    Ok((0..count).map(|i| load(&bytes[4 + i * size_of::<Extent>()..])).collect())
+}
+
+fn store_extents(bytes: &mut [u8], extents: &[Extent]) {
+    store(bytes, &(extents.len() as u32));
+    for (i, extent) in extents.iter().enumerate() {
+        store(&mut bytes[4 + i * size_of::<Extent>()..], extent);
+    }
+}
+
+///The on-disk structures are plain old data, like in the kernel they are read straight from
+///the block content
+fn load<T>(bytes: &[u8]) -> T {
+    assert!(bytes.len() >= size_of::<T>());
+    unsafe { (bytes.as_ptr() as *const T).read_unaligned() }
+}
+
+fn store<T>(bytes: &mut [u8], value: &T) {
+    bytes[..size_of::<T>()].copy_from_slice(bytes_of(value));
+}
+
+fn bytes_of<T>(value: &T) -> &[u8] {
+    unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) }
+}
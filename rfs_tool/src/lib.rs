@@ -0,0 +1,42 @@
+//!Host side access to rfs disk images, through the same on-disk structures as the kernel driver
+
+pub mod gpt;
+pub mod image;
+
+use std::fmt;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    ///Image doesn't contain what it should, like a GPT header or an rfs partition
+    InvalidImage(String),
+    InvalidArgument(String),
+    NoEntry(String),
+    NotDirectory(String),
+    AlreadyExists(String),
+    NameTooLong(String),
+    NoSpace,
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(error) => write!(f, "{}", error),
+            Error::InvalidImage(reason) => write!(f, "invalid image: {}", reason),
+            Error::InvalidArgument(reason) => write!(f, "{}", reason),
+            Error::NoEntry(path) => write!(f, "{}: no such file or directory", path),
+            Error::NotDirectory(path) => write!(f, "{}: not a directory", path),
+            Error::AlreadyExists(path) => write!(f, "{}: already exists", path),
+            Error::NameTooLong(name) => write!(f, "{}: name is too long", name),
+            Error::NoSpace => write!(f, "no space left in the filesystem"),
+        }
+    }
+}
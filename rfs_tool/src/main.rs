@@ -0,0 +1,242 @@
+//!Creates and edits disk images with rfs partitions on the host. Run as `mkfs.rfs` (through a
+//!link) it only formats, the same as `rfs-tool mkfs`
+
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    os::unix::fs::{PermissionsExt, symlink},
+    path::Path,
+    process::ExitCode,
+};
+
+use rfs_fsck::Upgrade;
+use rfs_layout::{InodeMode, PARTITION_TYPE_GUID};
+use rfs_tool::{Error, Result, gpt, image::RfsImage};
+
+const USAGE: &str = "usage:
+    rfs-tool mkfs <image> <label>:<size in MiB>...
+    rfs-tool [-p <label>] ls <image> [<path>]
+    rfs-tool [-p <label>] mkdir <image> <path>
+    rfs-tool [-p <label>] insert <image> <host path> <path>
+    rfs-tool [-p <label>] extract <image> <path> <host path>
+    rfs-tool [-p <label>] fsck <image> [--repair]
+    rfs-tool [-p <label>] enable-checksums <image>
+-p picks the rfs partition when the image has more than one";
+
+fn main() -> ExitCode {
+    let mut args = env::args().collect::<Vec<_>>();
+    let program = args.remove(0);
+    if Path::new(&program).file_name().is_some_and(|name| name == "mkfs.rfs") {
+        args.insert(0, "mkfs".into());
+    }
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("rfs-tool: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &[String]) -> Result<()> {
+    let usage = || Error::InvalidArgument(USAGE.into());
+    let (label, args) = match args {
+        [flag, label, rest @ ..] if flag == "-p" => (Some(label.as_str()), rest),
+        _ => (None, args),
+    };
+    match args {
+        [command, image, partitions @ ..] if command == "mkfs" && !partitions.is_empty() => mkfs(image, partitions),
+        [command, image, rest @ ..] => {
+            let file = OpenOptions::new().read(true).write(true).open(image)?;
+            let mut rfs = if command == "fsck" {
+                RfsImage::open_for_check(file, label)?
+            } else {
+                RfsImage::open(file, label)?
+            };
+            match (command.as_str(), rest) {
+                ("ls", []) => list(&mut rfs, "/")?,
+                ("ls", [path]) => list(&mut rfs, path)?,
+                ("mkdir", [path]) => {
+                    let (parent, name) = split_path(&mut rfs, path)?;
+                    rfs.create(parent, name, InodeMode::new_dir(0o755))?;
+                }
+                ("insert", [source, path]) => {
+                    //like cp, an existing directory gets the source inside of it
+                    let (parent, name) = match rfs.lookup(path) {
+                        Ok(index) if rfs.stat(index)?.inode_type_mode.is_dir() => {
+                            let name = Path::new(source).file_name().and_then(|name| name.to_str());
+                            (index, name.ok_or_else(usage)?)
+                        }
+                        _ => split_path(&mut rfs, path)?,
+                    };
+                    insert(&mut rfs, Path::new(source), parent, name)?;
+                }
+                ("extract", [path, destination]) => {
+                    let index = rfs.lookup(path)?;
+                    extract(&mut rfs, index, Path::new(destination))?;
+                }
+                ("fsck", []) => fsck(&mut rfs, false)?,
+                ("fsck", [flag]) if flag == "--repair" => fsck(&mut rfs, true)?,
+                ("enable-checksums", []) => enable_checksums(&mut rfs)?,
+                _ => return Err(usage()),
+            }
+            rfs.close()
+        }
+        _ => Err(usage()),
+    }
+}
+
+///Creates a new image with one formatted rfs partition per `<label>:<size in MiB>`
+fn mkfs(image: &str, partitions: &[String]) -> Result<()> {
+    let mut specs = Vec::new();
+    for partition in partitions {
+        let (label, size) = partition
+            .rsplit_once(':')
+            .ok_or_else(|| Error::InvalidArgument(format!("{} is not <label>:<size in MiB>", partition)))?;
+        let size = size
+            .parse::<u64>()
+            .map_err(|_| Error::InvalidArgument(format!("{} is not a size in MiB", size)))?;
+        specs.push((label.to_string(), PARTITION_TYPE_GUID, size * 1024 * 1024 / 512));
+    }
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(image)?;
+    for partition in gpt::create(&mut file, &specs)? {
+        RfsImage::format(file.try_clone()?, &partition)?.close()?;
+    }
+    Ok(())
+}
+
+///Prints the problems found, fails if any of them is left on the image
+fn fsck(rfs: &mut RfsImage, repair: bool) -> Result<()> {
+    let report = rfs.check(repair)?;
+    for problem in report.problems.iter() {
+        let fixed = if report.repaired && problem.repairable() {
+            " (repaired)"
+        } else {
+            ""
+        };
+        println!("{}{}", problem, fixed);
+    }
+    let left = report
+        .problems
+        .iter()
+        .filter(|problem| !report.repaired || !problem.repairable())
+        .count();
+    match left {
+        0 => Ok(()),
+        _ => Err(Error::InvalidImage(format!("{} problems left", left))),
+    }
+}
+
+///Upgrades an image formatted without checksums, which has to pass fsck first
+fn enable_checksums(rfs: &mut RfsImage) -> Result<()> {
+    match rfs.enable_checksums()? {
+        Upgrade::Enabled => Ok(()),
+        Upgrade::AlreadyEnabled => {
+            println!("checksums are already enabled");
+            Ok(())
+        }
+        Upgrade::NotClean(report) => {
+            for problem in report.problems.iter() {
+                println!("{}", problem);
+            }
+            Err(Error::InvalidImage("fsck --repair has to fix these first".into()))
+        }
+        Upgrade::NoSpace => Err(Error::NoSpace),
+    }
+}
+
+///Parent directory and name of a new entry
+fn split_path<'a>(rfs: &mut RfsImage, path: &'a str) -> Result<(u32, &'a str)> {
+    let path = path.trim_end_matches('/');
+    let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+    Ok((rfs.lookup(parent)?, name))
+}
+
+fn list(rfs: &mut RfsImage, path: &str) -> Result<()> {
+    let index = rfs.lookup(path)?;
+    let entries = if rfs.stat(index)?.inode_type_mode.is_dir() {
+        rfs.read_dir(index)?
+    } else {
+        vec![(path.to_string(), index)]
+    };
+    for (name, index) in entries {
+        let inode = rfs.stat(index)?;
+        let mode = inode.inode_type_mode;
+        let kind = if mode.is_dir() {
+            'd'
+        } else if mode.is_symlink() {
+            'l'
+        } else {
+            '-'
+        };
+        let perms = (0..9)
+            .map(|bit| {
+                if mode.perms() & (0o400 >> bit) != 0 {
+                    b"rwx"[bit % 3] as char
+                } else {
+                    '-'
+                }
+            })
+            .collect::<String>();
+        println!(
+            "{}{} {:>3} {:>10} {:>6} {}",
+            kind,
+            perms,
+            inode.link_count,
+            inode.size.size(),
+            index,
+            name
+        );
+    }
+    Ok(())
+}
+
+///Copies a host file, symlink or directory tree into the image. Directories are read in name
+///order and times are left at 0, so the same tree always gives the same image
+fn insert(rfs: &mut RfsImage, source: &Path, parent: u32, name: &str) -> Result<()> {
+    let metadata = fs::symlink_metadata(source)?;
+    let perms = metadata.permissions().mode() & 0o7777;
+    if metadata.is_dir() {
+        let index = rfs.create(parent, name, InodeMode::new_dir(perms))?;
+        let mut children = fs::read_dir(source)?.collect::<std::io::Result<Vec<_>>>()?;
+        children.sort_by_key(|child| child.file_name());
+        for child in children {
+            let child_name = child.file_name();
+            let child_name = child_name
+                .to_str()
+                .ok_or_else(|| Error::InvalidArgument(format!("{} is not valid UTF-8", child.path().display())))?;
+            insert(rfs, &child.path(), index, child_name)?;
+        }
+    } else if metadata.is_symlink() {
+        let target = fs::read_link(source)?;
+        let index = rfs.create(parent, name, InodeMode::new_symlink(0o777))?;
+        rfs.write_file(index, target.as_os_str().as_encoded_bytes())?;
+    } else {
+        let index = rfs.create(parent, name, InodeMode::new_file(perms))?;
+        rfs.write_file(index, &fs::read(source)?)?;
+    }
+    Ok(())
+}
+
+fn extract(rfs: &mut RfsImage, index: u32, destination: &Path) -> Result<()> {
+    let mode = rfs.stat(index)?.inode_type_mode;
+    if mode.is_dir() {
+        fs::create_dir_all(destination)?;
+        for (name, child) in rfs.read_dir(index)? {
+            extract(rfs, child, &destination.join(name))?;
+        }
+    } else if mode.is_symlink() {
+        let target = String::from_utf8_lossy(&rfs.read_file(index)?).into_owned();
+        symlink(target, destination)?;
+        return Ok(());
+    } else {
+        fs::write(destination, rfs.read_file(index)?)?;
+    }
+    fs::set_permissions(destination, fs::Permissions::from_mode(mode.perms()))?;
+    Ok(())
+}
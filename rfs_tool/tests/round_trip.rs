@@ -0,0 +1,319 @@
+//!Images built by rfs-tool the way make_disk.sh builds them: mkfs, insert a host tree, extract it
+//!again and compare, with fsck passing in between. Corrupted images have to be found by fsck and
+//!repaired, a transaction left in the journal is replayed on open only if its commit record made it
+
+use std::{
+    fs::{self, File, OpenOptions},
+    os::unix::fs::{FileExt, PermissionsExt, symlink},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use rfs_fsck::Problem;
+use rfs_layout::{
+    BLOCK_SIZE, COMMIT_MAGIC, CommitRecord, DESCRIPTOR_MAGIC, Descriptor, INDEXED_DIR_ENTRIES, Inode, InodeMode,
+    PARTITION_TYPE_GUID, ROOT_INODE_BLOCK, ROOT_INODE_INDEX, SUPERBLOCK_BLOCK, SuperBlock, checksum_slot, crc32c,
+};
+use rfs_tool::{gpt, image::RfsImage};
+
+///Fresh directory for a test, removed when dropped
+struct TestDir(PathBuf);
+
+impl TestDir {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("rfs_tool_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn rfs_tool(args: &[&str]) {
+    let status = Command::new(env!("CARGO_BIN_EXE_rfs-tool")).args(args).status().unwrap();
+    assert!(status.success(), "rfs-tool {:?} failed", args);
+}
+
+///Content that differs in every block, so misplaced blocks don't compare equal
+fn content(len: usize, seed: u64) -> Vec<u8> {
+    let mut state = seed | 1;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
+
+///Host tree covering inline data, files of one and of many blocks, an indexed directory, nesting
+///and a symlink
+fn make_tree(root: &Path) {
+    fs::create_dir_all(root.join("nested/deeper")).unwrap();
+    fs::write(root.join("empty"), b"").unwrap();
+    fs::write(root.join("inline"), content(100, 1)).unwrap();
+    fs::write(root.join("one_block"), content(BLOCK_SIZE, 2)).unwrap();
+    fs::write(root.join("nested/large"), content(3 * 1024 * 1024 + 17, 3)).unwrap();
+    fs::write(root.join("nested/deeper/file"), content(5000, 4)).unwrap();
+    fs::set_permissions(root.join("inline"), fs::Permissions::from_mode(0o640)).unwrap();
+    symlink("nested/large", root.join("link")).unwrap();
+    fs::create_dir(root.join("many")).unwrap();
+    for i in 0..INDEXED_DIR_ENTRIES + 10 {
+        fs::write(root.join("many").join(format!("entry_{}", i)), i.to_string()).unwrap();
+    }
+}
+
+///Compares two host trees, content, permissions and symlink targets
+fn assert_same_tree(expected: &Path, found: &Path) {
+    let metadata = fs::symlink_metadata(expected).unwrap();
+    let found_metadata = fs::symlink_metadata(found).unwrap();
+    if metadata.is_symlink() {
+        assert!(found_metadata.is_symlink(), "{} is no symlink", found.display());
+        assert_eq!(fs::read_link(expected).unwrap(), fs::read_link(found).unwrap());
+        return;
+    }
+    assert_eq!(
+        metadata.permissions().mode() & 0o7777,
+        found_metadata.permissions().mode() & 0o7777,
+        "permissions of {}",
+        found.display()
+    );
+    if metadata.is_dir() {
+        let names = |dir: &Path| {
+            let mut names = fs::read_dir(dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name())
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+        let expected_names = names(expected);
+        assert_eq!(expected_names, names(found), "entries of {}", found.display());
+        for name in expected_names {
+            assert_same_tree(&expected.join(&name), &found.join(&name));
+        }
+    } else {
+        assert!(fs::read(expected).unwrap() == fs::read(found).unwrap(), "content of {}", found.display());
+    }
+}
+
+fn open_image(image: &Path) -> File {
+    OpenOptions::new().read(true).write(true).open(image).unwrap()
+}
+
+#[test]
+fn insert_extract_round_trip() {
+    let dir = TestDir::new("round_trip");
+    let source = dir.0.join("source");
+    make_tree(&source);
+    let image = dir.0.join("disk.img");
+    let image = image.to_str().unwrap();
+
+    rfs_tool(&["mkfs", image, "root:16", "other:16"]);
+    for entry in ["empty", "inline", "one_block", "nested", "link", "many"] {
+        rfs_tool(&["-p", "root", "insert", image, source.join(entry).to_str().unwrap(), "/"]);
+    }
+    rfs_tool(&["-p", "root", "fsck", image]);
+    rfs_tool(&["-p", "other", "fsck", image]);
+
+    let extracted = dir.0.join("extracted");
+    rfs_tool(&["-p", "root", "extract", image, "/", extracted.to_str().unwrap()]);
+    //the extracted root gets the permissions of the root inode, the rest has to match
+    fs::set_permissions(&extracted, fs::metadata(&source).unwrap().permissions()).unwrap();
+    assert_same_tree(&source, &extracted);
+}
+
+#[test]
+fn mkfs_is_reproducible() {
+    let dir = TestDir::new("reproducible");
+    let source = dir.0.join("source");
+    make_tree(&source);
+    let build = |name: &str| {
+        let image = dir.0.join(name);
+        let image = image.to_str().unwrap();
+        rfs_tool(&["mkfs", image, "root:16", "rfs_test:8"]);
+        rfs_tool(&["-p", "root", "insert", image, source.join("nested").to_str().unwrap(), "/"]);
+        fs::read(image).unwrap()
+    };
+    assert!(build("first.img") == build("second.img"));
+
+    //the partitions are found by label, their guids only have to stay the same between builds
+    let partitions = gpt::read(&mut open_image(&dir.0.join("first.img"))).unwrap();
+    let names = partitions.iter().map(|partition| partition.name.as_str()).collect::<Vec<_>>();
+    assert_eq!(names, ["root", "rfs_test"]);
+    assert!(partitions.iter().all(|partition| partition.type_guid == PARTITION_TYPE_GUID));
+    assert_ne!(partitions[0].unique_guid, partitions[1].unique_guid);
+}
+
+///Image with one partition holding a few files, and the byte offset of the partition
+fn filled_image(dir: &TestDir) -> (PathBuf, u64) {
+    let image = dir.0.join("disk.img");
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&image)
+        .unwrap();
+    let partitions = gpt::create(&mut file, &[("root".into(), PARTITION_TYPE_GUID, 16 * 1024 * 2)]).unwrap();
+    let mut rfs = RfsImage::format(file, &partitions[0]).unwrap();
+    for (i, len) in [10, BLOCK_SIZE * 3, 200_000].into_iter().enumerate() {
+        let name = format!("file_{}", i);
+        let index = rfs.create(ROOT_INODE_INDEX, &name, InodeMode::new_file(0o644)).unwrap();
+        rfs.write_file(index, &content(len, i as u64)).unwrap();
+    }
+    rfs.close().unwrap();
+    (image, partitions[0].start_lba * 512)
+}
+
+#[test]
+fn fsck_passes_written_image() {
+    let dir = TestDir::new("fsck_clean");
+    let (image, _) = filled_image(&dir);
+    let mut rfs = RfsImage::open_for_check(open_image(&image), None).unwrap();
+    let report = rfs.check(false).unwrap();
+    assert!(report.is_clean(), "{:?}", report.problems);
+
+    let mut rfs = RfsImage::open(open_image(&image), None).unwrap();
+    let index = rfs.lookup("/file_2").unwrap();
+    assert!(rfs.read_file(index).unwrap() == content(200_000, 2));
+}
+
+#[test]
+fn fsck_repairs_cleared_bitmap() {
+    let dir = TestDir::new("fsck_bitmap");
+    let (image, offset) = filled_image(&dir);
+    //the bitmap of the first group is its first block, everything in use now reads as free
+    let file = open_image(&image);
+    let mut bitmap = vec![0; BLOCK_SIZE];
+    file.read_exact_at(&mut bitmap, offset).unwrap();
+    bitmap[..64].fill(0);
+    file.write_all_at(&bitmap, offset).unwrap();
+
+    let mut rfs = RfsImage::open_for_check(open_image(&image), None).unwrap();
+    let report = rfs.check(false).unwrap();
+    assert!(
+        report
+            .problems
+            .iter()
+            .any(|problem| matches!(problem, Problem::BlocksMarkedFree { .. })),
+        "{:?}",
+        report.problems
+    );
+    assert!(report.problems.iter().all(Problem::repairable), "{:?}", report.problems);
+
+    let report = rfs.check(true).unwrap();
+    assert!(report.repaired);
+    let report = rfs.check(false).unwrap();
+    assert!(report.is_clean(), "{:?}", report.problems);
+}
+
+#[test]
+fn fsck_finds_bad_checksum() {
+    let dir = TestDir::new("fsck_checksum");
+    let (image, offset) = filled_image(&dir);
+    //the root directory holds its three entries inline in its inode block
+    let block = ROOT_INODE_BLOCK;
+    let file = open_image(&image);
+    let position = offset + block as u64 * BLOCK_SIZE as u64 + 600;
+    let mut byte = [0];
+    file.read_exact_at(&mut byte, position).unwrap();
+    file.write_all_at(&[byte[0] ^ 0xFF], position).unwrap();
+
+    assert!(RfsImage::open(open_image(&image), None).unwrap().lookup("/file_0").is_err());
+    let mut rfs = RfsImage::open_for_check(open_image(&image), None).unwrap();
+    let report = rfs.check(false).unwrap();
+    assert!(report.problems.contains(&Problem::BadChecksum { block }), "{:?}", report.problems);
+}
+
+const SEQUENCE: u64 = 7;
+
+fn set_u32(block: &mut [u8], offset: usize, value: u32) {
+    block[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn get_u32(block: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(block[offset..offset + 4].try_into().unwrap())
+}
+
+///Leaves a transaction in the journal that gives the root inode a new uid, along with the checksum
+///table block covering it, as if the kernel stopped before any block reached its location. Without
+///`committed` the commit record is a stale one from the transaction before, the new one never made it
+fn leave_transaction(image: &Path, offset: u64, uid: u16, committed: bool) {
+    let file = open_image(image);
+    let read = |block: u32| {
+        let mut content = vec![0; BLOCK_SIZE];
+        file.read_exact_at(&mut content, offset + block as u64 * BLOCK_SIZE as u64).unwrap();
+        content
+    };
+    let superblock = read(SUPERBLOCK_BLOCK);
+    let journal_start = get_u32(&superblock, core::mem::offset_of!(SuperBlock, journal_start));
+    let checksum_table = get_u32(&superblock, core::mem::offset_of!(SuperBlock, checksum_table));
+
+    let mut inode_block = read(ROOT_INODE_BLOCK);
+    let uid_offset = core::mem::offset_of!(Inode, uid);
+    inode_block[uid_offset..uid_offset + 2].copy_from_slice(&uid.to_le_bytes());
+    let (table_block, slot) = checksum_slot(ROOT_INODE_BLOCK);
+    let mut table = read(checksum_table + table_block);
+    set_u32(&mut table, slot * 4, crc32c(&inode_block));
+    //the table block ends with the checksum of everything before it
+    let own_checksum = crc32c(&table[..BLOCK_SIZE - 4]);
+    set_u32(&mut table, BLOCK_SIZE - 4, own_checksum);
+
+    let mut descriptor = vec![0; BLOCK_SIZE];
+    set_u32(&mut descriptor, core::mem::offset_of!(Descriptor, magic), DESCRIPTOR_MAGIC);
+    set_u32(&mut descriptor, core::mem::offset_of!(Descriptor, block_count), 2);
+    let sequence = core::mem::offset_of!(Descriptor, sequence);
+    descriptor[sequence..sequence + 8].copy_from_slice(&SEQUENCE.to_le_bytes());
+    let targets = core::mem::offset_of!(Descriptor, targets);
+    set_u32(&mut descriptor, targets, ROOT_INODE_BLOCK);
+    set_u32(&mut descriptor, targets + 4, checksum_table + table_block);
+
+    let mut commit = vec![0; 512];
+    set_u32(&mut commit, core::mem::offset_of!(CommitRecord, magic), COMMIT_MAGIC);
+    let commit_sequence = if committed { SEQUENCE } else { SEQUENCE - 1 };
+    let sequence = core::mem::offset_of!(CommitRecord, sequence);
+    commit[sequence..sequence + 8].copy_from_slice(&commit_sequence.to_le_bytes());
+
+    let journal = offset + journal_start as u64 * BLOCK_SIZE as u64;
+    for (i, block) in [descriptor, inode_block, table, commit].iter().enumerate() {
+        file.write_all_at(block, journal + (i * BLOCK_SIZE) as u64).unwrap();
+    }
+}
+
+#[test]
+fn open_replays_committed_transaction() {
+    let dir = TestDir::new("journal_committed");
+    let (image, offset) = filled_image(&dir);
+    leave_transaction(&image, offset, 1234, true);
+
+    let mut rfs = RfsImage::open(open_image(&image), None).unwrap();
+    assert_eq!(rfs.stat(ROOT_INODE_INDEX).unwrap().uid, 1234);
+    let index = rfs.lookup("/file_2").unwrap();
+    assert!(rfs.read_file(index).unwrap() == content(200_000, 2));
+    let mut rfs = RfsImage::open_for_check(open_image(&image), None).unwrap();
+    let report = rfs.check(false).unwrap();
+    assert!(report.is_clean(), "{:?}", report.problems);
+}
+
+#[test]
+fn open_ignores_uncommitted_transaction() {
+    let dir = TestDir::new("journal_torn");
+    let (image, offset) = filled_image(&dir);
+    let uid = RfsImage::open(open_image(&image), None).unwrap().stat(ROOT_INODE_INDEX).unwrap().uid;
+    leave_transaction(&image, offset, uid + 1234, false);
+
+    let mut rfs = RfsImage::open(open_image(&image), None).unwrap();
+    assert_eq!(rfs.stat(ROOT_INODE_INDEX).unwrap().uid, uid);
+    let index = rfs.lookup("/file_2").unwrap();
+    assert!(rfs.read_file(index).unwrap() == content(200_000, 2));
+    let mut rfs = RfsImage::open_for_check(open_image(&image), None).unwrap();
+    let report = rfs.check(false).unwrap();
+    assert!(report.is_clean(), "{:?}", report.problems);
+}
@@ -13,6 +13,47 @@ pub enum ErrorCode {
     UnsupportedFilesystem,
     InsufficientPermissions,
     UnsupportedOperation,
+    SymlinkLoop,
+    IsDirectory,
+    NotDirectory,
+    Exists,
+    NoSpace,
+    BadFd,
+    NameTooLong,
+    InvalidArgument,
+    DirectoryNotEmpty,
+    Busy,
+    ReadOnlyFilesystem,
+    BadAddress,
+    NoSyscall,
+}
+
+impl ErrorCode {
+    ///Value userland sees in errno. The values are listed in documentation/syscalls.md
+    pub fn errno(&self) -> u64 {
+        match self {
+            ErrorCode::UnsupportedOperation => 1,
+            ErrorCode::InodeNotPresent | ErrorCode::NoEntry => 2,
+            ErrorCode::Unknown
+            | ErrorCode::FileSystemInconsistency
+            | ErrorCode::InternalFSError => 5,
+            ErrorCode::BadFd => 9,
+            ErrorCode::BadAddress => 14,
+            ErrorCode::Busy => 16,
+            ErrorCode::InsufficientPermissions => 13,
+            ErrorCode::Exists => 17,
+            ErrorCode::UnsupportedFilesystem => 19,
+            ErrorCode::NotDirectory => 20,
+            ErrorCode::IsDirectory => 21,
+            ErrorCode::InvalidString | ErrorCode::NotMounted | ErrorCode::InvalidArgument => 22,
+            ErrorCode::NoSpace => 28,
+            ErrorCode::ReadOnlyFilesystem => 30,
+            ErrorCode::NameTooLong => 36,
+            ErrorCode::NoSyscall => 38,
+            ErrorCode::DirectoryNotEmpty => 39,
+            ErrorCode::SymlinkLoop => 40,
+        }
+    }
 }
 
 impl Error for ErrorCode {}
@@ -30,6 +71,19 @@ impl core::fmt::Display for ErrorCode {
             ErrorCode::UnsupportedFilesystem => write!(f, "Filesystem type is unsupported"),
             ErrorCode::InsufficientPermissions => write!(f, "Insufficient permissions"),
             ErrorCode::UnsupportedOperation => write!(f, "Unsupported operation"),
+            ErrorCode::SymlinkLoop => write!(f, "Too many levels of symbolic links"),
+            ErrorCode::IsDirectory => write!(f, "Is a directory"),
+            ErrorCode::NotDirectory => write!(f, "Not a directory"),
+            ErrorCode::Exists => write!(f, "Entry already exists"),
+            ErrorCode::NoSpace => write!(f, "No space left on device"),
+            ErrorCode::BadFd => write!(f, "Bad file descriptor, or not opened for this operation"),
+            ErrorCode::NameTooLong => write!(f, "Name too long"),
+            ErrorCode::InvalidArgument => write!(f, "Invalid argument"),
+            ErrorCode::DirectoryNotEmpty => write!(f, "Directory not empty"),
+            ErrorCode::Busy => write!(f, "Device or resource busy"),
+            ErrorCode::ReadOnlyFilesystem => write!(f, "Read-only filesystem"),
+            ErrorCode::BadAddress => write!(f, "Bad address"),
+            ErrorCode::NoSyscall => write!(f, "No such syscall"),
         }
     }
 }
@@ -35,7 +35,7 @@ use core::panic::PanicInfo;
 pub use alloc::boxed;
 pub use alloc::boxed::*;
 pub use alloc::collections;
-pub use core::borrow;
+pub use alloc::borrow;
 pub use core::cell;
 pub use core::char;
 pub use core::clone;
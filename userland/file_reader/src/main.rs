@@ -0,0 +1,45 @@
+#![no_std]
+#![no_main]
+
+use core::ffi::CStr;
+
+use libr::{
+    env, io, println,
+    syscall::{self, CreateMode, OpenFlags},
+};
+
+const DEFAULT_PATH: &CStr = c"/test/test.txt";
+
+///Prints /test/test.txt to stdout, then parks
+#[unsafe(no_mangle)]
+fn main() -> u64 {
+    let fd = match syscall::fopen(DEFAULT_PATH, None, OpenFlags::READ, CreateMode::default()) {
+        Ok(fd) => fd,
+        Err(e) => {
+            println!("Error opening file: {}", e);
+            env::park()
+        }
+    };
+
+    let mut buffer = [0; 512];
+    loop {
+        match syscall::fread(fd, &mut buffer) {
+            Ok(0) => {
+                println!("End of file reached");
+                break;
+            }
+            Ok(read) => {
+                if io::write_all(io::STDOUT, &buffer[..read]).is_err() {
+                    env::park()
+                }
+            }
+            Err(e) => {
+                println!("Error reading file: {}", e);
+                env::park()
+            }
+        }
+    }
+
+    let _ = syscall::fclose(fd);
+    env::park()
+}
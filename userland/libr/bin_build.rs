@@ -0,0 +1,7 @@
+//Build script shared by the userland programs, set as `build` in their Cargo.toml.
+//The kernel loads PIE executables at a base address but leaves relocating them to their
+//interpreter. These programs have none, so they are linked at a fixed address instead
+fn main() {
+    println!("cargo:rustc-link-arg-bins=--no-pie");
+    println!("cargo:rerun-if-changed=../libr/bin_build.rs");
+}
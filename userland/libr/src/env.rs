@@ -0,0 +1,68 @@
+//! Process entry point and access to arguments and environment variables
+use core::ffi::{CStr, c_char};
+
+use crate::syscall;
+
+unsafe extern "Rust" {
+    ///Defined by the program with #[unsafe(no_mangle)]. The return value is the exit status
+    fn main() -> u64;
+}
+
+static mut ARGV: &[*const c_char] = &[];
+static mut ENVP: &[*const c_char] = &[];
+
+///The kernel starts the process with rsp pointing to argc, followed by argv, NULL, envp, NULL and
+///the auxiliary vector
+#[unsafe(no_mangle)]
+#[naked]
+unsafe extern "C" fn _start() -> ! {
+    unsafe {
+        core::arch::naked_asm!(
+            "mov rdi, rsp", //initial stack
+            "and rsp, -16",
+            "call {}",
+            sym start_rust
+        )
+    }
+}
+
+extern "C" fn start_rust(stack: *const u64) -> ! {
+    unsafe {
+        let argc = *stack as usize;
+        let argv = stack.add(1) as *const *const c_char;
+        let envp = argv.add(argc + 1);
+        let mut envc = 0;
+        while !(*envp.add(envc)).is_null() {
+            envc += 1;
+        }
+        ARGV = core::slice::from_raw_parts(argv, argc);
+        ENVP = core::slice::from_raw_parts(envp, envc);
+
+        syscall::exit(main())
+    }
+}
+
+///Spins forever. The kernel doesn't implement exit yet, so the sample programs, which it starts
+///when there is no init, end here instead of returning from main
+pub fn park() -> ! {
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+///Arguments of the process, the first one is usually the program path. Arguments that are not
+///valid UTF-8 are skipped
+pub fn args() -> impl Iterator<Item = &'static str> {
+    unsafe { ARGV.iter() }.filter_map(|arg| unsafe { CStr::from_ptr(*arg) }.to_str().ok())
+}
+
+///Environment variables in KEY=VALUE form
+pub fn vars() -> impl Iterator<Item = (&'static str, &'static str)> {
+    unsafe { ENVP.iter() }
+        .filter_map(|var| unsafe { CStr::from_ptr(*var) }.to_str().ok())
+        .map(|var| var.split_once('=').unwrap_or((var, "")))
+}
+
+pub fn var(key: &str) -> Option<&'static str> {
+    vars().find(|(var_key, _)| *var_key == key).map(|(_, value)| value)
+}
@@ -0,0 +1,133 @@
+use core::fmt;
+
+///Error codes returned by the kernel in rdx
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Errno {
+    ///Operation not permitted
+    Perm,
+    ///No such file or directory
+    NoEntry,
+    ///I/O error, the filesystem or disk failed
+    Io,
+    ///Not an open file descriptor, or not opened for this operation
+    BadFd,
+    ///Operation would block
+    WouldBlock,
+    ///Out of memory
+    NoMemory,
+    ///Permission denied
+    Access,
+    ///Invalid pointer
+    Fault,
+    ///Device or resource busy, like a filesystem with open files
+    Busy,
+    ///File already exists
+    Exists,
+    ///Unknown filesystem type or no such device
+    NoDevice,
+    ///Not a directory
+    NotDirectory,
+    ///Is a directory
+    IsDirectory,
+    ///Invalid argument
+    Invalid,
+    ///No space left on device
+    NoSpace,
+    ///Filesystem is mounted read only
+    ReadOnly,
+    ///A path component is too long
+    NameTooLong,
+    ///Syscall is not implemented
+    NotImplemented,
+    ///Directory is not empty
+    NotEmpty,
+    ///Too many symbolic links
+    Loop,
+    ///Any code not known by this library
+    Unknown(u64),
+}
+
+impl Errno {
+    pub fn code(&self) -> u64 {
+        match self {
+            Self::Perm => 1,
+            Self::NoEntry => 2,
+            Self::Io => 5,
+            Self::BadFd => 9,
+            Self::WouldBlock => 11,
+            Self::NoMemory => 12,
+            Self::Access => 13,
+            Self::Fault => 14,
+            Self::Busy => 16,
+            Self::Exists => 17,
+            Self::NoDevice => 19,
+            Self::NotDirectory => 20,
+            Self::IsDirectory => 21,
+            Self::Invalid => 22,
+            Self::NoSpace => 28,
+            Self::ReadOnly => 30,
+            Self::NameTooLong => 36,
+            Self::NotImplemented => 38,
+            Self::NotEmpty => 39,
+            Self::Loop => 40,
+            Self::Unknown(code) => *code,
+        }
+    }
+}
+
+impl From<u64> for Errno {
+    fn from(code: u64) -> Self {
+        match code {
+            1 => Self::Perm,
+            2 => Self::NoEntry,
+            5 => Self::Io,
+            9 => Self::BadFd,
+            11 => Self::WouldBlock,
+            12 => Self::NoMemory,
+            13 => Self::Access,
+            14 => Self::Fault,
+            16 => Self::Busy,
+            17 => Self::Exists,
+            19 => Self::NoDevice,
+            20 => Self::NotDirectory,
+            21 => Self::IsDirectory,
+            22 => Self::Invalid,
+            28 => Self::NoSpace,
+            30 => Self::ReadOnly,
+            36 => Self::NameTooLong,
+            38 => Self::NotImplemented,
+            39 => Self::NotEmpty,
+            40 => Self::Loop,
+            code => Self::Unknown(code),
+        }
+    }
+}
+
+impl fmt::Display for Errno {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            Self::Perm => "operation not permitted",
+            Self::NoEntry => "no such file or directory",
+            Self::Io => "i/o error",
+            Self::BadFd => "bad file descriptor",
+            Self::WouldBlock => "operation would block",
+            Self::NoMemory => "out of memory",
+            Self::Access => "permission denied",
+            Self::Fault => "bad address",
+            Self::Busy => "device or resource busy",
+            Self::Exists => "file exists",
+            Self::NoDevice => "no such device",
+            Self::NotDirectory => "not a directory",
+            Self::IsDirectory => "is a directory",
+            Self::Invalid => "invalid argument",
+            Self::NoSpace => "no space left on device",
+            Self::ReadOnly => "read-only filesystem",
+            Self::NameTooLong => "file name too long",
+            Self::NotImplemented => "syscall not implemented",
+            Self::NotEmpty => "directory not empty",
+            Self::Loop => "too many symbolic links",
+            Self::Unknown(code) => return write!(f, "unknown error {}", code),
+        };
+        write!(f, "{}", description)
+    }
+}
@@ -0,0 +1,126 @@
+//! Global allocator on top of anonymous mmap. Small allocations are taken from power of two size
+//! classes carved out of larger mappings, big ones get their own mapping
+//!
+//! The kernel doesn't implement mmap yet, so nothing may allocate until it does. The sample
+//! programs don't
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    cell::UnsafeCell,
+    ptr,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::syscall::{self, MmapFlags};
+
+const PAGE_SIZE: usize = 0x1000;
+///Size of the mappings small allocations are carved from
+const ARENA_SIZE: usize = 0x10000;
+const SIZE_CLASSES: [usize; 8] = [16, 32, 64, 128, 256, 512, 1024, 2048];
+
+#[global_allocator]
+static ALLOCATOR: MmapAllocator = MmapAllocator::new();
+
+struct FreeBlock {
+    next: *mut FreeBlock,
+}
+
+struct Heap {
+    free_lists: [*mut FreeBlock; SIZE_CLASSES.len()],
+    arena_next: usize,
+    arena_end: usize,
+}
+
+pub struct MmapAllocator {
+    locked: AtomicBool,
+    heap: UnsafeCell<Heap>,
+}
+
+//access to heap is guarded by locked
+unsafe impl Sync for MmapAllocator {}
+
+impl MmapAllocator {
+    const fn new() -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            heap: UnsafeCell::new(Heap {
+                free_lists: [ptr::null_mut(); SIZE_CLASSES.len()],
+                arena_next: 0,
+                arena_end: 0,
+            }),
+        }
+    }
+
+    fn with_heap<T>(&self, f: impl FnOnce(&mut Heap) -> T) -> T {
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+        let result = f(unsafe { &mut *self.heap.get() });
+        self.locked.store(false, Ordering::Release);
+        result
+    }
+}
+
+///Blocks are aligned to their size, so any alignment up to the size is satisfied
+fn size_class(layout: &Layout) -> Option<usize> {
+    let size = layout.size().max(layout.align());
+    SIZE_CLASSES.iter().position(|class| *class >= size)
+}
+
+fn map_anonymous(size: usize) -> *mut u8 {
+    syscall::mmap(None, 0, None, size as u64, MmapFlags::READ | MmapFlags::WRITE).unwrap_or(ptr::null_mut())
+}
+
+impl Heap {
+    fn allocate_small(&mut self, class: usize) -> *mut u8 {
+        let free = self.free_lists[class];
+        if !free.is_null() {
+            self.free_lists[class] = unsafe { (*free).next };
+            return free as *mut u8;
+        }
+
+        let size = SIZE_CLASSES[class];
+        let mut start = self.arena_next.next_multiple_of(size);
+        if start + size > self.arena_end {
+            //the rest of the old arena is lost, it is smaller than the requested class anyway
+            let arena = map_anonymous(ARENA_SIZE);
+            if arena.is_null() {
+                return ptr::null_mut();
+            }
+            start = arena as usize;
+            self.arena_end = start + ARENA_SIZE;
+        }
+        self.arena_next = start + size;
+        start as *mut u8
+    }
+
+    fn free_small(&mut self, ptr: *mut u8, class: usize) {
+        let block = ptr as *mut FreeBlock;
+        unsafe { (*block).next = self.free_lists[class] };
+        self.free_lists[class] = block;
+    }
+}
+
+unsafe impl GlobalAlloc for MmapAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match size_class(&layout) {
+            Some(class) => self.with_heap(|heap| heap.allocate_small(class)),
+            //mappings are page aligned
+            None if layout.align() <= PAGE_SIZE => map_anonymous(layout.size().next_multiple_of(PAGE_SIZE)),
+            None => ptr::null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        match size_class(&layout) {
+            Some(class) => self.with_heap(|heap| heap.free_small(ptr, class)),
+            None => {
+                //nothing to do if the kernel refuses
+                let _ = syscall::munmap(ptr, layout.size().next_multiple_of(PAGE_SIZE) as u64);
+            }
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use core::fmt::{self, Write};
+
+use crate::{
+    errno::Errno,
+    syscall::{self, Fd},
+};
+
+pub const STDIN: Fd = 0;
+pub const STDOUT: Fd = 1;
+pub const STDERR: Fd = 2;
+
+///Writes the whole buffer, retrying on short writes
+pub fn write_all(fd: Fd, mut buffer: &[u8]) -> Result<(), Errno> {
+    while !buffer.is_empty() {
+        let written = syscall::fwrite(fd, buffer)?;
+        if written == 0 {
+            return Err(Errno::Io);
+        }
+        buffer = &buffer[written..];
+    }
+    Ok(())
+}
+
+///Collects formatted output on the stack, so printing does not need the heap and most prints are
+///a single syscall
+struct BufferedWriter {
+    fd: Fd,
+    buffer: [u8; 256],
+    len: usize,
+}
+
+impl BufferedWriter {
+    fn new(fd: Fd) -> Self {
+        Self {
+            fd,
+            buffer: [0; 256],
+            len: 0,
+        }
+    }
+
+    fn flush(&mut self) -> fmt::Result {
+        let result = write_all(self.fd, &self.buffer[..self.len]).map_err(|_| fmt::Error);
+        self.len = 0;
+        result
+    }
+}
+
+impl Write for BufferedWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for chunk in s.as_bytes().chunks(self.buffer.len()) {
+            if self.len + chunk.len() > self.buffer.len() {
+                self.flush()?;
+            }
+            self.buffer[self.len..self.len + chunk.len()].copy_from_slice(chunk);
+            self.len += chunk.len();
+        }
+        Ok(())
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    let mut writer = BufferedWriter::new(STDOUT);
+    //nowhere to report a failed print to
+    let _ = writer.write_fmt(args);
+    let _ = writer.flush();
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => {
+        $crate::io::_print(format_args!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! println {
+    () => {
+        $crate::print!("\n")
+    };
+    ($($arg:tt)*) => {
+        $crate::io::_print(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
@@ -0,0 +1,20 @@
+//! Runtime for userspace programs. Provides the process entry point, typed syscall wrappers, a
+//! heap on top of mmap and printing to stdout
+#![no_std]
+#![feature(naked_functions)]
+
+extern crate alloc;
+
+pub mod env;
+pub mod errno;
+mod heap;
+pub mod io;
+pub mod syscall;
+
+pub use errno::Errno;
+
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    println!("{}", info);
+    syscall::exit(101)
+}
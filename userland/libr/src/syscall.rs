@@ -0,0 +1,279 @@
+//! Typed wrappers for the syscalls in documentation/syscalls.md
+use alloc::vec::Vec;
+use core::{ffi::CStr, ops::BitOr, time::Duration};
+
+use crate::errno::Errno;
+
+pub type Fd = u64;
+pub type Pid = u64;
+
+const EXIT: u64 = 1;
+const EXEC: u64 = 2;
+const CLONE: u64 = 3;
+const FOPEN: u64 = 4;
+const FCLOSE: u64 = 5;
+const FREAD: u64 = 6;
+const FWRITE: u64 = 7;
+const FSEEK: u64 = 8;
+const MMAP: u64 = 9;
+const MUNMAP: u64 = 10;
+const SLEEP: u64 = 11;
+const TIME: u64 = 12;
+const SYNC: u64 = 13;
+const MOUNT: u64 = 14;
+const UMOUNT: u64 = 15;
+const SYMLINK: u64 = 16;
+const UNLINK: u64 = 17;
+const FTRUNCATE: u64 = 18;
+
+///Value of -1 as returned by the kernel
+const ERR_RET: u64 = u64::MAX;
+
+///Makes a syscall with the x86_64 convention. Returns (return value, errno). Unused arguments
+///should be 0
+///
+/// # Safety
+/// Arguments have to be valid for the syscall, pointers have to point to memory of the right size
+pub unsafe fn syscall(number: u64, args: [u64; 6]) -> (u64, u64) {
+    let ret: u64;
+    let errno: u64;
+    unsafe {
+        core::arch::asm!(
+            "syscall",
+            inlateout("rax") number => ret,
+            in("rdi") args[0],
+            in("rsi") args[1],
+            inlateout("rdx") args[2] => errno,
+            in("r10") args[3],
+            in("r8") args[4],
+            in("r9") args[5],
+            lateout("rcx") _,
+            lateout("r11") _,
+            options(nostack)
+        );
+    }
+    (ret, errno)
+}
+
+///Most syscalls return -1 on failure and set errno
+fn check(ret: u64, errno: u64) -> Result<u64, Errno> {
+    if ret == ERR_RET { Err(Errno::from(errno)) } else { Ok(ret) }
+}
+
+macro_rules! flags {
+    ($name:ident { $($flag:ident = $bit:expr),* $(,)? }) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+        pub struct $name(pub u64);
+
+        impl $name {
+            $(pub const $flag: Self = Self(1 << $bit);)*
+
+            pub fn contains(&self, other: Self) -> bool {
+                self.0 & other.0 == other.0
+            }
+        }
+
+        impl BitOr for $name {
+            type Output = Self;
+
+            fn bitor(self, rhs: Self) -> Self {
+                Self(self.0 | rhs.0)
+            }
+        }
+    };
+}
+
+flags!(CloneFlags {
+    CLONE_MEM = 0,
+    NO_FD = 1,
+    NO_STDIO = 2,
+});
+
+flags!(OpenFlags {
+    READ = 0,
+    WRITE = 1,
+    APPEND = 2,
+    CREATE = 3,
+    TRUNCATE = 4,
+    NO_FOLLOW = 6,
+});
+
+flags!(CreateMode {
+    USER_READ = 0,
+    USER_WRITE = 1,
+    USER_EXECUTE = 2,
+    GROUP_READ = 3,
+    GROUP_WRITE = 4,
+    GROUP_EXECUTE = 5,
+    OTHER_READ = 6,
+    OTHER_WRITE = 7,
+    OTHER_EXECUTE = 8,
+    STICKY = 9,
+    SETUID = 10,
+    SETGID = 11,
+    DIRECTORY = 12,
+});
+
+flags!(MmapFlags {
+    READ = 0,
+    WRITE = 1,
+    EXECUTE = 2,
+    CLEAR = 3,
+    STACK = 4,
+});
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u64)]
+pub enum Whence {
+    Set = 0,
+    Current = 1,
+    End = 2,
+}
+
+pub fn exit(status: u64) -> ! {
+    unsafe { syscall(EXIT, [status, 0, 0, 0, 0, 0]) };
+    //the kernel never returns from exit
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+///Spawns a new process running the executable at path
+pub fn exec(path: &CStr, argv: &[&CStr], envp: &[&CStr]) -> Result<Pid, Errno> {
+    let argv_ptrs = argv.iter().map(|arg| arg.as_ptr()).collect::<Vec<_>>();
+    let envp_ptrs = envp.iter().map(|var| var.as_ptr()).collect::<Vec<_>>();
+    let (ret, errno) = unsafe {
+        syscall(
+            EXEC,
+            [
+                path.as_ptr() as u64,
+                argv_ptrs.len() as u64,
+                argv_ptrs.as_ptr() as u64,
+                envp_ptrs.len() as u64,
+                envp_ptrs.as_ptr() as u64,
+                0,
+            ],
+        )
+    };
+    check(ret, errno)
+}
+
+///Returns the pid of the new process in the caller, 0 in the new process
+pub fn clone(flags: CloneFlags) -> Result<Pid, Errno> {
+    let (ret, errno) = unsafe { syscall(CLONE, [flags.0, 0, 0, 0, 0, 0]) };
+    check(ret, errno)
+}
+
+///Opens path. Relative paths start at dir if it is set, otherwise at the working directory
+pub fn fopen(path: &CStr, dir: Option<Fd>, flags: OpenFlags, create_mode: CreateMode) -> Result<Fd, Errno> {
+    let (ret, errno) = unsafe { syscall(FOPEN, [path.as_ptr() as u64, dir.unwrap_or(0), flags.0, create_mode.0, 0, 0]) };
+    check(ret, errno)
+}
+
+pub fn fclose(fd: Fd) -> Result<(), Errno> {
+    let (ret, errno) = unsafe { syscall(FCLOSE, [fd, 0, 0, 0, 0, 0]) };
+    check(ret, errno).map(|_| ())
+}
+
+///Returns the number of bytes read, 0 at the end of the file
+pub fn fread(fd: Fd, buffer: &mut [u8]) -> Result<usize, Errno> {
+    let (ret, errno) = unsafe { syscall(FREAD, [fd, buffer.as_mut_ptr() as u64, buffer.len() as u64, 0, 0, 0]) };
+    check(ret, errno).map(|read| read as usize)
+}
+
+///Returns the number of bytes written, which may be less than the buffer length
+pub fn fwrite(fd: Fd, buffer: &[u8]) -> Result<usize, Errno> {
+    let (ret, errno) = unsafe { syscall(FWRITE, [fd, buffer.as_ptr() as u64, buffer.len() as u64, 0, 0, 0]) };
+    check(ret, errno).map(|written| written as usize)
+}
+
+///Returns the new offset from the start of the file
+pub fn fseek(fd: Fd, offset: i64, whence: Whence) -> Result<u64, Errno> {
+    let (ret, errno) = unsafe { syscall(FSEEK, [fd, offset as u64, whence as u64, 0, 0, 0]) };
+    check(ret, errno)
+}
+
+///Maps size bytes of fd starting at offset, or anonymous memory if fd is None. addr is only a hint
+pub fn mmap(fd: Option<Fd>, offset: u64, addr: Option<*mut u8>, size: u64, flags: MmapFlags) -> Result<*mut u8, Errno> {
+    let fd = fd.unwrap_or(ERR_RET);
+    let addr = addr.map(|addr| addr as u64).unwrap_or(0);
+    let (ret, errno) = unsafe { syscall(MMAP, [fd, offset, addr, size, flags.0, 0]) };
+    //mmap returns NULL on failure instead of -1
+    if ret == 0 {
+        Err(Errno::from(errno))
+    } else {
+        Ok(ret as *mut u8)
+    }
+}
+
+pub fn munmap(addr: *mut u8, size: u64) -> Result<(), Errno> {
+    let (ret, errno) = unsafe { syscall(MUNMAP, [addr as u64, size, 0, 0, 0, 0]) };
+    check(ret, errno).map(|_| ())
+}
+
+///Sleeps for at least duration. A duration of 0 only yields
+pub fn sleep(duration: Duration) -> Result<(), Errno> {
+    let (ret, errno) = unsafe { syscall(SLEEP, [duration.as_micros() as u64, 0, 0, 0, 0, 0]) };
+    check(ret, errno).map(|_| ())
+}
+
+///Time since the unix epoch
+pub fn time() -> Result<Duration, Errno> {
+    let mut seconds: u64 = 0;
+    let mut nanoseconds: u64 = 0;
+    let (_, errno) = unsafe {
+        syscall(
+            TIME,
+            [
+                &mut seconds as *mut u64 as u64,
+                &mut nanoseconds as *mut u64 as u64,
+                0,
+                0,
+                0,
+                0,
+            ],
+        )
+    };
+    if errno != 0 {
+        return Err(Errno::from(errno));
+    }
+    Ok(Duration::new(seconds, nanoseconds as u32))
+}
+
+///Writes all cached file data to disk
+pub fn sync() -> Result<(), Errno> {
+    let (ret, errno) = unsafe { syscall(SYNC, [0, 0, 0, 0, 0, 0]) };
+    check(ret, errno).map(|_| ())
+}
+
+///Mounts source (`PARTUUID=`, `PARTLABEL=`, a partition uuid or a /dev node) at target. The
+///filesystem type is detected if fs_type is None
+pub fn mount(source: &CStr, target: &CStr, fs_type: Option<&CStr>) -> Result<(), Errno> {
+    let fs_type = fs_type.map(|fs_type| fs_type.as_ptr() as u64).unwrap_or(0);
+    let (ret, errno) = unsafe { syscall(MOUNT, [source.as_ptr() as u64, target.as_ptr() as u64, fs_type, 0, 0, 0]) };
+    check(ret, errno).map(|_| ())
+}
+
+///Fails with [`Errno::Busy`] while files on the filesystem are open
+pub fn umount(target: &CStr) -> Result<(), Errno> {
+    let (ret, errno) = unsafe { syscall(UMOUNT, [target.as_ptr() as u64, 0, 0, 0, 0, 0]) };
+    check(ret, errno).map(|_| ())
+}
+
+///Creates a symbolic link at link_path pointing to target. The target does not have to exist
+pub fn symlink(target: &CStr, link_path: &CStr) -> Result<(), Errno> {
+    let (ret, errno) = unsafe { syscall(SYMLINK, [target.as_ptr() as u64, link_path.as_ptr() as u64, 0, 0, 0, 0]) };
+    check(ret, errno).map(|_| ())
+}
+
+///Removes a file, link or empty directory. Open descriptors of a file keep working until closed
+pub fn unlink(path: &CStr) -> Result<(), Errno> {
+    let (ret, errno) = unsafe { syscall(UNLINK, [path.as_ptr() as u64, 0, 0, 0, 0, 0]) };
+    check(ret, errno).map(|_| ())
+}
+
+///Cuts the file to size bytes or extends it with zeros. fd has to be open for writing
+pub fn ftruncate(fd: Fd, size: u64) -> Result<(), Errno> {
+    let (ret, errno) = unsafe { syscall(FTRUNCATE, [fd, size, 0, 0, 0, 0]) };
+    check(ret, errno).map(|_| ())
+}
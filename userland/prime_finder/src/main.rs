@@ -0,0 +1,37 @@
+#![no_std]
+#![no_main]
+
+use libr::{env, println};
+
+const DEFAULT_LIMIT: u64 = 100_000;
+
+fn is_prime(n: u64) -> bool {
+    if n < 2 {
+        return false;
+    }
+    let mut divisor = 2;
+    while divisor * divisor <= n {
+        if n % divisor == 0 {
+            return false;
+        }
+        divisor += 1;
+    }
+    true
+}
+
+///Counts primes below the limit given as the first argument, then parks
+#[unsafe(no_mangle)]
+fn main() -> u64 {
+    let limit = env::args().nth(1).and_then(|arg| arg.parse().ok()).unwrap_or(DEFAULT_LIMIT);
+
+    let mut count = 0;
+    let mut largest = 0;
+    for n in 2..limit {
+        if is_prime(n) {
+            count += 1;
+            largest = n;
+        }
+    }
+    println!("Found {} primes below {}, largest is {}", count, limit, largest);
+    env::park()
+}
@@ -0,0 +1,26 @@
+#![no_std]
+#![no_main]
+
+use core::time::Duration;
+
+use libr::{env, println, syscall};
+
+///Prints the time about once a second
+#[unsafe(no_mangle)]
+fn main() -> u64 {
+    let Ok(mut last_print) = syscall::time() else {
+        println!("Failed to get time");
+        env::park()
+    };
+    loop {
+        let Ok(now) = syscall::time() else {
+            println!("Failed to get time");
+            env::park()
+        };
+        //saturating, a clock that goes back would panic the subtraction
+        if now.saturating_sub(last_print) >= Duration::from_secs(1) {
+            println!("Time in nanoseconds: {}", now.as_nanos());
+            last_print = now;
+        }
+    }
+}